@@ -28,16 +28,12 @@ fn main() {
 
     let mut core = Core::new().unwrap();
 
-    let handshaker = SimpleHandshaker {
-        user: user,
-        pass: pass,
-        virtual_host: "/".into(),
-    };
+    let handshaker = SimpleHandshaker::new(user, pass, "/");
 
     let future = TcpStream::connect(&addr.parse().unwrap(), &core.handle())
         .map_err(|e| Error::from(e))
         .and_then(|socket| start_handshake(handshaker, socket))
-        .and_then(|socket| open_channel(LOCAL_CHANNEL_ID, socket))
+        .and_then(|(_info, socket)| open_channel(LOCAL_CHANNEL_ID, socket))
         .and_then(|socket| {
             let option = DeclareExchangeOption {
                 name: EXCHANGE_NAME.into(),
@@ -0,0 +1,79 @@
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{AckMethod, BasicClass, NackMethod, RejectMethod};
+
+use futures::sink::{Send, Sink};
+
+pub type Acked<S> = Send<S>;
+
+/// Send `Ack` message to AMQP server.
+/// If `is_multiple` is true, all messages up to and including `delivery_tag` are acknowledged.
+///
+/// # Notice
+/// Server does not reply to `Ack` message, so returned future will be completed when finish
+/// to send.
+pub fn ack<S>(channel_id: u16, socket: S, delivery_tag: u64, is_multiple: bool) -> Acked<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    socket.send(ack_frame(channel_id, delivery_tag, is_multiple))
+}
+
+pub(crate) fn ack_frame(channel_id: u16, delivery_tag: u64, is_multiple: bool) -> Frame {
+    let ack = AckMethod {
+        delivery_tag,
+        multiple: is_multiple,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Ack(ack))),
+    }
+}
+
+pub type Rejected<S> = Send<S>;
+
+/// Send `Reject` message to AMQP server.
+/// If `requeue` is false, the message is discarded or dead-lettered.
+///
+/// # Notice
+/// Server does not reply to `Reject` message, so returned future will be completed when finish
+/// to send.
+pub fn reject<S>(channel_id: u16, socket: S, delivery_tag: u64, requeue: bool) -> Rejected<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    socket.send(reject_frame(channel_id, delivery_tag, requeue))
+}
+
+pub(crate) fn reject_frame(channel_id: u16, delivery_tag: u64, requeue: bool) -> Frame {
+    let reject = RejectMethod {
+        delivery_tag,
+        requeue,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Reject(reject))),
+    }
+}
+
+/// `basic.nack` has no `requeue` bit in `amqpr_codec`, so server receives `requeue: false`
+/// and the items are discarded or dead-lettered.
+pub(crate) fn nack_frame(channel_id: u16, delivery_tag: u64, is_multiple: bool) -> Frame {
+    let nack = NackMethod {
+        delivery_tag,
+        multiple: is_multiple,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Nack(nack))),
+    }
+}
@@ -1,7 +1,7 @@
 use amqpr_codec::{Frame, FrameHeader, FramePayload};
 use amqpr_codec::args::AmqpString;
 use amqpr_codec::method::MethodPayload;
-use amqpr_codec::method::basic::{BasicClass, ConsumeMethod};
+use amqpr_codec::method::basic::{BasicClass, CancelMethod, ConsumeMethod};
 
 use futures::sink::{Send, Sink};
 
@@ -20,6 +20,10 @@ where
     S: Sink<SinkItem = Frame>,
     S::SinkError: From<Error>,
 {
+    socket.send(consume_frame(channel_id, option))
+}
+
+pub(crate) fn consume_frame(channel_id: u16, option: StartConsumeOption) -> Frame {
     let consume = ConsumeMethod {
         reserved1: 0,
         queue: option.queue,
@@ -31,14 +35,26 @@ where
         arguments: HashMap::new(),
     };
 
-    let frame = Frame {
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
         payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(consume))),
+    }
+}
+
+pub(crate) fn cancel_frame(channel_id: u16, consumer_tag: AmqpString) -> Frame {
+    let cancel = CancelMethod {
+        consumer_tag,
+        no_wait: false,
     };
 
-    socket.send(frame)
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Cancel(cancel))),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1,12 +1,15 @@
 use amqpr_codec::Frame;
-use amqpr_codec::content_header::ContentHeaderPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
 use amqpr_codec::content_body::ContentBodyPayload;
 use amqpr_codec::frame::method::basic::DeliverMethod;
 
+use bytes::Bytes;
+
 use futures::{Async, Future, Poll, Stream};
 
-use basic::publish::PublishItem;
+use basic::publish::{PublishItem, PublishOption};
 use common::Should;
+use protocol_log;
 use errors::*;
 
 /// Get `DeliveredItem` from given stream.
@@ -17,6 +20,8 @@ use errors::*;
 /// Maybe it is useful to use `subscribe_stream`. That function returns `Stream` of
 /// `DeliveredItem`.
 ///
+/// Heartbeat frames are skipped because server may send them between the three frames.
+///
 /// # Error
 /// `Delivered` future might be `Error` when `stream: S` yields unexpected frame.
 pub fn get_delivered<S>(stream: S) -> Delivered<S>
@@ -36,8 +41,31 @@ pub struct DeliveredItem {
 }
 
 impl DeliveredItem {
-    pub fn make_response(&self) -> PublishItem {
-        panic!();
+    /// Make `PublishItem` being a response to this item.
+    /// It is published to the default exchange with `reply_to` property of this item as
+    /// routing key, and has the same `correlation_id` property as this item.
+    ///
+    /// # Error
+    /// Returns `NoReplyTo` error if this item does not have `reply_to` property.
+    pub fn make_response<B: Into<Bytes>>(&self, body: B) -> Result<PublishItem, Error> {
+        let reply_to = match self.header.properties.reply_to {
+            Some(ref reply_to) => reply_to.clone(),
+            None => return Err(ErrorKind::NoReplyTo(self.meta.delivery_tag).into()),
+        };
+
+        let mut properties = Properties::new();
+        properties.correlation_id = self.header.properties.correlation_id.clone();
+
+        Ok(PublishItem {
+            meta: PublishOption {
+                exchange: "".into(),
+                routing_key: reply_to,
+                is_mandatory: false,
+                is_immediate: false,
+            },
+            header: properties,
+            body: body.into(),
+        })
     }
 }
 
@@ -61,6 +89,9 @@ where
         *self = match self {
             &mut ReceivingDeliverMethod(ref mut socket) => {
                 let frame = try_stream_ready!(socket.as_mut().poll());
+                if frame.heartbeat().is_some() {
+                    return self.poll();
+                }
 
                 let is_deliver = frame
                     .method()
@@ -75,12 +106,15 @@ where
                         ))))
                     }
                 };
-                info!("Deliver method is received : {:?}", deliver);
+                debug!("Receive {}", protocol_log::display(&frame));
                 ReceivingContentHeader(Should::new(socket.take()), Should::new(deliver))
             }
 
             &mut ReceivingContentHeader(ref mut socket, ref mut deliver) => {
                 let frame = try_stream_ready!(socket.as_mut().poll());
+                if frame.heartbeat().is_some() {
+                    return self.poll();
+                }
                 let header = match frame.content_header() {
                     Some(ch) => ch.clone(),
                     None => {
@@ -90,7 +124,7 @@ where
                         ))))
                     }
                 };
-                info!("Content header is received : {:?}", header);
+                debug!("Receive {}", protocol_log::display(&frame));
 
                 ReceivingContentBody(
                     Should::new(socket.take()),
@@ -100,6 +134,9 @@ where
 
             &mut ReceivingContentBody(ref mut socket, ref mut piece) => {
                 let frame = try_stream_ready!(socket.as_mut().poll());
+                if frame.heartbeat().is_some() {
+                    return self.poll();
+                }
                 let body = match frame.content_body() {
                     Some(cb) => cb.clone(),
                     None => {
@@ -109,8 +146,8 @@ where
                         ))))
                     }
                 };
+                debug!("Receive {}", protocol_log::display(&frame));
 
-                info!("Content body is received : {:?}", body);
                 let (meta, header) = piece.take();
                 let item = DeliveredItem {
                     meta: meta,
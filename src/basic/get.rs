@@ -0,0 +1,24 @@
+use amqpr_codec::{AmqpString, Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, GetMethod};
+
+pub(crate) fn get_frame(channel_id: u16, option: GetOption) -> Frame {
+    let get = GetMethod {
+        reserved1: 0,
+        queue: option.queue,
+        no_ack: option.is_no_ack,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Get(get))),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetOption {
+    pub queue: AmqpString,
+    pub is_no_ack: bool,
+}
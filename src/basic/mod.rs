@@ -1,7 +1,17 @@
 pub mod publish;
 pub mod deliver;
 pub mod consume;
+pub mod get;
+pub mod ack;
+pub mod qos;
+pub mod recover;
+pub mod properties;
 
 pub use self::publish::{publish, PublishItem, PublishOption, Published};
 pub use self::deliver::{get_delivered, Delivered};
 pub use self::consume::{start_consume, ConsumeStarted, StartConsumeOption};
+pub use self::get::GetOption;
+pub use self::ack::{ack, reject, Acked, Rejected};
+pub use self::qos::{qos, QosOption, QosSet};
+pub use self::recover::{recover, recover_async, RecoverAsyncSent, Recovered};
+pub use self::properties::{DeliveryMode, PropertiesBuilder};
@@ -0,0 +1,263 @@
+//! Builder of outgoing message properties, and typed accessors of delivered ones.
+
+use amqpr_codec::{AmqpString, FieldArgument};
+use amqpr_codec::content_header::Properties;
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use basic::deliver::DeliveredItem;
+
+/// `delivery_mode` property of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Message may be lost when server restarts.
+    Transient,
+    /// Message is written to disk if it is routed to a durable queue.
+    Persistent,
+}
+
+impl DeliveryMode {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            DeliveryMode::Transient => 1,
+            DeliveryMode::Persistent => 2,
+        }
+    }
+
+    pub fn from_u8(mode: u8) -> Option<DeliveryMode> {
+        match mode {
+            1 => Some(DeliveryMode::Transient),
+            2 => Some(DeliveryMode::Persistent),
+            _ => None,
+        }
+    }
+}
+
+const MAX_PRIORITY: u8 = 9;
+
+/// Builder of `Properties` being sent with `PublishItem`.
+///
+/// ```
+/// # extern crate amqpr_api;
+/// # fn main() {
+/// use std::time::Duration;
+/// use amqpr_api::basic::properties::{DeliveryMode, PropertiesBuilder};
+///
+/// let properties = PropertiesBuilder::new()
+///     .content_type("application/json")
+///     .delivery_mode(DeliveryMode::Persistent)
+///     .expiration(Duration::from_secs(60))
+///     .build();
+/// assert_eq!(properties.delivery_mode, Some(2));
+/// assert_eq!(properties.expiration, Some("60000".into()));
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertiesBuilder {
+    properties: Properties,
+}
+
+impl PropertiesBuilder {
+    pub fn new() -> PropertiesBuilder {
+        PropertiesBuilder {
+            properties: Properties::new(),
+        }
+    }
+
+    /// MIME type of the body such as "application/json".
+    pub fn content_type<S: Into<String>>(mut self, content_type: S) -> PropertiesBuilder {
+        self.properties.content_type = Some(amqp_string(content_type));
+        self
+    }
+
+    /// Encoding of the body such as "gzip".
+    pub fn content_encoding<S: Into<String>>(mut self, encoding: S) -> PropertiesBuilder {
+        self.properties.content_encoding = Some(amqp_string(encoding));
+        self
+    }
+
+    pub fn delivery_mode(mut self, mode: DeliveryMode) -> PropertiesBuilder {
+        self.properties.delivery_mode = Some(mode.to_u8());
+        self
+    }
+
+    /// Shorthand of `delivery_mode(DeliveryMode::Persistent)`.
+    pub fn persistent(self) -> PropertiesBuilder {
+        self.delivery_mode(DeliveryMode::Persistent)
+    }
+
+    /// Priority from 0 to 9. It works only with a priority queue.
+    /// `priority` greater than 9 is clamped to 9, so that the builder never fails.
+    pub fn priority(mut self, priority: u8) -> PropertiesBuilder {
+        self.properties.priority = Some(::std::cmp::min(priority, MAX_PRIORITY));
+        self
+    }
+
+    pub fn correlation_id<S: Into<String>>(mut self, correlation_id: S) -> PropertiesBuilder {
+        self.properties.correlation_id = Some(amqp_string(correlation_id));
+        self
+    }
+
+    /// Name of a queue to which the response should be sent.
+    pub fn reply_to<S: Into<String>>(mut self, reply_to: S) -> PropertiesBuilder {
+        self.properties.reply_to = Some(amqp_string(reply_to));
+        self
+    }
+
+    /// Message is discarded if it stays in a queue longer than `ttl`.
+    /// It is sent as milliseconds, so sub-millisecond part is truncated.
+    pub fn expiration(mut self, ttl: Duration) -> PropertiesBuilder {
+        self.properties.expiration = Some(amqp_string(ttl.as_millis().to_string()));
+        self
+    }
+
+    pub fn message_id<S: Into<String>>(mut self, message_id: S) -> PropertiesBuilder {
+        self.properties.message_id = Some(amqp_string(message_id));
+        self
+    }
+
+    /// It is sent as seconds since unix epoch, so sub-second part is truncated.
+    pub fn timestamp(mut self, timestamp: SystemTime) -> PropertiesBuilder {
+        let secs = match timestamp.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        self.properties.timestamp = Some(secs);
+        self
+    }
+
+    /// Application specific type of the message such as "order.created".
+    pub fn message_type<S: Into<String>>(mut self, message_type: S) -> PropertiesBuilder {
+        self.properties.type_ = Some(amqp_string(message_type));
+        self
+    }
+
+    /// RabbitMQ rejects the message if it differs from the user of the connection.
+    pub fn user_id<S: Into<String>>(mut self, user_id: S) -> PropertiesBuilder {
+        self.properties.user_id = Some(amqp_string(user_id));
+        self
+    }
+
+    pub fn app_id<S: Into<String>>(mut self, app_id: S) -> PropertiesBuilder {
+        self.properties.app_id = Some(amqp_string(app_id));
+        self
+    }
+
+    /// Add a header. A header having the same key is overwritten.
+    pub fn header<K: Into<String>>(mut self, key: K, value: FieldArgument) -> PropertiesBuilder {
+        self.properties
+            .headers
+            .get_or_insert_with(HashMap::new)
+            .insert(amqp_string(key), value);
+        self
+    }
+
+    pub fn headers(mut self, headers: HashMap<AmqpString, FieldArgument>) -> PropertiesBuilder {
+        self.properties.headers = Some(headers);
+        self
+    }
+
+    pub fn build(self) -> Properties {
+        self.properties
+    }
+}
+
+impl Default for PropertiesBuilder {
+    fn default() -> PropertiesBuilder {
+        PropertiesBuilder::new()
+    }
+}
+
+impl From<Properties> for PropertiesBuilder {
+    fn from(properties: Properties) -> PropertiesBuilder {
+        PropertiesBuilder { properties }
+    }
+}
+
+fn amqp_string<S: Into<String>>(s: S) -> AmqpString {
+    AmqpString::from(s.into())
+}
+
+fn as_str(s: &Option<AmqpString>) -> Option<&str> {
+    s.as_ref().map(|s| &**s)
+}
+
+/// Typed accessors of the properties of a delivered item.
+impl DeliveredItem {
+    pub fn properties(&self) -> &Properties {
+        &self.header.properties
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        as_str(&self.properties().content_type)
+    }
+
+    pub fn content_encoding(&self) -> Option<&str> {
+        as_str(&self.properties().content_encoding)
+    }
+
+    pub fn headers(&self) -> Option<&HashMap<AmqpString, FieldArgument>> {
+        self.properties().headers.as_ref()
+    }
+
+    /// Value of a header having `key`.
+    pub fn header_value(&self, key: &str) -> Option<&FieldArgument> {
+        self.headers()
+            .and_then(|headers| headers.get(&AmqpString::from(key.to_string())))
+    }
+
+    /// Returns `None` if the property is missing or has unknown value.
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        self.properties().delivery_mode.and_then(DeliveryMode::from_u8)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.delivery_mode() == Some(DeliveryMode::Persistent)
+    }
+
+    pub fn priority(&self) -> Option<u8> {
+        self.properties().priority
+    }
+
+    pub fn correlation_id(&self) -> Option<&str> {
+        as_str(&self.properties().correlation_id)
+    }
+
+    pub fn reply_to(&self) -> Option<&str> {
+        as_str(&self.properties().reply_to)
+    }
+
+    /// Returns `None` if the property is missing or is not milliseconds.
+    pub fn expiration(&self) -> Option<Duration> {
+        as_str(&self.properties().expiration)
+            .and_then(|millis| millis.parse().ok())
+            .map(Duration::from_millis)
+    }
+
+    pub fn message_id(&self) -> Option<&str> {
+        as_str(&self.properties().message_id)
+    }
+
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.properties().timestamp.map(|secs| {
+            if secs >= 0 {
+                UNIX_EPOCH + Duration::from_secs(secs as u64)
+            } else {
+                UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+            }
+        })
+    }
+
+    pub fn message_type(&self) -> Option<&str> {
+        as_str(&self.properties().type_)
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        as_str(&self.properties().user_id)
+    }
+
+    pub fn app_id(&self) -> Option<&str> {
+        as_str(&self.properties().app_id)
+    }
+}
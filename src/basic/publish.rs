@@ -10,6 +10,7 @@ use futures::{Async, Future, Poll, Sink};
 use futures::sink::Send;
 
 use common::Should;
+use protocol_log;
 
 /// Publish an item to AMQP server.
 /// If you want to publish a lot number of items, please consider to use `publish_sink` function.
@@ -19,8 +20,32 @@ where
     S: Sink<SinkItem = Frame>,
 {
     let (meta, header, body) = (item.meta, item.header, item.body);
+    let frame = publish_method_frame(channel_id, meta);
 
-    let declare = PublishMethod {
+    debug!("Sending {}", protocol_log::display(&frame));
+
+    Published {
+        state: SendingContentState::SendingPublishMethod(
+            socket.send(frame),
+            Should::new(header),
+            Should::new(body),
+        ),
+        channel_id: channel_id,
+    }
+}
+
+/// Make three frames which consist a `Publish` message; method, content header and
+/// content body.
+pub(crate) fn publish_frames(channel_id: u16, item: PublishItem) -> Vec<Frame> {
+    vec![
+        publish_method_frame(channel_id, item.meta),
+        content_header_frame(channel_id, item.header, item.body.len()),
+        content_body_frame(channel_id, item.body),
+    ]
+}
+
+fn publish_method_frame(channel_id: u16, meta: PublishOption) -> Frame {
+    let publish = PublishMethod {
         reserved1: 0,
         exchange: meta.exchange,
         routing_key: meta.routing_key,
@@ -28,22 +53,35 @@ where
         immediate: meta.is_immediate,
     };
 
-    let frame = Frame {
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
-        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Publish(declare))),
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Publish(publish))),
+    }
+}
+
+fn content_header_frame(channel_id: u16, properties: Properties, body_size: usize) -> Frame {
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: body_size as u64,
+        properties,
     };
 
-    debug!("Sending publish method : {:?}", frame);
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::ContentHeader(header),
+    }
+}
 
-    Published {
-        state: SendingContentState::SendingPublishMethod(
-            socket.send(frame),
-            Should::new(header),
-            Should::new(body),
-        ),
-        channel_id: channel_id,
+fn content_body_frame(channel_id: u16, bytes: Bytes) -> Frame {
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::ContentBody(ContentBodyPayload { bytes }),
     }
 }
 
@@ -93,34 +131,15 @@ where
         self.state = match &mut self.state {
             &mut SendingPublishMethod(ref mut sending, ref mut properties, ref mut bytes) => {
                 let socket = try_ready!(sending.poll());
-                let header = ContentHeaderPayload {
-                    class_id: 60,
-                    body_size: bytes.as_ref().len() as u64,
-                    properties: properties.take(),
-                };
-                let frame = Frame {
-                    header: FrameHeader {
-                        channel: self.channel_id,
-                    },
-                    payload: FramePayload::ContentHeader(header),
-                };
+                let frame =
+                    content_header_frame(self.channel_id, properties.take(), bytes.as_ref().len());
                 debug!("Sent publish method");
                 SendingContentHeader(socket.send(frame), bytes.clone())
             }
 
             &mut SendingContentHeader(ref mut sending, ref mut bytes) => {
                 let socket = try_ready!(sending.poll());
-                let frame = {
-                    let payload = ContentBodyPayload {
-                        bytes: bytes.take(),
-                    };
-                    Frame {
-                        header: FrameHeader {
-                            channel: self.channel_id,
-                        },
-                        payload: FramePayload::ContentBody(payload),
-                    }
-                };
+                let frame = content_body_frame(self.channel_id, bytes.take());
                 debug!("Sent content header");
                 SendingContentBody(socket.send(frame))
             }
@@ -0,0 +1,89 @@
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, QosMethod};
+
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send;
+
+use common::Should;
+use errors::*;
+
+#[derive(Debug, Clone)]
+pub struct QosOption {
+    /// Window size in octets. 0 means no limit. RabbitMQ does not support it.
+    pub prefetch_size: u32,
+    /// Number of unacknowledged deliveries server sends. 0 means no limit.
+    pub prefetch_count: u16,
+    /// If true, the limit is applied to the whole connection (RabbitMQ: shared by all
+    /// consumers on the channel).
+    pub is_global: bool,
+}
+
+/// Limit deliveries which are not acknowledged yet, and wait to receive `Qos-Ok` method.
+pub fn qos<S, E>(channel_id: u16, socket: S, option: QosOption) -> QosSet<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    QosSet::Sending(socket.send(qos_frame(channel_id, option)))
+}
+
+pub(crate) fn qos_frame(channel_id: u16, option: QosOption) -> Frame {
+    let qos = QosMethod {
+        prefetch_size: option.prefetch_size,
+        prefetch_count: option.prefetch_count,
+        global: option.is_global,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Qos(qos))),
+    }
+}
+
+pub enum QosSet<S>
+where
+    S: Sink,
+{
+    Sending(Send<S>),
+    Receiving(Should<S>),
+}
+
+impl<S, E> Future for QosSet<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    type Item = S;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        use self::QosSet::*;
+
+        *self = match self {
+            Sending(sending) => {
+                let socket = try_ready!(sending.poll());
+                Receiving(Should::new(socket))
+            }
+            Receiving(socket) => {
+                let frame = try_stream_ready!(socket.as_mut().poll());
+                match frame.method().and_then(|m| m.basic()).and_then(|c| c.qos_ok()) {
+                    Some(()) => {
+                        debug!("Receive qos-ok response");
+                        return Ok(Async::Ready(socket.take()));
+                    }
+                    None => {
+                        return Err(E::from(Error::from(ErrorKind::UnexpectedFrame(
+                            "QosOk".into(),
+                            frame.clone(),
+                        ))))
+                    }
+                }
+            }
+        };
+
+        self.poll()
+    }
+}
@@ -0,0 +1,109 @@
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, RecoverAsyncMethod, RecoverMethod};
+
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send;
+
+use common::Should;
+use errors::*;
+
+pub type RecoverAsyncSent<S> = Send<S>;
+
+/// Ask AMQP server to redeliver all unacknowledged messages on given channel, and wait to
+/// receive `Recover-Ok` method.
+/// If `requeue` is false, messages are redelivered to the original recipient. Otherwise,
+/// server may redeliver them to another consumer.
+///
+/// # Notice
+/// If the channel has an active consumer, deliveries may arrive before `Recover-Ok` and make
+/// returned future fail. In that case, please use `AckHandle::recover` of `SubscribeStream`.
+pub fn recover<S, E>(channel_id: u16, socket: S, requeue: bool) -> Recovered<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    Recovered::Sending(socket.send(recover_frame(channel_id, requeue)))
+}
+
+/// Send `Recover-Async` message to AMQP server.
+/// Server does not reply to this message.
+///
+/// # Notice
+/// This method is deprecated by AMQP 0-9-1 spec and RabbitMQ does not support it.
+/// Please use `recover` function if you talk with RabbitMQ.
+pub fn recover_async<S>(channel_id: u16, socket: S, requeue: bool) -> RecoverAsyncSent<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    let recover = RecoverAsyncMethod { requeue };
+
+    let frame = Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::RecoverAsync(recover))),
+    };
+
+    socket.send(frame)
+}
+
+pub(crate) fn recover_frame(channel_id: u16, requeue: bool) -> Frame {
+    let recover = RecoverMethod { requeue };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Basic(BasicClass::Recover(recover))),
+    }
+}
+
+pub enum Recovered<S>
+where
+    S: Sink,
+{
+    Sending(Send<S>),
+    Receiving(Should<S>),
+}
+
+impl<S, E> Future for Recovered<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    type Item = S;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<S, E> {
+        use self::Recovered::*;
+
+        *self = match self {
+            Sending(sending) => {
+                let socket = try_ready!(sending.poll());
+                Receiving(Should::new(socket))
+            }
+            Receiving(socket) => {
+                let frame = try_stream_ready!(socket.as_mut().poll());
+                match frame
+                    .method()
+                    .and_then(|m| m.basic())
+                    .and_then(|c| c.recover_ok())
+                {
+                    Some(()) => {
+                        debug!("Receive recover-ok response");
+                        return Ok(Async::Ready(socket.take()));
+                    }
+                    None => {
+                        return Err(E::from(Error::from(ErrorKind::UnexpectedFrame(
+                            "RecoverOk".into(),
+                            frame.clone(),
+                        ))))
+                    }
+                }
+            }
+        };
+
+        self.poll()
+    }
+}
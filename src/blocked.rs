@@ -0,0 +1,142 @@
+//! Watch `connection.blocked` and `connection.unblocked` notifications.
+//!
+//! RabbitMQ sends `Blocked` method when it hits memory or disk alarms and stops reading
+//! from connections which publish. `Unblocked` method is sent when the alarm is cleared.
+//! These notifications are sent only if client advertises `connection.blocked` capability,
+//! which `SimpleHandshaker` does.
+
+use futures::{Async, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use amqpr_codec::{AmqpString, Frame};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Wrap given socket and returns it with `BlockedStream`.
+/// Returned socket removes `Blocked` and `Unblocked` methods from inbound frames and
+/// notifies them to `BlockedStream`. So other functions in this crate never see these methods.
+pub fn watch_blocked<S>(socket: S) -> (BlockedWatched<S>, BlockedStream) {
+    let (tx, rx) = unbounded();
+    let is_blocked = Arc::new(AtomicBool::new(false));
+
+    let watched = BlockedWatched {
+        socket,
+        tx,
+        is_blocked: is_blocked.clone(),
+    };
+    let stream = BlockedStream { rx, is_blocked };
+
+    (watched, stream)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockedEvent {
+    /// Server stops reading from this connection. Publishers should pause.
+    Blocked { reason: AmqpString },
+    /// Server resumes reading from this connection.
+    Unblocked,
+}
+
+/// Socket returned by `watch_blocked` function.
+pub struct BlockedWatched<S> {
+    socket: S,
+    tx: UnboundedSender<BlockedEvent>,
+    is_blocked: Arc<AtomicBool>,
+}
+
+impl<S> BlockedWatched<S> {
+    /// Returns true while the connection is blocked by server.
+    pub fn is_blocked(&self) -> bool {
+        self.is_blocked.load(Ordering::SeqCst)
+    }
+
+    fn notify(&self, event: BlockedEvent) {
+        info!("Connection is notified : {:?}", event);
+        let is_blocked = match event {
+            BlockedEvent::Blocked { .. } => true,
+            BlockedEvent::Unblocked => false,
+        };
+        self.is_blocked.store(is_blocked, Ordering::SeqCst);
+
+        // Nobody is interested in this event if receiver is already dropped.
+        let _ = self.tx.unbounded_send(event);
+    }
+}
+
+impl<S> Stream for BlockedWatched<S>
+where
+    S: Stream<Item = Frame>,
+{
+    type Item = Frame;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, S::Error> {
+        loop {
+            let frame = match try_ready!(self.socket.poll()) {
+                Some(frame) => frame,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let event = match frame.method().and_then(|m| m.connection()) {
+                Some(c) => match (c.blocked(), c.unblocked()) {
+                    (Some(blocked), _) => Some(BlockedEvent::Blocked {
+                        reason: blocked.reason.clone(),
+                    }),
+                    (None, Some(())) => Some(BlockedEvent::Unblocked),
+                    (None, None) => None,
+                },
+                None => None,
+            };
+
+            match event {
+                Some(event) => self.notify(event),
+                None => return Ok(Async::Ready(Some(frame))),
+            }
+        }
+    }
+}
+
+impl<S> Sink for BlockedWatched<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    type SinkItem = Frame;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Frame) -> StartSend<Frame, S::SinkError> {
+        self.socket.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.close()
+    }
+}
+
+/// Stream of `BlockedEvent`.
+/// This stream ends when corresponding `BlockedWatched` socket is dropped.
+pub struct BlockedStream {
+    rx: UnboundedReceiver<BlockedEvent>,
+    is_blocked: Arc<AtomicBool>,
+}
+
+impl BlockedStream {
+    /// Returns true while the connection is blocked by server.
+    /// Publishers can check it before sending items.
+    pub fn is_blocked(&self) -> bool {
+        self.is_blocked.load(Ordering::SeqCst)
+    }
+}
+
+impl Stream for BlockedStream {
+    type Item = BlockedEvent;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<BlockedEvent>, ()> {
+        self.rx.poll()
+    }
+}
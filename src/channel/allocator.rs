@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+
+use errors::*;
+
+/// Hands out unused channel ids between 1 and negotiated `channel_max`.
+/// Released ids are reused from the smallest one.
+///
+/// `Connection` uses it internally. It is also useful when you open channels by
+/// `open_channel` function on a raw socket.
+#[derive(Debug, Clone)]
+pub struct ChannelIdAllocator {
+    channel_max: u16,
+    /// Every id in `1..next` which is not in `released` is in use.
+    next: u32,
+    released: BTreeSet<u16>,
+}
+
+impl ChannelIdAllocator {
+    /// `channel_max` is the one in `ConnectionInfo`. 0 means no limit.
+    pub fn new(channel_max: u16) -> ChannelIdAllocator {
+        ChannelIdAllocator {
+            channel_max: if channel_max == 0 {
+                u16::MAX
+            } else {
+                channel_max
+            },
+            next: 1,
+            released: BTreeSet::new(),
+        }
+    }
+
+    pub fn channel_max(&self) -> u16 {
+        self.channel_max
+    }
+
+    /// Returns the smallest unused id.
+    pub fn allocate(&mut self) -> Result<u16, Error> {
+        if let Some(id) = self.released.iter().next().cloned() {
+            self.released.remove(&id);
+            return Ok(id);
+        }
+        if self.next > u32::from(self.channel_max) {
+            return Err(ErrorKind::ChannelIdExhausted(self.channel_max).into());
+        }
+        let id = self.next as u16;
+        self.next += 1;
+        Ok(id)
+    }
+
+    /// Mark given id as being used.
+    /// Fails if it is 0, greater than `channel_max` or already in use.
+    pub fn reserve(&mut self, id: u16) -> Result<(), Error> {
+        if id == 0 || id > self.channel_max {
+            return Err(ErrorKind::InvalidChannelId(id, self.channel_max).into());
+        }
+        if self.is_allocated(id) {
+            return Err(ErrorKind::ChannelIdInUse(id).into());
+        }
+        if u32::from(id) < self.next {
+            self.released.remove(&id);
+        } else {
+            self.released.extend((self.next as u16)..id);
+            self.next = u32::from(id) + 1;
+        }
+        Ok(())
+    }
+
+    /// Make given id available again. Call it after `Close-Ok` method is exchanged.
+    pub fn release(&mut self, id: u16) {
+        if !self.is_allocated(id) {
+            return;
+        }
+        self.released.insert(id);
+        while self.next > 1 && self.released.remove(&((self.next - 1) as u16)) {
+            self.next -= 1;
+        }
+    }
+
+    pub fn is_allocated(&self, id: u16) -> bool {
+        id != 0 && u32::from(id) < self.next && !self.released.contains(&id)
+    }
+}
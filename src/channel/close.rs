@@ -0,0 +1,95 @@
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::channel::{ChannelClass, CloseMethod};
+
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send;
+
+use common::Should;
+use protocol_log;
+use errors::*;
+
+/// Reply code of `Close` method which means normal shutdown.
+pub const REPLY_SUCCESS: u16 = 200;
+
+/// Close given channel, and wait to receive `Close-Ok` method.
+/// After that, you can reuse the channel id to open new channel.
+///
+/// # Notice
+/// Inbound frames arriving before `Close-Ok` method such as `Deliver` method are skipped.
+pub fn close_channel<S, E>(channel_id: u16, socket: S) -> ChannelClosed<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    ChannelClosed::Sending(socket.send(close_frame(channel_id)))
+}
+
+pub(crate) fn close_frame(channel_id: u16) -> Frame {
+    let close = CloseMethod {
+        reply_code: REPLY_SUCCESS,
+        reply_text: "Normal shutdown".into(),
+        class_id: 0,
+        method_id: 0,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Channel(ChannelClass::Close(close))),
+    }
+}
+
+/// Make `Close-Ok` frame being a reply to `Close` method sent by server.
+pub(crate) fn close_ok_frame(channel_id: u16) -> Frame {
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Channel(ChannelClass::CloseOk)),
+    }
+}
+
+pub enum ChannelClosed<S>
+where
+    S: Sink,
+{
+    Sending(Send<S>),
+    Receiving(Should<S>),
+}
+
+impl<S, E> Future for ChannelClosed<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    type Item = S;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        use self::ChannelClosed::*;
+
+        *self = match self {
+            Sending(sending) => {
+                let socket = try_ready!(sending.poll());
+                Receiving(Should::new(socket))
+            }
+            Receiving(socket) => loop {
+                let frame = try_stream_ready!(socket.as_mut().poll());
+                let is_close_ok = frame
+                    .method()
+                    .and_then(|m| m.channel())
+                    .and_then(|c| c.close_ok())
+                    .is_some();
+                if is_close_ok {
+                    debug!("Receive close-ok response");
+                    return Ok(Async::Ready(socket.take()));
+                }
+                debug!("Skip inbound frame while closing channel : {}", protocol_log::display(&frame));
+            },
+        };
+
+        self.poll()
+    }
+}
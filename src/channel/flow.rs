@@ -0,0 +1,93 @@
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::channel::{ChannelClass, FlowMethod, FlowOkMethod};
+
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send;
+
+use common::Should;
+use errors::*;
+
+/// Ask AMQP server to stop (`active: false`) or restart (`active: true`) sending
+/// deliveries on given channel, and wait to receive `Flow-Ok` method.
+///
+/// Returned future consists of the `active` flag server replied with and `S`.
+pub fn channel_flow<S, E>(channel_id: u16, socket: S, active: bool) -> ChannelFlowed<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    ChannelFlowed::Sending(socket.send(flow_frame(channel_id, active)))
+}
+
+pub(crate) fn flow_frame(channel_id: u16, active: bool) -> Frame {
+    let flow = FlowMethod { active };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Channel(ChannelClass::Flow(flow))),
+    }
+}
+
+/// Make `Flow-Ok` frame being a reply to `Flow` method sent by server.
+pub(crate) fn flow_ok_frame(channel_id: u16, active: bool) -> Frame {
+    let flow_ok = FlowOkMethod { active };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Channel(ChannelClass::FlowOk(flow_ok))),
+    }
+}
+
+pub enum ChannelFlowed<S>
+where
+    S: Sink,
+{
+    Sending(Send<S>),
+    Receiving(Should<S>),
+}
+
+impl<S, E> Future for ChannelFlowed<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    type Item = (bool, S);
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        use self::ChannelFlowed::*;
+
+        *self = match self {
+            Sending(sending) => {
+                let socket = try_ready!(sending.poll());
+                Receiving(Should::new(socket))
+            }
+            Receiving(socket) => {
+                let frame = try_stream_ready!(socket.as_mut().poll());
+                match frame
+                    .method()
+                    .and_then(|m| m.channel())
+                    .and_then(|c| c.flow_ok())
+                {
+                    Some(flow_ok) => {
+                        debug!("Receive flow-ok response : {:?}", flow_ok);
+                        return Ok(Async::Ready((flow_ok.active, socket.take())));
+                    }
+                    None => {
+                        return Err(E::from(Error::from(ErrorKind::UnexpectedFrame(
+                            "FlowOk".into(),
+                            frame.clone(),
+                        ))))
+                    }
+                }
+            }
+        };
+
+        self.poll()
+    }
+}
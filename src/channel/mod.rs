@@ -1,3 +1,9 @@
 pub mod open;
+pub mod flow;
+pub mod close;
+pub mod allocator;
 
 pub use self::open::open_channel;
+pub use self::flow::{channel_flow, ChannelFlowed};
+pub use self::close::{close_channel, ChannelClosed};
+pub use self::allocator::ChannelIdAllocator;
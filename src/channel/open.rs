@@ -17,17 +17,20 @@ where
     S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
     E: From<Error>,
 {
+    ChannelOpened::Sending(socket.send(open_frame(channel_id)))
+}
+
+pub(crate) fn open_frame(channel_id: u16) -> Frame {
     let open = OpenMethod {
         reserved1: "".into(),
     };
-    let frame = Frame {
+
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
         payload: FramePayload::Method(MethodPayload::Channel(ChannelClass::Open(open))),
-    };
-
-    ChannelOpened::Sending(socket.send(frame))
+    }
 }
 
 pub enum ChannelOpened<S>
@@ -0,0 +1,454 @@
+//! Body compression keyed on `content_encoding` property.
+//!
+//! Each algorithm is enabled by its own feature; `gzip`, `zstd` and `lz4`.
+//! `Compressions` keeps algorithms by their `content_encoding` name.
+//!
+//! - `publish_compressed` and `BroadcastSink::compress` compress published items.
+//! - `decompress_delivered` and `decompress_stream` decompress items from `get_delivered`
+//!   and `SubscribeStream`.
+//! - `Connection::set_compressions` decompresses items delivered to every `Consumer` on
+//!   the connection, and `Channel::publish_compressed` compresses an item.
+//!
+//! Decompressed body is limited to `Compressions::max_decompressed_size`, so that a small
+//! malicious message can not exhaust memory.
+
+use bytes::Bytes;
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+
+use amqpr_codec::{AmqpString, Frame};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use basic::deliver::DeliveredItem;
+use basic::publish::{publish, PublishItem, Published};
+use errors::*;
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+use std::io::{Read, Write};
+
+/// Default of `Compressions::max_decompressed_size`. It is the default max message size
+/// of RabbitMQ.
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 128 * 1024 * 1024;
+
+/// An algorithm to compress message body.
+pub trait Compression: Send + Sync {
+    /// Name which is set to `content_encoding` property such as "gzip".
+    fn encoding(&self) -> &str;
+
+    fn compress(&self, bytes: &[u8]) -> Result<Bytes, Error>;
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Bytes, Error>;
+
+    /// Same as `decompress` but fails with `DecompressedTooLarge` error if decompressed body
+    /// is larger than `max_size`. The default implementation checks the size after
+    /// decompressing everything, so an algorithm should stop reading at `max_size` instead.
+    fn decompress_limited(&self, bytes: &[u8], max_size: usize) -> Result<Bytes, Error> {
+        let decompressed = self.decompress(bytes)?;
+        if decompressed.len() > max_size {
+            return Err(ErrorKind::DecompressedTooLarge(self.encoding().into(), max_size).into());
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Read at most `max_size` bytes from `reader`.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+fn read_limited<R: Read>(reader: R, encoding: &str, max_size: usize) -> Result<Bytes, Error> {
+    let mut decompressed = Vec::new();
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| Error::from(ErrorKind::DecompressMessage(encoding.into(), e.to_string())))?;
+    if decompressed.len() > max_size {
+        return Err(ErrorKind::DecompressedTooLarge(encoding.into(), max_size).into());
+    }
+    Ok(Bytes::from(decompressed))
+}
+
+/// `gzip` encoding.
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy)]
+pub struct Gzip {
+    /// From 0 (no compression) to 9 (best compression).
+    pub level: u32,
+}
+
+#[cfg(feature = "gzip")]
+impl Default for Gzip {
+    fn default() -> Gzip {
+        Gzip { level: 6 }
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Compression for Gzip {
+    fn encoding(&self) -> &str {
+        "gzip"
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        use flate2::write::GzEncoder;
+
+        let mut encoder = GzEncoder::new(Vec::new(), ::flate2::Compression::new(self.level));
+        encoder
+            .write_all(bytes)
+            .and_then(|()| encoder.finish())
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::CompressMessage("gzip".into(), e.to_string()).into())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        let mut decompressed = Vec::new();
+        ::flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut decompressed)
+            .map(|_| Bytes::from(decompressed))
+            .map_err(|e| ErrorKind::DecompressMessage("gzip".into(), e.to_string()).into())
+    }
+
+    fn decompress_limited(&self, bytes: &[u8], max_size: usize) -> Result<Bytes, Error> {
+        read_limited(::flate2::read::GzDecoder::new(bytes), "gzip", max_size)
+    }
+}
+
+/// `zstd` encoding.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zstd {
+    /// From 1 to 22. 0 means the default level of zstd.
+    pub level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Compression for Zstd {
+    fn encoding(&self) -> &str {
+        "zstd"
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        ::zstd::stream::encode_all(bytes, self.level)
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::CompressMessage("zstd".into(), e.to_string()).into())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        ::zstd::stream::decode_all(bytes)
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::DecompressMessage("zstd".into(), e.to_string()).into())
+    }
+
+    fn decompress_limited(&self, bytes: &[u8], max_size: usize) -> Result<Bytes, Error> {
+        let decoder = ::zstd::stream::read::Decoder::new(bytes)
+            .map_err(|e| Error::from(ErrorKind::DecompressMessage("zstd".into(), e.to_string())))?;
+        read_limited(decoder, "zstd", max_size)
+    }
+}
+
+/// `lz4` encoding. Body is in LZ4 frame format.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl Compression for Lz4 {
+    fn encoding(&self) -> &str {
+        "lz4"
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        let mut encoder = ::lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder
+            .write_all(bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|()| encoder.finish().map_err(|e| e.to_string()))
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::CompressMessage("lz4".into(), e).into())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        let mut decompressed = Vec::new();
+        ::lz4_flex::frame::FrameDecoder::new(bytes)
+            .read_to_end(&mut decompressed)
+            .map(|_| Bytes::from(decompressed))
+            .map_err(|e| ErrorKind::DecompressMessage("lz4".into(), e.to_string()).into())
+    }
+
+    fn decompress_limited(&self, bytes: &[u8], max_size: usize) -> Result<Bytes, Error> {
+        read_limited(::lz4_flex::frame::FrameDecoder::new(bytes), "lz4", max_size)
+    }
+}
+
+/// Registered compression algorithms.
+///
+/// `Compressions::default()` has every algorithm enabled by features.
+#[derive(Clone)]
+pub struct Compressions {
+    algorithms: HashMap<String, Arc<dyn Compression>>,
+    max_decompressed_size: usize,
+}
+
+impl Compressions {
+    /// Returns `Compressions` having no algorithm.
+    pub fn new() -> Compressions {
+        Compressions {
+            algorithms: HashMap::new(),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Max size of decompressed body. `DEFAULT_MAX_DECOMPRESSED_SIZE` by default.
+    pub fn max_decompressed_size(mut self, max_size: usize) -> Compressions {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// Register an algorithm. An algorithm having the same encoding is replaced.
+    pub fn register<C: Compression + 'static>(mut self, compression: C) -> Compressions {
+        self.algorithms
+            .insert(compression.encoding().to_string(), Arc::new(compression));
+        self
+    }
+
+    pub fn get(&self, encoding: &str) -> Option<&dyn Compression> {
+        self.algorithms.get(encoding).map(|c| &**c)
+    }
+
+    /// Compress body of `item` and set `content_encoding`.
+    ///
+    /// # Error
+    /// Returns `UnknownContentEncoding` error if `encoding` is not registered, and
+    /// `AlreadyEncoded` error if `item` already has `content_encoding`.
+    pub fn compress(&self, encoding: &str, mut item: PublishItem) -> Result<PublishItem, Error> {
+        let compression = self.get(encoding)
+            .ok_or_else(|| Error::from(ErrorKind::UnknownContentEncoding(encoding.into())))?;
+        if let Some(ref current) = item.header.content_encoding {
+            return Err(ErrorKind::AlreadyEncoded(String::from(&**current)).into());
+        }
+        item.body = compression.compress(&item.body)?;
+        item.header.content_encoding = Some(AmqpString::from(encoding.to_string()));
+        Ok(item)
+    }
+
+    /// Decompress body of `item` if its `content_encoding` is registered, and clear
+    /// `content_encoding`. An item having unknown or no `content_encoding` is returned
+    /// untouched.
+    ///
+    /// # Error
+    /// Returns `DecompressedTooLarge` error if decompressed body is larger than
+    /// `max_decompressed_size`.
+    pub fn decompress(&self, mut item: DeliveredItem) -> Result<DeliveredItem, Error> {
+        let compression = match item.content_encoding().and_then(|e| self.get(e)) {
+            Some(compression) => compression,
+            None => return Ok(item),
+        };
+        let body = compression.decompress_limited(&item.body.bytes, self.max_decompressed_size)?;
+        item.header.body_size = body.len() as u64;
+        item.header.properties.content_encoding = None;
+        item.body.bytes = body;
+        Ok(item)
+    }
+
+    /// Same as `decompress`, but an item failing to be decompressed is returned untouched
+    /// with a warning.
+    pub(crate) fn decompress_or_keep(&self, item: DeliveredItem) -> DeliveredItem {
+        match self.decompress(item.clone()) {
+            Ok(decompressed) => decompressed,
+            Err(e) => {
+                warn!("Fail to decompress delivered item {} : {}", item.meta.delivery_tag, e);
+                item
+            }
+        }
+    }
+}
+
+impl Default for Compressions {
+    #[allow(unused_mut)]
+    fn default() -> Compressions {
+        let mut compressions = Compressions::new();
+        #[cfg(feature = "gzip")]
+        {
+            compressions = compressions.register(Gzip::default());
+        }
+        #[cfg(feature = "zstd")]
+        {
+            compressions = compressions.register(Zstd::default());
+        }
+        #[cfg(feature = "lz4")]
+        {
+            compressions = compressions.register(Lz4);
+        }
+        compressions
+    }
+}
+
+/// Returns `CompressSink` which compresses every item with `encoding` before passing it
+/// to `sink`. Fails on `start_send` if `encoding` is not registered.
+pub fn compress_sink<S>(sink: S, compressions: Compressions, encoding: &str) -> CompressSink<S>
+where
+    S: Sink<SinkItem = PublishItem>,
+    S::SinkError: From<Error>,
+{
+    CompressSink {
+        sink,
+        compressions,
+        encoding: encoding.to_string(),
+        buffer: None,
+    }
+}
+
+pub struct CompressSink<S> {
+    sink: S,
+    compressions: Compressions,
+    encoding: String,
+    /// Compressed item which `sink` did not accept yet.
+    buffer: Option<PublishItem>,
+}
+
+impl<S> CompressSink<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S> CompressSink<S>
+where
+    S: Sink<SinkItem = PublishItem>,
+{
+    fn try_empty_buffer(&mut self) -> Poll<(), S::SinkError> {
+        if let Some(item) = self.buffer.take() {
+            if let AsyncSink::NotReady(item) = self.sink.start_send(item)? {
+                self.buffer = Some(item);
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<S> Sink for CompressSink<S>
+where
+    S: Sink<SinkItem = PublishItem>,
+    S::SinkError: From<Error>,
+{
+    type SinkItem = PublishItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: PublishItem) -> StartSend<PublishItem, S::SinkError> {
+        if self.try_empty_buffer()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        // Compressed item is buffered, so that the same item is not compressed again.
+        self.buffer = Some(self.compressions.compress(&self.encoding, item)?);
+        self.try_empty_buffer()?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        try_ready!(self.try_empty_buffer());
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        try_ready!(self.try_empty_buffer());
+        self.sink.close()
+    }
+}
+
+/// Returns `DecompressStream` which decompresses every item from `stream`.
+///
+/// An item failing to be decompressed is yielded untouched with a warning, so that you
+/// can still acknowledge or reject it. You can tell it by `content_encoding` property
+/// which is left.
+pub fn decompress_stream<St>(stream: St, compressions: Compressions) -> DecompressStream<St>
+where
+    St: Stream<Item = DeliveredItem>,
+{
+    DecompressStream {
+        stream,
+        compressions,
+    }
+}
+
+pub struct DecompressStream<St> {
+    stream: St,
+    compressions: Compressions,
+}
+
+impl<St> DecompressStream<St> {
+    pub fn get_ref(&self) -> &St {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> St {
+        self.stream
+    }
+}
+
+impl<St> Stream for DecompressStream<St>
+where
+    St: Stream<Item = DeliveredItem>,
+{
+    type Item = DeliveredItem;
+    type Error = St::Error;
+
+    fn poll(&mut self) -> Poll<Option<DeliveredItem>, St::Error> {
+        let item = try_ready!(self.stream.poll());
+        Ok(Async::Ready(item.map(|item| self.compressions.decompress_or_keep(item))))
+    }
+}
+
+/// Compress body of `item` by `encoding` and publish it.
+///
+/// # Error
+/// Returns an error of `Compressions::compress` without sending anything.
+pub fn publish_compressed<S>(
+    channel_id: u16,
+    socket: S,
+    item: PublishItem,
+    compressions: &Compressions,
+    encoding: &str,
+) -> Result<Published<S>, Error>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    let item = compressions.compress(encoding, item)?;
+    Ok(publish(channel_id, socket, item))
+}
+
+/// Returns `DecompressDelivered` which decompresses an item delivered by `delivered`
+/// future, which is usually returned by `get_delivered`.
+///
+/// Like `decompress_stream`, an item failing to be decompressed is passed untouched with
+/// a warning.
+pub fn decompress_delivered<F, S>(delivered: F, compressions: Compressions) -> DecompressDelivered<F>
+where
+    F: Future<Item = (DeliveredItem, S)>,
+{
+    DecompressDelivered {
+        delivered,
+        compressions,
+    }
+}
+
+pub struct DecompressDelivered<F> {
+    delivered: F,
+    compressions: Compressions,
+}
+
+impl<F, S> Future for DecompressDelivered<F>
+where
+    F: Future<Item = (DeliveredItem, S)>,
+{
+    type Item = (DeliveredItem, S);
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<(DeliveredItem, S), F::Error> {
+        let (item, socket) = try_ready!(self.delivered.poll());
+        Ok(Async::Ready((self.compressions.decompress_or_keep(item), socket)))
+    }
+}
@@ -0,0 +1,399 @@
+use amqpr_codec::{AmqpString, Frame};
+
+use futures::{Async, Future, Poll, Stream};
+use futures::unsync::{mpsc, oneshot};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use basic::ack::{ack_frame, nack_frame, reject_frame};
+use basic::consume::{cancel_frame, consume_frame, StartConsumeOption};
+use basic::deliver::DeliveredItem;
+use basic::get::{get_frame, GetOption};
+use basic::publish::{publish_frames, PublishItem};
+use basic::qos::{qos_frame, QosOption};
+use basic::recover::recover_frame;
+use channel::flow::flow_frame;
+use direct_reply_to::DIRECT_REPLY_TO;
+use exchange::declare::{declare_exchange_frame, DeclareExchangeOption};
+use exchange::delete::{delete_exchange_frame, DeleteExchangeOption};
+use queue::bind::{bind_queue_frame, BindQueueOption};
+use queue::declare::{declare_queue_frame, DeclareQueueOption, DeclareResult};
+use queue::delete::{delete_queue_frame, DeleteQueueOption};
+use queue::purge::purge_queue_frame;
+use queue::unbind::{unbind_queue_frame, UnbindQueueOption};
+use super::driver::{release, Inner, ReplyTo};
+use errors::*;
+
+/// A handle of an open channel on a `Connection`.
+/// Every method queues frames immediately, so frames are sent in the same order as
+/// methods are called. Returned futures only wait for the result.
+///
+/// You can clone it to share the channel.
+#[derive(Clone)]
+pub struct Channel {
+    id: u16,
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        release(&self.inner);
+    }
+}
+
+impl Channel {
+    pub(crate) fn new(id: u16, inner: Rc<RefCell<Inner>>) -> Channel {
+        Channel { id, inner }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns false while AMQP server asks us to stop publishing on this channel.
+    /// `Flow-Ok` method is replied automatically.
+    pub fn is_flow_active(&self) -> bool {
+        self.inner.borrow().is_flow_active(self.id)
+    }
+
+    /// Declare an exchange and wait to receive `Declare-Ok` method.
+    pub fn declare_exchange(&self, option: DeclareExchangeOption) -> Reply<()> {
+        let frame = declare_exchange_frame(self.id, option);
+        self.call(frame, "Exchange.DeclareOk", |f| {
+            f.method()
+                .and_then(|m| m.exchange())
+                .and_then(|c| c.declare_ok())
+        })
+    }
+
+    /// Declare a queue and wait to receive `Declare-Ok` method.
+    /// If you declare a queue with empty name, returned `DeclareResult` has the name
+    /// server generates.
+    pub fn declare_queue(&self, option: DeclareQueueOption) -> Reply<DeclareResult> {
+        let frame = declare_queue_frame(self.id, option);
+        self.call(frame, "Queue.DeclareOk", |f| {
+            f.method()
+                .and_then(|m| m.queue())
+                .and_then(|c| c.declare_ok())
+                .cloned()
+        })
+    }
+
+    /// Bind a queue. This is `no-wait` mode, so returned future will be completed when
+    /// finish to send.
+    pub fn bind_queue(&self, option: BindQueueOption) -> Written {
+        self.send(vec![bind_queue_frame(self.id, option)])
+    }
+
+    /// Delete an exchange and wait to receive `Delete-Ok` method.
+    pub fn delete_exchange(&self, option: DeleteExchangeOption) -> Reply<()> {
+        let frame = delete_exchange_frame(self.id, option);
+        self.call(frame, "Exchange.DeleteOk", |f| {
+            f.method()
+                .and_then(|m| m.exchange())
+                .and_then(|c| c.delete_ok())
+        })
+    }
+
+    /// Unbind a queue and wait to receive `Unbind-Ok` method.
+    pub fn unbind_queue(&self, option: UnbindQueueOption) -> Reply<()> {
+        let frame = unbind_queue_frame(self.id, option);
+        self.call(frame, "Queue.UnbindOk", |f| {
+            f.method()
+                .and_then(|m| m.queue())
+                .and_then(|c| c.unbind_ok())
+        })
+    }
+
+    /// Remove all messages in a queue which are not delivered yet, and wait to receive
+    /// `Purge-Ok` method. Returned future has the number of purged messages.
+    pub fn purge_queue<Q: Into<AmqpString>>(&self, queue: Q) -> Reply<u32> {
+        let frame = purge_queue_frame(self.id, queue.into());
+        self.call(frame, "Queue.PurgeOk", |f| {
+            f.method()
+                .and_then(|m| m.queue())
+                .and_then(|c| c.purge_ok())
+                .map(|purge_ok| purge_ok.message_count)
+        })
+    }
+
+    /// Delete a queue and wait to receive `Delete-Ok` method. Returned future has the number
+    /// of deleted messages.
+    pub fn delete_queue(&self, option: DeleteQueueOption) -> Reply<u32> {
+        let frame = delete_queue_frame(self.id, option);
+        self.call(frame, "Queue.DeleteOk", |f| {
+            f.method()
+                .and_then(|m| m.queue())
+                .and_then(|c| c.delete_ok())
+                .map(|delete_ok| delete_ok.message_count)
+        })
+    }
+
+    /// Publish an item. Returned future will be completed when finish to send.
+    ///
+    /// # Error
+    /// If `reply_to` of `item` is `amq.rabbitmq.reply-to`, the pseudo-queue must be
+    /// consumed on this channel beforehand. Otherwise returned future fails with
+    /// `DirectReplyToNotConsumed` error without sending it, instead of server closing
+    /// the channel.
+    pub fn publish(&self, item: PublishItem) -> Written {
+        let is_direct_reply_to = match item.header.reply_to {
+            Some(ref reply_to) => &**reply_to == DIRECT_REPLY_TO,
+            None => false,
+        };
+        if is_direct_reply_to && !self.inner.borrow().is_direct_reply_to_consumed(self.id) {
+            let (tx, rx) = oneshot::channel();
+            let _ = tx.send(Err(ErrorKind::DirectReplyToNotConsumed(self.id).into()));
+            return Written(rx);
+        }
+        self.send(publish_frames(self.id, item))
+    }
+
+    /// Compress body of `item` by `encoding` and publish it. Algorithms are the ones set by
+    /// `Connection::set_compressions`.
+    ///
+    /// # Error
+    /// Returned future fails with an error of `Compressions::compress` without sending it.
+    /// If no compression is set on the connection, it is `UnknownContentEncoding` error.
+    pub fn publish_compressed(&self, item: PublishItem, encoding: &str) -> Written {
+        let compressed = match self.inner.borrow().compressions {
+            Some(ref compressions) => compressions.compress(encoding, item),
+            None => Err(ErrorKind::UnknownContentEncoding(encoding.into()).into()),
+        };
+        match compressed {
+            Ok(item) => self.publish(item),
+            Err(e) => {
+                let (tx, rx) = oneshot::channel();
+                let _ = tx.send(Err(e));
+                Written(rx)
+            }
+        }
+    }
+
+    /// Start consuming and returns `Consumer` which is `Stream` of `DeliveredItem`.
+    /// If `consumer_tag` of `option` is empty, a unique tag is generated.
+    ///
+    /// # Notice
+    /// `Consume` method is sent in `no-wait` mode. If server refuses it, the channel is
+    /// closed and `Consumer` yields the error.
+    pub fn consume(&self, mut option: StartConsumeOption) -> Consumer {
+        let (tx, rx) = mpsc::unbounded();
+        option.consumer_tag = self.inner
+            .borrow_mut()
+            .register_consumer(self.id, option.consumer_tag, tx);
+        let consumer_tag = String::from(&*option.consumer_tag);
+        if &*option.queue == DIRECT_REPLY_TO {
+            self.inner.borrow_mut().mark_direct_reply_to_consumed(self.id);
+        }
+        self.inner
+            .borrow_mut()
+            .enqueue(self.id, vec![consume_frame(self.id, option)], ReplyTo::Nothing);
+        Consumer { consumer_tag, rx }
+    }
+
+    /// Stop the consumer having given `consumer_tag`, and wait to receive `Cancel-Ok` method.
+    /// Its `Consumer` finishes after items delivered before `Cancel-Ok`.
+    pub fn cancel<T: Into<AmqpString>>(&self, consumer_tag: T) -> Reply<()> {
+        self.call(cancel_frame(self.id, consumer_tag.into()), "Basic.CancelOk", |f| {
+            f.method()
+                .and_then(|m| m.basic())
+                .and_then(|c| c.cancel_ok())
+                .map(|_| ())
+        })
+    }
+
+    /// Fetch an item from a queue without consuming it. Returned future has `None` if the
+    /// queue is empty. `consumer_tag` of the item is empty.
+    pub fn get(&self, option: GetOption) -> Got {
+        let (tx, rx) = oneshot::channel();
+        self.inner
+            .borrow_mut()
+            .enqueue(self.id, vec![get_frame(self.id, option)], ReplyTo::Get(tx));
+        Got(rx)
+    }
+
+    /// Acknowledge an item having given `delivery_tag`.
+    /// If `is_multiple` is true, all items up to and including it are acknowledged.
+    pub fn ack(&self, delivery_tag: u64, is_multiple: bool) -> Written {
+        self.send(vec![ack_frame(self.id, delivery_tag, is_multiple)])
+    }
+
+    /// Reject an item having given `delivery_tag`.
+    /// If `requeue` is false, the item is discarded or dead-lettered.
+    pub fn reject(&self, delivery_tag: u64, requeue: bool) -> Written {
+        self.send(vec![reject_frame(self.id, delivery_tag, requeue)])
+    }
+
+    /// Reject items up to and including `delivery_tag` if `is_multiple` is true, or the item
+    /// having it otherwise.
+    ///
+    /// # Notice
+    /// `amqpr_codec` can not encode `requeue` flag of `Nack` method, so the items are always
+    /// discarded or dead-lettered. Use `reject` to requeue an item.
+    pub fn nack(&self, delivery_tag: u64, is_multiple: bool) -> Written {
+        self.send(vec![nack_frame(self.id, delivery_tag, is_multiple)])
+    }
+
+    /// Ask AMQP server to redeliver all unacknowledged items on this channel, and wait to
+    /// receive `Recover-Ok` method.
+    /// If `requeue` is false, items are redelivered to the original consumer. Otherwise,
+    /// server may redeliver them to another consumer.
+    pub fn recover(&self, requeue: bool) -> Reply<()> {
+        self.call(recover_frame(self.id, requeue), "Basic.RecoverOk", |f| {
+            f.method()
+                .and_then(|m| m.basic())
+                .and_then(|c| c.recover_ok())
+        })
+    }
+
+    /// Limit deliveries which are not acknowledged yet, and wait to receive `Qos-Ok` method.
+    pub fn qos(&self, option: QosOption) -> Reply<()> {
+        self.call(qos_frame(self.id, option), "Basic.QosOk", |f| {
+            f.method().and_then(|m| m.basic()).and_then(|c| c.qos_ok())
+        })
+    }
+
+    /// Ask AMQP server to stop (`active: false`) or restart (`active: true`) sending
+    /// deliveries on this channel. Returned future has the `active` flag server replied with.
+    pub fn flow(&self, active: bool) -> Reply<bool> {
+        self.call(flow_frame(self.id, active), "Channel.FlowOk", |f| {
+            f.method()
+                .and_then(|m| m.channel())
+                .and_then(|c| c.flow_ok())
+                .map(|flow_ok| flow_ok.active)
+        })
+    }
+
+    /// Close this channel and wait to receive `Close-Ok` method.
+    /// New methods on this channel fail with `ChannelClosed` error from now on. Consumers
+    /// on this channel finish after `Close-Ok`, and the channel id is reused by the
+    /// connection.
+    pub fn close(&self) -> Reply<()> {
+        let (tx, rx) = oneshot::channel();
+        self.inner
+            .borrow_mut()
+            .close_channel(self.id, ReplyTo::Method(tx));
+        Reply::new(rx, "Channel.CloseOk", |f| {
+            f.method()
+                .and_then(|m| m.channel())
+                .and_then(|c| c.close_ok())
+        })
+    }
+
+    fn send(&self, frames: Vec<Frame>) -> Written {
+        let (tx, rx) = oneshot::channel();
+        self.inner
+            .borrow_mut()
+            .enqueue(self.id, frames, ReplyTo::Written(tx));
+        Written(rx)
+    }
+
+    fn call<T>(&self, frame: Frame, expected: &'static str, extract: fn(&Frame) -> Option<T>) -> Reply<T> {
+        let (tx, rx) = oneshot::channel();
+        self.inner
+            .borrow_mut()
+            .enqueue(self.id, vec![frame], ReplyTo::Method(tx));
+        Reply::new(rx, expected, extract)
+    }
+}
+
+
+
+/// A future which will be completed when server replies to a method.
+pub struct Reply<T> {
+    rx: oneshot::Receiver<Result<Frame, Error>>,
+    expected: &'static str,
+    extract: fn(&Frame) -> Option<T>,
+}
+
+impl<T> Reply<T> {
+    pub(crate) fn new(
+        rx: oneshot::Receiver<Result<Frame, Error>>,
+        expected: &'static str,
+        extract: fn(&Frame) -> Option<T>,
+    ) -> Reply<T> {
+        Reply {
+            rx,
+            expected,
+            extract,
+        }
+    }
+}
+
+impl<T> Future for Reply<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<T, Error> {
+        let frame = match self.rx.poll() {
+            Ok(Async::Ready(result)) => result?,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(_canceled) => return Err(ErrorKind::UnexpectedConnectionClose.into()),
+        };
+        match (self.extract)(&frame) {
+            Some(item) => Ok(Async::Ready(item)),
+            None => Err(ErrorKind::UnexpectedFrame(self.expected.into(), frame).into()),
+        }
+    }
+}
+
+/// A future which will be completed when frames are written to the socket.
+pub struct Written(oneshot::Receiver<Result<(), Error>>);
+
+impl Future for Written {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        match self.0.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_canceled) => Err(ErrorKind::UnexpectedConnectionClose.into()),
+        }
+    }
+}
+
+/// A future which will be completed when server replies to `basic.get`.
+pub struct Got(oneshot::Receiver<Result<Option<DeliveredItem>, Error>>);
+
+impl Future for Got {
+    type Item = Option<DeliveredItem>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<DeliveredItem>, Error> {
+        match self.0.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_canceled) => Err(ErrorKind::UnexpectedConnectionClose.into()),
+        }
+    }
+}
+
+/// Stream of items delivered to a consumer.
+/// It finishes when the channel is closed by us or the consumer is cancelled, and yields
+/// an error when the channel or the connection is closed by server.
+pub struct Consumer {
+    consumer_tag: String,
+    rx: mpsc::UnboundedReceiver<Result<DeliveredItem, Error>>,
+}
+
+impl Consumer {
+    pub fn consumer_tag(&self) -> &str {
+        &self.consumer_tag
+    }
+}
+
+impl Stream for Consumer {
+    type Item = DeliveredItem;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<DeliveredItem>, Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Some(result))) => result.map(|item| Async::Ready(Some(item))),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
@@ -0,0 +1,735 @@
+//! Background task which owns the socket of a `Connection`.
+//!
+//! The protocol itself is run by `Engine`. `Connection` and `Channel` handles queue
+//! frames into it and wait for replies through oneshot channels. `Driver` is the I/O loop
+//! around it: it writes frames the engine queues, feeds inbound frames to it, calls it at
+//! heartbeat timeouts, and routes its events to a waiter or a consumer of each channel.
+
+use amqpr_codec::{AmqpString, Frame};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, CancelMethod, CancelOkMethod};
+use amqpr_codec::method::channel::{ChannelClass, OpenOkMethod};
+use amqpr_codec::method::connection::{self, ConnectionClass};
+use amqpr_codec::method::exchange::ExchangeClass;
+use amqpr_codec::method::queue::QueueClass;
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use futures::task::{self, Task};
+use futures::unsync::{mpsc, oneshot};
+
+use tokio_core::reactor::{Handle, Timeout};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use basic::deliver::DeliveredItem;
+use compression::Compressions;
+use engine::{Engine, Event};
+use handshake::{ConnectionInfo, Handshaker};
+use metrics::{self, Observer, Sent};
+use protocol_log;
+use errors::*;
+
+pub(crate) type MethodSender = oneshot::Sender<Result<Frame, Error>>;
+pub(crate) type WrittenSender = oneshot::Sender<Result<(), Error>>;
+pub(crate) type ConsumerSender = mpsc::UnboundedSender<Result<DeliveredItem, Error>>;
+pub(crate) type GetSender = oneshot::Sender<Result<Option<DeliveredItem>, Error>>;
+
+/// Handshaker of `Engine` run by `Driver`, which never exists because handshake is already
+/// completed.
+pub(crate) enum Handshaken {}
+
+impl Handshaker for Handshaken {
+    fn reply_to_start(
+        &mut self,
+        _: &connection::StartMethod,
+    ) -> Result<connection::StartOkMethod, Error> {
+        match *self {}
+    }
+    fn reply_to_secure(
+        &mut self,
+        _: &connection::SecureMethod,
+    ) -> Result<connection::SecureOkMethod, Error> {
+        match *self {}
+    }
+    fn reply_to_tune(&mut self, _: &connection::TuneMethod) -> connection::TuneOkMethod {
+        match *self {}
+    }
+    fn create_open(&mut self) -> connection::OpenMethod {
+        match *self {}
+    }
+    fn inspect_open_ok(&mut self, _: &connection::OpenOkMethod) {
+        match *self {}
+    }
+}
+
+/// State shared by `Connection`, `Channel` and `Driver`.
+pub(crate) struct Inner {
+    engine: Engine<Handshaken>,
+    pub(crate) is_blocked: bool,
+    /// Frames which are queued into `engine` and waited to be written, in the queued order.
+    written: VecDeque<Queued>,
+    channels: HashMap<u16, ChannelSlot>,
+    next_consumer_id: u64,
+    closed: Option<Closed>,
+    driver: Option<Task>,
+    pub(crate) observer: Option<Arc<dyn Observer>>,
+    pub(crate) compressions: Option<Compressions>,
+}
+
+/// What to do after frames queued into `Engine` are written.
+struct Queued {
+    /// `Engine::queued_frames` right after the frames are queued.
+    until: u64,
+    channel_id: u16,
+    sender: Option<WrittenSender>,
+    /// Set for `basic.publish` to report its latency.
+    published_at: Option<Instant>,
+}
+
+pub(crate) enum ReplyTo {
+    Nothing,
+    /// Notify when all frames are sent.
+    Written(WrittenSender),
+    /// Wait for a method replied by server on the same channel.
+    Method(MethodSender),
+    /// Wait for `basic.get-ok` with its content, or `basic.get-empty`.
+    Get(GetSender),
+}
+
+/// Waiters and consumers of a channel.
+struct ChannelSlot {
+    waiters: VecDeque<MethodSender>,
+    /// Waiters of `basic.get`, which are replied separately from other methods.
+    getters: VecDeque<GetSender>,
+    consumers: HashMap<AmqpString, ConsumerSender>,
+    is_flow_active: bool,
+    /// Whether `amq.rabbitmq.reply-to` is consumed on this channel.
+    is_direct_reply_to_consumed: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Closed {
+    ByClient,
+    ByServer(u16, String),
+    Lost,
+    MissedHeartbeats(u32),
+}
+
+impl Closed {
+    fn error(&self) -> Error {
+        match *self {
+            Closed::ByClient => ErrorKind::ConnectionClosed.into(),
+            Closed::ByServer(code, ref text) => {
+                ErrorKind::ConnectionClosedByServer(code, text.clone()).into()
+            }
+            Closed::Lost => ErrorKind::UnexpectedConnectionClose.into(),
+            Closed::MissedHeartbeats(missed) => ErrorKind::MissedHeartbeats(missed).into(),
+        }
+    }
+}
+
+impl Inner {
+    pub(crate) fn new(info: ConnectionInfo) -> Inner {
+        let mut channels = HashMap::new();
+        // Channel 0 is used by `Connection` class methods.
+        channels.insert(0, ChannelSlot::new());
+        Inner {
+            engine: Engine::connected(info, Instant::now()),
+            is_blocked: false,
+            written: VecDeque::new(),
+            channels,
+            next_consumer_id: 0,
+            closed: None,
+            driver: None,
+            observer: None,
+            compressions: None,
+        }
+    }
+
+    pub(crate) fn info(&self) -> &ConnectionInfo {
+        self.engine.info().expect("Engine is started after handshake")
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.is_some()
+    }
+
+    /// Open a channel and wait for `Open-Ok` method by `reply`. If `id` is `None`, an
+    /// unused id is allocated. Returns the id unless `reply` is failed.
+    pub(crate) fn open_channel(&mut self, id: Option<u16>, reply: MethodSender) -> Option<u16> {
+        let opened = self.check_closed().and_then(|()| match id {
+            Some(id) => self.engine.open_channel_with_id(id).map(|()| id),
+            None => self.engine.open_channel(),
+        });
+        match opened {
+            Ok(id) => {
+                self.channels.insert(id, ChannelSlot::new());
+                self.queued(id, ReplyTo::Method(reply), None);
+                Some(id)
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                None
+            }
+        }
+    }
+
+    /// Send `Close` method on the channel. New methods on it fail with `ChannelClosed`
+    /// error from now on.
+    pub(crate) fn close_channel(&mut self, channel_id: u16, reply: ReplyTo) {
+        match self.check_closed().and_then(|()| self.engine.close_channel(channel_id)) {
+            Ok(()) => self.queued(channel_id, reply, None),
+            Err(e) => reply.fail(e),
+        }
+    }
+
+    /// Send `Close` method of the connection.
+    pub(crate) fn close(&mut self, reply: ReplyTo) {
+        match self.check_closed().and_then(|()| self.engine.close()) {
+            Ok(()) => self.queued(0, reply, None),
+            Err(e) => reply.fail(e),
+        }
+    }
+
+    pub(crate) fn mark_direct_reply_to_consumed(&mut self, channel_id: u16) {
+        if let Some(slot) = self.channels.get_mut(&channel_id) {
+            slot.is_direct_reply_to_consumed = true;
+        }
+    }
+
+    pub(crate) fn is_direct_reply_to_consumed(&self, channel_id: u16) -> bool {
+        self.channels
+            .get(&channel_id)
+            .map(|slot| slot.is_direct_reply_to_consumed)
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn is_flow_active(&self, channel_id: u16) -> bool {
+        self.channels
+            .get(&channel_id)
+            .map(|slot| slot.is_flow_active)
+            .unwrap_or(false)
+    }
+
+    /// Register a consumer and returns its consumer tag.
+    /// If `consumer_tag` is empty, new one is generated.
+    pub(crate) fn register_consumer(
+        &mut self,
+        channel_id: u16,
+        consumer_tag: AmqpString,
+        sender: ConsumerSender,
+    ) -> AmqpString {
+        let consumer_tag = if consumer_tag.is_empty() {
+            self.next_consumer_id += 1;
+            AmqpString::from(format!("amqpr.ctag-{}.{}", channel_id, self.next_consumer_id))
+        } else {
+            consumer_tag
+        };
+        if let Some(slot) = self.channels.get_mut(&channel_id) {
+            slot.consumers.insert(consumer_tag.clone(), sender);
+        }
+        consumer_tag
+    }
+
+    /// Queue frames being sent by `Driver`.
+    /// If the channel or the connection is already closed, `reply` is notified with error
+    /// immediately.
+    pub(crate) fn enqueue(&mut self, channel_id: u16, frames: Vec<Frame>, reply: ReplyTo) {
+        let is_publish = matches!(
+            frames.first().and_then(|f| f.method()),
+            Some(&MethodPayload::Basic(BasicClass::Publish(_)))
+        );
+        match self.check_closed().and_then(|()| self.engine.send_frames(channel_id, frames)) {
+            Ok(()) => {
+                let published_at = if is_publish { Some(Instant::now()) } else { None };
+                self.queued(channel_id, reply, published_at);
+            }
+            Err(e) => reply.fail(e),
+        }
+    }
+
+    fn check_closed(&self) -> Result<(), Error> {
+        match self.closed {
+            Some(ref closed) => Err(closed.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Register `reply` of frames which are just queued into `engine`, and wake `Driver`
+    /// up to write them.
+    fn queued(&mut self, channel_id: u16, reply: ReplyTo, published_at: Option<Instant>) {
+        let sender = match reply {
+            ReplyTo::Nothing => None,
+            ReplyTo::Written(sender) => Some(sender),
+            // Frames are written in the queued order, so waiters are ordered in the same
+            // way as replies.
+            ReplyTo::Method(sender) => {
+                if let Some(slot) = self.channels.get_mut(&channel_id) {
+                    slot.waiters.push_back(sender);
+                }
+                None
+            }
+            ReplyTo::Get(sender) => {
+                if let Some(slot) = self.channels.get_mut(&channel_id) {
+                    slot.getters.push_back(sender);
+                }
+                None
+            }
+        };
+        if sender.is_some() || published_at.is_some() {
+            self.written.push_back(Queued {
+                until: self.engine.queued_frames(),
+                channel_id,
+                sender,
+                published_at,
+            });
+        }
+        if let Some(task) = self.driver.take() {
+            task.notify();
+        }
+    }
+
+    /// Notify that frames up to `written`th one are written.
+    fn notify_written(&mut self, written: u64) {
+        while self.written.front().map(|q| q.until <= written).unwrap_or(false) {
+            let queued = self.written.pop_front().unwrap();
+            if let (Some(observer), Some(at)) = (self.observer.as_ref(), queued.published_at) {
+                observer.published(queued.channel_id, at.elapsed());
+            }
+            if let Some(sender) = queued.sender {
+                let _ = sender.send(Ok(()));
+            }
+        }
+    }
+
+    /// Close the connection, and notify every waiter and consumer with error.
+    fn shutdown(&mut self, closed: Closed) {
+        info!("Connection is closed : {:?}", closed);
+        for (_, slot) in self.channels.drain() {
+            slot.fail(&|| closed.error());
+        }
+        for queued in self.written.drain(..) {
+            if let Some(sender) = queued.sender {
+                let _ = sender.send(Err(closed.error()));
+            }
+        }
+        self.closed = Some(closed);
+    }
+
+    /// Route events of `engine` to waiters and consumers.
+    fn dispatch_events(&mut self) {
+        while let Some(event) = self.engine.poll_event() {
+            match event {
+                Event::Connected(_) => {}
+                Event::ChannelOpened(channel_id) => {
+                    let open_ok = OpenOkMethod {
+                        reserved1: "".into(),
+                    };
+                    let open_ok = MethodPayload::Channel(ChannelClass::OpenOk(open_ok));
+                    self.reply(Frame::new_method(channel_id, open_ok));
+                }
+                Event::Method(channel_id, MethodPayload::Basic(BasicClass::Cancel(cancel))) => {
+                    self.cancel_consumer(channel_id, cancel)
+                }
+                Event::Method(channel_id, MethodPayload::Basic(BasicClass::GetEmpty(_))) => {
+                    self.got(channel_id, None)
+                }
+                Event::Method(channel_id, method) => {
+                    if let MethodPayload::Basic(BasicClass::CancelOk(ref cancel_ok)) = method {
+                        if let Some(slot) = self.channels.get_mut(&channel_id) {
+                            slot.consumers.remove(&cancel_ok.consumer_tag);
+                        }
+                    }
+                    let frame = Frame::new_method(channel_id, method);
+                    if is_reply(&frame) {
+                        self.reply(frame);
+                    } else {
+                        warn!("Skip unexpected {}", protocol_log::display(&frame));
+                    }
+                }
+                Event::Delivered(channel_id, item) => {
+                    let item = match self.compressions {
+                        Some(ref compressions) => compressions.decompress_or_keep(item),
+                        None => item,
+                    };
+                    if let Some(slot) = self.channels.get_mut(&channel_id) {
+                        slot.deliver(item);
+                    }
+                }
+                Event::Got(channel_id, item) => {
+                    let item = match self.compressions {
+                        Some(ref compressions) => compressions.decompress_or_keep(item),
+                        None => item,
+                    };
+                    self.got(channel_id, Some(item));
+                }
+                Event::Flow(channel_id, active) => {
+                    info!("Receive flow method on channel {} : active = {}", channel_id, active);
+                    if let Some(slot) = self.channels.get_mut(&channel_id) {
+                        slot.is_flow_active = active;
+                    }
+                }
+                Event::ChannelClosed(channel_id, by_server) => {
+                    self.remove_channel(channel_id, by_server)
+                }
+                Event::Blocked(reason) => {
+                    warn!("Connection is blocked : {}", reason);
+                    self.is_blocked = true;
+                }
+                Event::Unblocked => {
+                    info!("Connection is unblocked");
+                    self.is_blocked = false;
+                }
+                Event::HeartbeatMissed(_) => {
+                    if let Some(ref observer) = self.observer {
+                        observer.heartbeat_missed();
+                    }
+                }
+                // `Driver` shuts down by itself when the engine fails.
+                Event::Closed(_) if self.is_closed() => {}
+                Event::Closed(Some((code, text))) => self.shutdown(Closed::ByServer(code, text)),
+                Event::Closed(None) => {
+                    let close_ok = MethodPayload::Connection(ConnectionClass::CloseOk);
+                    self.reply(Frame::new_method(0, close_ok));
+                    self.shutdown(Closed::ByClient);
+                }
+            }
+        }
+    }
+
+    /// Send a method replied by server to the first waiter on the channel.
+    fn reply(&mut self, frame: Frame) {
+        let waiter = self.channels
+            .get_mut(&frame.header.channel)
+            .and_then(|slot| slot.waiters.pop_front());
+        match waiter {
+            Some(waiter) => {
+                let _ = waiter.send(Ok(frame));
+            }
+            None => warn!("Skip unexpected {}", protocol_log::display(&frame)),
+        }
+    }
+
+    /// Send a reply of `basic.get` to the first waiter on the channel.
+    fn got(&mut self, channel_id: u16, item: Option<DeliveredItem>) {
+        let getter = self.channels
+            .get_mut(&channel_id)
+            .and_then(|slot| slot.getters.pop_front());
+        match getter {
+            Some(getter) => {
+                let _ = getter.send(Ok(item));
+            }
+            None => warn!("Skip unexpected reply of basic.get on channel {}", channel_id),
+        }
+    }
+
+    /// Finish a consumer which server cancels, e.g. because its queue is deleted.
+    fn cancel_consumer(&mut self, channel_id: u16, cancel: CancelMethod) {
+        info!("Consumer {:?} is cancelled by server", cancel.consumer_tag);
+        // The consumer finishes by dropping its sender.
+        if let Some(slot) = self.channels.get_mut(&channel_id) {
+            slot.consumers.remove(&cancel.consumer_tag);
+        }
+        if cancel.no_wait {
+            return;
+        }
+        let cancel_ok = CancelOkMethod {
+            consumer_tag: cancel.consumer_tag,
+        };
+        let cancel_ok = MethodPayload::Basic(BasicClass::CancelOk(cancel_ok));
+        if let Err(e) = self.engine.send_method(channel_id, cancel_ok) {
+            warn!("Failed to reply to basic.cancel : {}", e);
+        }
+    }
+
+    /// Remove a channel which is closed by server (`by_server` is `Some`) or by us.
+    fn remove_channel(&mut self, channel_id: u16, by_server: Option<(u16, String)>) {
+        let mut slot = match self.channels.remove(&channel_id) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        match by_server {
+            Some((code, text)) => {
+                warn!("Channel {} is closed by server : {} {}", channel_id, code, text);
+                slot.fail(&|| ErrorKind::ChannelClosedByServer(channel_id, code, text.clone()).into());
+            }
+            None => {
+                debug!("Channel {} is closed", channel_id);
+                if let Some(waiter) = slot.waiters.pop_front() {
+                    let close_ok = MethodPayload::Channel(ChannelClass::CloseOk);
+                    let _ = waiter.send(Ok(Frame::new_method(channel_id, close_ok)));
+                }
+                // Consumers just finish by dropping their senders.
+                for waiter in slot.waiters {
+                    let _ = waiter.send(Err(ErrorKind::ChannelClosed(channel_id).into()));
+                }
+                for getter in slot.getters {
+                    let _ = getter.send(Err(ErrorKind::ChannelClosed(channel_id).into()));
+                }
+            }
+        }
+    }
+}
+
+/// Called when a `Connection` or `Channel` handle is dropped. Wakes `Driver` up if the
+/// handle is the last one, so that it closes the connection.
+pub(crate) fn release(inner: &Rc<RefCell<Inner>>) {
+    // `Driver` holds another one.
+    if Rc::strong_count(inner) != 2 {
+        return;
+    }
+    if let Ok(mut inner) = inner.try_borrow_mut() {
+        if let Some(task) = inner.driver.take() {
+            task.notify();
+        }
+    }
+}
+
+impl ReplyTo {
+    fn fail(self, error: Error) {
+        match self {
+            ReplyTo::Nothing => {}
+            ReplyTo::Written(sender) => {
+                let _ = sender.send(Err(error));
+            }
+            ReplyTo::Method(sender) => {
+                let _ = sender.send(Err(error));
+            }
+            ReplyTo::Get(sender) => {
+                let _ = sender.send(Err(error));
+            }
+        }
+    }
+}
+
+impl ChannelSlot {
+    fn new() -> ChannelSlot {
+        ChannelSlot {
+            waiters: VecDeque::new(),
+            getters: VecDeque::new(),
+            consumers: HashMap::new(),
+            is_flow_active: true,
+            is_direct_reply_to_consumed: false,
+        }
+    }
+
+    /// Notify waiters and consumers with error. Consumers finish after the error.
+    fn fail(self, error: &dyn Fn() -> Error) {
+        for waiter in self.waiters {
+            let _ = waiter.send(Err(error()));
+        }
+        for getter in self.getters {
+            let _ = getter.send(Err(error()));
+        }
+        for (_, consumer) in self.consumers {
+            let _ = consumer.unbounded_send(Err(error()));
+        }
+    }
+
+    /// Send the item to its consumer. The consumer is deregistered if it is dropped.
+    fn deliver(&mut self, item: DeliveredItem) {
+        let consumer_tag = item.meta.consumer_tag.clone();
+        let is_dropped = match self.consumers.get(&consumer_tag) {
+            Some(consumer) => consumer.unbounded_send(Ok(item)).is_err(),
+            None => {
+                warn!("Skip delivered item for unknown consumer : {:?}", item.meta);
+                false
+            }
+        };
+        if is_dropped {
+            debug!("Consumer {:?} is dropped", consumer_tag);
+            self.consumers.remove(&consumer_tag);
+        }
+    }
+}
+
+/// Whether `frame` is a reply to a method which a waiter sent. Other methods are sent by
+/// server on its own.
+fn is_reply(frame: &Frame) -> bool {
+    let method = match frame.method() {
+        Some(method) => method,
+        None => return false,
+    };
+    matches!(
+        *method,
+        MethodPayload::Channel(ChannelClass::FlowOk(_))
+            | MethodPayload::Exchange(ExchangeClass::DeclareOk)
+            | MethodPayload::Exchange(ExchangeClass::DeleteOk)
+            | MethodPayload::Exchange(ExchangeClass::BindOk)
+            | MethodPayload::Exchange(ExchangeClass::UnbindOk)
+            | MethodPayload::Queue(QueueClass::DeclareOk(_))
+            | MethodPayload::Queue(QueueClass::BindOk)
+            | MethodPayload::Queue(QueueClass::UnbindOk)
+            | MethodPayload::Queue(QueueClass::PurgeOk(_))
+            | MethodPayload::Queue(QueueClass::DeleteOk(_))
+            | MethodPayload::Basic(BasicClass::QosOk)
+            | MethodPayload::Basic(BasicClass::ConsumeOk(_))
+            | MethodPayload::Basic(BasicClass::CancelOk(_))
+            | MethodPayload::Basic(BasicClass::RecoverOk)
+    )
+}
+
+/// A future which drives the socket until the connection is closed.
+///
+/// When every `Connection` and `Channel` handle is dropped, it closes the connection and
+/// finishes after `Close-Ok` method. It fails the connection when `Engine` finds server
+/// silent for `MAX_MISSED_HEARTBEATS` heartbeat intervals.
+pub(crate) struct Driver<S> {
+    socket: S,
+    inner: Rc<RefCell<Inner>>,
+    handle: Handle,
+    /// Timer for `Engine::poll_timeout`, with the time it is set at.
+    timeout: Option<(Instant, Timeout)>,
+    /// A frame the socket did not accept yet.
+    sending: Option<Frame>,
+    /// Number of frames written to the socket.
+    written: u64,
+    is_released: bool,
+}
+
+impl<S> Driver<S>
+where
+    S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+{
+    pub(crate) fn new(socket: S, inner: Rc<RefCell<Inner>>, handle: &Handle) -> Driver<S> {
+        Driver {
+            socket,
+            inner,
+            handle: handle.clone(),
+            timeout: None,
+            sending: None,
+            written: 0,
+            is_released: false,
+        }
+    }
+
+    /// Call `Engine::handle_timeout` at the time it asks.
+    fn poll_timeout(&mut self) -> Result<(), Error> {
+        loop {
+            let at = match self.inner.borrow().engine.poll_timeout() {
+                Some(at) => at,
+                None => {
+                    self.timeout = None;
+                    return Ok(());
+                }
+            };
+            let is_set = match self.timeout {
+                Some((set_at, _)) => set_at == at,
+                None => false,
+            };
+            if !is_set {
+                self.timeout = Some((at, Timeout::new_at(at, &self.handle)?));
+            }
+            if let Some((_, ref mut timeout)) = self.timeout {
+                if timeout.poll()?.is_not_ready() {
+                    return Ok(());
+                }
+            }
+            let mut inner = self.inner.borrow_mut();
+            inner.engine.handle_timeout(Instant::now())?;
+            inner.dispatch_events();
+        }
+    }
+
+    /// Close the connection once every handle is dropped.
+    fn check_released(&mut self) {
+        if self.is_released || Rc::strong_count(&self.inner) > 1 {
+            return;
+        }
+        self.is_released = true;
+        let mut inner = self.inner.borrow_mut();
+        if !inner.is_closed() {
+            info!("Close the connection because every handle is dropped");
+            inner.close(ReplyTo::Nothing);
+        }
+    }
+
+    /// Write frames queued in `Engine` as many as the socket accepts.
+    fn send_outgoing(&mut self) -> Result<(), Error> {
+        loop {
+            let frame = match self.sending.take() {
+                Some(frame) => frame,
+                None => match self.inner.borrow_mut().engine.poll_frame() {
+                    Some(frame) => frame,
+                    None => return Ok(()),
+                },
+            };
+            let sent = Sent::of(&frame);
+            if let AsyncSink::NotReady(frame) = self.socket.start_send(frame)? {
+                self.sending = Some(frame);
+                return Ok(());
+            }
+            self.written += 1;
+            let mut inner = self.inner.borrow_mut();
+            if let Some(ref observer) = inner.observer {
+                sent.report(&**observer);
+            }
+            inner.notify_written(self.written);
+        }
+    }
+
+    fn poll_socket(&mut self) -> Poll<(), Error> {
+        self.inner.borrow_mut().driver = Some(task::current());
+        self.check_released();
+        self.poll_timeout()?;
+
+        loop {
+            self.send_outgoing()?;
+            let is_flushed = self.socket.poll_complete()?.is_ready();
+            if self.inner.borrow().is_closed() && self.sending.is_none() && is_flushed {
+                return Ok(Async::Ready(()));
+            }
+
+            match self.socket.poll()? {
+                Async::Ready(Some(frame)) => {
+                    debug!("Receive {}", protocol_log::display(&frame));
+                    let mut inner = self.inner.borrow_mut();
+                    if let Some(ref observer) = inner.observer {
+                        metrics::observe_received(&**observer, &frame);
+                    }
+                    inner.engine.handle_frame(frame, Instant::now())?;
+                    inner.dispatch_events();
+                }
+                Async::Ready(None) => {
+                    self.inner.borrow_mut().engine.handle_eof()?;
+                    return Ok(Async::Ready(()));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S> Future for Driver<S>
+where
+    S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.poll_socket() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(())),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                let mut inner = self.inner.borrow_mut();
+                if !inner.is_closed() {
+                    error!("Connection is lost : {}", e);
+                    let closed = match *e.kind() {
+                        ErrorKind::MissedHeartbeats(missed) => Closed::MissedHeartbeats(missed),
+                        _ => Closed::Lost,
+                    };
+                    inner.shutdown(closed);
+                }
+                // Report heartbeats missed before the failure.
+                inner.dispatch_events();
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
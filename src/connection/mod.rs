@@ -0,0 +1,261 @@
+//! High level api built on top of the functions in other modules.
+//!
+//! A `Connection` spawns a background task which owns the socket, so you do not need to
+//! thread the socket through `and_then` chains, or to choose channel ids by hand.
+//!
+//! ```no_run
+//! extern crate amqpr_api;
+//! extern crate futures;
+//! extern crate tokio_core;
+//!
+//! use amqpr_api::{Connection, ConnectionConfig};
+//! use amqpr_api::handshake::SimpleHandshaker;
+//! use amqpr_api::queue::declare::DeclareQueueOption;
+//! use futures::Future;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let config = ConnectionConfig {
+//!     addr: "127.0.0.1:5672".parse().unwrap(),
+//!     handshaker: SimpleHandshaker::new("guest", "guest", "/"),
+//!     handshake_timeout: None,
+//!     observer: None,
+//! };
+//!
+//! let future = Connection::open(config, &core.handle())
+//!     .and_then(|connection| connection.create_channel())
+//!     .and_then(|channel| {
+//!         let option = DeclareQueueOption {
+//!             name: "example".into(),
+//!             is_passive: false,
+//!             is_durable: false,
+//!             is_exclusive: false,
+//!             is_auto_delete: true,
+//!         };
+//!         channel.declare_queue(option)
+//!     });
+//! let declared = core.run(future).unwrap();
+//! # }
+//! ```
+
+pub mod channel;
+mod driver;
+
+pub use self::channel::{Channel, Consumer, Got, Reply, Written};
+
+use amqpr_codec::Frame;
+
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::unsync::oneshot;
+
+use tokio_core::net::{TcpStream, TcpStreamNew};
+use tokio_core::reactor::Handle;
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use handshake::{start_handshake, start_handshake_with_timeout, ConnectionInfo, Handshaker,
+                Handshaking};
+use self::driver::{release, Driver, Inner, ReplyTo};
+use common::Should;
+use compression::Compressions;
+use metrics::{observe_io, ObservedIo, Observer};
+use errors::*;
+
+/// Options to open a `Connection`.
+pub struct ConnectionConfig<H> {
+    pub addr: SocketAddr,
+    pub handshaker: H,
+    /// If it is `Some`, opening connection fails when handshake is not completed in time.
+    pub handshake_timeout: Option<Duration>,
+    /// Receives metrics of the connection. See `metrics` module.
+    pub observer: Option<Arc<dyn Observer>>,
+}
+
+/// A handle of an AMQP connection.
+/// You can clone it to share the connection. The socket is owned by a background task
+/// spawned on the `Handle` until the connection is closed. When every `Connection` and
+/// `Channel` on it is dropped, the connection is closed and the task finishes.
+#[derive(Clone)]
+pub struct Connection {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Connection {
+    /// Connect to AMQP server, complete handshake and returns `Connection`.
+    pub fn open<H>(config: ConnectionConfig<H>, handle: &Handle) -> ConnectionOpened<H>
+    where
+        H: Handshaker,
+    {
+        let connecting = TcpStream::connect(&config.addr, handle);
+        ConnectionOpened {
+            state: OpenState::Connecting(connecting, Should::new(config.handshaker)),
+            timeout: config.handshake_timeout,
+            observer: config.observer,
+            handle: handle.clone(),
+        }
+    }
+
+    /// Make `Connection` from a socket which already finished handshake.
+    pub fn from_socket<S>(socket: S, info: ConnectionInfo, handle: &Handle) -> Connection
+    where
+        S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error> + 'static,
+    {
+        let inner = Rc::new(RefCell::new(Inner::new(info)));
+        handle.spawn(Driver::new(socket, inner.clone(), handle));
+        Connection { inner }
+    }
+
+    /// Report metrics of this connection to `observer` from now on.
+    /// Bytes are not counted unless `ConnectionConfig::observer` is set.
+    pub fn set_observer(&self, observer: Arc<dyn Observer>) {
+        self.inner.borrow_mut().observer = Some(observer);
+    }
+
+    /// Decompress items delivered to every `Consumer` on this connection by `compressions`
+    /// from now on. An item failing to be decompressed is passed untouched with a warning.
+    /// `Channel::publish_compressed` also uses them.
+    pub fn set_compressions(&self, compressions: Compressions) {
+        self.inner.borrow_mut().compressions = Some(compressions);
+    }
+
+    pub fn connection_info(&self) -> ConnectionInfo {
+        self.inner.borrow().info().clone()
+    }
+
+    /// Returns true while server blocks publishing because of resource alarm.
+    pub fn is_blocked(&self) -> bool {
+        self.inner.borrow().is_blocked
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.borrow().is_closed()
+    }
+
+    /// Open new channel with an unused channel id.
+    /// Fails if every id up to negotiated `channel_max` is in use.
+    pub fn create_channel(&self) -> ChannelCreated {
+        self.create_channel_inner(None)
+    }
+
+    /// Open new channel with given channel id.
+    /// Fails if the id is 0, greater than negotiated `channel_max` or already in use.
+    pub fn create_channel_with_id(&self, channel_id: u16) -> ChannelCreated {
+        self.create_channel_inner(Some(channel_id))
+    }
+
+    fn create_channel_inner(&self, channel_id: Option<u16>) -> ChannelCreated {
+        let (tx, rx) = oneshot::channel();
+        let opened = self.inner.borrow_mut().open_channel(channel_id, tx);
+        let channel = opened.map(|id| Channel::new(id, self.inner.clone()));
+        let opened = Reply::new(rx, "Channel.OpenOk", |f| {
+            f.method()
+                .and_then(|m| m.channel())
+                .and_then(|c| c.open_ok())
+                .map(|_| ())
+        });
+        ChannelCreated { opened, channel }
+    }
+
+    /// Close the connection and wait to receive `Close-Ok` method.
+    /// Every channel on this connection is closed as well.
+    pub fn close(&self) -> Reply<()> {
+        let (tx, rx) = oneshot::channel();
+        self.inner.borrow_mut().close(ReplyTo::Method(tx));
+        Reply::new(rx, "Connection.CloseOk", |f| {
+            f.method()
+                .and_then(|m| m.connection())
+                .and_then(|c| c.close_ok())
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        release(&self.inner);
+    }
+}
+
+/// A future which will be completed when a connection is opened.
+pub struct ConnectionOpened<H>
+where
+    H: Handshaker,
+{
+    state: OpenState<H>,
+    timeout: Option<Duration>,
+    observer: Option<Arc<dyn Observer>>,
+    handle: Handle,
+}
+
+enum OpenState<H>
+where
+    H: Handshaker,
+{
+    Connecting(TcpStreamNew, Should<H>),
+    Handshaking(Box<Handshaking<H, ObservedIo<TcpStream>>>),
+}
+
+impl<H> Future for ConnectionOpened<H>
+where
+    H: Handshaker,
+{
+    type Item = Connection;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Connection, Error> {
+        use self::OpenState::*;
+
+        self.state = match self.state {
+            Connecting(ref mut connecting, ref mut handshaker) => {
+                let socket = try_ready!(connecting.poll());
+                let socket = match self.observer {
+                    Some(ref observer) => observe_io(socket, observer.clone()),
+                    None => ObservedIo::unobserved(socket),
+                };
+                let handshaker = handshaker.take();
+                match self.timeout {
+                    Some(timeout) => Handshaking(Box::new(start_handshake_with_timeout(
+                        handshaker,
+                        socket,
+                        timeout,
+                        &self.handle,
+                    ))),
+                    None => Handshaking(Box::new(start_handshake(handshaker, socket))),
+                }
+            }
+            Handshaking(ref mut handshaking) => {
+                let (info, socket) = try_ready!(handshaking.poll());
+                let connection = Connection::from_socket(socket, info, &self.handle);
+                if let Some(observer) = self.observer.take() {
+                    connection.set_observer(observer);
+                }
+                return Ok(Async::Ready(connection));
+            }
+        };
+
+        self.poll()
+    }
+}
+
+/// A future which will be completed when server replies to `Open` method.
+pub struct ChannelCreated {
+    opened: Reply<()>,
+    channel: Option<Channel>,
+}
+
+impl Future for ChannelCreated {
+    type Item = Channel;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Channel, Error> {
+        try_ready!(self.opened.poll());
+        let channel = self.channel
+            .take()
+            .expect("You never poll ChannelCreated after it is completed");
+        Ok(Async::Ready(channel))
+    }
+}
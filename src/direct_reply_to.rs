@@ -0,0 +1,116 @@
+//! Convenient module to use RabbitMQ's direct reply-to.
+//!
+//! RabbitMQ requires a client to consume the `amq.rabbitmq.reply-to` pseudo-queue in
+//! `no-ack` mode before it publishes a request with that `reply_to` on the same channel.
+//! Replies are delivered to the consumer without any queue being declared.
+
+use futures::{Poll, Sink, Stream};
+
+use amqpr_codec::{AmqpString, Frame};
+
+use basic::consume::StartConsumeOption;
+use basic::deliver::DeliveredItem;
+use basic::publish::{publish_frames, PublishItem};
+use subscribe_stream::{subscribe_stream, AckHandle, SubscribeStream};
+use errors::Error;
+
+/// Name of RabbitMQ's pseudo-queue for direct reply-to.
+pub const DIRECT_REPLY_TO: &str = "amq.rabbitmq.reply-to";
+
+/// Returns `DirectReplyTo` which is `Stream` of replies.
+///
+/// This function sends `Consume` method of the pseudo-queue by `start_consume` first.
+/// Requests published through `RequestHandle` are sent after it, so that server never
+/// refuses them.
+///
+/// ```no_run
+/// # extern crate amqpr_api;
+/// # extern crate amqpr_codec;
+/// # extern crate futures;
+/// # use amqpr_codec::Frame;
+/// # use amqpr_api::basic::PublishItem;
+/// # use amqpr_api::errors::Error;
+/// # fn run<S>(socket: S, request: PublishItem)
+/// # where
+/// #     S: futures::Stream<Item = Frame, Error = Error> + futures::Sink<SinkItem = Frame, SinkError = Error>,
+/// # {
+/// use futures::Stream;
+/// use amqpr_api::direct_reply_to::direct_reply_to;
+///
+/// let replies = direct_reply_to(1, socket);
+/// replies.request_handle().publish(request);
+/// let first_reply = replies.into_future();
+/// # }
+/// # fn main() {}
+/// ```
+pub fn direct_reply_to<S, E>(channel_id: u16, socket: S) -> DirectReplyTo<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    let option = StartConsumeOption {
+        queue: DIRECT_REPLY_TO.into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: true,
+        is_exclusive: false,
+    };
+
+    DirectReplyTo {
+        channel_id,
+        stream: subscribe_stream(channel_id, socket, option),
+    }
+}
+
+/// Stream of replies sent to `amq.rabbitmq.reply-to`.
+pub struct DirectReplyTo<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+{
+    channel_id: u16,
+    stream: SubscribeStream<S, E>,
+}
+
+impl<S, E> DirectReplyTo<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+{
+    /// Returns `RequestHandle` which publishes requests through the socket this stream
+    /// holds. Queued requests are sent when this stream is polled next time.
+    pub fn request_handle(&self) -> RequestHandle {
+        RequestHandle {
+            channel_id: self.channel_id,
+            handle: self.stream.ack_handle(),
+        }
+    }
+}
+
+impl<S, E> Stream for DirectReplyTo<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
+{
+    type Item = DeliveredItem;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<DeliveredItem>, E> {
+        self.stream.poll()
+    }
+}
+
+/// A handle to publish requests whose replies are yielded by `DirectReplyTo`.
+/// You can clone it and move it into a closure.
+#[derive(Clone)]
+pub struct RequestHandle {
+    channel_id: u16,
+    handle: AckHandle,
+}
+
+impl RequestHandle {
+    /// Publish a request. `reply_to` property of `item` is overwritten by
+    /// `amq.rabbitmq.reply-to`.
+    pub fn publish(&self, mut item: PublishItem) {
+        item.header.reply_to = Some(AmqpString::from(DIRECT_REPLY_TO));
+        self.handle.push_frames(publish_frames(self.channel_id, item));
+    }
+}
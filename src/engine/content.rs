@@ -0,0 +1,126 @@
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::ContentHeaderPayload;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, DeliverMethod};
+use amqpr_codec::AmqpString;
+
+use bytes::BytesMut;
+
+use basic::deliver::DeliveredItem;
+use protocol_log;
+
+/// Assembles `basic.deliver` or `basic.get-ok` and following content frames on a channel
+/// into `DeliveredItem`. Content of `basic.return` is received and discarded.
+#[derive(Default)]
+pub(crate) struct ContentAssembler {
+    incoming: Option<Incoming>,
+}
+
+/// Content which is received whole.
+pub(crate) enum Assembled {
+    Delivered(DeliveredItem),
+    /// Content of `basic.get-ok`. `consumer_tag` of the item is empty.
+    Got(DeliveredItem),
+}
+
+/// Content which is being received.
+/// `deliver` is `None` when the content belongs to `Return` method.
+struct Incoming {
+    deliver: Option<DeliverMethod>,
+    is_get: bool,
+    header: Option<ContentHeaderPayload>,
+    body: BytesMut,
+}
+
+impl ContentAssembler {
+    /// Returns `Err(frame)` if `frame` is not a part of content, and `Ok(Some(assembled))`
+    /// when whole body of an item is received.
+    pub(crate) fn handle(&mut self, frame: Frame) -> Result<Option<Assembled>, Frame> {
+        match frame.payload {
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Deliver(ref deliver))) => {
+                self.incoming = Some(Incoming {
+                    deliver: Some(deliver.clone()),
+                    is_get: false,
+                    header: None,
+                    body: BytesMut::new(),
+                });
+                Ok(None)
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::GetOk(ref get_ok))) => {
+                let deliver = DeliverMethod {
+                    consumer_tag: AmqpString::from(""),
+                    delivery_tag: get_ok.delivery_tag,
+                    redeliverd: get_ok.redeliverd,
+                    exchange: get_ok.exchange.clone(),
+                    routing_key: get_ok.routing_key.clone(),
+                };
+                self.incoming = Some(Incoming {
+                    deliver: Some(deliver),
+                    is_get: true,
+                    header: None,
+                    body: BytesMut::new(),
+                });
+                Ok(None)
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Return(_))) => {
+                warn!("Published message is returned : {}", protocol_log::display(&frame));
+                self.incoming = Some(Incoming {
+                    deliver: None,
+                    is_get: false,
+                    header: None,
+                    body: BytesMut::new(),
+                });
+                Ok(None)
+            }
+            FramePayload::ContentHeader(ref header) => {
+                match self.incoming {
+                    Some(ref mut incoming) if incoming.header.is_none() => {
+                        incoming.header = Some(header.clone())
+                    }
+                    _ => warn!("Skip unexpected {}", protocol_log::display(&frame)),
+                }
+                Ok(self.complete())
+            }
+            FramePayload::ContentBody(ref body) => {
+                match self.incoming {
+                    Some(ref mut incoming) if incoming.header.is_some() => {
+                        incoming.body.extend_from_slice(&body.bytes)
+                    }
+                    _ => warn!("Skip unexpected {}", protocol_log::display(&frame)),
+                }
+                Ok(self.complete())
+            }
+            _ => Err(frame),
+        }
+    }
+
+    /// Take the item if whole body is received.
+    fn complete(&mut self) -> Option<Assembled> {
+        let is_completed = match self.incoming {
+            Some(Incoming {
+                header: Some(ref header),
+                ref body,
+                ..
+            }) => body.len() as u64 >= header.body_size,
+            _ => false,
+        };
+        if !is_completed {
+            return None;
+        }
+
+        let incoming = self.incoming.take().unwrap();
+        let item = DeliveredItem {
+            meta: incoming.deliver?,
+            header: incoming.header.unwrap(),
+            body: ContentBodyPayload {
+                bytes: incoming.body.freeze(),
+            },
+        };
+        if incoming.is_get {
+            Some(Assembled::Got(item))
+        } else {
+            Some(Assembled::Delivered(item))
+        }
+    }
+}
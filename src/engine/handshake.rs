@@ -0,0 +1,215 @@
+use amqpr_codec::Frame;
+use amqpr_codec::method::connection::*;
+use amqpr_codec::method::MethodPayload;
+
+use std::collections::VecDeque;
+
+use handshake::{ConnectionInfo, Handshaker};
+use errors::*;
+
+const GLOBAL_CHANNEL_ID: u16 = 0;
+
+// Reply codes of `Close` method.
+const ACCESS_REFUSED: u16 = 403;
+const NOT_ALLOWED: u16 = 530;
+
+/// Client side of connection handshake without any I/O.
+///
+/// Give every frame from server to `handle_frame`, and send frames `poll_frame` returns
+/// until `is_done` becomes true. The protocol header is not handled by this.
+pub struct HandshakeEngine<H> {
+    handshaker: H,
+    stage: Stage,
+    virtual_host: String,
+    start: Option<StartMethod>,
+    info: Option<ConnectionInfo>,
+    outgoing: VecDeque<Frame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Start,
+    SecureOrTune,
+    OpenOk,
+    Done,
+}
+
+impl<H: Handshaker> HandshakeEngine<H> {
+    pub fn new(handshaker: H) -> HandshakeEngine<H> {
+        HandshakeEngine {
+            handshaker,
+            stage: Stage::Start,
+            virtual_host: String::new(),
+            start: None,
+            info: None,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    /// Handle a frame from server. Replies are queued for `poll_frame`.
+    pub fn handle_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        match self.stage {
+            Stage::Start => {
+                if let Some(close) = is_close(frame) {
+                    return Err(closed_by_server(close));
+                }
+                let start = is_start(frame)?;
+                let start_ok = self.handshaker.reply_to_start(start)?;
+                self.start = Some(start.clone());
+                self.outgoing.push_back(start_ok_frame(start_ok));
+                self.stage = Stage::SecureOrTune;
+            }
+
+            Stage::SecureOrTune => {
+                if let Some(close) = is_close(frame) {
+                    return Err(match close.reply_code {
+                        ACCESS_REFUSED => {
+                            ErrorKind::AuthenticationFailure(close.reply_text.to_string()).into()
+                        }
+                        _ => closed_by_server(close),
+                    });
+                }
+                match is_secure_or_tune_method(frame)? {
+                    SecureOrTune::Secure(s) => {
+                        let secure_ok = self.handshaker.reply_to_secure(s)?;
+                        self.outgoing.push_back(secure_ok_frame(secure_ok));
+                    }
+                    SecureOrTune::Tune(t) => {
+                        let tune_ok = self.handshaker.reply_to_tune(t);
+                        let start = self.start.take().expect("Start method is already received");
+                        self.info = Some(ConnectionInfo::new(&start, &tune_ok));
+                        self.outgoing.push_back(tune_ok_frame(tune_ok));
+
+                        let open = self.handshaker.create_open();
+                        self.virtual_host = open.virtual_host.to_string();
+                        self.outgoing.push_back(open_frame(open));
+                        self.stage = Stage::OpenOk;
+                    }
+                }
+            }
+
+            Stage::OpenOk => {
+                if let Some(close) = is_close(frame) {
+                    return Err(match close.reply_code {
+                        ACCESS_REFUSED | NOT_ALLOWED => ErrorKind::VirtualHostAccessRefused(
+                            self.virtual_host.clone(),
+                            close.reply_text.to_string(),
+                        ).into(),
+                        _ => closed_by_server(close),
+                    });
+                }
+                self.handshaker.inspect_open_ok(is_open_ok(frame)?);
+                self.stage = Stage::Done;
+            }
+
+            Stage::Done => {
+                return Err(ErrorKind::UnexpectedFrame("Nothing".into(), frame.clone()).into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Error to fail with when server closes the socket before handshake is completed.
+    pub fn handle_eof(&self) -> Error {
+        match self.stage {
+            // Server closes connection without any method when authentication failed
+            // unless it supports "authentication_failure_close" capability.
+            Stage::SecureOrTune => ErrorKind::AuthenticationFailure(
+                "Connection was closed by server right after start-ok method".into(),
+            ).into(),
+            _ => ErrorKind::UnexpectedConnectionClose.into(),
+        }
+    }
+
+    /// Next frame being sent to server.
+    pub fn poll_frame(&mut self) -> Option<Frame> {
+        self.outgoing.pop_front()
+    }
+
+    /// True after `open-ok` method is received.
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Available after `tune-ok` method is made.
+    pub fn info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
+
+    /// What this is waiting for, such as "waiting for start method".
+    pub fn describe(&self) -> &'static str {
+        match self.stage {
+            Stage::Start => "waiting for start method",
+            Stage::SecureOrTune => "waiting for secure or tune method",
+            Stage::OpenOk => "waiting for open-ok method",
+            Stage::Done => "completed",
+        }
+    }
+}
+
+fn start_ok_frame(start_ok: StartOkMethod) -> Frame {
+    connection_frame(ConnectionClass::StartOk(start_ok))
+}
+
+fn secure_ok_frame(secure_ok: SecureOkMethod) -> Frame {
+    connection_frame(ConnectionClass::SecureOk(secure_ok))
+}
+
+fn tune_ok_frame(tune_ok: TuneOkMethod) -> Frame {
+    connection_frame(ConnectionClass::TuneOk(tune_ok))
+}
+
+fn open_frame(open: OpenMethod) -> Frame {
+    connection_frame(ConnectionClass::Open(open))
+}
+
+fn connection_frame(connection_class: ConnectionClass) -> Frame {
+    Frame::new_method(
+        GLOBAL_CHANNEL_ID,
+        MethodPayload::Connection(connection_class),
+    )
+}
+
+fn closed_by_server(close: &CloseMethod) -> Error {
+    ErrorKind::ConnectionClosedByServer(close.reply_code, close.reply_text.to_string()).into()
+}
+
+fn is_close(frame: &Frame) -> Option<&CloseMethod> {
+    frame
+        .method()
+        .and_then(|m| m.connection())
+        .and_then(|c| c.close())
+}
+
+fn is_start(frame: &Frame) -> Result<&StartMethod, Error> {
+    frame
+        .method()
+        .and_then(|m| m.connection())
+        .and_then(|c| c.start())
+        .ok_or_else(|| ErrorKind::UnexpectedFrame("Start".into(), frame.clone()).into())
+}
+
+enum SecureOrTune<'a> {
+    Secure(&'a SecureMethod),
+    Tune(&'a TuneMethod),
+}
+
+fn is_secure_or_tune_method(frame: &Frame) -> Result<SecureOrTune<'_>, Error> {
+    frame
+        .method()
+        .and_then(|m| m.connection())
+        .and_then(|c| {
+            let secure_op = c.secure().map(SecureOrTune::Secure);
+            let tune_op = c.tune().map(SecureOrTune::Tune);
+            secure_op.or(tune_op)
+        })
+        .ok_or_else(|| ErrorKind::UnexpectedFrame("Secure or Tune".into(), frame.clone()).into())
+}
+
+fn is_open_ok(frame: &Frame) -> Result<&OpenOkMethod, Error> {
+    frame
+        .method()
+        .and_then(|m| m.connection())
+        .and_then(|c| c.open_ok())
+        .ok_or_else(|| ErrorKind::UnexpectedFrame("OpenOk".into(), frame.clone()).into())
+}
@@ -0,0 +1,532 @@
+//! Protocol engine without any I/O.
+//!
+//! `Engine` performs handshake, tracks channels, replies to server's `close`, `flow` and
+//! heartbeat, and assembles deliveries. It never reads, writes or waits by itself, so that
+//! it runs over any byte stream on any runtime, or over an in-memory buffer in tests.
+//!
+//! - Give bytes from server to `handle_bytes`, and write bytes `poll_transmit` returns.
+//!   If your transport already exchanges frames, use `handle_frame` and `poll_frame`
+//!   instead, after exchanging the protocol header by yourself.
+//! - The engine never reads the clock. Pass the current time to methods taking `now`.
+//! - Take what happened by `poll_event`.
+//! - Call `handle_timeout` at the time `poll_timeout` returns to send heartbeats. It fails
+//!   when server is silent for `MAX_MISSED_HEARTBEATS` heartbeat intervals.
+//!
+//! ```text
+//! loop {
+//!     while let Some(bytes) = engine.poll_transmit() { write(bytes) }
+//!     while let Some(event) = engine.poll_event() { ... }
+//!     select! {
+//!         read(buf) => engine.handle_bytes(buf, now())?,
+//!         sleep_until(engine.poll_timeout()) => engine.handle_timeout(now())?,
+//!     }
+//! }
+//! ```
+//!
+//! `HandshakeEngine` is the handshake part of it, which `start_handshake` drives.
+//! `Connection` runs `Engine::connected` in its background task on tokio-core.
+
+mod handshake;
+pub(crate) mod content;
+
+pub use self::handshake::HandshakeEngine;
+
+use amqpr_codec::{Codec, Frame, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::channel::ChannelClass;
+use amqpr_codec::method::connection::{CloseMethod, ConnectionClass};
+
+use tokio_io::codec::{Decoder, Encoder};
+
+use bytes::{Bytes, BytesMut};
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use basic::deliver::DeliveredItem;
+use basic::publish::{publish_frames, PublishItem};
+use channel::allocator::ChannelIdAllocator;
+use channel::close::{close_frame, close_ok_frame, REPLY_SUCCESS};
+use channel::flow::flow_ok_frame;
+use channel::open::open_frame;
+use handshake::{ConnectionInfo, Handshaker, PROTOCOL_HEADER};
+use protocol_log;
+use self::content::{Assembled, ContentAssembler};
+use errors::*;
+
+/// What `Engine` tells after handling inbound frames.
+// Most events are `Delivered`, so boxing it would not save anything.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Event {
+    /// Handshake is completed.
+    Connected(ConnectionInfo),
+    /// `channel.open-ok` is received for a channel `open_channel` opened.
+    ChannelOpened(u16),
+    /// Reply to a method sent by `send_method`, such as `queue.declare-ok`.
+    Method(u16, MethodPayload),
+    /// Whole content of `basic.deliver` is received.
+    Delivered(u16, DeliveredItem),
+    /// Whole content of `basic.get-ok` is received. `consumer_tag` of the item is empty.
+    /// `basic.get-empty` comes as `Method`.
+    Got(u16, DeliveredItem),
+    /// Server asks to stop (`false`) or restart (`true`) publishing on the channel.
+    /// `flow-ok` is already replied.
+    Flow(u16, bool),
+    /// Channel is closed. Reply code and text are given if server closed it.
+    ChannelClosed(u16, Option<(u16, String)>),
+    /// Server stops reading from us because of resource alarm. The reason is given.
+    Blocked(String),
+    Unblocked,
+    /// Server is silent for the heartbeat interval. The number of missed intervals in a row
+    /// is given.
+    HeartbeatMissed(u32),
+    /// Connection is closed. Reply code and text are given if server closed it.
+    Closed(Option<(u16, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Handshaking,
+    Open,
+    /// `connection.close` is sent and waiting for `close-ok`.
+    Closing,
+    Closed,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    is_closing: bool,
+    content: ContentAssembler,
+}
+
+struct Heartbeat {
+    /// Negotiated heartbeat interval.
+    timeout: Duration,
+    /// Interval to send heartbeat.
+    interval: Duration,
+    next: Instant,
+    last_received: Instant,
+    missed: u32,
+}
+
+/// `handle_timeout` fails when server is silent for this number of heartbeat intervals.
+pub const MAX_MISSED_HEARTBEATS: u32 = 2;
+
+/// Client side of an AMQP connection without any I/O. See module document.
+pub struct Engine<H> {
+    handshake: Option<HandshakeEngine<H>>,
+    info: Option<ConnectionInfo>,
+    state: State,
+    read_buf: BytesMut,
+    is_header_checked: bool,
+    is_header_sent: bool,
+    outgoing: VecDeque<Frame>,
+    /// Number of frames ever queued into `outgoing`.
+    queued: u64,
+    events: VecDeque<Event>,
+    channels: HashMap<u16, ChannelState>,
+    allocator: ChannelIdAllocator,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl<H: Handshaker> Engine<H> {
+    pub fn new(handshaker: H) -> Engine<H> {
+        Engine {
+            handshake: Some(HandshakeEngine::new(handshaker)),
+            info: None,
+            state: State::Handshaking,
+            read_buf: BytesMut::new(),
+            is_header_checked: false,
+            is_header_sent: false,
+            outgoing: VecDeque::new(),
+            queued: 0,
+            events: VecDeque::new(),
+            channels: HashMap::new(),
+            allocator: ChannelIdAllocator::new(0),
+            heartbeat: None,
+        }
+    }
+
+    /// Engine on a transport which already finished handshake, such as the socket
+    /// `start_handshake` returns. No protocol header nor `Event::Connected` is exchanged.
+    /// `now` is when the handshake is completed.
+    pub fn connected(info: ConnectionInfo, now: Instant) -> Engine<H> {
+        let mut engine = Engine {
+            handshake: None,
+            info: None,
+            state: State::Handshaking,
+            read_buf: BytesMut::new(),
+            is_header_checked: true,
+            is_header_sent: true,
+            outgoing: VecDeque::new(),
+            queued: 0,
+            events: VecDeque::new(),
+            channels: HashMap::new(),
+            allocator: ChannelIdAllocator::new(0),
+            heartbeat: None,
+        };
+        engine.start(info, now);
+        engine
+    }
+
+    /// Available after handshake is completed.
+    pub fn info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Open
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Handle bytes received from server. They do not need to be split at frame boundary.
+    pub fn handle_bytes(&mut self, bytes: &[u8], now: Instant) -> Result<(), Error> {
+        self.read_buf.extend_from_slice(bytes);
+        if !self.is_header_checked {
+            if self.read_buf.len() < PROTOCOL_HEADER.len() {
+                return Ok(());
+            }
+            // Server replies its supported protocol header if it does not support ours.
+            if self.read_buf.starts_with(b"AMQP") {
+                self.state = State::Closed;
+                let header = self.read_buf.split_to(PROTOCOL_HEADER.len());
+                return Err(ErrorKind::WrongProtocolHeader(header.to_vec()).into());
+            }
+            self.is_header_checked = true;
+        }
+        while let Some(frame) = Codec.decode(&mut self.read_buf)? {
+            self.handle_frame(frame, now)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a frame received from server at `now`.
+    pub fn handle_frame(&mut self, frame: Frame, now: Instant) -> Result<(), Error> {
+        if let Some(ref mut heartbeat) = self.heartbeat {
+            heartbeat.last_received = now;
+            heartbeat.missed = 0;
+        }
+        match self.state {
+            State::Handshaking => self.handle_handshake_frame(&frame, now),
+            State::Closed => {
+                warn!("Skip {} after connection is closed", protocol_log::display(&frame));
+                Ok(())
+            }
+            _ if frame.header.channel == 0 => {
+                self.handle_connection_frame(frame);
+                Ok(())
+            }
+            _ => {
+                self.handle_channel_frame(frame);
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle end of the byte stream. Returns error unless the connection is closed.
+    pub fn handle_eof(&mut self) -> Result<(), Error> {
+        let error = match self.state {
+            State::Closed => return Ok(()),
+            State::Handshaking => self.handshake().handle_eof(),
+            _ => ErrorKind::UnexpectedConnectionClose.into(),
+        };
+        self.shutdown(None);
+        Err(error)
+    }
+
+    /// When `handle_timeout` should be called next. `None` if heartbeat is disabled.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.state {
+            State::Open => self.heartbeat.as_ref().map(|h| h.next),
+            _ => None,
+        }
+    }
+
+    /// Send a heartbeat if it is the time, and count heartbeat intervals in which server
+    /// is silent. Fails with `MissedHeartbeats` error and closes the connection when they
+    /// reach `MAX_MISSED_HEARTBEATS`.
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<(), Error> {
+        if self.state != State::Open {
+            return Ok(());
+        }
+        let (already_missed, missed) = match self.heartbeat {
+            Some(ref mut heartbeat) if now >= heartbeat.next => {
+                heartbeat.next = now + heartbeat.interval;
+                let silent = now.saturating_duration_since(heartbeat.last_received);
+                let already_missed = heartbeat.missed;
+                while silent >= heartbeat.timeout * (heartbeat.missed + 1) {
+                    heartbeat.missed += 1;
+                }
+                (already_missed, heartbeat.missed)
+            }
+            _ => return Ok(()),
+        };
+        self.push(Frame::new_heartbeat(0));
+        for n in already_missed + 1..missed + 1 {
+            warn!("Server missed heartbeat {} times", n);
+            self.events.push_back(Event::HeartbeatMissed(n));
+        }
+        if missed >= MAX_MISSED_HEARTBEATS {
+            self.shutdown(None);
+            return Err(ErrorKind::MissedHeartbeats(missed).into());
+        }
+        Ok(())
+    }
+
+    /// Bytes being written to server, starting with the protocol header.
+    pub fn poll_transmit(&mut self) -> Option<Bytes> {
+        let mut buf = BytesMut::new();
+        if !self.is_header_sent {
+            self.is_header_sent = true;
+            buf.extend_from_slice(&PROTOCOL_HEADER);
+        }
+        while let Some(frame) = self.poll_frame() {
+            Codec.encode(frame, &mut buf).expect("Encoding into memory never fails");
+        }
+        if buf.is_empty() {
+            None
+        } else {
+            Some(buf.freeze())
+        }
+    }
+
+    /// Next frame being sent to server. Use it instead of `poll_transmit` on a transport
+    /// which exchanges frames.
+    pub fn poll_frame(&mut self) -> Option<Frame> {
+        self.outgoing.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Send `channel.open` on an unused channel id and returns the id.
+    /// `Event::ChannelOpened` follows when server replies.
+    pub fn open_channel(&mut self) -> Result<u16, Error> {
+        self.check_open()?;
+        let channel_id = self.allocator.allocate()?;
+        self.start_channel(channel_id);
+        Ok(channel_id)
+    }
+
+    /// Send `channel.open` on given channel id.
+    /// Fails if the id is 0, greater than negotiated `channel_max` or already in use.
+    pub fn open_channel_with_id(&mut self, channel_id: u16) -> Result<(), Error> {
+        self.check_open()?;
+        self.allocator.reserve(channel_id)?;
+        self.start_channel(channel_id);
+        Ok(())
+    }
+
+    /// Send a method on the channel. Its reply comes as `Event::Method`.
+    pub fn send_method(&mut self, channel_id: u16, method: MethodPayload) -> Result<(), Error> {
+        self.send_frames(channel_id, vec![Frame::new_method(channel_id, method)])
+    }
+
+    pub fn publish(&mut self, channel_id: u16, item: PublishItem) -> Result<(), Error> {
+        self.send_frames(channel_id, publish_frames(channel_id, item))
+    }
+
+    /// Send frames built by yourself on the channel, such as the ones of
+    /// `queue::declare::declare_queue_frame`. Use `open_channel`, `close_channel` and
+    /// `close` instead of sending those methods by this.
+    pub fn send_frames(&mut self, channel_id: u16, frames: Vec<Frame>) -> Result<(), Error> {
+        self.check_channel(channel_id)?;
+        for frame in frames {
+            self.push(frame);
+        }
+        Ok(())
+    }
+
+    /// Send `channel.close`. `Event::ChannelClosed` follows when server replies.
+    pub fn close_channel(&mut self, channel_id: u16) -> Result<(), Error> {
+        self.check_channel(channel_id)?;
+        self.channels.get_mut(&channel_id).unwrap().is_closing = true;
+        self.push(close_frame(channel_id));
+        Ok(())
+    }
+
+    /// Send `connection.close`. `Event::Closed` follows when server replies.
+    pub fn close(&mut self) -> Result<(), Error> {
+        self.check_open()?;
+        let close = CloseMethod {
+            reply_code: REPLY_SUCCESS,
+            reply_text: "Normal shutdown".into(),
+            class_id: 0,
+            method_id: 0,
+        };
+        self.push(Frame::new_method(
+            0,
+            MethodPayload::Connection(ConnectionClass::Close(close)),
+        ));
+        self.state = State::Closing;
+        Ok(())
+    }
+
+    /// Number of frames ever queued for `poll_frame`. `Driver` compares it with frames it
+    /// wrote to tell when frames of a method are written.
+    pub(crate) fn queued_frames(&self) -> u64 {
+        self.queued
+    }
+
+    fn push(&mut self, frame: Frame) {
+        self.queued += 1;
+        self.outgoing.push_back(frame);
+    }
+
+    fn handshake(&mut self) -> &mut HandshakeEngine<H> {
+        self.handshake.as_mut().expect("Engine is handshaking")
+    }
+
+    fn check_open(&self) -> Result<(), Error> {
+        match self.state {
+            State::Handshaking => Err(ErrorKind::HandshakeNotCompleted.into()),
+            State::Open => Ok(()),
+            State::Closing | State::Closed => Err(ErrorKind::ConnectionClosed.into()),
+        }
+    }
+
+    fn check_channel(&self, channel_id: u16) -> Result<(), Error> {
+        self.check_open()?;
+        match self.channels.get(&channel_id) {
+            Some(channel) if !channel.is_closing => Ok(()),
+            _ => Err(ErrorKind::ChannelClosed(channel_id).into()),
+        }
+    }
+
+    fn handle_handshake_frame(&mut self, frame: &Frame, now: Instant) -> Result<(), Error> {
+        if let Err(e) = self.handshake().handle_frame(frame) {
+            self.state = State::Closed;
+            return Err(e);
+        }
+        while let Some(frame) = self.handshake().poll_frame() {
+            self.push(frame);
+        }
+        if self.handshake().is_done() {
+            let info = self.handshake().info().cloned().expect("Tune-ok method is already sent");
+            self.start(info.clone(), now);
+            self.events.push_back(Event::Connected(info));
+        }
+        Ok(())
+    }
+
+    /// Start the connection after handshake, which is completed at `now`.
+    fn start(&mut self, info: ConnectionInfo, now: Instant) {
+        self.allocator = ChannelIdAllocator::new(info.channel_max);
+        self.heartbeat = match info.heartbeat {
+            0 => None,
+            secs => {
+                // Send heartbeat twice in the interval so that server never misses it.
+                let timeout = Duration::from_secs(u64::from(secs));
+                Some(Heartbeat {
+                    timeout,
+                    interval: timeout / 2,
+                    next: now + timeout / 2,
+                    last_received: now,
+                    missed: 0,
+                })
+            }
+        };
+        self.info = Some(info);
+        self.state = State::Open;
+    }
+
+    fn start_channel(&mut self, channel_id: u16) {
+        self.channels.insert(channel_id, ChannelState::default());
+        self.push(open_frame(channel_id));
+    }
+
+    fn handle_connection_frame(&mut self, frame: Frame) {
+        match frame.payload {
+            FramePayload::Heartbeat => debug!("Receive heartbeat"),
+            FramePayload::Method(MethodPayload::Connection(ConnectionClass::Close(close))) => {
+                self.shutdown(Some((close.reply_code, String::from(&*close.reply_text))));
+                let close_ok = Frame::new_method(0, MethodPayload::Connection(ConnectionClass::CloseOk));
+                self.push(close_ok);
+            }
+            FramePayload::Method(MethodPayload::Connection(ConnectionClass::CloseOk)) => {
+                self.shutdown(None);
+            }
+            FramePayload::Method(MethodPayload::Connection(ConnectionClass::Blocked(blocked))) => {
+                self.events.push_back(Event::Blocked(String::from(&*blocked.reason)));
+            }
+            FramePayload::Method(MethodPayload::Connection(ConnectionClass::Unblocked)) => {
+                self.events.push_back(Event::Unblocked);
+            }
+            _ => warn!("Skip unexpected {}", protocol_log::display(&frame)),
+        }
+    }
+
+    fn handle_channel_frame(&mut self, frame: Frame) {
+        let channel_id = frame.header.channel;
+        let frame = {
+            let channel = match self.channels.get_mut(&channel_id) {
+                Some(channel) => channel,
+                None => {
+                    return warn!(
+                        "Skip {} on channel which is not open",
+                        protocol_log::display(&frame)
+                    )
+                }
+            };
+            match channel.content.handle(frame) {
+                Ok(Some(Assembled::Delivered(item))) => {
+                    return self.events.push_back(Event::Delivered(channel_id, item))
+                }
+                Ok(Some(Assembled::Got(item))) => {
+                    return self.events.push_back(Event::Got(channel_id, item))
+                }
+                Ok(None) => return,
+                Err(frame) => frame,
+            }
+        };
+
+        let event = match frame.payload {
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::OpenOk(_))) => {
+                Event::ChannelOpened(channel_id)
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Flow(flow))) => {
+                self.push(flow_ok_frame(channel_id, flow.active));
+                Event::Flow(channel_id, flow.active)
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Close(close))) => {
+                self.push(close_ok_frame(channel_id));
+                self.release_channel(channel_id);
+                Event::ChannelClosed(channel_id, Some((close.reply_code, String::from(&*close.reply_text))))
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::CloseOk)) => {
+                self.release_channel(channel_id);
+                Event::ChannelClosed(channel_id, None)
+            }
+            FramePayload::Method(method) => Event::Method(channel_id, method),
+            payload => {
+                return warn!(
+                    "Skip unexpected {}",
+                    protocol_log::display(&Frame { header: frame.header, payload })
+                )
+            }
+        };
+        self.events.push_back(event);
+    }
+
+    fn release_channel(&mut self, channel_id: u16) {
+        self.channels.remove(&channel_id);
+        self.allocator.release(channel_id);
+    }
+
+    /// Close the connection. Frames not sent yet are discarded.
+    fn shutdown(&mut self, by_server: Option<(u16, String)>) {
+        let was_connected = self.state == State::Open || self.state == State::Closing;
+        self.state = State::Closed;
+        self.outgoing.clear();
+        self.channels.clear();
+        self.heartbeat = None;
+        if was_connected {
+            self.events.push_back(Event::Closed(by_server));
+        }
+    }
+}
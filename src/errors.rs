@@ -16,6 +16,123 @@ error_chain! {
             description("Fail to complete handshake")
             display("Fail to complete handshake")
         }
+        HandshakeTimeout(stage: String) {
+            description("Handshake is timed out")
+            display("Handshake is timed out while {}", stage)
+        }
+        HandshakeNotCompleted {
+            description("Handshake is not completed yet")
+            display("Handshake is not completed yet")
+        }
+        WrongProtocolHeader(received: Vec<u8>) {
+            description("Server does not support AMQP 0-9-1")
+            display("Server does not support AMQP 0-9-1. It replied protocol header {:?}", received)
+        }
+        AuthenticationFailure(reason: String) {
+            description("Fail to authenticate")
+            display("Fail to authenticate : {}", reason)
+        }
+        VirtualHostAccessRefused(virtual_host: String, reason: String) {
+            description("Access to virtual host is refused")
+            display("Access to virtual host \"{}\" is refused : {}", virtual_host, reason)
+        }
+        ConnectionClosedByServer(reply_code: u16, reply_text: String) {
+            description("Connection was closed by server")
+            display("Connection was closed by server : {} {}", reply_code, reply_text)
+        }
+        MissedHeartbeats(missed: u32) {
+            description("Server missed heartbeats")
+            display("Server missed {} heartbeats", missed)
+        }
+
+        ConnectionClosed {
+            description("Connection is already closed")
+            display("Connection is already closed")
+        }
+        ChannelClosed(channel_id: u16) {
+            description("Channel is already closed")
+            display("Channel {} is already closed", channel_id)
+        }
+        ChannelIdExhausted(channel_max: u16) {
+            description("No channel id is available")
+            display("Every channel id up to channel_max {} is in use", channel_max)
+        }
+        InvalidChannelId(channel_id: u16, channel_max: u16) {
+            description("Invalid channel id")
+            display("Channel id {} is out of range 1..={}", channel_id, channel_max)
+        }
+        ChannelIdInUse(channel_id: u16) {
+            description("Channel id is already in use")
+            display("Channel id {} is already in use", channel_id)
+        }
+        ChannelClosedByServer(channel_id: u16, reply_code: u16, reply_text: String) {
+            description("Channel was closed by server")
+            display("Channel {} was closed by server : {} {}", channel_id, reply_code, reply_text)
+        }
+        ReconnectFailed(attempts: u32) {
+            description("Fail to reconnect")
+            display("Fail to reconnect after {} attempts", attempts)
+        }
+        NoSupportedSaslMechanism(offered: String) {
+            description("No mutually supported SASL mechanism")
+            display("No mutually supported SASL mechanism in \"{}\" offered by server", offered)
+        }
+        SaslFailure(reason: String) {
+            description("Fail to authenticate with SASL mechanism")
+            display("Fail to authenticate with SASL mechanism : {}", reason)
+        }
+        RpcTimeout(correlation_id: String) {
+            description("RPC request is timed out")
+            display("RPC request {} is timed out", correlation_id)
+        }
+        RpcClientClosed(reason: String) {
+            description("RPC client can not receive replies any more")
+            display("RPC client can not receive replies any more : {}", reason)
+        }
+        NoReplyTo(delivery_tag: u64) {
+            description("Delivered item has no reply_to property")
+            display("Delivered item {} has no reply_to property to respond", delivery_tag)
+        }
+        DirectReplyToNotConsumed(channel_id: u16) {
+            description("amq.rabbitmq.reply-to is not consumed on the channel")
+            display("Channel {} publishes to amq.rabbitmq.reply-to before consuming it", channel_id)
+        }
+        EncodeMessage(reason: String) {
+            description("Fail to encode message body")
+            display("Fail to encode message body : {}", reason)
+        }
+        DecodeMessage(reason: String) {
+            description("Fail to decode message body")
+            display("Fail to decode message body : {}", reason)
+        }
+        UnexpectedContentType(expected: String, found: String) {
+            description("Message has unexpected content_type")
+            display("Expected content_type {} but found {}", expected, found)
+        }
+        CompressMessage(encoding: String, reason: String) {
+            description("Fail to compress message body")
+            display("Fail to compress message body by {} : {}", encoding, reason)
+        }
+        DecompressMessage(encoding: String, reason: String) {
+            description("Fail to decompress message body")
+            display("Fail to decompress message body by {} : {}", encoding, reason)
+        }
+        DecompressedTooLarge(encoding: String, max_size: usize) {
+            description("Decompressed message body is too large")
+            display("Message body decompressed by {} is larger than {} bytes", encoding, max_size)
+        }
+        UnknownContentEncoding(encoding: String) {
+            description("Compression is not registered")
+            display("Compression for content_encoding {} is not registered", encoding)
+        }
+        AlreadyEncoded(encoding: String) {
+            description("Message body is already encoded")
+            display("Message body is already encoded by {}", encoding)
+        }
+        ScriptMismatch(diff: String) {
+            description("Frame does not match the script")
+            display("{}", diff)
+        }
         UnexpectedFrame(expected: String, found: ::amqpr_codec::Frame) {
             description("Receive unexpected frame")
             display("Expected \"{}\" but found \"{:?}\"", expected, found)
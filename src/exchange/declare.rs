@@ -18,6 +18,10 @@ pub fn declare_exchange<S>(
 where
     S: Sink<SinkItem = Frame>,
 {
+    socket.send(declare_exchange_frame(channel_id, option))
+}
+
+pub(crate) fn declare_exchange_frame(channel_id: u16, option: DeclareExchangeOption) -> Frame {
     let declare = DeclareMethod {
         reserved1: 0,
         exchange: option.name,
@@ -30,14 +34,12 @@ where
         arguments: HashMap::new(),
     };
 
-    let frame = Frame {
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
         payload: FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Declare(declare))),
-    };
-
-    socket.send(frame)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,26 @@
+use amqpr_codec::{AmqpString, Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::exchange::{DeleteMethod, ExchangeClass};
+
+pub(crate) fn delete_exchange_frame(channel_id: u16, option: DeleteExchangeOption) -> Frame {
+    let delete = DeleteMethod {
+        reserved1: 0,
+        exchange: option.name,
+        if_unused: option.is_if_unused,
+        no_wait: false,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Delete(delete))),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteExchangeOption {
+    pub name: AmqpString,
+    /// Delete the exchange only if it has no queue binding.
+    pub is_if_unused: bool,
+}
@@ -1,3 +1,5 @@
 pub mod declare;
+pub mod delete;
 
 pub use self::declare::declare_exchange;
+pub use self::delete::DeleteExchangeOption;
@@ -0,0 +1,71 @@
+use amqpr_codec::{AmqpString, FieldArgument};
+use amqpr_codec::method::connection::{StartMethod, TuneOkMethod};
+
+use std::collections::HashMap;
+
+/// What we learned about the connection through handshake.
+/// This is returned by `Handshaking` future alongside the socket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    pub version_major: u8,
+    pub version_minor: u8,
+    pub server_properties: HashMap<AmqpString, FieldArgument>,
+    /// SASL mechanisms server supports.
+    pub mechanisms: Vec<String>,
+    /// Message locales server supports.
+    pub locales: Vec<String>,
+    /// Negotiated max channel number. 0 means no limit.
+    pub channel_max: u16,
+    /// Negotiated max frame size in bytes. 0 means no limit.
+    pub frame_max: u32,
+    /// Negotiated heartbeat interval in seconds. 0 means heartbeat is disabled.
+    pub heartbeat: u16,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(start: &StartMethod, tune_ok: &TuneOkMethod) -> ConnectionInfo {
+        ConnectionInfo {
+            version_major: start.version_major,
+            version_minor: start.version_minor,
+            server_properties: start.server_properties.clone(),
+            mechanisms: start.mechanisms.split_whitespace().map(String::from).collect(),
+            locales: start.locales.split_whitespace().map(String::from).collect(),
+            channel_max: tune_ok.channel_max,
+            frame_max: tune_ok.frame_max,
+            heartbeat: tune_ok.heartbeat,
+        }
+    }
+
+    /// Server product name such as "RabbitMQ".
+    pub fn product(&self) -> Option<&str> {
+        self.server_property_str("product")
+    }
+
+    /// Server product version such as "3.6.12".
+    pub fn version(&self) -> Option<&str> {
+        self.server_property_str("version")
+    }
+
+    pub fn platform(&self) -> Option<&str> {
+        self.server_property_str("platform")
+    }
+
+    /// Returns true if server advertises given capability such as "publisher_confirms".
+    pub fn has_capability(&self, name: &str) -> bool {
+        match self.server_properties.get(&AmqpString::from("capabilities")) {
+            Some(FieldArgument::NestedTable(caps)) => {
+                caps.get(&AmqpString::from(name.to_string())) == Some(&FieldArgument::Boolean(true))
+            }
+            _ => false,
+        }
+    }
+
+    fn server_property_str(&self, name: &'static str) -> Option<&str> {
+        match self.server_properties.get(&AmqpString::from(name)) {
+            Some(FieldArgument::LongString(s)) | Some(FieldArgument::ShortString(s)) => {
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! # Connection handshake
+//!
+//! ```text
+//! Client                               AMQP Server
+//!    +                                        +
+//!    |     Protocol header (not framed)       |
+//!    +--------------------------------------> +
+//!    |                                        |
+//!    |     Start method                       |
+//!    | <--------------------------------------+
+//!    |                                        |
+//!    |     Start-ok method                    |
+//!    +--------------------------------------> +
+//!    |                                        |
+//!    |     Secure method                      |
+//!    | <--------------------------------------+
+//!    |                                        |
+//!    |     Secure-ok method                   |
+//!    +--------------------------------------> +
+//!    |                                        |
+//!    |     Tune method                        |
+//!    | <--------------------------------------+
+//!    |                                        |
+//!    |     Tune-ok method                     |
+//!    +--------------------------------------> +
+//!    |                                        |
+//!    |     Open method                        |
+//!    +--------------------------------------> +
+//!    |                                        |
+//!    |     Open-ok method                     |
+//!    | <--------------------------------------+
+//!    |                                        |
+//!    |                   X                    |
+//!    |                   X                    |
+//!    |                   X                    |
+//!    |                   X                    |
+//!    |                                        |
+//!    |     Close method (both peer can send)  |
+//!    +--------------------------------------> |
+//!    | <--------------------------------------+
+//!    |                                        |
+//!    |     Close-ok method                    |
+//!    | <--------------------------------------+
+//!    +--------------------------------------> |
+//!    |                                        |
+//!    |                                        |
+//!    +                                        +
+//! ```
+//!
+//!
+
+pub mod sasl;
+pub mod info;
+pub mod properties;
+
+use amqpr_codec::method::connection::*;
+use amqpr_codec::args::AmqpString;
+use amqpr_codec::Frame;
+
+use futures::sink::Send;
+use futures::{Async, Future, Poll, Sink, Stream};
+
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::codec::{Framed, FramedParts};
+use tokio_io::io::{read_exact, write_all, ReadExact, WriteAll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use bytes::BytesMut;
+
+use std::time::Duration;
+
+use AmqpSocket;
+use common::Should;
+use engine::HandshakeEngine;
+use protocol_log::method_name;
+use errors::*;
+use self::sasl::{challenge_bytes, response_string, select_mechanism, AmqPlain, Plain, SaslMechanism};
+pub use self::info::ConnectionInfo;
+pub use self::properties::ClientProperties;
+
+pub(crate) const PROTOCOL_HEADER: [u8; 8] = [b'A', b'M', b'Q', b'P', 0, 0, 9, 1];
+
+/// Start connection handshake on given socket.
+///
+/// Returned future consists of `ConnectionInfo` and `AmqpSocket`. `ConnectionInfo` holds
+/// server properties and negotiated parameters such as `frame_max`.
+///
+/// `socket` is usually `TcpStream`, but any `AsyncRead + AsyncWrite` such as
+/// `recording::Recorder` works.
+pub fn start_handshake<H, T>(handshaker: H, socket: T) -> Handshaking<H, T>
+where
+    H: Handshaker,
+    T: AsyncRead + AsyncWrite,
+{
+    Handshaking {
+        stage: HandshakeStage::SendingProtoHeader(write_all(socket, PROTOCOL_HEADER)),
+        handshaker: Some(handshaker),
+        timer: HandshakeTimer::Disabled,
+    }
+}
+
+/// Same as `start_handshake` but returned future fails with `HandshakeTimeout` error
+/// if handshake is not completed within `timeout`.
+pub fn start_handshake_with_timeout<H, T>(
+    handshaker: H,
+    socket: T,
+    timeout: Duration,
+    handle: &Handle,
+) -> Handshaking<H, T>
+where
+    H: Handshaker,
+    T: AsyncRead + AsyncWrite,
+{
+    let mut handshaking = start_handshake(handshaker, socket);
+    handshaking.timer = HandshakeTimer::NotStarted(timeout, handle.clone());
+    handshaking
+}
+
+/// Start connection handshake on a socket which already exchanges frames, such as
+/// `mock::MockSocket`. The protocol header is not sent, so server must send start method
+/// first.
+///
+/// `start_handshake` runs this future after the protocol header is exchanged.
+pub fn start_frame_handshake<H, S>(handshaker: H, socket: S) -> FrameHandshaking<H, S>
+where
+    H: Handshaker,
+    S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+{
+    FrameHandshaking {
+        stage: FrameStage::Receiving(Should::new(socket)),
+        engine: HandshakeEngine::new(handshaker),
+    }
+}
+
+pub struct Handshaking<H, T = TcpStream>
+where
+    H: Handshaker,
+    T: AsyncRead + AsyncWrite,
+{
+    stage: HandshakeStage<H, T>,
+    /// Moved into `FrameHandshaking` after the protocol header is exchanged.
+    handshaker: Option<H>,
+    timer: HandshakeTimer,
+}
+
+pub struct FrameHandshaking<H, S>
+where
+    H: Handshaker,
+    S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+{
+    stage: FrameStage<S>,
+    engine: HandshakeEngine<H>,
+}
+
+// HandshakeStage {{{
+enum HandshakeStage<H, T>
+where
+    H: Handshaker,
+    T: AsyncRead + AsyncWrite,
+{
+    SendingProtoHeader(WriteAll<T, [u8; 8]>),
+    ReceivingProtoHeaderOrStart(ReadExact<T, [u8; 8]>),
+    Frames(Box<FrameHandshaking<H, AmqpSocket<T>>>),
+}
+
+impl<H: Handshaker, T: AsyncRead + AsyncWrite> HandshakeStage<H, T> {
+    fn describe(&self) -> String {
+        use self::HandshakeStage::*;
+        match *self {
+            SendingProtoHeader(_) => "sending protocol header".into(),
+            ReceivingProtoHeaderOrStart(_) => "waiting for start method".into(),
+            Frames(ref handshaking) => handshaking.stage.describe(&handshaking.engine),
+        }
+    }
+}
+
+enum FrameStage<S: Sink> {
+    Receiving(Should<S>),
+    /// Sending a frame `HandshakeEngine` made. `&str` is its method name.
+    Sending(Box<Send<S>>, &'static str),
+}
+
+impl<S: Sink> FrameStage<S> {
+    fn describe<H: Handshaker>(&self, engine: &HandshakeEngine<H>) -> String {
+        match *self {
+            FrameStage::Receiving(_) => engine.describe().into(),
+            FrameStage::Sending(_, method) => format!("sending {} method", method),
+        }
+    }
+}
+
+/// Send a frame `engine` made if any, or receive next one.
+fn next_stage<H, S>(engine: &mut HandshakeEngine<H>, socket: S) -> FrameStage<S>
+where
+    H: Handshaker,
+    S: Sink<SinkItem = Frame>,
+{
+    match engine.poll_frame() {
+        Some(frame) => {
+            let method = frame.method().map(method_name).unwrap_or("unknown");
+            FrameStage::Sending(Box::new(socket.send(frame)), method)
+        }
+        None => FrameStage::Receiving(Should::new(socket)),
+    }
+}
+// }}}
+
+// HandshakeTimer {{{
+enum HandshakeTimer {
+    Disabled,
+    NotStarted(Duration, Handle),
+    Running(Timeout),
+}
+
+impl HandshakeTimer {
+    /// Returns true if timer is expired.
+    fn poll_expired(&mut self) -> Result<bool, Error> {
+        let timeout = match *self {
+            HandshakeTimer::Disabled => return Ok(false),
+            HandshakeTimer::NotStarted(dur, ref handle) => Timeout::new(dur, handle)?,
+            HandshakeTimer::Running(ref mut timeout) => return Ok(timeout.poll()?.is_ready()),
+        };
+        *self = HandshakeTimer::Running(timeout);
+        self.poll_expired()
+    }
+}
+// }}}
+
+// Handshaker {{{
+pub trait Handshaker {
+    fn reply_to_start(&mut self, start: &StartMethod) -> Result<StartOkMethod, Error>;
+    fn reply_to_secure(&mut self, secure: &SecureMethod) -> Result<SecureOkMethod, Error>;
+    fn reply_to_tune(&mut self, tune: &TuneMethod) -> TuneOkMethod;
+    fn create_open(&mut self) -> OpenMethod;
+    fn inspect_open_ok(&mut self, open_ok: &OpenOkMethod);
+}
+
+/// `Handshaker` which authenticates with one of given SASL mechanisms.
+/// A mechanism is selected from the list server offers, in the order of `mechanisms`.
+///
+/// `channel_max`, `frame_max` and `heartbeat` are negotiated with server. The smaller one of
+/// ours and server's is used, and 0 means no limit.
+pub struct SimpleHandshaker {
+    mechanisms: Vec<Box<dyn SaslMechanism>>,
+    selected: Option<usize>,
+    virtual_host: String,
+    client_properties: ClientProperties,
+    channel_max: u16,
+    frame_max: u32,
+    heartbeat: u16,
+}
+
+impl SimpleHandshaker {
+    /// Authenticate with `PLAIN` or `AMQPLAIN` mechanism.
+    pub fn new<U, P, V>(user: U, pass: P, virtual_host: V) -> SimpleHandshaker
+    where
+        U: Into<String>,
+        P: Into<String>,
+        V: Into<String>,
+    {
+        let (user, pass) = (user.into(), pass.into());
+        let plain = Plain {
+            user: user.clone(),
+            pass: pass.clone(),
+        };
+        let amqplain = AmqPlain { user, pass };
+        SimpleHandshaker::with_mechanisms(vec![Box::new(plain), Box::new(amqplain)], virtual_host)
+    }
+
+    /// Authenticate with one of given mechanisms, such as `External` for TLS client
+    /// certificate authentication.
+    pub fn with_mechanisms<V>(
+        mechanisms: Vec<Box<dyn SaslMechanism>>,
+        virtual_host: V,
+    ) -> SimpleHandshaker
+    where
+        V: Into<String>,
+    {
+        SimpleHandshaker {
+            mechanisms,
+            selected: None,
+            virtual_host: virtual_host.into(),
+            client_properties: ClientProperties::new(),
+            channel_max: 0,
+            frame_max: 0,
+            heartbeat: 60,
+        }
+    }
+
+    /// Set `client_properties` being sent to server.
+    pub fn client_properties(mut self, client_properties: ClientProperties) -> SimpleHandshaker {
+        self.client_properties = client_properties;
+        self
+    }
+
+    /// Set name of this connection, which is shown in management UI of RabbitMQ.
+    pub fn connection_name<S: Into<String>>(mut self, name: S) -> SimpleHandshaker {
+        self.client_properties = self.client_properties.connection_name(name);
+        self
+    }
+
+    /// Set max channel number we want. Default is 0 (no limit).
+    pub fn channel_max(mut self, channel_max: u16) -> SimpleHandshaker {
+        self.channel_max = channel_max;
+        self
+    }
+
+    /// Set max frame size we want. Default is 0 (no limit).
+    pub fn frame_max(mut self, frame_max: u32) -> SimpleHandshaker {
+        self.frame_max = frame_max;
+        self
+    }
+
+    /// Set heartbeat interval in seconds we want. Default is 60.
+    pub fn heartbeat(mut self, heartbeat: u16) -> SimpleHandshaker {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    fn selected_mechanism(&mut self) -> Result<&mut Box<dyn SaslMechanism>, Error> {
+        match self.selected {
+            Some(idx) => Ok(&mut self.mechanisms[idx]),
+            None => Err(ErrorKind::SaslFailure("No mechanism is selected yet".into()).into()),
+        }
+    }
+}
+
+impl Handshaker for SimpleHandshaker {
+    fn reply_to_start(&mut self, start: &StartMethod) -> Result<StartOkMethod, Error> {
+        debug!(
+            "Receive connection.start version={}.{} mechanisms={}",
+            start.version_major, start.version_minor, &*start.mechanisms
+        );
+
+        self.selected = Some(select_mechanism(&self.mechanisms, &start.mechanisms)?);
+        let client_properties = self.client_properties.clone().into_table();
+        let mechanism = self.selected_mechanism()?;
+        info!("Use {} mechanism for sasl", mechanism.name());
+
+        let response = mechanism.initial_response()?;
+        Ok(StartOkMethod {
+            client_properties,
+            mechanism: AmqpString::from(mechanism.name().to_string()),
+            response: response_string(mechanism.name(), response)?,
+            locale: "en_US".into(),
+        })
+    }
+
+    fn reply_to_secure(&mut self, secure: &SecureMethod) -> Result<SecureOkMethod, Error> {
+        let challenge = challenge_bytes(&secure.challenge)?;
+        let mechanism = self.selected_mechanism()?;
+        let response = mechanism.reply_to_challenge(&challenge)?;
+        Ok(SecureOkMethod {
+            response: response_string(mechanism.name(), response)?,
+        })
+    }
+
+    fn reply_to_tune(&mut self, tune: &TuneMethod) -> TuneOkMethod {
+        debug!(
+            "Receive connection.tune channel_max={} frame_max={} heartbeat={}",
+            tune.channel_max, tune.frame_max, tune.heartbeat
+        );
+
+        TuneOkMethod {
+            channel_max: negotiate(self.channel_max, tune.channel_max),
+            frame_max: negotiate(self.frame_max, tune.frame_max),
+            heartbeat: negotiate(self.heartbeat, tune.heartbeat),
+        }
+    }
+
+    fn create_open(&mut self) -> OpenMethod {
+        OpenMethod {
+            virtual_host: AmqpString::from(self.virtual_host.clone()),
+            reserved1: "".into(),
+            reserved2: false,
+        }
+    }
+
+    fn inspect_open_ok(&mut self, _open_ok: &OpenOkMethod) {
+        debug!("Receive connection.open-ok");
+    }
+}
+// }}}
+
+/// Returns smaller one of `client` and `server` while 0 means no limit.
+fn negotiate<T>(client: T, server: T) -> T
+where
+    T: Ord + From<u8>,
+{
+    if client == T::from(0) {
+        server
+    } else if server == T::from(0) {
+        client
+    } else {
+        ::std::cmp::min(client, server)
+    }
+}
+
+// Implement Future for Handshaking {{{
+impl<H, T> Future for Handshaking<H, T>
+where
+    H: Handshaker,
+    T: AsyncRead + AsyncWrite,
+{
+    type Item = (ConnectionInfo, AmqpSocket<T>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.timer.poll_expired()? {
+            return Err(ErrorKind::HandshakeTimeout(self.stage.describe()).into());
+        }
+
+        use self::HandshakeStage::*;
+        self.stage = match self.stage {
+            SendingProtoHeader(ref mut sending_future) => {
+                let (socket, _buf) = try_ready!(sending_future.poll());
+                ReceivingProtoHeaderOrStart(read_exact(socket, [0; 8]))
+            }
+
+            ReceivingProtoHeaderOrStart(ref mut reading_future) => {
+                let (socket, buf) = try_ready!(reading_future.poll());
+                // Server replies its supported protocol header if it does not support ours.
+                if buf.starts_with(b"AMQP") {
+                    return Err(ErrorKind::WrongProtocolHeader(buf.to_vec()).into());
+                }
+                let parts = FramedParts {
+                    inner: socket,
+                    readbuf: BytesMut::from(&buf[..]),
+                    writebuf: BytesMut::new(),
+                };
+                let framed = Framed::from_parts(parts, ::amqpr_codec::Codec);
+                let handshaker = self.handshaker.take().expect("Handshake is already started");
+                Frames(Box::new(start_frame_handshake(handshaker, AmqpSocket(framed))))
+            }
+
+            Frames(ref mut handshaking) => return handshaking.poll(),
+        };
+
+        self.poll()
+    }
+}
+// }}}
+
+// Implement Future for FrameHandshaking {{{
+impl<H, S> Future for FrameHandshaking<H, S>
+where
+    H: Handshaker,
+    S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+{
+    type Item = (ConnectionInfo, S);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.stage = match self.stage {
+            FrameStage::Receiving(ref mut should_socket) => {
+                if self.engine.is_done() {
+                    let info = self.engine.info().cloned().expect("Tune-ok method is already sent");
+                    return Ok(Async::Ready((info, should_socket.take())));
+                }
+                let frame = match try_ready!(should_socket.as_mut().poll()) {
+                    Some(frame) => frame,
+                    None => return Err(self.engine.handle_eof()),
+                };
+                self.engine.handle_frame(&frame)?;
+                next_stage(&mut self.engine, should_socket.take())
+            }
+
+            FrameStage::Sending(ref mut sending_future, _) => {
+                let socket = try_ready!(sending_future.poll());
+                next_stage(&mut self.engine, socket)
+            }
+        };
+
+        self.poll()
+    }
+}
+// }}}
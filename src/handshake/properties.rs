@@ -0,0 +1,102 @@
+use amqpr_codec::{AmqpString, FieldArgument};
+
+use std::collections::HashMap;
+
+/// `client_properties` being sent with `Start-Ok` method.
+/// These are shown in management UI of RabbitMQ.
+///
+/// ```
+/// extern crate amqpr_api;
+/// extern crate amqpr_codec;
+///
+/// use amqpr_api::handshake::ClientProperties;
+/// use amqpr_codec::FieldArgument;
+///
+/// # fn main() {
+/// let properties = ClientProperties::new()
+///     .connection_name("order-service-7f9c")
+///     .property("service", FieldArgument::LongString("order-service".into()));
+/// # let _ = properties;
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientProperties {
+    table: HashMap<AmqpString, FieldArgument>,
+}
+
+impl ClientProperties {
+    /// Properties describing this crate, and capabilities it supports.
+    pub fn new() -> ClientProperties {
+        ClientProperties {
+            table: HashMap::new(),
+        }.product("amqpr-api")
+            .version(env!("CARGO_PKG_VERSION"))
+            .platform("Rust")
+            .property("copyright", long_string("(C) 2017 Atsuki-Tak"))
+            .information(env!("CARGO_PKG_REPOSITORY"))
+            .property("capabilities", FieldArgument::NestedTable(capabilities()))
+    }
+
+    /// Name of this connection. RabbitMQ shows it in management UI.
+    pub fn connection_name<S: Into<String>>(self, name: S) -> ClientProperties {
+        self.property("connection_name", long_string(name))
+    }
+
+    pub fn product<S: Into<String>>(self, product: S) -> ClientProperties {
+        self.property("product", long_string(product))
+    }
+
+    pub fn version<S: Into<String>>(self, version: S) -> ClientProperties {
+        self.property("version", long_string(version))
+    }
+
+    pub fn platform<S: Into<String>>(self, platform: S) -> ClientProperties {
+        self.property("platform", long_string(platform))
+    }
+
+    pub fn information<S: Into<String>>(self, information: S) -> ClientProperties {
+        self.property("information", long_string(information))
+    }
+
+    /// Set arbitrary property such as metadata of your service.
+    /// A property having the same key is overwritten.
+    pub fn property<K: Into<String>>(mut self, key: K, value: FieldArgument) -> ClientProperties {
+        self.table.insert(AmqpString::from(key.into()), value);
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&FieldArgument> {
+        self.table.get(&AmqpString::from(key.to_string()))
+    }
+
+    pub fn into_table(self) -> HashMap<AmqpString, FieldArgument> {
+        self.table
+    }
+}
+
+impl Default for ClientProperties {
+    fn default() -> ClientProperties {
+        ClientProperties::new()
+    }
+}
+
+fn long_string<S: Into<String>>(s: S) -> FieldArgument {
+    FieldArgument::LongString(AmqpString::from(s.into()))
+}
+
+/// Capabilities this crate advertises in `client_properties`.
+/// Without `connection.blocked`, RabbitMQ never notifies us of resource alarms.
+/// `consumer_cancel_notify` and `basic.nack` are not advertised because nothing handles
+/// `basic.cancel` nor `basic.nack` from server.
+fn capabilities() -> HashMap<AmqpString, FieldArgument> {
+    let mut map = HashMap::new();
+    for name in &[
+        "publisher_confirms",
+        "connection.blocked",
+        "authentication_failure_close",
+        "exchange_exchange_bindings",
+    ] {
+        map.insert(AmqpString::from(*name), FieldArgument::Boolean(true));
+    }
+    map
+}
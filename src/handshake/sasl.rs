@@ -0,0 +1,164 @@
+//! SASL mechanisms used to authenticate in connection handshake.
+//!
+//! A `Handshaker` selects one of its mechanisms from the list server offers in `Start` method.
+//! The initial response is sent with `Start-Ok` method, and each `Secure` method
+//! (challenge) is answered with `Secure-Ok` method until server sends `Tune` method.
+
+use amqpr_codec::AmqpString;
+
+use errors::*;
+
+pub trait SaslMechanism {
+    /// Name of this mechanism such as "PLAIN".
+    fn name(&self) -> &str;
+
+    /// Response being sent with `Start-Ok` method.
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error>;
+
+    /// Response to a challenge being sent with `Secure` method.
+    /// Single step mechanisms do not need to implement it.
+    fn reply_to_challenge(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(ErrorKind::SaslFailure(format!(
+            "{} mechanism does not accept any challenge",
+            self.name()
+        )).into())
+    }
+}
+
+/// Select the first mechanism in `mechanisms` which is contained in `offered`.
+/// `offered` is space separated list of mechanism names, which is sent by server.
+pub(crate) fn select_mechanism(
+    mechanisms: &[Box<dyn SaslMechanism>],
+    offered: &str,
+) -> Result<usize, Error> {
+    mechanisms
+        .iter()
+        .position(|m| offered.split_whitespace().any(|o| o == m.name()))
+        .ok_or_else(|| ErrorKind::NoSupportedSaslMechanism(offered.into()).into())
+}
+
+/// `PLAIN` mechanism defined in RFC 4616.
+#[derive(Debug, Clone)]
+pub struct Plain {
+    pub user: String,
+    pub pass: String,
+}
+
+impl SaslMechanism for Plain {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(format!("\0{}\0{}", self.user, self.pass).into_bytes())
+    }
+}
+
+/// `AMQPLAIN` mechanism. Credentials are sent as AMQP field table without its size.
+///
+/// # Notice
+/// Lengths of credentials longer than 127 bytes may not be valid UTF-8, which `amqpr_codec`
+/// can not send. Handshake fails with `SaslFailure` error in that case.
+#[derive(Debug, Clone)]
+pub struct AmqPlain {
+    pub user: String,
+    pub pass: String,
+}
+
+impl SaslMechanism for AmqPlain {
+    fn name(&self) -> &str {
+        "AMQPLAIN"
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        encode_long_string_field(&mut buf, "LOGIN", &self.user);
+        encode_long_string_field(&mut buf, "PASSWORD", &self.pass);
+        Ok(buf)
+    }
+}
+
+fn encode_long_string_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(name.as_bytes());
+    buf.push(b'S');
+    let len = value.len() as u32;
+    buf.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// `EXTERNAL` mechanism defined in RFC 4422.
+/// Server authenticates client by other means such as TLS client certificate.
+#[derive(Debug, Clone, Default)]
+pub struct External {
+    /// Identity to act as. If it is empty, server derives it from the credentials.
+    pub authzid: String,
+}
+
+impl SaslMechanism for External {
+    fn name(&self) -> &str {
+        "EXTERNAL"
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(self.authzid.clone().into_bytes())
+    }
+}
+
+/// Raw bytes of a challenge sent by server.
+///
+/// `AmqpString` exposes its bytes only through `Deref<Target = str>`, which panics on non
+/// UTF-8 bytes. Its `Debug` prints them as an escaped byte string, so they are read back
+/// from it.
+pub(crate) fn challenge_bytes(challenge: &AmqpString) -> Result<Vec<u8>, Error> {
+    let debug = format!("{:?}", challenge);
+    let malformed = || -> Error {
+        ErrorKind::SaslFailure(format!("Can not read challenge {}", debug)).into()
+    };
+    let escaped = match debug
+        .strip_prefix("AmqpString(b\"")
+        .and_then(|s| s.strip_suffix("\")"))
+    {
+        Some(escaped) => escaped.as_bytes(),
+        None => return Err(malformed()),
+    };
+
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut iter = escaped.iter();
+    while let Some(&c) = iter.next() {
+        if c != b'\\' {
+            bytes.push(c);
+            continue;
+        }
+        let byte = match iter.next() {
+            Some(&b'n') => b'\n',
+            Some(&b'r') => b'\r',
+            Some(&b't') => b'\t',
+            Some(&b'0') => b'\0',
+            Some(&b'\\') => b'\\',
+            Some(&b'"') => b'"',
+            Some(&b'x') => {
+                let hi = *iter.next().ok_or_else(&malformed)?;
+                let lo = *iter.next().ok_or_else(&malformed)?;
+                let hex = [hi, lo];
+                let hex = ::std::str::from_utf8(&hex).map_err(|_| malformed())?;
+                u8::from_str_radix(hex, 16).map_err(|_| malformed())?
+            }
+            _ => return Err(malformed()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Wrap a response of a mechanism to send it.
+/// `amqpr_codec` encodes strings through `str`, so it fails unless `response` is UTF-8.
+pub(crate) fn response_string(mechanism: &str, response: Vec<u8>) -> Result<AmqpString, Error> {
+    match String::from_utf8(response) {
+        Ok(response) => Ok(AmqpString::from(response)),
+        Err(_) => Err(ErrorKind::SaslFailure(format!(
+            "Response of {} mechanism is not valid UTF-8, which can not be sent",
+            mechanism
+        )).into()),
+    }
+}
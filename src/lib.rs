@@ -3,6 +3,9 @@
 //! There is two kind of channel controllers; GlobalChannelController and LocalChannelController.
 //!
 
+// `error_chain!` in `errors` module needs deeper macro recursion.
+#![recursion_limit = "256"]
+
 extern crate bytes;
 #[macro_use]
 extern crate error_chain;
@@ -15,6 +18,21 @@ extern crate tokio_io;
 
 extern crate amqpr_codec;
 
+#[cfg(feature = "codec")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "bincode")]
+extern crate bincode;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "lz4")]
+extern crate lz4_flex;
+
 macro_rules! try_stream_ready {
     ($polled: expr) => {
         match $polled {
@@ -33,29 +51,51 @@ pub mod queue;
 pub mod basic;
 pub mod subscribe_stream;
 pub mod publish_sink;
+pub mod direct_reply_to;
+#[cfg(feature = "codec")]
+pub mod message_codec;
+pub mod compression;
+pub mod trace_context;
+pub mod recording;
+pub mod protocol_log;
+pub mod metrics;
+pub mod blocked;
+pub mod reconnect;
+pub mod connection;
+pub mod engine;
+pub mod rpc;
+#[cfg(feature = "test-util")]
+pub mod mock;
 
 pub mod handshake;
 pub mod errors;
 pub(crate) mod common;
 
 pub use handshake::start_handshake;
-pub use channel::open_channel;
+pub use channel::{channel_flow, open_channel};
 pub use exchange::declare_exchange;
 pub use queue::{bind_queue, declare_queue};
-pub use basic::{get_delivered, start_consume};
+pub use basic::{ack, get_delivered, recover, start_consume};
 pub use basic::publish::publish;
 pub use subscribe_stream::subscribe_stream;
-pub use publish_sink::publish_sink;
+pub use publish_sink::{publish_sink, publish_sink_without_flow};
+pub use direct_reply_to::direct_reply_to;
+pub use blocked::watch_blocked;
+pub use protocol_log::trace_frames;
+pub use connection::{Channel, Connection, ConnectionConfig};
 
 use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use tokio_core::net::TcpStream;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::Framed;
 use errors::Error;
-use amqpr_codec::Frame;
-
-type RawSocket = tokio_io::codec::Framed<tokio_core::net::TcpStream, amqpr_codec::Codec>;
+use amqpr_codec::{Codec, Frame};
 
-pub struct AmqpSocket(RawSocket);
+/// Socket which exchanges frames with AMQP server on `T`.
+/// `T` is `TcpStream` unless you give another transport to `start_handshake`.
+pub struct AmqpSocket<T = TcpStream>(Framed<T, Codec>);
 
-impl Stream for AmqpSocket {
+impl<T: AsyncRead + AsyncWrite> Stream for AmqpSocket<T> {
     type Item = Frame;
     type Error = Error;
 
@@ -64,7 +104,7 @@ impl Stream for AmqpSocket {
     }
 }
 
-impl Sink for AmqpSocket {
+impl<T: AsyncRead + AsyncWrite> Sink for AmqpSocket<T> {
     type SinkItem = Frame;
     type SinkError = Error;
 
@@ -0,0 +1,264 @@
+//! Typed messages encoded by serde.
+//!
+//! This module is available with `codec` feature. Each format is enabled by its own
+//! feature; `json`, `msgpack` and `bincode`.
+//!
+//! `typed_sink` wraps a sink of `PublishItem` such as `BroadcastSink`, and
+//! `typed_stream` wraps a stream of `DeliveredItem` such as `SubscribeStream`.
+//! `content_type` property is set when publishing, and checked when decoding.
+
+use bytes::Bytes;
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use amqpr_codec::AmqpString;
+use amqpr_codec::content_header::Properties;
+
+use std::marker::PhantomData;
+
+use basic::deliver::DeliveredItem;
+use basic::publish::{PublishItem, PublishOption};
+use errors::*;
+
+/// Format of message body.
+pub trait MessageCodec {
+    /// MIME type which is set to `content_type` property.
+    fn content_type(&self) -> &str;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Bytes, Error>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// `application/json`
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl MessageCodec for Json {
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Bytes, Error> {
+        ::serde_json::to_vec(value)
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::EncodeMessage(e.to_string()).into())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        ::serde_json::from_slice(bytes).map_err(|e| ErrorKind::DecodeMessage(e.to_string()).into())
+    }
+}
+
+/// `application/msgpack`. Structs are encoded as maps so that fields can be added later.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl MessageCodec for MessagePack {
+    fn content_type(&self) -> &str {
+        "application/msgpack"
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Bytes, Error> {
+        ::rmp_serde::to_vec_named(value)
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::EncodeMessage(e.to_string()).into())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        ::rmp_serde::from_slice(bytes).map_err(|e| ErrorKind::DecodeMessage(e.to_string()).into())
+    }
+}
+
+/// `application/x-bincode`. Both sides must use the same type definition.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl MessageCodec for Bincode {
+    fn content_type(&self) -> &str {
+        "application/x-bincode"
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Bytes, Error> {
+        ::bincode::serialize(value)
+            .map(Bytes::from)
+            .map_err(|e| ErrorKind::EncodeMessage(e.to_string()).into())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        ::bincode::deserialize(bytes).map_err(|e| ErrorKind::DecodeMessage(e.to_string()).into())
+    }
+}
+
+/// Encode `value` into `PublishItem` having `content_type` of `codec`.
+pub fn encode_item<C, T>(codec: &C, meta: PublishOption, mut header: Properties, value: &T) -> Result<PublishItem, Error>
+where
+    C: MessageCodec,
+    T: Serialize,
+{
+    header.content_type = Some(AmqpString::from(codec.content_type().to_string()));
+    Ok(PublishItem {
+        meta,
+        header,
+        body: codec.encode(value)?,
+    })
+}
+
+/// Decode body of `item`.
+/// Fails with `UnexpectedContentType` error if `item` has another `content_type`.
+/// An item without `content_type` is decoded anyway.
+pub fn decode_item<C, T>(codec: &C, item: &DeliveredItem) -> Result<T, Error>
+where
+    C: MessageCodec,
+    T: DeserializeOwned,
+{
+    match item.content_type() {
+        Some(content_type) if content_type != codec.content_type() => {
+            return Err(ErrorKind::UnexpectedContentType(
+                codec.content_type().into(),
+                content_type.into(),
+            ).into())
+        }
+        _ => {}
+    }
+    codec.decode(&item.body.bytes)
+}
+
+/// Returns `TypedSink` which is `Sink` of `T`.
+/// Every item is published with `option`.
+pub fn typed_sink<S, C, T>(sink: S, codec: C, option: PublishOption) -> TypedSink<S, C, T>
+where
+    S: Sink<SinkItem = PublishItem>,
+    S::SinkError: From<Error>,
+    C: MessageCodec,
+    T: Serialize,
+{
+    TypedSink {
+        sink,
+        codec,
+        option,
+        properties: Properties::new(),
+        _value: PhantomData,
+    }
+}
+
+pub struct TypedSink<S, C, T> {
+    sink: S,
+    codec: C,
+    option: PublishOption,
+    properties: Properties,
+    _value: PhantomData<fn(T)>,
+}
+
+impl<S, C, T> TypedSink<S, C, T> {
+    /// Set properties of every item. `content_type` is overwritten by the codec.
+    pub fn properties(mut self, properties: Properties) -> TypedSink<S, C, T> {
+        self.properties = properties;
+        self
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S, C, T> Sink for TypedSink<S, C, T>
+where
+    S: Sink<SinkItem = PublishItem>,
+    S::SinkError: From<Error>,
+    C: MessageCodec,
+    T: Serialize,
+{
+    type SinkItem = T;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, value: T) -> StartSend<T, S::SinkError> {
+        let item = encode_item(&self.codec, self.option.clone(), self.properties.clone(), &value)?;
+        match self.sink.start_send(item)? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(value)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.close()
+    }
+}
+
+/// Returns `TypedStream` which is `Stream` of `Decoded<T>`.
+/// A message which fails to be decoded does not finish the stream.
+pub fn typed_stream<St, C, T>(stream: St, codec: C) -> TypedStream<St, C, T>
+where
+    St: Stream<Item = DeliveredItem>,
+    C: MessageCodec,
+    T: DeserializeOwned,
+{
+    TypedStream {
+        stream,
+        codec,
+        _value: PhantomData,
+    }
+}
+
+pub struct TypedStream<St, C, T> {
+    stream: St,
+    codec: C,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<St, C, T> TypedStream<St, C, T> {
+    pub fn get_ref(&self) -> &St {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> St {
+        self.stream
+    }
+}
+
+/// A delivered item and its decoded body.
+/// You still have `item` to acknowledge or reject it when decoding fails.
+#[derive(Debug)]
+pub struct Decoded<T> {
+    pub item: DeliveredItem,
+    pub value: Result<T, Error>,
+}
+
+impl<St, C, T> Stream for TypedStream<St, C, T>
+where
+    St: Stream<Item = DeliveredItem>,
+    C: MessageCodec,
+    T: DeserializeOwned,
+{
+    type Item = Decoded<T>;
+    type Error = St::Error;
+
+    fn poll(&mut self) -> Poll<Option<Decoded<T>>, St::Error> {
+        let item = match try_ready!(self.stream.poll()) {
+            Some(item) => item,
+            None => return Ok(Async::Ready(None)),
+        };
+        let value = decode_item(&self.codec, &item);
+        if let Err(ref e) = value {
+            warn!("Fail to decode delivered item {} : {}", item.meta.delivery_tag, e);
+        }
+        Ok(Async::Ready(Some(Decoded { item, value })))
+    }
+}
@@ -0,0 +1,241 @@
+//! Client side metrics.
+//!
+//! `Observer` is called on events such as frames sent and received, bytes on the wire,
+//! deliveries, acks and reconnects. Every method has an empty default implementation, so
+//! that you only implement what you need.
+//!
+//! - `Connection` calls it after `Connection::set_observer`, or from the start if
+//!   `ConnectionConfig::observer` is set. Only the latter counts bytes.
+//! - `ReconnectingSocket` reports reconnections after `ReconnectingSocket::set_observer`.
+//! - With the functions in other modules, wrap the transport given to `start_handshake`
+//!   with `observe_io` and the socket with `observe_frames`.
+//!
+//! `PrometheusObserver` in `prometheus` feature exports them in Prometheus text format.
+
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::{serve, PrometheusObserver};
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::BasicClass;
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use protocol_log::method_name;
+
+/// Receiver of client side events.
+/// `kind` is a method name such as `basic.deliver`, `content-header`, `content-body` or
+/// `heartbeat`.
+pub trait Observer: Send + Sync {
+    fn frame_sent(&self, _channel_id: u16, _kind: &'static str) {}
+
+    fn frame_received(&self, _channel_id: u16, _kind: &'static str) {}
+
+    fn bytes_sent(&self, _bytes: usize) {}
+
+    fn bytes_received(&self, _bytes: usize) {}
+
+    /// A message published through `Channel::publish` is written to the socket.
+    /// `latency` includes time being queued in `Connection`.
+    ///
+    /// # Notice
+    /// Publisher confirms are not supported, so this is not a latency to `basic.ack`.
+    fn published(&self, _channel_id: u16, _latency: Duration) {}
+
+    /// `basic.deliver` or `basic.get-ok` is received.
+    fn delivered(&self, _channel_id: u16) {}
+
+    /// `basic.ack` is sent.
+    fn acked(&self, _channel_id: u16) {}
+
+    /// `basic.reject` or `basic.nack` is sent.
+    fn nacked(&self, _channel_id: u16) {}
+
+    /// Server sent nothing for another negotiated heartbeat interval.
+    fn heartbeat_missed(&self) {}
+
+    /// Connection is established again and its topology is recovered.
+    fn reconnected(&self) {}
+}
+
+/// `kind` of `frame` passed to `Observer`.
+pub fn frame_kind(frame: &Frame) -> &'static str {
+    match frame.payload {
+        FramePayload::Method(ref method) => method_name(method),
+        FramePayload::ContentHeader(_) => "content-header",
+        FramePayload::ContentBody(_) => "content-body",
+        FramePayload::Heartbeat => "heartbeat",
+    }
+}
+
+/// What is reported when a frame is sent. It is taken before the frame is moved into a
+/// socket, because the socket may refuse it.
+pub(crate) struct Sent {
+    channel_id: u16,
+    kind: &'static str,
+    settle: Option<Settle>,
+}
+
+enum Settle {
+    Ack,
+    Nack,
+}
+
+impl Sent {
+    pub(crate) fn of(frame: &Frame) -> Sent {
+        let settle = match frame.method() {
+            Some(&MethodPayload::Basic(BasicClass::Ack(_))) => Some(Settle::Ack),
+            Some(&MethodPayload::Basic(BasicClass::Reject(_)))
+            | Some(&MethodPayload::Basic(BasicClass::Nack(_))) => Some(Settle::Nack),
+            _ => None,
+        };
+        Sent {
+            channel_id: frame.header.channel,
+            kind: frame_kind(frame),
+            settle,
+        }
+    }
+
+    pub(crate) fn report(&self, observer: &dyn Observer) {
+        observer.frame_sent(self.channel_id, self.kind);
+        match self.settle {
+            Some(Settle::Ack) => observer.acked(self.channel_id),
+            Some(Settle::Nack) => observer.nacked(self.channel_id),
+            None => {}
+        }
+    }
+}
+
+pub(crate) fn observe_received(observer: &dyn Observer, frame: &Frame) {
+    let channel_id = frame.header.channel;
+    observer.frame_received(channel_id, frame_kind(frame));
+    match frame.method() {
+        Some(&MethodPayload::Basic(BasicClass::Deliver(_)))
+        | Some(&MethodPayload::Basic(BasicClass::GetOk(_))) => observer.delivered(channel_id),
+        _ => {}
+    }
+}
+
+/// Wrap given socket and report frames going through it to `observer`.
+pub fn observe_frames<S>(socket: S, observer: Arc<dyn Observer>) -> ObservedFrames<S> {
+    ObservedFrames { socket, observer }
+}
+
+/// Socket returned by `observe_frames` function.
+pub struct ObservedFrames<S> {
+    socket: S,
+    observer: Arc<dyn Observer>,
+}
+
+impl<S> ObservedFrames<S> {
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+impl<S> Stream for ObservedFrames<S>
+where
+    S: Stream<Item = Frame>,
+{
+    type Item = Frame;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, S::Error> {
+        let frame = try_ready!(self.socket.poll());
+        if let Some(ref frame) = frame {
+            observe_received(&*self.observer, frame);
+        }
+        Ok(Async::Ready(frame))
+    }
+}
+
+impl<S> Sink for ObservedFrames<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    type SinkItem = Frame;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Frame) -> StartSend<Frame, S::SinkError> {
+        let sent = Sent::of(&item);
+        let result = self.socket.start_send(item)?;
+        if let AsyncSink::Ready = result {
+            sent.report(&*self.observer);
+        }
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.close()
+    }
+}
+
+/// Wrap given transport and report bytes read and written to `observer`.
+pub fn observe_io<T>(io: T, observer: Arc<dyn Observer>) -> ObservedIo<T> {
+    ObservedIo {
+        io,
+        observer: Some(observer),
+    }
+}
+
+/// Transport returned by `observe_io` function.
+pub struct ObservedIo<T> {
+    io: T,
+    observer: Option<Arc<dyn Observer>>,
+}
+
+impl<T> ObservedIo<T> {
+    /// Transport which reports nothing.
+    pub(crate) fn unobserved(io: T) -> ObservedIo<T> {
+        ObservedIo { io, observer: None }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+}
+
+impl<T: Read> Read for ObservedIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        if let Some(ref observer) = self.observer {
+            observer.bytes_received(n);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for ObservedIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        if let Some(ref observer) = self.observer {
+            observer.bytes_sent(n);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for ObservedIo<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for ObservedIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
@@ -0,0 +1,179 @@
+//! `Observer` which exports metrics in Prometheus text exposition format.
+
+use futures::{Future, Stream};
+
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use tokio_io::io::{read, write_all};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use metrics::Observer;
+use errors::*;
+
+/// Upper bounds of `amqpr_publish_latency_seconds` histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// `Observer` which keeps counters and renders them for Prometheus.
+///
+/// Frames are counted by `kind` label. Channel ids are not used as labels because
+/// they are reused and would make too many series.
+#[derive(Default)]
+pub struct PrometheusObserver {
+    frames_sent: Mutex<BTreeMap<&'static str, u64>>,
+    frames_received: Mutex<BTreeMap<&'static str, u64>>,
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    deliveries: AtomicUsize,
+    acks: AtomicUsize,
+    nacks: AtomicUsize,
+    heartbeats_missed: AtomicUsize,
+    reconnects: AtomicUsize,
+    latency: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; 10],
+    count: u64,
+    sum: f64,
+}
+
+impl PrometheusObserver {
+    pub fn new() -> PrometheusObserver {
+        PrometheusObserver::default()
+    }
+
+    /// Metrics in Prometheus text exposition format version 0.0.4.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_labeled(&mut out, "amqpr_frames_sent_total", "Frames sent by kind.", &self.frames_sent);
+        render_labeled(
+            &mut out,
+            "amqpr_frames_received_total",
+            "Frames received by kind.",
+            &self.frames_received,
+        );
+        let counters = [
+            ("amqpr_bytes_sent_total", "Bytes written to the socket.", &self.bytes_sent),
+            ("amqpr_bytes_received_total", "Bytes read from the socket.", &self.bytes_received),
+            ("amqpr_deliveries_total", "Messages delivered to the client.", &self.deliveries),
+            ("amqpr_acks_total", "basic.ack sent.", &self.acks),
+            ("amqpr_nacks_total", "basic.reject and basic.nack sent.", &self.nacks),
+            ("amqpr_heartbeats_missed_total", "Heartbeat intervals server was silent.", &self.heartbeats_missed),
+            ("amqpr_reconnects_total", "Reconnections.", &self.reconnects),
+        ];
+        for &(name, help, value) in &counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        let name = "amqpr_publish_latency_seconds";
+        let latency = self.latency.lock().unwrap();
+        let _ = writeln!(
+            out,
+            "# HELP {} Time from Channel::publish until written to the socket.\n# TYPE {} histogram",
+            name, name
+        );
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, latency.count);
+        let _ = writeln!(out, "{}_sum {}", name, latency.sum);
+        let _ = writeln!(out, "{}_count {}", name, latency.count);
+        out
+    }
+}
+
+fn render_labeled(out: &mut String, name: &str, help: &str, values: &Mutex<BTreeMap<&'static str, u64>>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (kind, count) in values.lock().unwrap().iter() {
+        let _ = writeln!(out, "{}{{kind=\"{}\"}} {}", name, kind, count);
+    }
+}
+
+fn increment(values: &Mutex<BTreeMap<&'static str, u64>>, kind: &'static str) {
+    *values.lock().unwrap().entry(kind).or_insert(0) += 1;
+}
+
+impl Observer for PrometheusObserver {
+    fn frame_sent(&self, _channel_id: u16, kind: &'static str) {
+        increment(&self.frames_sent, kind);
+    }
+
+    fn frame_received(&self, _channel_id: u16, kind: &'static str) {
+        increment(&self.frames_received, kind);
+    }
+
+    fn bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn bytes_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn published(&self, _channel_id: u16, latency: Duration) {
+        let secs = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+        let mut histogram = self.latency.lock().unwrap();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    fn delivered(&self, _channel_id: u16) {
+        self.deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn acked(&self, _channel_id: u16) {
+        self.acks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn nacked(&self, _channel_id: u16) {
+        self.nacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn heartbeat_missed(&self) {
+        self.heartbeats_missed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Serve `observer.render()` over HTTP at `addr`, whatever path is requested.
+/// Returned future never completes unless the listener fails.
+pub fn serve(
+    observer: Arc<PrometheusObserver>,
+    addr: &SocketAddr,
+    handle: &Handle,
+) -> Result<Box<dyn Future<Item = (), Error = Error>>, Error> {
+    let listener = TcpListener::bind(addr, handle)?;
+    let handle = handle.clone();
+    let future = listener.incoming().map_err(Error::from).for_each(move |(socket, _)| {
+        let observer = observer.clone();
+        // Read a request just to be polite. Its contents do not matter.
+        let response = read(socket, vec![0; 1024]).and_then(move |(socket, _, _)| {
+            let body = observer.render();
+            let response = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            write_all(socket, response.into_bytes())
+        });
+        handle.spawn(response.map(|_| ()).map_err(|e| warn!("Fail to serve metrics : {}", e)));
+        Ok(())
+    });
+    Ok(Box::new(future))
+}
@@ -0,0 +1,273 @@
+//! In-memory mock broker and scripted socket for tests.
+//!
+//! This module is available with `test-util` feature. `MockBroker` performs the server
+//! side of handshake with `FrameHandshaking` future and returns `MockSocket`, which you can
+//! pass to every function in this crate instead of `AmqpSocket`, or to
+//! `Connection::from_socket`.
+//!
+//! ```no_run
+//! extern crate amqpr_api;
+//! extern crate tokio_core;
+//!
+//! use amqpr_api::mock::MockBroker;
+//! use amqpr_api::handshake::SimpleHandshaker;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let broker = MockBroker::new();
+//! let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+//! let conn = broker.connection(handshaker, &core.handle()).unwrap();
+//! # }
+//! ```
+//!
+//! Supported features are
+//!
+//! - `direct`, `fanout` and `topic` exchanges, including the default exchange.
+//! - Queues, bindings, `Qos` (`prefetch_count` only), `Consume`, `Cancel`, `Get`, `Ack`,
+//!   `Reject`, `Nack` and `Recover`.
+//! - Channel exceptions such as 404 NOT_FOUND.
+//!
+//! `Script` is a lower level test double. It checks every frame client sends against
+//! expected ones, and yields frames in the order you write.
+//!
+//! Frames never go through bytes. `amqpr_codec` does not encode methods which only server
+//! sends, and fails to decode any method from bytes. So `MockBroker` can not listen on a
+//! local port, and the protocol header exchange in `start_handshake` is not covered.
+//!
+//! Publisher confirms are not supported, because `amqpr_codec` does not have `Confirm`
+//! class.
+
+mod state;
+mod script;
+
+pub use self::state::Message;
+pub use self::script::{Script, ScriptedSocket};
+
+use amqpr_codec::{AmqpString, FieldArgument, Frame};
+use amqpr_codec::method::connection::StartMethod;
+use amqpr_codec::content_header::Properties;
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::task;
+
+use tokio_core::reactor::Handle;
+
+use bytes::Bytes;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use connection::Connection;
+use handshake::{start_frame_handshake, ConnectionInfo, Handshaker};
+use errors::*;
+use self::state::State;
+
+/// Broker running in memory. Cloned `MockBroker` shares exchanges and queues.
+#[derive(Clone)]
+pub struct MockBroker {
+    state: Rc<RefCell<State>>,
+    user: String,
+    pass: String,
+    virtual_host: String,
+    channel_max: u16,
+}
+
+impl MockBroker {
+    /// Returns `MockBroker` which accepts "guest" user on "/" virtual host.
+    pub fn new() -> MockBroker {
+        MockBroker {
+            state: Rc::new(RefCell::new(State::new())),
+            user: "guest".into(),
+            pass: "guest".into(),
+            virtual_host: "/".into(),
+            channel_max: 2047,
+        }
+    }
+
+    pub fn user<U, P>(mut self, user: U, pass: P) -> MockBroker
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.user = user.into();
+        self.pass = pass.into();
+        self
+    }
+
+    pub fn virtual_host<V: Into<String>>(mut self, virtual_host: V) -> MockBroker {
+        self.virtual_host = virtual_host.into();
+        self
+    }
+
+    /// `channel_max` sent by `Tune` method. 0 means no limit.
+    pub fn channel_max(mut self, channel_max: u16) -> MockBroker {
+        self.channel_max = channel_max;
+        self
+    }
+
+    /// Connect without handshake. Run `handshake::start_frame_handshake` on returned socket.
+    /// Only `PLAIN` mechanism is offered.
+    pub fn socket(&self) -> MockSocket {
+        let login = state::Login {
+            user: self.user.clone(),
+            pass: self.pass.clone(),
+            virtual_host: self.virtual_host.clone(),
+            channel_max: self.channel_max,
+        };
+        let mut state = self.state.borrow_mut();
+        let conn = state.next_connection_id;
+        state.next_connection_id += 1;
+        state.connections.insert(conn, state::Connection::new(login, self.start_method()));
+        MockSocket {
+            conn,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Perform handshake with `handshaker` by running `FrameHandshaking` future on `socket()`
+    /// to completion. It never blocks because `MockSocket` replies as soon as a frame is sent.
+    ///
+    /// # Error
+    /// Returns `AuthenticationFailure` error if user or password is wrong, and
+    /// `VirtualHostAccessRefused` error if virtual host is wrong.
+    pub fn handshake<H: Handshaker>(&self, handshaker: H) -> Result<(ConnectionInfo, MockSocket), Error> {
+        start_frame_handshake(handshaker, self.socket()).wait()
+    }
+
+    /// Perform handshake and make `Connection` on `MockSocket`.
+    pub fn connection<H: Handshaker>(&self, handshaker: H, handle: &Handle) -> Result<Connection, Error> {
+        let (info, socket) = self.handshake(handshaker)?;
+        Ok(Connection::from_socket(socket, info, handle))
+    }
+
+    /// Frames every client sent, in order. Handshake methods are included.
+    pub fn received_frames(&self) -> Vec<Frame> {
+        self.state.borrow().received.clone()
+    }
+
+    /// Publish a message as if another client published it.
+    /// Returns false if no queue receives it.
+    pub fn publish<E, R>(&self, exchange: E, routing_key: R, properties: Properties, body: Bytes) -> bool
+    where
+        E: Into<String>,
+        R: Into<String>,
+    {
+        let message = Message {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            properties,
+            body,
+            redelivered: false,
+        };
+        self.state.borrow_mut().route(message)
+    }
+
+    pub fn has_exchange(&self, name: &str) -> bool {
+        self.state.borrow().has_exchange(name)
+    }
+
+    pub fn has_queue(&self, name: &str) -> bool {
+        self.state.borrow().queue_messages(name).is_some()
+    }
+
+    /// Messages which are ready to be delivered in the queue.
+    /// Unacknowledged messages are not included.
+    pub fn messages(&self, queue: &str) -> Vec<Message> {
+        self.state.borrow().queue_messages(queue).unwrap_or_default()
+    }
+
+    /// Number of messages delivered but not acknowledged yet on every channel.
+    pub fn unacked_count(&self) -> usize {
+        self.state.borrow().unacked_count()
+    }
+
+    fn start_method(&self) -> StartMethod {
+        let mut capabilities = HashMap::new();
+        for cap in &["basic.nack", "consumer_cancel_notify", "connection.blocked"] {
+            capabilities.insert(AmqpString::from(*cap), FieldArgument::Boolean(true));
+        }
+        let mut server_properties = HashMap::new();
+        server_properties.insert(
+            AmqpString::from("product"),
+            FieldArgument::LongString(AmqpString::from("amqpr-mock")),
+        );
+        server_properties.insert(
+            AmqpString::from("capabilities"),
+            FieldArgument::NestedTable(capabilities),
+        );
+        StartMethod {
+            version_major: 0,
+            version_minor: 9,
+            server_properties,
+            mechanisms: "PLAIN".into(),
+            locales: "en_US".into(),
+        }
+    }
+}
+
+impl Default for MockBroker {
+    fn default() -> MockBroker {
+        MockBroker::new()
+    }
+}
+
+/// Client side of a connection to `MockBroker`.
+///
+/// Every frame is processed as soon as it is sent. Dropping `MockSocket` disconnects
+/// from the broker; unacknowledged messages are requeued and exclusive queues are deleted.
+pub struct MockSocket {
+    conn: usize,
+    state: Rc<RefCell<State>>,
+}
+
+impl Stream for MockSocket {
+    type Item = Frame;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, Error> {
+        let mut state = self.state.borrow_mut();
+        let connection = match state.connections.get_mut(&self.conn) {
+            Some(connection) => connection,
+            None => return Ok(Async::Ready(None)),
+        };
+        match connection.outgoing.pop_front() {
+            Some(frame) => Ok(Async::Ready(Some(frame))),
+            None if connection.is_closed => Ok(Async::Ready(None)),
+            None => {
+                connection.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl Sink for MockSocket {
+    type SinkItem = Frame;
+    type SinkError = Error;
+
+    fn start_send(&mut self, frame: Frame) -> StartSend<Frame, Error> {
+        let mut state = self.state.borrow_mut();
+        let is_closed = match state.connections.get(&self.conn) {
+            Some(c) => c.is_closed,
+            None => true,
+        };
+        if is_closed {
+            return Err(ErrorKind::ConnectionClosed.into());
+        }
+        state.received.push(frame.clone());
+        state.handle(self.conn, frame);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for MockSocket {
+    fn drop(&mut self) {
+        self.state.borrow_mut().disconnect(self.conn);
+    }
+}
@@ -0,0 +1,226 @@
+//! Scripted socket which checks frames client sends against expected ones.
+
+use amqpr_codec::Frame;
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::task::{self, Task};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use errors::*;
+
+/// Expected conversation between client and server.
+///
+/// `expect` steps are frames client must send, and `reply` steps are frames `ScriptedSocket`
+/// yields. A `reply` step is yielded only after every `expect` step before it is satisfied,
+/// but client may send a frame before reading `reply` frames ahead of it, as it can on a
+/// real socket.
+///
+/// ```no_run
+/// extern crate amqpr_api;
+/// extern crate amqpr_codec;
+///
+/// use amqpr_api::mock::Script;
+/// use amqpr_codec::Frame;
+/// use amqpr_codec::method::MethodPayload;
+/// use amqpr_codec::method::channel::{ChannelClass, OpenMethod, OpenOkMethod};
+///
+/// # fn main() {
+/// let open = OpenMethod { reserved1: "".into() };
+/// let open_ok = OpenOkMethod { reserved1: "".into() };
+/// let script = Script::new()
+///     .expect(Frame::new_method(1, MethodPayload::Channel(ChannelClass::Open(open))))
+///     .reply(Frame::new_method(1, MethodPayload::Channel(ChannelClass::OpenOk(open_ok))));
+///
+/// let socket = script.socket();
+/// // Run code under test on `socket`, then
+/// script.assert_done();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Script {
+    shared: Rc<RefCell<Shared>>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Expect(Frame),
+    Reply(Frame),
+}
+
+struct Shared {
+    steps: VecDeque<Step>,
+    /// Error which is already returned to client.
+    mismatch: Option<String>,
+    task: Option<Task>,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script {
+            shared: Rc::new(RefCell::new(Shared {
+                steps: VecDeque::new(),
+                mismatch: None,
+                task: None,
+            })),
+        }
+    }
+
+    /// Client must send `frame` next.
+    pub fn expect(self, frame: Frame) -> Script {
+        self.shared.borrow_mut().steps.push_back(Step::Expect(frame));
+        self
+    }
+
+    /// Yield `frame` to client.
+    pub fn reply(self, frame: Frame) -> Script {
+        self.shared.borrow_mut().steps.push_back(Step::Reply(frame));
+        self
+    }
+
+    /// Returns `ScriptedSocket` running this script.
+    /// Every socket returned by this method shares the same steps.
+    pub fn socket(&self) -> ScriptedSocket {
+        ScriptedSocket {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns true if every step is done.
+    pub fn is_done(&self) -> bool {
+        self.shared.borrow().steps.is_empty()
+    }
+
+    /// Panics if client sent an unexpected frame or any step is left.
+    pub fn assert_done(&self) {
+        let shared = self.shared.borrow();
+        if let Some(ref mismatch) = shared.mismatch {
+            panic!("{}", mismatch);
+        }
+        if !shared.steps.is_empty() {
+            let steps: Vec<String> = shared.steps.iter().map(|s| format!("{:?}", s)).collect();
+            panic!("Script has {} steps left :\n{}", steps.len(), steps.join("\n"));
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Script {
+        Script::new()
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("steps", &self.shared.borrow().steps)
+            .finish()
+    }
+}
+
+/// `Stream + Sink` of `Frame` which follows `Script`.
+///
+/// It finishes when every step is done. `start_send` fails with `ScriptMismatch` error
+/// which has a diff between expected and sent frames.
+pub struct ScriptedSocket {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Stream for ScriptedSocket {
+    type Item = Frame;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, Error> {
+        let mut shared = self.shared.borrow_mut();
+        if let Some(ref mismatch) = shared.mismatch {
+            return Err(ErrorKind::ScriptMismatch(mismatch.clone()).into());
+        }
+        match shared.steps.pop_front() {
+            Some(Step::Reply(frame)) => Ok(Async::Ready(Some(frame))),
+            Some(expect) => {
+                shared.steps.push_front(expect);
+                shared.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl Sink for ScriptedSocket {
+    type SinkItem = Frame;
+    type SinkError = Error;
+
+    fn start_send(&mut self, frame: Frame) -> StartSend<Frame, Error> {
+        let mut shared = self.shared.borrow_mut();
+        if let Some(ref mismatch) = shared.mismatch {
+            return Err(ErrorKind::ScriptMismatch(mismatch.clone()).into());
+        }
+        let expected = shared.steps.iter().enumerate().find_map(|(idx, s)| match *s {
+            Step::Expect(ref expected) => Some((idx, expected.clone())),
+            Step::Reply(_) => None,
+        });
+        let mismatch = match expected {
+            Some((idx, ref expected)) if *expected == frame => {
+                shared.steps.remove(idx);
+                if let Some(task) = shared.task.take() {
+                    task.notify();
+                }
+                return Ok(AsyncSink::Ready);
+            }
+            Some((_, ref expected)) => {
+                format!("Client sent an unexpected frame (- expected, + sent) :\n{}", diff(expected, &frame))
+            }
+            None => format!("Client sent a frame after script ended :\n{:#?}", frame),
+        };
+        shared.mismatch = Some(mismatch.clone());
+        if let Some(task) = shared.task.take() {
+            task.notify();
+        }
+        Err(ErrorKind::ScriptMismatch(mismatch).into())
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Line diff of pretty printed frames.
+fn diff(expected: &Frame, sent: &Frame) -> String {
+    let expected = format!("{:#?}", expected);
+    let sent = format!("{:#?}", sent);
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = sent.lines().collect();
+
+    // Longest common subsequence table from the tail.
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                ::std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
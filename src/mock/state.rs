@@ -0,0 +1,1029 @@
+//! Server side of AMQP methods, which `MockBroker` applies to every frame clients send.
+
+use amqpr_codec::{AmqpString, Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{self, BasicClass};
+use amqpr_codec::method::channel::{self, ChannelClass};
+use amqpr_codec::method::connection::{self, ConnectionClass};
+use amqpr_codec::method::exchange::ExchangeClass;
+use amqpr_codec::method::queue::{self, QueueClass};
+
+use futures::task::Task;
+
+use bytes::{Bytes, BytesMut};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+pub const FRAME_MAX: u32 = 131_072;
+
+// Reply codes of `Close` method.
+const NO_ROUTE: u16 = 312;
+const ACCESS_REFUSED: u16 = 403;
+const NOT_FOUND: u16 = 404;
+const RESOURCE_LOCKED: u16 = 405;
+const PRECONDITION_FAILED: u16 = 406;
+const NOT_ALLOWED: u16 = 530;
+const COMMAND_INVALID: u16 = 503;
+const CHANNEL_ERROR: u16 = 504;
+const NOT_IMPLEMENTED: u16 = 540;
+
+/// A message stored in a queue.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub exchange: String,
+    pub routing_key: String,
+    pub properties: Properties,
+    pub body: Bytes,
+    pub redelivered: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExchangeType {
+    Direct,
+    Fanout,
+    Topic,
+}
+
+impl ExchangeType {
+    fn parse(typ: &str) -> Option<ExchangeType> {
+        match typ {
+            "direct" => Some(ExchangeType::Direct),
+            "fanout" => Some(ExchangeType::Fanout),
+            "topic" => Some(ExchangeType::Topic),
+            _ => None,
+        }
+    }
+}
+
+struct Exchange {
+    typ: ExchangeType,
+    /// Pairs of queue name and routing key.
+    bindings: Vec<(String, String)>,
+}
+
+impl Exchange {
+    fn new(typ: ExchangeType) -> Exchange {
+        Exchange {
+            typ,
+            bindings: Vec::new(),
+        }
+    }
+
+    fn route(&self, routing_key: &str) -> Vec<String> {
+        let mut queues: Vec<String> = self.bindings
+            .iter()
+            .filter(|(_, key)| match self.typ {
+                ExchangeType::Direct => key == routing_key,
+                ExchangeType::Fanout => true,
+                ExchangeType::Topic => topic_matches(key, routing_key),
+            })
+            .map(|(queue, _)| queue.clone())
+            .collect();
+        queues.dedup();
+        queues
+    }
+}
+
+/// Returns true if `routing_key` matches `pattern` having `*` (one word) and `#` (zero or
+/// more words).
+fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+    fn matches(pattern: &[&str], words: &[&str]) -> bool {
+        match pattern.split_first() {
+            None => words.is_empty(),
+            Some((&"#", rest)) => (0..words.len() + 1).any(|skip| matches(rest, &words[skip..])),
+            Some((&"*", rest)) => !words.is_empty() && matches(rest, &words[1..]),
+            Some((word, rest)) => words.first() == Some(word) && matches(rest, &words[1..]),
+        }
+    }
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let words: Vec<&str> = routing_key.split('.').collect();
+    matches(&pattern, &words)
+}
+
+struct Queue {
+    messages: VecDeque<Message>,
+    consumers: Vec<ConsumerRef>,
+    /// Connection which declared this queue as exclusive.
+    owner: Option<usize>,
+    is_auto_delete: bool,
+    /// Auto-delete queue is deleted when its last consumer is cancelled.
+    had_consumer: bool,
+}
+
+#[derive(Clone)]
+struct ConsumerRef {
+    conn: usize,
+    channel: u16,
+    tag: AmqpString,
+    no_ack: bool,
+}
+
+struct Unacked {
+    queue: String,
+    message: Message,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    next_delivery_tag: u64,
+    unacked: BTreeMap<u64, Unacked>,
+    prefetch_count: u16,
+    consumer_tags: Vec<AmqpString>,
+    publishing: Option<Publishing>,
+    /// Frames are ignored until client replies `Close-Ok` to our `Close`.
+    is_closing: bool,
+}
+
+/// Content which is being received.
+struct Publishing {
+    method: basic::PublishMethod,
+    header: Option<ContentHeaderPayload>,
+    body: BytesMut,
+}
+
+/// What `MockBroker` accepts in handshake.
+pub struct Login {
+    pub user: String,
+    pub pass: String,
+    pub virtual_host: String,
+    pub channel_max: u16,
+}
+
+pub struct Connection {
+    pub outgoing: VecDeque<Frame>,
+    pub task: Option<Task>,
+    /// True after `Close` and `Close-Ok` are exchanged. No frame is accepted any more.
+    pub is_closed: bool,
+    /// Frames are ignored until client replies `Close-Ok` to our `Close`.
+    is_closing: bool,
+    /// `Some` until `Open` method is accepted.
+    login: Option<Login>,
+    channels: HashMap<u16, ChannelState>,
+}
+
+impl Connection {
+    /// `start` is sent as soon as client connects.
+    pub fn new(login: Login, start: connection::StartMethod) -> Connection {
+        let start = Frame::new_method(0, MethodPayload::Connection(ConnectionClass::Start(start)));
+        Connection {
+            outgoing: vec![start].into_iter().collect(),
+            task: None,
+            is_closed: false,
+            is_closing: false,
+            login: Some(login),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+/// Everything on the broker.
+pub struct State {
+    pub connections: HashMap<usize, Connection>,
+    pub next_connection_id: usize,
+    /// Frames clients sent, in order.
+    pub received: Vec<Frame>,
+    exchanges: HashMap<String, Exchange>,
+    queues: HashMap<String, Queue>,
+    next_queue_id: u64,
+    next_consumer_id: u64,
+}
+
+impl State {
+    pub fn new() -> State {
+        let mut exchanges = HashMap::new();
+        exchanges.insert("amq.direct".to_string(), Exchange::new(ExchangeType::Direct));
+        exchanges.insert("amq.fanout".to_string(), Exchange::new(ExchangeType::Fanout));
+        exchanges.insert("amq.topic".to_string(), Exchange::new(ExchangeType::Topic));
+
+        State {
+            connections: HashMap::new(),
+            next_connection_id: 0,
+            received: Vec::new(),
+            exchanges,
+            queues: HashMap::new(),
+            next_queue_id: 0,
+            next_consumer_id: 0,
+        }
+    }
+
+    pub fn has_exchange(&self, name: &str) -> bool {
+        name.is_empty() || self.exchanges.contains_key(name)
+    }
+
+    pub fn queue_messages(&self, name: &str) -> Option<Vec<Message>> {
+        self.queues
+            .get(name)
+            .map(|queue| queue.messages.iter().cloned().collect())
+    }
+
+    pub fn unacked_count(&self) -> usize {
+        self.connections
+            .values()
+            .flat_map(|conn| conn.channels.values())
+            .map(|ch| ch.unacked.len())
+            .sum()
+    }
+
+    fn send(&mut self, conn: usize, frame: Frame) {
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            connection.outgoing.push_back(frame);
+            if let Some(task) = connection.task.take() {
+                task.notify();
+            }
+        }
+    }
+
+    fn send_method(&mut self, conn: usize, channel: u16, method: MethodPayload) {
+        self.send(conn, Frame::new_method(channel, method));
+    }
+
+    fn send_content(&mut self, conn: usize, channel: u16, message: &Message) {
+        let header = ContentHeaderPayload {
+            class_id: 60,
+            body_size: message.body.len() as u64,
+            properties: message.properties.clone(),
+        };
+        // Body frame is sent even if body is empty.
+        let body = ContentBodyPayload {
+            bytes: message.body.clone(),
+        };
+        self.send(conn, Frame::new(channel, FramePayload::ContentHeader(header)));
+        self.send(conn, Frame::new(channel, FramePayload::ContentBody(body)));
+    }
+
+    /// Close the channel because of an error in `method` which client sent.
+    fn channel_error(&mut self, conn: usize, channel: u16, code: u16, text: String, method: &MethodPayload) {
+        warn!("MockBroker closes channel {} : {} {}", channel, code, text);
+        let (class_id, method_id) = method_ids(method);
+        self.release_channel(conn, channel);
+        if let Some(ch) = self.connections
+            .get_mut(&conn)
+            .and_then(|c| c.channels.get_mut(&channel))
+        {
+            ch.is_closing = true;
+        }
+        let close = channel::CloseMethod {
+            reply_code: code,
+            reply_text: AmqpString::from(text),
+            class_id,
+            method_id,
+        };
+        self.send_method(conn, channel, MethodPayload::Channel(ChannelClass::Close(close)));
+    }
+
+    fn connection_error(&mut self, conn: usize, code: u16, text: String) {
+        warn!("MockBroker closes connection : {} {}", code, text);
+        self.release_connection(conn);
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            connection.is_closing = true;
+        }
+        let close = connection::CloseMethod {
+            reply_code: code,
+            reply_text: AmqpString::from(text),
+            class_id: 0,
+            method_id: 0,
+        };
+        self.send_method(conn, 0, MethodPayload::Connection(ConnectionClass::Close(close)));
+    }
+
+    /// Requeue unacknowledged messages and cancel consumers on the channel.
+    fn release_channel(&mut self, conn: usize, channel: u16) {
+        let ch = match self.connections
+            .get_mut(&conn)
+            .and_then(|c| c.channels.get_mut(&channel))
+        {
+            Some(ch) => ch,
+            None => return,
+        };
+        let unacked = ::std::mem::take(&mut ch.unacked);
+        let tags = ::std::mem::take(&mut ch.consumer_tags);
+        ch.publishing = None;
+
+        // Consumers are removed first so that requeued messages are not delivered to them.
+        for tag in tags {
+            self.remove_consumer(conn, channel, &tag);
+        }
+        self.requeue(unacked.into_values().collect());
+    }
+
+    fn release_connection(&mut self, conn: usize) {
+        let channels: Vec<u16> = match self.connections.get(&conn) {
+            Some(connection) => connection.channels.keys().cloned().collect(),
+            None => return,
+        };
+        for channel in channels {
+            self.release_channel(conn, channel);
+        }
+        // Exclusive queues are deleted with their owner.
+        self.queues.retain(|_, queue| queue.owner != Some(conn));
+        for exchange in self.exchanges.values_mut() {
+            let queues = &self.queues;
+            exchange.bindings.retain(|(q, _)| queues.contains_key(q));
+        }
+    }
+
+    /// Called when client drops the socket.
+    pub fn disconnect(&mut self, conn: usize) {
+        self.release_connection(conn);
+        self.connections.remove(&conn);
+    }
+
+    fn requeue(&mut self, mut messages: Vec<Unacked>) {
+        let mut queues = Vec::new();
+        // Requeued messages keep their original order at the head of the queue.
+        messages.reverse();
+        for Unacked { queue, mut message } in messages {
+            if let Some(q) = self.queues.get_mut(&queue) {
+                message.redelivered = true;
+                q.messages.push_front(message);
+                queues.push(queue);
+            }
+        }
+        queues.dedup();
+        for queue in queues {
+            self.dispatch(&queue);
+        }
+    }
+
+    fn remove_consumer(&mut self, conn: usize, channel: u16, tag: &AmqpString) {
+        let mut deleted = None;
+        for (name, queue) in &mut self.queues {
+            let before = queue.consumers.len();
+            queue
+                .consumers
+                .retain(|c| !(c.conn == conn && c.channel == channel && &c.tag == tag));
+            if queue.consumers.len() < before && queue.consumers.is_empty() && queue.is_auto_delete {
+                deleted = Some(name.clone());
+            }
+        }
+        if let Some(ch) = self.connections
+            .get_mut(&conn)
+            .and_then(|c| c.channels.get_mut(&channel))
+        {
+            ch.consumer_tags.retain(|t| t != tag);
+        }
+        if let Some(name) = deleted {
+            self.delete_queue(&name);
+        }
+    }
+
+    fn delete_queue(&mut self, name: &str) -> Option<Queue> {
+        let queue = self.queues.remove(name)?;
+        for exchange in self.exchanges.values_mut() {
+            exchange.bindings.retain(|(q, _)| q != name);
+        }
+        Some(queue)
+    }
+
+    /// Route a message to queues. Returns false if no queue receives it.
+    pub fn route(&mut self, message: Message) -> bool {
+        let queues = if message.exchange.is_empty() {
+            if self.queues.contains_key(&message.routing_key) {
+                vec![message.routing_key.clone()]
+            } else {
+                vec![]
+            }
+        } else {
+            match self.exchanges.get(&message.exchange) {
+                Some(exchange) => exchange.route(&message.routing_key),
+                None => vec![],
+            }
+        };
+
+        for queue in &queues {
+            if let Some(q) = self.queues.get_mut(queue) {
+                q.messages.push_back(message.clone());
+            }
+            self.dispatch(queue);
+        }
+        !queues.is_empty()
+    }
+
+    /// Deliver messages in the queue to its consumers in round robin.
+    fn dispatch(&mut self, queue: &str) {
+        loop {
+            let consumer = {
+                let q = match self.queues.get_mut(queue) {
+                    Some(q) if !q.messages.is_empty() => q,
+                    _ => return,
+                };
+                let connections = &self.connections;
+                let ready = q.consumers.iter().position(|c| {
+                    let channel = connections
+                        .get(&c.conn)
+                        .and_then(|conn| conn.channels.get(&c.channel));
+                    match channel {
+                        _ if c.no_ack => true,
+                        Some(ch) => ch.prefetch_count == 0 || ch.unacked.len() < ch.prefetch_count as usize,
+                        None => false,
+                    }
+                });
+                match ready {
+                    Some(idx) => {
+                        // Move the consumer to the tail for round robin.
+                        let consumer = q.consumers.remove(idx);
+                        q.consumers.push(consumer.clone());
+                        consumer
+                    }
+                    None => return,
+                }
+            };
+            let message = self.queues
+                .get_mut(queue)
+                .and_then(|q| q.messages.pop_front())
+                .unwrap();
+            self.deliver(consumer, queue, message);
+        }
+    }
+
+    fn deliver(&mut self, consumer: ConsumerRef, queue: &str, message: Message) {
+        let delivery_tag = match self.connections
+            .get_mut(&consumer.conn)
+            .and_then(|c| c.channels.get_mut(&consumer.channel))
+        {
+            Some(ch) => {
+                ch.next_delivery_tag += 1;
+                if !consumer.no_ack {
+                    let unacked = Unacked {
+                        queue: queue.to_string(),
+                        message: message.clone(),
+                    };
+                    ch.unacked.insert(ch.next_delivery_tag, unacked);
+                }
+                ch.next_delivery_tag
+            }
+            None => return,
+        };
+        let deliver = basic::DeliverMethod {
+            consumer_tag: consumer.tag.clone(),
+            delivery_tag,
+            redeliverd: message.redelivered,
+            exchange: AmqpString::from(message.exchange.clone()),
+            routing_key: AmqpString::from(message.routing_key.clone()),
+        };
+        self.send_method(consumer.conn, consumer.channel, MethodPayload::Basic(BasicClass::Deliver(deliver)));
+        self.send_content(consumer.conn, consumer.channel, &message);
+    }
+
+    /// Apply a frame sent by client.
+    pub fn handle(&mut self, conn: usize, frame: Frame) {
+        let channel = frame.header.channel;
+        let (is_closing, closing) = match self.connections.get(&conn) {
+            Some(c) if c.login.is_some() && !c.is_closing => return self.handle_handshake(conn, frame),
+            Some(c) => (c.is_closing, c.channels.get(&channel).map(|ch| ch.is_closing)),
+            None => return,
+        };
+
+        if channel == 0 {
+            return match frame.payload {
+                FramePayload::Method(MethodPayload::Connection(class)) => {
+                    self.handle_connection(conn, class)
+                }
+                FramePayload::Heartbeat => {}
+                _ if is_closing => {}
+                _ => self.connection_error(conn, CHANNEL_ERROR, "CHANNEL_ERROR - unexpected frame on channel 0".into()),
+            };
+        }
+        if is_closing {
+            return;
+        }
+
+        match frame.payload {
+            FramePayload::Method(MethodPayload::Channel(class)) => {
+                self.handle_channel(conn, channel, class, closing)
+            }
+            _ if closing.is_none() => self.connection_error(
+                conn,
+                CHANNEL_ERROR,
+                format!("CHANNEL_ERROR - channel {} is not open", channel),
+            ),
+            _ if closing == Some(true) => {}
+            FramePayload::Method(method) => self.handle_method(conn, channel, method),
+            FramePayload::ContentHeader(header) => self.handle_content_header(conn, channel, header),
+            FramePayload::ContentBody(body) => self.handle_content_body(conn, channel, body),
+            FramePayload::Heartbeat => {}
+        }
+    }
+
+    /// Server side of handshake. Only `PLAIN` mechanism is accepted.
+    fn handle_handshake(&mut self, conn: usize, frame: Frame) {
+        let class = match frame.payload {
+            FramePayload::Method(MethodPayload::Connection(class)) if frame.header.channel == 0 => class,
+            _ => {
+                let text = "COMMAND_INVALID - connection is not open yet".into();
+                return self.connection_error(conn, COMMAND_INVALID, text);
+            }
+        };
+        match class {
+            ConnectionClass::StartOk(start_ok) => {
+                let (expected, channel_max) = {
+                    let login = self.login(conn);
+                    (AmqpString::from(format!("\0{}\0{}", login.user, login.pass)), login.channel_max)
+                };
+                // Compare bytes, because a response of other mechanisms may not be UTF-8.
+                if start_ok.mechanism != AmqpString::from("PLAIN") || start_ok.response != expected {
+                    let text = "ACCESS_REFUSED - Login was refused using authentication mechanism PLAIN";
+                    return self.connection_error(conn, ACCESS_REFUSED, text.into());
+                }
+                let tune = connection::TuneMethod {
+                    channel_max,
+                    frame_max: FRAME_MAX,
+                    heartbeat: 0,
+                };
+                self.send_method(conn, 0, MethodPayload::Connection(ConnectionClass::Tune(tune)));
+            }
+            ConnectionClass::TuneOk(_) => {}
+            ConnectionClass::Open(open) => {
+                if open.virtual_host != AmqpString::from(self.login(conn).virtual_host.clone()) {
+                    return self.connection_error(conn, NOT_ALLOWED, "NOT_ALLOWED - vhost not found".into());
+                }
+                if let Some(connection) = self.connections.get_mut(&conn) {
+                    connection.login = None;
+                }
+                let open_ok = connection::OpenOkMethod {
+                    reserved1: "".into(),
+                };
+                self.send_method(conn, 0, MethodPayload::Connection(ConnectionClass::OpenOk(open_ok)));
+            }
+            other => self.handle_connection(conn, other),
+        }
+    }
+
+    fn login(&self, conn: usize) -> &Login {
+        self.connections[&conn]
+            .login
+            .as_ref()
+            .expect("Connection is in handshake")
+    }
+
+    fn handle_connection(&mut self, conn: usize, class: ConnectionClass) {
+        match class {
+            ConnectionClass::Close(_) => {
+                self.release_connection(conn);
+                self.send_method(conn, 0, MethodPayload::Connection(ConnectionClass::CloseOk));
+                self.close_connection(conn);
+            }
+            ConnectionClass::CloseOk => self.close_connection(conn),
+            other => {
+                let text = format!("COMMAND_INVALID - unexpected method {:?}", other);
+                self.connection_error(conn, 503, text);
+            }
+        }
+    }
+
+    fn close_connection(&mut self, conn: usize) {
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            connection.is_closed = true;
+            if let Some(task) = connection.task.take() {
+                task.notify();
+            }
+        }
+    }
+
+    fn handle_channel(&mut self, conn: usize, channel: u16, class: ChannelClass, closing: Option<bool>) {
+        let method = MethodPayload::Channel(class.clone());
+        match (class, closing) {
+            (ChannelClass::Open(_), None) => {
+                self.connections
+                    .get_mut(&conn)
+                    .unwrap()
+                    .channels
+                    .insert(channel, ChannelState::default());
+                let open_ok = channel::OpenOkMethod {
+                    reserved1: "".into(),
+                };
+                self.send_method(conn, channel, MethodPayload::Channel(ChannelClass::OpenOk(open_ok)));
+            }
+            (ChannelClass::Open(_), Some(_)) => self.connection_error(
+                conn,
+                CHANNEL_ERROR,
+                format!("CHANNEL_ERROR - channel {} is already open", channel),
+            ),
+            (_, None) => self.connection_error(
+                conn,
+                CHANNEL_ERROR,
+                format!("CHANNEL_ERROR - channel {} is not open", channel),
+            ),
+            (ChannelClass::Close(_), Some(_)) => {
+                self.release_channel(conn, channel);
+                self.connections.get_mut(&conn).unwrap().channels.remove(&channel);
+                self.send_method(conn, channel, MethodPayload::Channel(ChannelClass::CloseOk));
+            }
+            (ChannelClass::CloseOk, Some(true)) => {
+                self.connections.get_mut(&conn).unwrap().channels.remove(&channel);
+            }
+            (_, Some(true)) => {}
+            (ChannelClass::Flow(flow), Some(false)) => {
+                let flow_ok = channel::FlowOkMethod {
+                    active: flow.active,
+                };
+                self.send_method(conn, channel, MethodPayload::Channel(ChannelClass::FlowOk(flow_ok)));
+            }
+            // Reply to `Flow` method. The broker never sends it.
+            (ChannelClass::FlowOk(_), Some(false)) => {}
+            (other, Some(false)) => {
+                let text = format!("COMMAND_INVALID - unexpected method {:?}", other);
+                self.channel_error(conn, channel, 503, text, &method);
+            }
+        }
+    }
+
+    fn handle_method(&mut self, conn: usize, channel: u16, method: MethodPayload) {
+        let result = match method {
+            MethodPayload::Exchange(ref class) => self.handle_exchange(conn, channel, class),
+            MethodPayload::Queue(ref class) => self.handle_queue(conn, channel, class),
+            MethodPayload::Basic(ref class) => self.handle_basic(conn, channel, class),
+            _ => Err((NOT_IMPLEMENTED, "NOT_IMPLEMENTED - MockBroker does not support it".into())),
+        };
+        if let Err((code, text)) = result {
+            self.channel_error(conn, channel, code, text, &method);
+        }
+    }
+
+    fn handle_exchange(&mut self, conn: usize, channel: u16, class: &ExchangeClass) -> Result<(), (u16, String)> {
+        match *class {
+            ExchangeClass::Declare(ref declare) => {
+                let name = declare.exchange.to_string();
+                let typ = ExchangeType::parse(&declare.typ).ok_or_else(|| {
+                    (NOT_IMPLEMENTED, format!("NOT_IMPLEMENTED - exchange type {}", &*declare.typ))
+                })?;
+                match self.exchanges.get(&name) {
+                    Some(exchange) if !declare.passive && exchange.typ != typ => {
+                        return Err((
+                            PRECONDITION_FAILED,
+                            format!("PRECONDITION_FAILED - exchange {} has another type", name),
+                        ))
+                    }
+                    Some(_) => {}
+                    None if declare.passive => {
+                        return Err((NOT_FOUND, format!("NOT_FOUND - no exchange '{}'", name)))
+                    }
+                    None if name.starts_with("amq.") => {
+                        return Err((ACCESS_REFUSED, format!("ACCESS_REFUSED - exchange name '{}'", name)))
+                    }
+                    None => {
+                        self.exchanges.insert(name, Exchange::new(typ));
+                    }
+                }
+                if !declare.no_wait {
+                    self.send_method(conn, channel, MethodPayload::Exchange(ExchangeClass::DeclareOk));
+                }
+                Ok(())
+            }
+            ExchangeClass::Delete(ref delete) => {
+                if self.exchanges.remove(&*delete.exchange).is_none() {
+                    return Err((NOT_FOUND, format!("NOT_FOUND - no exchange '{}'", &*delete.exchange)));
+                }
+                if !delete.no_wait {
+                    self.send_method(conn, channel, MethodPayload::Exchange(ExchangeClass::DeleteOk));
+                }
+                Ok(())
+            }
+            _ => Err((NOT_IMPLEMENTED, "NOT_IMPLEMENTED - MockBroker does not support it".into())),
+        }
+    }
+
+    fn handle_queue(&mut self, conn: usize, channel: u16, class: &QueueClass) -> Result<(), (u16, String)> {
+        match *class {
+            QueueClass::Declare(ref declare) => {
+                let name = if declare.queue.is_empty() {
+                    self.next_queue_id += 1;
+                    format!("amq.gen-{}", self.next_queue_id)
+                } else {
+                    declare.queue.to_string()
+                };
+                match self.queues.get(&name) {
+                    Some(queue) if queue.owner.is_some() && queue.owner != Some(conn) => {
+                        return Err((
+                            RESOURCE_LOCKED,
+                            format!("RESOURCE_LOCKED - queue '{}' is exclusive", name),
+                        ))
+                    }
+                    Some(_) => {}
+                    None if declare.passive => {
+                        return Err((NOT_FOUND, format!("NOT_FOUND - no queue '{}'", name)))
+                    }
+                    None => {
+                        let queue = Queue {
+                            messages: VecDeque::new(),
+                            consumers: Vec::new(),
+                            owner: if declare.exclusive { Some(conn) } else { None },
+                            is_auto_delete: declare.auto_delete,
+                            had_consumer: false,
+                        };
+                        self.queues.insert(name.clone(), queue);
+                    }
+                }
+                if !declare.no_wait {
+                    let queue = &self.queues[&name];
+                    let declare_ok = queue::DeclareOkMethod {
+                        queue: AmqpString::from(name.clone()),
+                        message_count: queue.messages.len() as u32,
+                        consumer_count: queue.consumers.len() as u32,
+                    };
+                    self.send_method(conn, channel, MethodPayload::Queue(QueueClass::DeclareOk(declare_ok)));
+                }
+                Ok(())
+            }
+            QueueClass::Bind(ref bind) => {
+                self.find_queue(&bind.queue)?;
+                let exchange = self.exchanges.get_mut(&*bind.exchange).ok_or_else(|| {
+                    (NOT_FOUND, format!("NOT_FOUND - no exchange '{}'", &*bind.exchange))
+                })?;
+                let binding = (bind.queue.to_string(), bind.routing_key.to_string());
+                if !exchange.bindings.contains(&binding) {
+                    exchange.bindings.push(binding);
+                }
+                if !bind.no_wait {
+                    self.send_method(conn, channel, MethodPayload::Queue(QueueClass::BindOk));
+                }
+                Ok(())
+            }
+            QueueClass::Unbind(ref unbind) => {
+                if let Some(exchange) = self.exchanges.get_mut(&*unbind.exchange) {
+                    exchange
+                        .bindings
+                        .retain(|(q, k)| !(q == &*unbind.queue && k == &*unbind.routing_key));
+                }
+                self.send_method(conn, channel, MethodPayload::Queue(QueueClass::UnbindOk));
+                Ok(())
+            }
+            QueueClass::Purge(ref purge) => {
+                let queue = self.queues.get_mut(&*purge.queue).ok_or_else(|| {
+                    (NOT_FOUND, format!("NOT_FOUND - no queue '{}'", &*purge.queue))
+                })?;
+                let message_count = queue.messages.len() as u32;
+                queue.messages.clear();
+                if !purge.no_wait {
+                    let purge_ok = queue::PurgeOkMethod { message_count };
+                    self.send_method(conn, channel, MethodPayload::Queue(QueueClass::PurgeOk(purge_ok)));
+                }
+                Ok(())
+            }
+            QueueClass::Delete(ref delete) => {
+                let message_count = {
+                    let queue = self.find_queue(&delete.queue)?;
+                    if delete.if_unused && !queue.consumers.is_empty() {
+                        return Err((PRECONDITION_FAILED, "PRECONDITION_FAILED - queue in use".into()));
+                    }
+                    if delete.if_empty && !queue.messages.is_empty() {
+                        return Err((PRECONDITION_FAILED, "PRECONDITION_FAILED - queue not empty".into()));
+                    }
+                    queue.messages.len() as u32
+                };
+                self.delete_queue(&delete.queue);
+                if !delete.no_wait {
+                    let delete_ok = queue::DeleteOkMethod { message_count };
+                    self.send_method(conn, channel, MethodPayload::Queue(QueueClass::DeleteOk(delete_ok)));
+                }
+                Ok(())
+            }
+            _ => Err((NOT_IMPLEMENTED, "NOT_IMPLEMENTED - MockBroker does not support it".into())),
+        }
+    }
+
+    fn find_queue(&self, name: &str) -> Result<&Queue, (u16, String)> {
+        self.queues
+            .get(name)
+            .ok_or_else(|| (NOT_FOUND, format!("NOT_FOUND - no queue '{}'", name)))
+    }
+
+    fn channel_mut(&mut self, conn: usize, channel: u16) -> &mut ChannelState {
+        self.connections
+            .get_mut(&conn)
+            .and_then(|c| c.channels.get_mut(&channel))
+            .expect("Channel is checked to be open")
+    }
+
+    fn handle_basic(&mut self, conn: usize, channel: u16, class: &BasicClass) -> Result<(), (u16, String)> {
+        match *class {
+            BasicClass::Qos(ref qos) => {
+                self.channel_mut(conn, channel).prefetch_count = qos.prefetch_count;
+                self.send_method(conn, channel, MethodPayload::Basic(BasicClass::QosOk));
+            }
+            BasicClass::Consume(ref consume) => {
+                let tag = if consume.consumer_tag.is_empty() {
+                    self.next_consumer_id += 1;
+                    AmqpString::from(format!("amq.ctag-{}", self.next_consumer_id))
+                } else {
+                    consume.consumer_tag.clone()
+                };
+                let queue = self.queues.get_mut(&*consume.queue).ok_or_else(|| {
+                    (NOT_FOUND, format!("NOT_FOUND - no queue '{}'", &*consume.queue))
+                })?;
+                if queue.owner.is_some() && queue.owner != Some(conn) {
+                    return Err((RESOURCE_LOCKED, "RESOURCE_LOCKED - queue is exclusive".into()));
+                }
+                queue.consumers.push(ConsumerRef {
+                    conn,
+                    channel,
+                    tag: tag.clone(),
+                    no_ack: consume.no_ack,
+                });
+                queue.had_consumer = true;
+                self.channel_mut(conn, channel).consumer_tags.push(tag.clone());
+                if !consume.no_wait {
+                    let consume_ok = basic::ConsumeOkMethod { consumer_tag: tag };
+                    self.send_method(conn, channel, MethodPayload::Basic(BasicClass::ConsumeOk(consume_ok)));
+                }
+                self.dispatch(&consume.queue);
+            }
+            BasicClass::Cancel(ref cancel) => {
+                self.remove_consumer(conn, channel, &cancel.consumer_tag);
+                if !cancel.no_wait {
+                    let cancel_ok = basic::CancelOkMethod {
+                        consumer_tag: cancel.consumer_tag.clone(),
+                    };
+                    self.send_method(conn, channel, MethodPayload::Basic(BasicClass::CancelOk(cancel_ok)));
+                }
+            }
+            BasicClass::Publish(ref publish) => {
+                if !self.has_exchange(&publish.exchange) {
+                    return Err((NOT_FOUND, format!("NOT_FOUND - no exchange '{}'", &*publish.exchange)));
+                }
+                self.channel_mut(conn, channel).publishing = Some(Publishing {
+                    method: publish.clone(),
+                    header: None,
+                    body: BytesMut::new(),
+                });
+            }
+            BasicClass::Get(ref get) => {
+                let (message, message_count) = {
+                    let queue = self.queues.get_mut(&*get.queue).ok_or_else(|| {
+                        (NOT_FOUND, format!("NOT_FOUND - no queue '{}'", &*get.queue))
+                    })?;
+                    (queue.messages.pop_front(), queue.messages.len() as u32)
+                };
+                let message = match message {
+                    Some(message) => message,
+                    None => {
+                        let get_empty = basic::GetEmptyMethod {
+                            reserved1: "".into(),
+                        };
+                        self.send_method(conn, channel, MethodPayload::Basic(BasicClass::GetEmpty(get_empty)));
+                        return Ok(());
+                    }
+                };
+                let delivery_tag = {
+                    let ch = self.channel_mut(conn, channel);
+                    ch.next_delivery_tag += 1;
+                    if !get.no_ack {
+                        let unacked = Unacked {
+                            queue: get.queue.to_string(),
+                            message: message.clone(),
+                        };
+                        ch.unacked.insert(ch.next_delivery_tag, unacked);
+                    }
+                    ch.next_delivery_tag
+                };
+                let get_ok = basic::GetOkMethod {
+                    delivery_tag,
+                    redeliverd: message.redelivered,
+                    exchange: AmqpString::from(message.exchange.clone()),
+                    routing_key: AmqpString::from(message.routing_key.clone()),
+                    message_count,
+                };
+                self.send_method(conn, channel, MethodPayload::Basic(BasicClass::GetOk(get_ok)));
+                self.send_content(conn, channel, &message);
+            }
+            BasicClass::Ack(ref ack) => {
+                let acked = self.settle(conn, channel, ack.delivery_tag, ack.multiple)?;
+                self.redispatch(acked);
+            }
+            BasicClass::Reject(ref reject) => {
+                let rejected = self.settle(conn, channel, reject.delivery_tag, false)?;
+                if reject.requeue {
+                    self.requeue(rejected);
+                } else {
+                    self.redispatch(rejected);
+                }
+            }
+            // `Nack` method of the codec does not have `requeue` flag, so it is always requeued.
+            BasicClass::Nack(ref nack) => {
+                let rejected = self.settle(conn, channel, nack.delivery_tag, nack.multiple)?;
+                self.requeue(rejected);
+            }
+            // Messages are always requeued, even if `requeue` is false.
+            BasicClass::Recover(_) | BasicClass::RecoverAsync(_) => {
+                let unacked = ::std::mem::take(&mut self.channel_mut(conn, channel).unacked);
+                if let BasicClass::Recover(_) = *class {
+                    self.send_method(conn, channel, MethodPayload::Basic(BasicClass::RecoverOk));
+                }
+                self.requeue(unacked.into_values().collect());
+            }
+            _ => return Err((NOT_IMPLEMENTED, "NOT_IMPLEMENTED - MockBroker does not support it".into())),
+        }
+        Ok(())
+    }
+
+    /// Remove unacknowledged messages up to `delivery_tag`.
+    fn settle(&mut self, conn: usize, channel: u16, delivery_tag: u64, multiple: bool) -> Result<Vec<Unacked>, (u16, String)> {
+        let ch = self.channel_mut(conn, channel);
+        if multiple {
+            let rest = ch.unacked.split_off(&(delivery_tag + 1));
+            let settled = ::std::mem::replace(&mut ch.unacked, rest);
+            return Ok(settled.into_values().collect());
+        }
+        match ch.unacked.remove(&delivery_tag) {
+            Some(unacked) => Ok(vec![unacked]),
+            None => Err((
+                PRECONDITION_FAILED,
+                format!("PRECONDITION_FAILED - unknown delivery tag {}", delivery_tag),
+            )),
+        }
+    }
+
+    /// Dispatch queues again because settled messages make room for prefetch.
+    fn redispatch(&mut self, settled: Vec<Unacked>) {
+        let mut queues: Vec<String> = settled.into_iter().map(|u| u.queue).collect();
+        queues.dedup();
+        for queue in queues {
+            self.dispatch(&queue);
+        }
+    }
+
+    fn handle_content_header(&mut self, conn: usize, channel: u16, header: ContentHeaderPayload) {
+        let is_completed = match self.channel_mut(conn, channel).publishing {
+            Some(ref mut publishing) if publishing.header.is_none() => {
+                let is_empty = header.body_size == 0;
+                publishing.header = Some(header);
+                is_empty
+            }
+            _ => {
+                let text = "UNEXPECTED_FRAME - content header without publish".to_string();
+                return self.connection_error(conn, 505, text);
+            }
+        };
+        if is_completed {
+            self.complete_publishing(conn, channel);
+        }
+    }
+
+    fn handle_content_body(&mut self, conn: usize, channel: u16, body: ContentBodyPayload) {
+        let is_completed = match self.channel_mut(conn, channel).publishing {
+            Some(Publishing {
+                header: Some(ref header),
+                body: ref mut buf,
+                ..
+            }) => {
+                buf.extend_from_slice(&body.bytes);
+                buf.len() as u64 >= header.body_size
+            }
+            // Empty body frame after a content which has no body.
+            None if body.bytes.is_empty() => return,
+            _ => {
+                let text = "UNEXPECTED_FRAME - content body without header".to_string();
+                return self.connection_error(conn, 505, text);
+            }
+        };
+        if is_completed {
+            self.complete_publishing(conn, channel);
+        }
+    }
+
+    fn complete_publishing(&mut self, conn: usize, channel: u16) {
+        let publishing = match self.channel_mut(conn, channel).publishing.take() {
+            Some(publishing) => publishing,
+            None => return,
+        };
+        let message = Message {
+            exchange: publishing.method.exchange.to_string(),
+            routing_key: publishing.method.routing_key.to_string(),
+            properties: publishing.header.unwrap().properties,
+            body: publishing.body.freeze(),
+            redelivered: false,
+        };
+        if self.route(message.clone()) || !publishing.method.mandatory {
+            return;
+        }
+        let ret = basic::ReturnMethod {
+            reply_code: NO_ROUTE,
+            reply_text: "NO_ROUTE".into(),
+            exchange: publishing.method.exchange,
+            routing_key: publishing.method.routing_key,
+        };
+        self.send_method(conn, channel, MethodPayload::Basic(BasicClass::Return(ret)));
+        self.send_content(conn, channel, &message);
+    }
+}
+
+/// Class id and method id of `Close` method.
+fn method_ids(method: &MethodPayload) -> (u16, u16) {
+    match *method {
+        MethodPayload::Connection(_) => (10, 0),
+        MethodPayload::Channel(_) => (20, 0),
+        MethodPayload::Exchange(ExchangeClass::Declare(_)) => (40, 10),
+        MethodPayload::Exchange(_) => (40, 0),
+        MethodPayload::Queue(QueueClass::Declare(_)) => (50, 10),
+        MethodPayload::Queue(QueueClass::Bind(_)) => (50, 20),
+        MethodPayload::Queue(_) => (50, 0),
+        MethodPayload::Basic(BasicClass::Consume(_)) => (60, 20),
+        MethodPayload::Basic(BasicClass::Publish(_)) => (60, 40),
+        MethodPayload::Basic(BasicClass::Get(_)) => (60, 70),
+        MethodPayload::Basic(BasicClass::Ack(_)) => (60, 80),
+        MethodPayload::Basic(_) => (60, 0),
+        MethodPayload::Tx(_) => (90, 0),
+    }
+}
@@ -0,0 +1,451 @@
+//! Human-readable protocol trace.
+//!
+//! `FrameDisplay` formats a frame in one line such as
+//! `channel=42 basic.deliver tag=17 exchange=x rk=y`. Message bodies are not shown unless
+//! `LogConfig::log_body` is set, and credentials in `connection.start-ok` and
+//! `connection.secure-ok` are always redacted.
+//!
+//! Wrap a socket with `trace_frames` to log every frame going through it at `debug` level.
+//! Inbound frames are prefixed with `<` and outbound ones with `>`.
+//!
+//! Note that `amqpr_codec` logs whole frames including credentials at `debug` level by
+//! itself. Keep its level at `info` or above when you enable this module's log.
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::args::AmqpString;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::BasicClass;
+use amqpr_codec::method::channel::ChannelClass;
+use amqpr_codec::method::connection::ConnectionClass;
+use amqpr_codec::method::exchange::ExchangeClass;
+use amqpr_codec::method::queue::QueueClass;
+use amqpr_codec::method::tx::TxClass;
+use amqpr_codec::content_header::Properties;
+
+use std::fmt;
+
+/// Options of protocol trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Show message bodies. Default is false, so that only their sizes are shown.
+    pub log_body: bool,
+    /// Bodies longer than this number of bytes are truncated. Default is 64.
+    pub max_body_len: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            log_body: false,
+            max_body_len: 64,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn display<'a>(&self, frame: &'a Frame) -> FrameDisplay<'a> {
+        FrameDisplay {
+            frame,
+            config: *self,
+        }
+    }
+}
+
+/// Format `frame` with default `LogConfig`.
+pub fn display<'a>(frame: &'a Frame) -> FrameDisplay<'a> {
+    LogConfig::default().display(frame)
+}
+
+/// One line representation of a frame. See module document.
+pub struct FrameDisplay<'a> {
+    frame: &'a Frame,
+    config: LogConfig,
+}
+
+impl<'a> fmt::Display for FrameDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "channel={} ", self.frame.header.channel)?;
+        match self.frame.payload {
+            FramePayload::Method(ref method) => {
+                f.write_str(method_name(method))?;
+                write_args(f, method)
+            }
+            FramePayload::ContentHeader(ref header) => {
+                write!(f, "content-header class={} size={}", header.class_id, header.body_size)?;
+                write_properties(f, &header.properties)
+            }
+            FramePayload::ContentBody(ref body) => {
+                write!(f, "content-body size={}", body.bytes.len())?;
+                if self.config.log_body {
+                    let len = ::std::cmp::min(body.bytes.len(), self.config.max_body_len);
+                    let shown = String::from_utf8_lossy(&body.bytes[..len]);
+                    write!(f, " body={:?}", shown)?;
+                    if len < body.bytes.len() {
+                        f.write_str("...")?;
+                    }
+                }
+                Ok(())
+            }
+            FramePayload::Heartbeat => f.write_str("heartbeat"),
+        }
+    }
+}
+
+/// Name of `method` such as `basic.deliver`.
+pub fn method_name(method: &MethodPayload) -> &'static str {
+    match *method {
+        MethodPayload::Connection(ref c) => match *c {
+            ConnectionClass::Start(_) => "connection.start",
+            ConnectionClass::StartOk(_) => "connection.start-ok",
+            ConnectionClass::Secure(_) => "connection.secure",
+            ConnectionClass::SecureOk(_) => "connection.secure-ok",
+            ConnectionClass::Tune(_) => "connection.tune",
+            ConnectionClass::TuneOk(_) => "connection.tune-ok",
+            ConnectionClass::Open(_) => "connection.open",
+            ConnectionClass::OpenOk(_) => "connection.open-ok",
+            ConnectionClass::Close(_) => "connection.close",
+            ConnectionClass::CloseOk => "connection.close-ok",
+            ConnectionClass::Blocked(_) => "connection.blocked",
+            ConnectionClass::Unblocked => "connection.unblocked",
+        },
+        MethodPayload::Channel(ref c) => match *c {
+            ChannelClass::Open(_) => "channel.open",
+            ChannelClass::OpenOk(_) => "channel.open-ok",
+            ChannelClass::Flow(_) => "channel.flow",
+            ChannelClass::FlowOk(_) => "channel.flow-ok",
+            ChannelClass::Close(_) => "channel.close",
+            ChannelClass::CloseOk => "channel.close-ok",
+        },
+        MethodPayload::Exchange(ref c) => match *c {
+            ExchangeClass::Declare(_) => "exchange.declare",
+            ExchangeClass::DeclareOk => "exchange.declare-ok",
+            ExchangeClass::Delete(_) => "exchange.delete",
+            ExchangeClass::DeleteOk => "exchange.delete-ok",
+            ExchangeClass::Bind(_) => "exchange.bind",
+            ExchangeClass::BindOk => "exchange.bind-ok",
+            ExchangeClass::Unbind(_) => "exchange.unbind",
+            ExchangeClass::UnbindOk => "exchange.unbind-ok",
+        },
+        MethodPayload::Queue(ref c) => match *c {
+            QueueClass::Declare(_) => "queue.declare",
+            QueueClass::DeclareOk(_) => "queue.declare-ok",
+            QueueClass::Bind(_) => "queue.bind",
+            QueueClass::BindOk => "queue.bind-ok",
+            QueueClass::Unbind(_) => "queue.unbind",
+            QueueClass::UnbindOk => "queue.unbind-ok",
+            QueueClass::Purge(_) => "queue.purge",
+            QueueClass::PurgeOk(_) => "queue.purge-ok",
+            QueueClass::Delete(_) => "queue.delete",
+            QueueClass::DeleteOk(_) => "queue.delete-ok",
+        },
+        MethodPayload::Basic(ref c) => match *c {
+            BasicClass::Qos(_) => "basic.qos",
+            BasicClass::QosOk => "basic.qos-ok",
+            BasicClass::Consume(_) => "basic.consume",
+            BasicClass::ConsumeOk(_) => "basic.consume-ok",
+            BasicClass::Cancel(_) => "basic.cancel",
+            BasicClass::CancelOk(_) => "basic.cancel-ok",
+            BasicClass::Publish(_) => "basic.publish",
+            BasicClass::Return(_) => "basic.return",
+            BasicClass::Deliver(_) => "basic.deliver",
+            BasicClass::Get(_) => "basic.get",
+            BasicClass::GetOk(_) => "basic.get-ok",
+            BasicClass::GetEmpty(_) => "basic.get-empty",
+            BasicClass::Ack(_) => "basic.ack",
+            BasicClass::Reject(_) => "basic.reject",
+            BasicClass::Nack(_) => "basic.nack",
+            BasicClass::RecoverAsync(_) => "basic.recover-async",
+            BasicClass::Recover(_) => "basic.recover",
+            BasicClass::RecoverOk => "basic.recover-ok",
+        },
+        MethodPayload::Tx(ref c) => match *c {
+            TxClass::Select => "tx.select",
+            TxClass::SelectOk => "tx.select-ok",
+            TxClass::Commit => "tx.commit",
+            TxClass::CommitOk => "tx.commit-ok",
+            TxClass::Rollback => "tx.rollback",
+            TxClass::RollbackOk => "tx.rollback-ok",
+        },
+    }
+}
+
+/// String argument which is quoted if it is empty or has a space.
+struct Str<'a>(&'a AmqpString);
+
+impl<'a> fmt::Display for Str<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: &str = self.0;
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            write!(f, "{:?}", s)
+        } else {
+            f.write_str(s)
+        }
+    }
+}
+
+fn write_args(f: &mut fmt::Formatter, method: &MethodPayload) -> fmt::Result {
+    match *method {
+        MethodPayload::Connection(ref c) => match *c {
+            ConnectionClass::Start(ref m) => write!(
+                f,
+                " version={}.{} mechanisms={} locales={}",
+                m.version_major,
+                m.version_minor,
+                Str(&m.mechanisms),
+                Str(&m.locales)
+            ),
+            ConnectionClass::StartOk(ref m) => write!(
+                f,
+                " mechanism={} response=<redacted> locale={}",
+                Str(&m.mechanism),
+                Str(&m.locale)
+            ),
+            ConnectionClass::SecureOk(_) => f.write_str(" response=<redacted>"),
+            ConnectionClass::Tune(ref m) => write!(
+                f,
+                " channel_max={} frame_max={} heartbeat={}",
+                m.channel_max, m.frame_max, m.heartbeat
+            ),
+            ConnectionClass::TuneOk(ref m) => write!(
+                f,
+                " channel_max={} frame_max={} heartbeat={}",
+                m.channel_max, m.frame_max, m.heartbeat
+            ),
+            ConnectionClass::Open(ref m) => write!(f, " vhost={}", Str(&m.virtual_host)),
+            ConnectionClass::Close(ref m) => write!(
+                f,
+                " code={} text={} method={}.{}",
+                m.reply_code,
+                Str(&m.reply_text),
+                m.class_id,
+                m.method_id
+            ),
+            ConnectionClass::Blocked(ref m) => write!(f, " reason={}", Str(&m.reason)),
+            _ => Ok(()),
+        },
+        MethodPayload::Channel(ref c) => match *c {
+            ChannelClass::Flow(ref m) => write!(f, " active={}", m.active),
+            ChannelClass::FlowOk(ref m) => write!(f, " active={}", m.active),
+            ChannelClass::Close(ref m) => write!(
+                f,
+                " code={} text={} method={}.{}",
+                m.reply_code,
+                Str(&m.reply_text),
+                m.class_id,
+                m.method_id
+            ),
+            _ => Ok(()),
+        },
+        MethodPayload::Exchange(ref c) => match *c {
+            ExchangeClass::Declare(ref m) => write!(
+                f,
+                " exchange={} type={} durable={} auto_delete={}",
+                Str(&m.exchange),
+                Str(&m.typ),
+                m.durable,
+                m.auto_delete
+            ),
+            ExchangeClass::Delete(ref m) => write!(f, " exchange={}", Str(&m.exchange)),
+            ExchangeClass::Bind(ref m) => write!(
+                f,
+                " destination={} source={} rk={}",
+                Str(&m.destination),
+                Str(&m.source),
+                Str(&m.routing_key)
+            ),
+            ExchangeClass::Unbind(ref m) => write!(
+                f,
+                " destination={} source={} rk={}",
+                Str(&m.destination),
+                Str(&m.source),
+                Str(&m.routing_key)
+            ),
+            _ => Ok(()),
+        },
+        MethodPayload::Queue(ref c) => match *c {
+            QueueClass::Declare(ref m) => write!(
+                f,
+                " queue={} durable={} exclusive={} auto_delete={}",
+                Str(&m.queue),
+                m.durable,
+                m.exclusive,
+                m.auto_delete
+            ),
+            QueueClass::DeclareOk(ref m) => write!(
+                f,
+                " queue={} messages={} consumers={}",
+                Str(&m.queue),
+                m.message_count,
+                m.consumer_count
+            ),
+            QueueClass::Bind(ref m) => write!(
+                f,
+                " queue={} exchange={} rk={}",
+                Str(&m.queue),
+                Str(&m.exchange),
+                Str(&m.routing_key)
+            ),
+            QueueClass::Unbind(ref m) => write!(
+                f,
+                " queue={} exchange={} rk={}",
+                Str(&m.queue),
+                Str(&m.exchange),
+                Str(&m.routing_key)
+            ),
+            QueueClass::Purge(ref m) => write!(f, " queue={}", Str(&m.queue)),
+            QueueClass::PurgeOk(ref m) => write!(f, " messages={}", m.message_count),
+            QueueClass::Delete(ref m) => write!(f, " queue={}", Str(&m.queue)),
+            QueueClass::DeleteOk(ref m) => write!(f, " messages={}", m.message_count),
+            _ => Ok(()),
+        },
+        MethodPayload::Basic(ref c) => match *c {
+            BasicClass::Qos(ref m) => write!(
+                f,
+                " prefetch_size={} prefetch_count={} global={}",
+                m.prefetch_size, m.prefetch_count, m.global
+            ),
+            BasicClass::Consume(ref m) => write!(
+                f,
+                " queue={} consumer={} no_ack={} exclusive={}",
+                Str(&m.queue),
+                Str(&m.consumer_tag),
+                m.no_ack,
+                m.exclusive
+            ),
+            BasicClass::ConsumeOk(ref m) => write!(f, " consumer={}", Str(&m.consumer_tag)),
+            BasicClass::Cancel(ref m) => write!(f, " consumer={}", Str(&m.consumer_tag)),
+            BasicClass::CancelOk(ref m) => write!(f, " consumer={}", Str(&m.consumer_tag)),
+            BasicClass::Publish(ref m) => write!(
+                f,
+                " exchange={} rk={} mandatory={}",
+                Str(&m.exchange),
+                Str(&m.routing_key),
+                m.mandatory
+            ),
+            BasicClass::Return(ref m) => write!(
+                f,
+                " code={} text={} exchange={} rk={}",
+                m.reply_code,
+                Str(&m.reply_text),
+                Str(&m.exchange),
+                Str(&m.routing_key)
+            ),
+            BasicClass::Deliver(ref m) => {
+                write!(
+                    f,
+                    " tag={} exchange={} rk={}",
+                    m.delivery_tag,
+                    Str(&m.exchange),
+                    Str(&m.routing_key)
+                )?;
+                if m.redeliverd {
+                    f.write_str(" redelivered")?;
+                }
+                Ok(())
+            }
+            BasicClass::Get(ref m) => write!(f, " queue={} no_ack={}", Str(&m.queue), m.no_ack),
+            BasicClass::GetOk(ref m) => write!(
+                f,
+                " tag={} exchange={} rk={} messages={}",
+                m.delivery_tag,
+                Str(&m.exchange),
+                Str(&m.routing_key),
+                m.message_count
+            ),
+            BasicClass::Ack(ref m) => write!(f, " tag={} multiple={}", m.delivery_tag, m.multiple),
+            BasicClass::Reject(ref m) => write!(f, " tag={} requeue={}", m.delivery_tag, m.requeue),
+            BasicClass::Nack(ref m) => write!(f, " tag={} multiple={}", m.delivery_tag, m.multiple),
+            BasicClass::RecoverAsync(ref m) => write!(f, " requeue={}", m.requeue),
+            BasicClass::Recover(ref m) => write!(f, " requeue={}", m.requeue),
+            _ => Ok(()),
+        },
+        MethodPayload::Tx(_) => Ok(()),
+    }
+}
+
+/// Properties which are useful to follow a message. Headers are not shown.
+fn write_properties(f: &mut fmt::Formatter, props: &Properties) -> fmt::Result {
+    if let Some(ref v) = props.content_type {
+        write!(f, " content_type={}", Str(v))?;
+    }
+    if let Some(ref v) = props.content_encoding {
+        write!(f, " content_encoding={}", Str(v))?;
+    }
+    if let Some(v) = props.delivery_mode {
+        write!(f, " delivery_mode={}", v)?;
+    }
+    if let Some(ref v) = props.correlation_id {
+        write!(f, " correlation_id={}", Str(v))?;
+    }
+    if let Some(ref v) = props.reply_to {
+        write!(f, " reply_to={}", Str(v))?;
+    }
+    if let Some(ref v) = props.message_id {
+        write!(f, " message_id={}", Str(v))?;
+    }
+    Ok(())
+}
+
+/// Wrap given socket and log every frame going through it.
+pub fn trace_frames<S>(socket: S, config: LogConfig) -> Traced<S> {
+    Traced { socket, config }
+}
+
+/// Socket returned by `trace_frames` function.
+pub struct Traced<S> {
+    socket: S,
+    config: LogConfig,
+}
+
+impl<S> Traced<S> {
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+impl<S> Stream for Traced<S>
+where
+    S: Stream<Item = Frame>,
+{
+    type Item = Frame;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, S::Error> {
+        let frame = try_ready!(self.socket.poll());
+        if let Some(ref frame) = frame {
+            debug!("< {}", self.config.display(frame));
+        }
+        Ok(Async::Ready(frame))
+    }
+}
+
+impl<S> Sink for Traced<S>
+where
+    S: Sink<SinkItem = Frame>,
+{
+    type SinkItem = Frame;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Frame) -> StartSend<Frame, S::SinkError> {
+        let line = if log_enabled!(::log::Level::Debug) {
+            Some(self.config.display(&item).to_string())
+        } else {
+            None
+        };
+        let sent = self.socket.start_send(item)?;
+        if let (&AsyncSink::Ready, Some(line)) = (&sent, line) {
+            debug!("> {}", line);
+        }
+        Ok(sent)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.socket.close()
+    }
+}
@@ -1,21 +1,92 @@
 //! Convenient module to publish item.
-use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend};
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::sink::Send;
 
-use amqpr_codec::Frame;
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::BasicClass;
+use amqpr_codec::method::channel::ChannelClass;
+use amqpr_codec::method::connection::ConnectionClass;
 
 use basic::publish::{publish, PublishItem, Published};
+use channel::flow::flow_ok_frame;
 use common::Should;
+use compression::Compressions;
+use protocol_log;
+use errors::*;
 
 /// Returns `BroadcastSink` which is `Sink` of `PublishItem`.
 ///
+/// `BroadcastSink` also watches inbound frames of given socket. When AMQP server sends
+/// `Flow` method with `active: false` on `channel`, it replies `Flow-Ok` and stops to accept
+/// new item until flow is re-enabled. `Close` method on `channel` or on connection makes
+/// the sink fail with `ChannelClosedByServer` or `ConnectionClosedByServer`. `Return` method
+/// is logged as warning. Other inbound frames are skipped.
+///
+/// If your socket is not a `Stream`, use `publish_sink_without_flow` function instead.
+///
 /// You may also need to have a look at `PublishItem` document.
-pub fn publish_sink<S>(channel: u16, socket: S) -> BroadcastSink<S>
+pub fn publish_sink<S, E>(channel: u16, socket: S) -> BroadcastSink<S>
 where
-    S: Sink<SinkItem = Frame>,
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
 {
     BroadcastSink {
-        channel: channel,
+        channel,
         state: PublishState::Waiting(Should::new(socket)),
+        is_flow_active: true,
+        compression: None,
+    }
+}
+
+/// Returns `BroadcastSink` which publishes items into `sink` without watching inbound frames.
+/// It never pauses because it can not receive `Flow` method.
+pub fn publish_sink_without_flow<S>(channel: u16, sink: S) -> BroadcastSink<SinkOnly<S>>
+where
+    S: Sink<SinkItem = Frame>,
+    S::SinkError: From<Error>,
+{
+    publish_sink(channel, SinkOnly { sink })
+}
+
+/// `Stream + Sink` which forwards frames into `S` and never yields any frame.
+pub struct SinkOnly<S> {
+    sink: S,
+}
+
+impl<S> SinkOnly<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S: Sink> Stream for SinkOnly<S> {
+    type Item = Frame;
+    type Error = S::SinkError;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, S::SinkError> {
+        Ok(Async::NotReady)
+    }
+}
+
+impl<S: Sink> Sink for SinkOnly<S> {
+    type SinkItem = S::SinkItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: S::SinkItem) -> StartSend<S::SinkItem, S::SinkError> {
+        self.sink.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.close()
     }
 }
 
@@ -26,35 +97,70 @@ where
 {
     channel: u16,
     state: PublishState<S>,
+    is_flow_active: bool,
+    /// Algorithms and `content_encoding` to compress every item.
+    compression: Option<(Compressions, String)>,
 }
 
 enum PublishState<S>
 where
     S: Sink<SinkItem = Frame>,
 {
-    Processing(Published<S>),
+    Processing(Box<Published<S>>),
+    ReplyingFlowOk(Box<Send<S>>),
     Waiting(Should<S>),
 }
 
-impl<S> Sink for BroadcastSink<S>
+impl<S> BroadcastSink<S>
 where
     S: Sink<SinkItem = Frame>,
+{
+    /// Returns false while AMQP server asks us to stop publishing.
+    pub fn is_flow_active(&self) -> bool {
+        self.is_flow_active
+    }
+
+    /// Compress body of every item by `encoding` before publishing it.
+    /// `start_send` fails if `encoding` is not registered or an item already has
+    /// `content_encoding`.
+    pub fn compress(mut self, compressions: Compressions, encoding: &str) -> BroadcastSink<S> {
+        self.compression = Some((compressions, encoding.to_string()));
+        self
+    }
+}
+
+impl<S, E> Sink for BroadcastSink<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+    E: From<Error>,
 {
     type SinkItem = PublishItem;
-    type SinkError = S::SinkError;
+    type SinkError = E;
 
     fn start_send(&mut self, item: PublishItem) -> StartSend<PublishItem, Self::SinkError> {
         if let Async::NotReady = self.poll_complete()? {
             return Ok(AsyncSink::NotReady(item));
         }
 
+        if !self.is_flow_active {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        // Compress only an accepted item, so that an item returned by `NotReady` is
+        // not compressed twice.
+        let item = match self.compression {
+            Some((ref compressions, ref encoding)) => compressions.compress(encoding, item)?,
+            None => item,
+        };
+
         use self::PublishState::*;
-        self.state = match &mut self.state {
-            &mut Processing(ref mut _published) => unreachable!(),
-            &mut Waiting(ref mut sink) => {
+        self.state = match self.state {
+            Processing(ref mut _published) => unreachable!(),
+            ReplyingFlowOk(ref mut _sending) => unreachable!(),
+            Waiting(ref mut sink) => {
                 let sink = sink.take();
                 let published = publish(self.channel, sink, item);
-                Processing(published)
+                Processing(Box::new(published))
             }
         };
 
@@ -64,14 +170,73 @@ where
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         use self::PublishState::*;
 
-        self.state = match &mut self.state {
-            &mut Processing(ref mut processing) => {
+        self.state = match self.state {
+            Processing(ref mut processing) => {
                 let sink = try_ready!(processing.poll());
                 Waiting(Should::new(sink))
             }
-            &mut Waiting(_) => return Ok(Async::Ready(())),
+            ReplyingFlowOk(ref mut sending) => {
+                let sink = try_ready!(sending.poll());
+                Waiting(Should::new(sink))
+            }
+            Waiting(ref mut sink) => {
+                let active = match poll_flow(self.channel, sink.as_mut())? {
+                    Some(active) => active,
+                    None => return Ok(Async::Ready(())),
+                };
+                info!("Receive flow method : active = {}", active);
+                self.is_flow_active = active;
+                let flow_ok = flow_ok_frame(self.channel, active);
+                ReplyingFlowOk(Box::new(sink.take().send(flow_ok)))
+            }
         };
 
         self.poll_complete()
     }
 }
+
+/// Read inbound frames which are already arrived, and returns `active` flag of `Flow`
+/// method on `channel` if there is.
+fn poll_flow<S, E>(channel: u16, stream: &mut S) -> Result<Option<bool>, E>
+where
+    S: Stream<Item = Frame, Error = E>,
+    E: From<Error>,
+{
+    loop {
+        let frame = match stream.poll()? {
+            Async::Ready(Some(frame)) => frame,
+            Async::Ready(None) => {
+                return Err(E::from(Error::from(ErrorKind::UnexpectedConnectionClose)))
+            }
+            Async::NotReady => return Ok(None),
+        };
+
+        match frame.payload {
+            FramePayload::Method(MethodPayload::Connection(ConnectionClass::Close(ref close))) => {
+                let kind = ErrorKind::ConnectionClosedByServer(
+                    close.reply_code,
+                    close.reply_text.to_string(),
+                );
+                return Err(E::from(Error::from(kind)));
+            }
+            _ if frame.header.channel != channel => {
+                debug!("Skip inbound {}", protocol_log::display(&frame))
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Flow(ref flow))) => {
+                return Ok(Some(flow.active))
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Close(ref close))) => {
+                let kind = ErrorKind::ChannelClosedByServer(
+                    channel,
+                    close.reply_code,
+                    close.reply_text.to_string(),
+                );
+                return Err(E::from(Error::from(kind)));
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Return(_))) => {
+                warn!("Published message is returned : {}", protocol_log::display(&frame))
+            }
+            _ => debug!("Skip inbound {}", protocol_log::display(&frame)),
+        }
+    }
+}
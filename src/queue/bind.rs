@@ -14,6 +14,10 @@ pub fn bind_queue<S>(channel_id: u16, socket: S, option: BindQueueOption) -> Que
 where
     S: Sink<SinkItem = Frame>,
 {
+    socket.send(bind_queue_frame(channel_id, option))
+}
+
+pub(crate) fn bind_queue_frame(channel_id: u16, option: BindQueueOption) -> Frame {
     let bind = BindMethod {
         reserved1: 0,
         queue: option.queue,
@@ -23,16 +27,15 @@ where
         arguments: HashMap::new(),
     };
 
-    let frame = Frame {
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
         payload: FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(bind))),
-    };
-
-    socket.send(frame)
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct BindQueueOption {
     pub queue: AmqpString,
     pub exchange: AmqpString,
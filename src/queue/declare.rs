@@ -22,6 +22,10 @@ where
     S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
     E: From<Error>,
 {
+    QueueDeclared::Sending(socket.send(declare_queue_frame(channel_id, option)))
+}
+
+pub(crate) fn declare_queue_frame(channel_id: u16, option: DeclareQueueOption) -> Frame {
     let declare = DeclareMethod {
         reserved1: 0,
         queue: option.name,
@@ -33,14 +37,12 @@ where
         arguments: HashMap::new(),
     };
 
-    let frame = Frame {
+    Frame {
         header: FrameHeader {
             channel: channel_id,
         },
         payload: FramePayload::Method(MethodPayload::Queue(QueueClass::Declare(declare))),
-    };
-
-    QueueDeclared::Sending(socket.send(frame))
+    }
 }
 
 pub enum QueueDeclared<S, E>
@@ -0,0 +1,29 @@
+use amqpr_codec::{AmqpString, Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::queue::{DeleteMethod, QueueClass};
+
+pub(crate) fn delete_queue_frame(channel_id: u16, option: DeleteQueueOption) -> Frame {
+    let delete = DeleteMethod {
+        reserved1: 0,
+        queue: option.name,
+        if_unused: option.is_if_unused,
+        if_empty: option.is_if_empty,
+        no_wait: false,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Queue(QueueClass::Delete(delete))),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteQueueOption {
+    pub name: AmqpString,
+    /// Delete the queue only if it has no consumer.
+    pub is_if_unused: bool,
+    /// Delete the queue only if it has no message.
+    pub is_if_empty: bool,
+}
@@ -1,5 +1,10 @@
 pub mod declare;
 pub mod bind;
+pub mod unbind;
+pub mod purge;
+pub mod delete;
 
 pub use self::declare::{declare_queue, DeclareQueueOption};
 pub use self::bind::{bind_queue, BindQueueOption};
+pub use self::unbind::UnbindQueueOption;
+pub use self::delete::DeleteQueueOption;
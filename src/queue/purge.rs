@@ -0,0 +1,18 @@
+use amqpr_codec::{AmqpString, Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::queue::{PurgeMethod, QueueClass};
+
+pub(crate) fn purge_queue_frame(channel_id: u16, queue: AmqpString) -> Frame {
+    let purge = PurgeMethod {
+        reserved1: 0,
+        queue,
+        no_wait: false,
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Queue(QueueClass::Purge(purge))),
+    }
+}
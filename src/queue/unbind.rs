@@ -0,0 +1,29 @@
+use amqpr_codec::{AmqpString, Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::queue::{QueueClass, UnbindMethod};
+
+use std::collections::HashMap;
+
+pub(crate) fn unbind_queue_frame(channel_id: u16, option: UnbindQueueOption) -> Frame {
+    let unbind = UnbindMethod {
+        reserved1: 0,
+        queue: option.queue,
+        exchange: option.exchange,
+        routing_key: option.routing_key,
+        arguments: HashMap::new(),
+    };
+
+    Frame {
+        header: FrameHeader {
+            channel: channel_id,
+        },
+        payload: FramePayload::Method(MethodPayload::Queue(QueueClass::Unbind(unbind))),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnbindQueueOption {
+    pub queue: AmqpString,
+    pub exchange: AmqpString,
+    pub routing_key: AmqpString,
+}
@@ -0,0 +1,826 @@
+//! Connection which reconnects automatically and recovers its topology.
+//!
+//! `ReconnectingSocket` can be used wherever `AmqpSocket` is used. It records frames which
+//! build topology while they are sent through it:
+//!
+//! - channel.open
+//! - exchange.declare and exchange.bind
+//! - queue.declare and queue.bind
+//! - basic.qos and basic.consume
+//!
+//! Records are dropped again by channel.close, exchange.delete, queue.delete, queue.unbind
+//! and basic.cancel. Channels are kept by id, so a channel which is closed and opened again
+//! is opened only once on replay.
+//!
+//! When the connection is lost, it connects again with backoff, runs the handshake, opens
+//! recorded channels and replays recorded frames in the order they were sent. Declarations
+//! made on a channel which is closed since then are replayed on a temporarily opened channel.
+//! Replies to replayed frames are consumed by `ReconnectingSocket` itself. If a server-named
+//! queue gets a new name, later frames referring the old name are rewritten.
+//!
+//! `connect` connects over TCP. Use `connect_with` and your own `Connector` to run on another
+//! transport.
+//!
+//! # Notice
+//! Frames which are in flight when the connection is lost may be lost, and delivery tags of
+//! unacknowledged messages become invalid after reconnection. Consumer tags server generated
+//! also change, because server generates them again.
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+
+use amqpr_codec::{AmqpString, Frame, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::BasicClass;
+use amqpr_codec::method::channel::ChannelClass;
+use amqpr_codec::method::connection::ConnectionClass;
+use amqpr_codec::method::exchange::ExchangeClass;
+use amqpr_codec::method::queue::QueueClass;
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use AmqpSocket;
+use channel::close::close_frame;
+use channel::open::open_frame;
+use handshake::{start_handshake, ConnectionInfo, Handshaker};
+use protocol_log;
+use metrics::Observer;
+use errors::*;
+
+const GLOBAL_CHANNEL_ID: u16 = 0;
+
+/// Connect to AMQP server at `addr`. Returned future is completed when the first
+/// connection is established, and yields `ReconnectingSocket`.
+///
+/// `make_handshaker` is called each time we connect to the server.
+pub fn connect<F, H>(
+    addr: SocketAddr,
+    make_handshaker: F,
+    option: ReconnectOption,
+    handle: &Handle,
+) -> Connecting<TcpConnector<F>>
+where
+    F: FnMut() -> H,
+    H: Handshaker + 'static,
+{
+    let connector = TcpConnector {
+        addr,
+        make_handshaker,
+    };
+    connect_with(connector, option, handle)
+}
+
+/// Same as `connect` but connects with `connector`.
+pub fn connect_with<C: Connector>(
+    connector: C,
+    option: ReconnectOption,
+    handle: &Handle,
+) -> Connecting<C> {
+    let mut socket = ReconnectingSocket {
+        connector,
+        option,
+        handle: handle.clone(),
+        state: ConnectionState::Disconnected,
+        info: None,
+        attempts: 0,
+        connections: 0,
+        topology: Topology::default(),
+        inbound: VecDeque::new(),
+        observer: None,
+    };
+    socket.state = ConnectionState::Connecting(socket.connector.connect(&socket.handle));
+
+    Connecting(Some(socket))
+}
+
+/// Makes a new connection each time `ReconnectingSocket` connects to the server.
+pub trait Connector {
+    type Socket: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>;
+
+    /// Returns a future of a socket whose handshake is completed.
+    fn connect(&mut self, handle: &Handle) -> Connect<Self::Socket>;
+}
+
+pub type Connect<S> = Box<dyn Future<Item = (ConnectionInfo, S), Error = Error>>;
+
+/// `Connector` which connects over TCP. Returned by `connect` function.
+pub struct TcpConnector<F> {
+    addr: SocketAddr,
+    make_handshaker: F,
+}
+
+impl<F, H> Connector for TcpConnector<F>
+where
+    F: FnMut() -> H,
+    H: Handshaker + 'static,
+{
+    type Socket = AmqpSocket;
+
+    fn connect(&mut self, handle: &Handle) -> Connect<AmqpSocket> {
+        let handshaker = (self.make_handshaker)();
+        let future = TcpStream::connect(&self.addr, handle)
+            .map_err(Error::from)
+            .and_then(move |socket| start_handshake(handshaker, socket));
+        Box::new(future)
+    }
+}
+
+/// How to retry connecting. Delay between attempts starts from `initial_backoff` and is
+/// doubled after each failure until it reaches `max_backoff`.
+///
+/// An attempt fails when connecting, handshake or replaying topology fails. Replaying fails
+/// when server closes the connection or a channel instead of replying, such as with
+/// `RESOURCE_LOCKED` on an exclusive queue.
+#[derive(Debug, Clone)]
+pub struct ReconnectOption {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up after this number of consecutive failed attempts. `None` means retrying
+    /// forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectOption {
+    fn default() -> ReconnectOption {
+        ReconnectOption {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Future being returned by `connect` function.
+pub struct Connecting<C: Connector>(Option<ReconnectingSocket<C>>);
+
+impl<C: Connector> Future for Connecting<C> {
+    type Item = ReconnectingSocket<C>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        try_ready!(self.0
+            .as_mut()
+            .expect("You never poll Connecting after it is completed")
+            .poll_connected());
+        Ok(Async::Ready(self.0.take().unwrap()))
+    }
+}
+
+/// `Stream` and `Sink` of `Frame` which survives connection loss.
+pub struct ReconnectingSocket<C: Connector> {
+    connector: C,
+    option: ReconnectOption,
+    handle: Handle,
+    state: ConnectionState<C::Socket>,
+    info: Option<ConnectionInfo>,
+    attempts: u32,
+    connections: u32,
+    topology: Topology,
+    // Frames which are received while replaying topology.
+    inbound: VecDeque<Frame>,
+    observer: Option<Arc<dyn Observer>>,
+}
+
+enum ConnectionState<S> {
+    Disconnected,
+    Waiting(Timeout),
+    Connecting(Connect<S>),
+    Replaying {
+        socket: S,
+        plan: Vec<Planned>,
+        idx: usize,
+        is_waiting_reply: bool,
+    },
+    Connected(S),
+}
+
+impl<C: Connector> ReconnectingSocket<C> {
+    /// `ConnectionInfo` of current connection.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
+
+    /// How many times this socket reconnected.
+    pub fn reconnects(&self) -> u32 {
+        self.connections.saturating_sub(1)
+    }
+
+    /// Report reconnections to `observer`. Wrap this socket with `observe_frames` to report
+    /// frames as well.
+    pub fn set_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    /// Drop current connection and wait for backoff before connecting again.
+    fn disconnected(&mut self, cause: Error) -> Result<(), Error> {
+        warn!("Connection is lost : {}", cause);
+        self.wait_backoff()
+    }
+
+    /// Count a failed attempt to connect, and wait for backoff before trying again.
+    /// Fails with `ReconnectFailed` error after `max_attempts` failures.
+    fn attempt_failed(&mut self, cause: Error) -> Result<(), Error> {
+        self.attempts += 1;
+        warn!("Attempt {} to connect failed : {}", self.attempts, cause);
+        if let Some(max) = self.option.max_attempts {
+            if self.attempts >= max {
+                self.state = ConnectionState::Disconnected;
+                return Err(Error::with_chain(
+                    cause,
+                    ErrorKind::ReconnectFailed(self.attempts),
+                ));
+            }
+        }
+        self.wait_backoff()
+    }
+
+    fn wait_backoff(&mut self) -> Result<(), Error> {
+        let backoff = self.backoff();
+        info!("Reconnect after {:?}", backoff);
+        self.state = ConnectionState::Waiting(Timeout::new(backoff, &self.handle)?);
+        Ok(())
+    }
+
+    fn backoff(&self) -> Duration {
+        let mut backoff = self.option.initial_backoff;
+        for _ in 1..self.attempts {
+            backoff *= 2;
+            if backoff >= self.option.max_backoff {
+                return self.option.max_backoff;
+            }
+        }
+        backoff
+    }
+
+    /// Drive reconnection until we get connected socket whose topology is recovered.
+    fn poll_connected(&mut self) -> Poll<(), Error> {
+        use self::ConnectionState::*;
+
+        loop {
+            let next = match self.state {
+                Connected(_) => return Ok(Async::Ready(())),
+                Disconnected => Connecting(self.connector.connect(&self.handle)),
+                Waiting(ref mut timeout) => {
+                    try_ready!(timeout.poll());
+                    Connecting(self.connector.connect(&self.handle))
+                }
+                Connecting(ref mut connecting) => match connecting.poll() {
+                    Ok(Async::Ready((info, socket))) => {
+                        info!("Connection is established. Start to recover topology");
+                        self.info = Some(info);
+                        Replaying {
+                            socket,
+                            plan: self.topology.plan(),
+                            idx: 0,
+                            is_waiting_reply: false,
+                        }
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.attempt_failed(e)?;
+                        continue;
+                    }
+                },
+                Replaying {
+                    ref mut socket,
+                    ref plan,
+                    ref mut idx,
+                    ref mut is_waiting_reply,
+                } => match self.topology
+                    .replay(socket, plan, idx, is_waiting_reply, &mut self.inbound)
+                {
+                    Ok(Async::Ready(())) => {
+                        info!("Topology is recovered");
+                        self.connections += 1;
+                        self.attempts = 0;
+                        if self.connections > 1 {
+                            if let Some(ref observer) = self.observer {
+                                observer.reconnected();
+                            }
+                        }
+                        continue_with_connected(&mut self.state);
+                        continue;
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.attempt_failed(e)?;
+                        continue;
+                    }
+                },
+            };
+            self.state = next;
+        }
+    }
+
+    fn socket(&mut self) -> &mut C::Socket {
+        match self.state {
+            ConnectionState::Connected(ref mut socket) => socket,
+            _ => unreachable!("You never use socket before it gets connected"),
+        }
+    }
+}
+
+fn continue_with_connected<S>(state: &mut ConnectionState<S>) {
+    let replaced = ::std::mem::replace(state, ConnectionState::Disconnected);
+    *state = match replaced {
+        ConnectionState::Replaying { socket, .. } => ConnectionState::Connected(socket),
+        _ => unreachable!(),
+    };
+}
+
+impl<C: Connector> Stream for ReconnectingSocket<C> {
+    type Item = Frame;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, Error> {
+        loop {
+            try_ready!(self.poll_connected());
+            if let Some(frame) = self.inbound.pop_front() {
+                return Ok(Async::Ready(Some(frame)));
+            }
+
+            let frame = match self.socket().poll() {
+                Ok(Async::Ready(Some(frame))) => frame,
+                Ok(Async::Ready(None)) => {
+                    self.disconnected(ErrorKind::UnexpectedConnectionClose.into())?;
+                    continue;
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.disconnected(e)?;
+                    continue;
+                }
+            };
+
+            let close = frame
+                .method()
+                .and_then(|m| m.connection())
+                .and_then(|c| c.close())
+                .cloned();
+            if let Some(close) = close {
+                // Best effort. Server closes the socket anyway.
+                let close_ok = Frame::new_method(
+                    GLOBAL_CHANNEL_ID,
+                    MethodPayload::Connection(ConnectionClass::CloseOk),
+                );
+                let _ = self.socket().start_send(close_ok);
+                let _ = self.socket().poll_complete();
+                let cause = ErrorKind::ConnectionClosedByServer(
+                    close.reply_code,
+                    close.reply_text.to_string(),
+                );
+                self.disconnected(cause.into())?;
+                continue;
+            }
+
+            self.topology.observe_inbound(&frame);
+            return Ok(Async::Ready(Some(frame)));
+        }
+    }
+}
+
+impl<C: Connector> Sink for ReconnectingSocket<C> {
+    type SinkItem = Frame;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Frame) -> StartSend<Frame, Error> {
+        loop {
+            if let Async::NotReady = self.poll_connected()? {
+                return Ok(AsyncSink::NotReady(item));
+            }
+
+            // Record the name user knows, which is renamed again on every reconnection.
+            let mut renamed = item.clone();
+            self.topology.rename_queue(&mut renamed);
+            match self.socket().start_send(renamed) {
+                Ok(AsyncSink::Ready) => {
+                    self.topology.observe_outgoing(item);
+                    return Ok(AsyncSink::Ready);
+                }
+                Ok(AsyncSink::NotReady(_)) => return Ok(AsyncSink::NotReady(item)),
+                Err(e) => self.disconnected(e)?,
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        loop {
+            try_ready!(self.poll_connected());
+            match self.socket().poll_complete() {
+                Ok(polled) => return Ok(polled),
+                Err(e) => self.disconnected(e)?,
+            }
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        match self.state {
+            ConnectionState::Connected(ref mut socket) => socket.close(),
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+// Topology {{{
+#[derive(Default)]
+struct Topology {
+    // Open channels in the order they were opened.
+    channels: Vec<u16>,
+    // Declarations, bindings, qos and consumers in the order they were sent.
+    entries: Vec<Entry>,
+    next_id: usize,
+    // Ids of `queue.declare` and `basic.consume` entries waiting for reply, for each channel.
+    pending_declares: HashMap<u16, VecDeque<usize>>,
+    pending_consumes: HashMap<u16, VecDeque<usize>>,
+    // Queue name which server replied at first, for each `queue.declare` entry.
+    declared_names: HashMap<usize, AmqpString>,
+    // Consumer tag which server replied at first, for each `basic.consume` entry.
+    consumer_tags: HashMap<usize, AmqpString>,
+    // Map from the name user knows to the current name.
+    renames: HashMap<AmqpString, AmqpString>,
+}
+
+struct Entry {
+    id: usize,
+    frame: Frame,
+}
+
+/// A frame being replayed. `entry` is `None` for `channel.open` and `channel.close`.
+struct Planned {
+    entry: Option<usize>,
+    frame: Frame,
+}
+
+impl Topology {
+    fn observe_outgoing(&mut self, frame: Frame) {
+        let channel = frame.header.channel;
+        match frame.payload {
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Open(_))) => {
+                self.channels.retain(|c| *c != channel);
+                self.channels.push(channel);
+                debug!("Record topology : {}", protocol_log::display(&frame));
+                return;
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Close(_))) => {
+                self.close_channel(channel);
+                return;
+            }
+            FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Delete(ref m))) => {
+                let exchange = &m.exchange;
+                self.remove(|_, e| match e.frame.payload {
+                    FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Declare(ref d))) => {
+                        d.exchange == *exchange
+                    }
+                    FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Bind(ref b))) => {
+                        b.destination == *exchange || b.source == *exchange
+                    }
+                    FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(ref b))) => {
+                        b.exchange == *exchange
+                    }
+                    _ => false,
+                });
+                return;
+            }
+            FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Unbind(ref m))) => {
+                self.remove(|_, e| match e.frame.payload {
+                    FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Bind(ref b))) => {
+                        b.destination == m.destination && b.source == m.source
+                            && b.routing_key == m.routing_key
+                            && b.arguments == m.arguments
+                    }
+                    _ => false,
+                });
+                return;
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Delete(ref m))) => {
+                let queue = &m.queue;
+                self.remove(|t, e| match e.frame.payload {
+                    FramePayload::Method(MethodPayload::Queue(QueueClass::Declare(ref d))) => {
+                        d.queue == *queue || t.declared_names.get(&e.id) == Some(queue)
+                    }
+                    FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(ref b))) => {
+                        b.queue == *queue
+                    }
+                    FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(ref c))) => {
+                        c.queue == *queue
+                    }
+                    _ => false,
+                });
+                return;
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Unbind(ref m))) => {
+                self.remove(|_, e| match e.frame.payload {
+                    FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(ref b))) => {
+                        b.queue == m.queue && b.exchange == m.exchange
+                            && b.routing_key == m.routing_key
+                            && b.arguments == m.arguments
+                    }
+                    _ => false,
+                });
+                return;
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Cancel(ref m))) => {
+                self.cancel(channel, &m.consumer_tag);
+                return;
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Declare(ref m))) => {
+                if !m.no_wait {
+                    self.pending_declares
+                        .entry(channel)
+                        .or_default()
+                        .push_back(self.next_id);
+                }
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(ref m))) => {
+                if !m.no_wait {
+                    self.pending_consumes
+                        .entry(channel)
+                        .or_default()
+                        .push_back(self.next_id);
+                }
+            }
+            FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Declare(_)))
+            | FramePayload::Method(MethodPayload::Exchange(ExchangeClass::Bind(_)))
+            | FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(_)))
+            | FramePayload::Method(MethodPayload::Basic(BasicClass::Qos(_))) => {}
+            _ => return,
+        }
+        debug!("Record topology : {}", protocol_log::display(&frame));
+        self.entries.push(Entry {
+            id: self.next_id,
+            frame,
+        });
+        self.next_id += 1;
+    }
+
+    fn observe_inbound(&mut self, frame: &Frame) {
+        let channel = frame.header.channel;
+        match frame.payload {
+            FramePayload::Method(MethodPayload::Queue(QueueClass::DeclareOk(ref m))) => {
+                let id = self.pending_declares
+                    .get_mut(&channel)
+                    .and_then(|pendings| pendings.pop_front());
+                if let Some(id) = id {
+                    self.declared_names.insert(id, m.queue.clone());
+                }
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::ConsumeOk(ref m))) => {
+                let id = self.pending_consumes
+                    .get_mut(&channel)
+                    .and_then(|pendings| pendings.pop_front());
+                if let Some(id) = id {
+                    self.consumer_tags.insert(id, m.consumer_tag.clone());
+                }
+            }
+            // Server cancels a consumer when its queue is deleted.
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Cancel(ref m))) => {
+                self.cancel(channel, &m.consumer_tag)
+            }
+            FramePayload::Method(MethodPayload::Channel(ChannelClass::Close(_))) => {
+                self.close_channel(channel)
+            }
+            _ => {}
+        }
+    }
+
+    /// Forget the channel and consumers and qos on it. Declarations and bindings made on it
+    /// are kept.
+    fn close_channel(&mut self, channel: u16) {
+        self.channels.retain(|c| *c != channel);
+        self.pending_declares.remove(&channel);
+        self.pending_consumes.remove(&channel);
+        self.remove(|_, e| {
+            e.frame.header.channel == channel
+                && matches!(
+                    e.frame.payload,
+                    FramePayload::Method(MethodPayload::Basic(BasicClass::Qos(_)))
+                        | FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(_)))
+                )
+        });
+    }
+
+    fn cancel(&mut self, channel: u16, consumer_tag: &AmqpString) {
+        self.remove(|t, e| match e.frame.payload {
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(ref c))) => {
+                e.frame.header.channel == channel
+                    && (c.consumer_tag == *consumer_tag
+                        || t.consumer_tags.get(&e.id) == Some(consumer_tag))
+            }
+            _ => false,
+        });
+    }
+
+    fn remove<P>(&mut self, mut predicate: P)
+    where
+        P: FnMut(&Topology, &Entry) -> bool,
+    {
+        let removed: Vec<usize> = self.entries
+            .iter()
+            .filter(|entry| predicate(self, entry))
+            .map(|entry| entry.id)
+            .collect();
+        for id in removed {
+            let idx = self.entries.iter().position(|e| e.id == id).unwrap();
+            let entry = self.entries.remove(idx);
+            debug!("Forget topology : {}", protocol_log::display(&entry.frame));
+            self.declared_names.remove(&id);
+            self.consumer_tags.remove(&id);
+        }
+    }
+
+    fn rename_queue(&self, frame: &mut Frame) {
+        let queue = match frame.payload {
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Bind(ref mut m))) => {
+                &mut m.queue
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Unbind(ref mut m))) => {
+                &mut m.queue
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Purge(ref mut m))) => {
+                &mut m.queue
+            }
+            FramePayload::Method(MethodPayload::Queue(QueueClass::Delete(ref mut m))) => {
+                &mut m.queue
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Consume(ref mut m))) => {
+                &mut m.queue
+            }
+            FramePayload::Method(MethodPayload::Basic(BasicClass::Get(ref mut m))) => {
+                &mut m.queue
+            }
+            _ => return,
+        };
+        if let Some(renamed) = self.renames.get(queue) {
+            *queue = renamed.clone();
+        }
+    }
+
+    /// Frames to replay. Channels are opened first, and channels which are only needed by
+    /// declarations are closed at last.
+    fn plan(&self) -> Vec<Planned> {
+        let mut channels = self.channels.clone();
+        for entry in &self.entries {
+            if !channels.contains(&entry.frame.header.channel) {
+                channels.push(entry.frame.header.channel);
+            }
+        }
+
+        let mut plan: Vec<Planned> = channels
+            .iter()
+            .map(|c| Planned {
+                entry: None,
+                frame: open_frame(*c),
+            })
+            .collect();
+        plan.extend(self.entries.iter().map(|entry| Planned {
+            entry: Some(entry.id),
+            frame: entry.frame.clone(),
+        }));
+        plan.extend(
+            channels
+                .iter()
+                .filter(|c| !self.channels.contains(c))
+                .map(|c| Planned {
+                    entry: None,
+                    frame: close_frame(*c),
+                }),
+        );
+        plan
+    }
+
+    /// Send planned frames one by one, waiting for reply if needed.
+    /// Frames which are not reply are pushed into `inbound`. Fails if server closes the
+    /// connection or a channel, because the reply never comes then.
+    fn replay<S>(
+        &mut self,
+        socket: &mut S,
+        plan: &[Planned],
+        idx: &mut usize,
+        is_waiting_reply: &mut bool,
+        inbound: &mut VecDeque<Frame>,
+    ) -> Poll<(), Error>
+    where
+        S: Stream<Item = Frame, Error = Error> + Sink<SinkItem = Frame, SinkError = Error>,
+    {
+        loop {
+            if *is_waiting_reply {
+                let frame = try_stream_ready!(socket.poll());
+                if let Some(error) = closed_by_server(socket, &frame) {
+                    return Err(error);
+                }
+                if is_reply(&plan[*idx].frame, &frame) {
+                    if let Some(id) = plan[*idx].entry {
+                        self.on_reply(id, &frame);
+                    }
+                    *is_waiting_reply = false;
+                    *idx += 1;
+                } else {
+                    inbound.push_back(frame);
+                }
+                continue;
+            }
+
+            if *idx == plan.len() {
+                return socket.poll_complete();
+            }
+
+            let mut frame = plan[*idx].frame.clone();
+            self.rename_queue(&mut frame);
+            debug!("Replay topology : {}", protocol_log::display(&frame));
+            if let AsyncSink::NotReady(_) = socket.start_send(frame)? {
+                try_ready!(socket.poll_complete());
+                continue;
+            }
+
+            if needs_reply(&plan[*idx].frame) {
+                socket.poll_complete()?;
+                *is_waiting_reply = true;
+            } else {
+                *idx += 1;
+            }
+        }
+    }
+
+    fn on_reply(&mut self, id: usize, reply: &Frame) {
+        let declare_ok = reply
+            .method()
+            .and_then(|m| m.queue())
+            .and_then(|c| c.declare_ok());
+        if let (Some(declare_ok), Some(name)) = (declare_ok, self.declared_names.get(&id)) {
+            if declare_ok.queue != *name {
+                info!("Queue {} is renamed to {}", &**name, &*declare_ok.queue);
+                self.renames.insert(name.clone(), declare_ok.queue.clone());
+            }
+        }
+    }
+}
+
+/// Error if `frame` is `close` method of the connection or a channel. `close-ok` of the
+/// connection is replied in best effort, because the connection is dropped anyway.
+fn closed_by_server<S>(socket: &mut S, frame: &Frame) -> Option<Error>
+where
+    S: Sink<SinkItem = Frame, SinkError = Error>,
+{
+    match frame.method() {
+        Some(MethodPayload::Connection(ConnectionClass::Close(close))) => {
+            let close_ok = Frame::new_method(
+                GLOBAL_CHANNEL_ID,
+                MethodPayload::Connection(ConnectionClass::CloseOk),
+            );
+            let _ = socket.start_send(close_ok);
+            let _ = socket.poll_complete();
+            let text = close.reply_text.to_string();
+            Some(ErrorKind::ConnectionClosedByServer(close.reply_code, text).into())
+        }
+        Some(MethodPayload::Channel(ChannelClass::Close(close))) => {
+            let text = close.reply_text.to_string();
+            Some(ErrorKind::ChannelClosedByServer(frame.header.channel, close.reply_code, text).into())
+        }
+        _ => None,
+    }
+}
+
+fn needs_reply(frame: &Frame) -> bool {
+    match frame.method() {
+        Some(MethodPayload::Channel(ChannelClass::Open(_))) => true,
+        Some(MethodPayload::Channel(ChannelClass::Close(_))) => true,
+        Some(MethodPayload::Exchange(ExchangeClass::Declare(m))) => !m.no_wait,
+        Some(MethodPayload::Exchange(ExchangeClass::Bind(m))) => !m.no_wait,
+        Some(MethodPayload::Queue(QueueClass::Declare(m))) => !m.no_wait,
+        Some(MethodPayload::Queue(QueueClass::Bind(m))) => !m.no_wait,
+        Some(MethodPayload::Basic(BasicClass::Qos(_))) => true,
+        Some(MethodPayload::Basic(BasicClass::Consume(m))) => !m.no_wait,
+        _ => false,
+    }
+}
+
+fn is_reply(sent: &Frame, received: &Frame) -> bool {
+    if sent.header.channel != received.header.channel {
+        return false;
+    }
+    match (sent.method(), received.method()) {
+        (Some(MethodPayload::Channel(sent)), Some(MethodPayload::Channel(received))) => {
+            (sent.open().is_some() && received.open_ok().is_some())
+                || (sent.close().is_some() && received.close_ok().is_some())
+        }
+        (Some(MethodPayload::Exchange(sent)), Some(MethodPayload::Exchange(received))) => {
+            (sent.declare().is_some() && received.declare_ok().is_some())
+                || (sent.bind().is_some() && received.bind_ok().is_some())
+        }
+        (Some(MethodPayload::Queue(sent)), Some(MethodPayload::Queue(received))) => {
+            (sent.declare().is_some() && received.declare_ok().is_some())
+                || (sent.bind().is_some() && received.bind_ok().is_some())
+        }
+        (Some(MethodPayload::Basic(sent)), Some(MethodPayload::Basic(received))) => {
+            (sent.qos().is_some() && received.qos_ok().is_some())
+                || (sent.consume().is_some() && received.consume_ok().is_some())
+        }
+        _ => false,
+    }
+}
+// }}}
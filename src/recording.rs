@@ -0,0 +1,500 @@
+//! Recording of AMQP traffic and its replay.
+//!
+//! `Recorder` wraps a transport such as `TcpStream` and writes every frame going through
+//! it to a file. Give it to `start_handshake` instead of the bare transport. `Replay`
+//! reads a recording and behaves as the transport, so that you can feed what the server
+//! sent back into client code offline.
+//!
+//! # File format
+//!
+//! A recording is UTF-8 text with one frame per line. Lines starting with `#` are
+//! comments. Each line has five fields separated by a space.
+//!
+//! ```text
+//! # amqpr-api recording 1
+//! 1508371200000000 > protocol-header - 414d515000000901
+//! 1508371200012345 < method 0 01000000000020000a000a...ce
+//! ```
+//!
+//! 1. Timestamp in microseconds since UNIX epoch.
+//! 2. Direction. `>` is from client to server and `<` is from server to client.
+//! 3. Frame type; `protocol-header`, `method`, `content-header`, `content-body`,
+//!    `heartbeat`, or the frame type octet in decimal if it is unknown.
+//! 4. Channel id. `-` for `protocol-header`.
+//! 5. The whole frame as it is on the wire in lowercase hex, including the frame header
+//!    and the frame end octet.
+//!
+//! Type and channel are redundant with the bytes. They are there for `grep`.
+//!
+//! `Start-Ok` and `Secure-Ok` methods carry credentials such as the password of `PLAIN`
+//! mechanism, so only their class and method id are recorded. Other frames are recorded
+//! as they are. Be careful of message bodies and headers before sharing a recording.
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILE_HEADER: &str = "# amqpr-api recording 1";
+const FRAME_END: u8 = 0xCE;
+const HEARTBEAT: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From client to server.
+    Outbound,
+    /// From server to client.
+    Inbound,
+}
+
+/// A line of recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Microseconds since UNIX epoch.
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// Protocol header or a whole frame.
+    pub bytes: Bytes,
+}
+
+impl Record {
+    fn is_protocol_header(&self) -> bool {
+        self.bytes.starts_with(b"AMQP")
+    }
+
+    fn frame_type(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    fn channel(&self) -> u16 {
+        (u16::from(self.bytes[1]) << 8) | u16::from(self.bytes[2])
+    }
+
+    fn is_heartbeat(&self) -> bool {
+        !self.is_protocol_header() && self.frame_type() == HEARTBEAT
+    }
+
+    /// Parse a line of recording. Returns `None` for a comment or an empty line.
+    pub fn parse(line: &str) -> io::Result<Option<Record>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() != 5 {
+            return Err(invalid_data(format!("Record must have 5 fields : {}", line)));
+        }
+        let timestamp = fields[0]
+            .parse()
+            .map_err(|_| invalid_data(format!("Invalid timestamp : {}", fields[0])))?;
+        let direction = match fields[1] {
+            ">" => Direction::Outbound,
+            "<" => Direction::Inbound,
+            dir => return Err(invalid_data(format!("Invalid direction : {}", dir))),
+        };
+        let bytes = decode_hex(fields[4])
+            .ok_or_else(|| invalid_data(format!("Invalid hex : {}", fields[4])))?;
+        let is_frame = bytes.len() >= 8 && bytes.last() == Some(&FRAME_END);
+        if !bytes.starts_with(b"AMQP") && !is_frame {
+            return Err(invalid_data(format!("Not a frame : {}", fields[4])));
+        }
+        Ok(Some(Record {
+            timestamp,
+            direction,
+            bytes: Bytes::from(bytes),
+        }))
+    }
+
+    /// Read every record in `reader`.
+    pub fn read_all<R: Read>(reader: R) -> io::Result<Vec<Record>> {
+        let mut records = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            if let Some(record) = Record::parse(&line?)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Outbound => ">",
+            Direction::Inbound => "<",
+        };
+        if self.is_protocol_header() {
+            return write!(f, "{} {} protocol-header - {}", self.timestamp, direction, Hex(&self.bytes));
+        }
+        let typ = match self.frame_type() {
+            1 => "method".to_string(),
+            2 => "content-header".to_string(),
+            3 => "content-body".to_string(),
+            HEARTBEAT => "heartbeat".to_string(),
+            other => other.to_string(),
+        };
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.timestamp,
+            direction,
+            typ,
+            self.channel(),
+            Hex(&self.bytes)
+        )
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// Connection class, and `Start-Ok` and `Secure-Ok` method ids.
+const CONNECTION_CLASS: u16 = 10;
+const CREDENTIAL_METHODS: [u16; 2] = [11, 21];
+
+/// Drop arguments of a method which carries credentials.
+fn redact(bytes: Bytes) -> Bytes {
+    let record = Record {
+        timestamp: 0,
+        direction: Direction::Outbound,
+        bytes,
+    };
+    let is_method = !record.is_protocol_header() && record.frame_type() == 1 && record.bytes.len() >= 11;
+    if !is_method {
+        return record.bytes;
+    }
+    let class_id = (u16::from(record.bytes[7]) << 8) | u16::from(record.bytes[8]);
+    let method_id = (u16::from(record.bytes[9]) << 8) | u16::from(record.bytes[10]);
+    if class_id != CONNECTION_CLASS || !CREDENTIAL_METHODS.contains(&method_id) {
+        return record.bytes;
+    }
+    // Frame header with payload size 4, class and method id, and frame end.
+    let mut redacted = record.bytes[..3].to_vec();
+    redacted.extend_from_slice(&[0, 0, 0, 4]);
+    redacted.extend_from_slice(&record.bytes[7..11]);
+    redacted.push(FRAME_END);
+    Bytes::from(redacted)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1_000_000 + u64::from(d.subsec_micros()))
+        .unwrap_or(0)
+}
+
+/// Splits a byte stream into protocol header and frames.
+struct Splitter {
+    buf: BytesMut,
+}
+
+impl Splitter {
+    fn new() -> Splitter {
+        Splitter {
+            buf: BytesMut::new(),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn next(&mut self) -> Option<Bytes> {
+        if self.buf.starts_with(b"AMQP") {
+            if self.buf.len() < 8 {
+                return None;
+            }
+            return Some(self.buf.split_to(8).freeze());
+        }
+        if self.buf.len() < 7 {
+            return None;
+        }
+        let size = self.buf[3..7]
+            .iter()
+            .fold(0usize, |size, b| (size << 8) | *b as usize);
+        // Frame header, payload and frame end.
+        let len = 7 + size + 1;
+        if self.buf.len() < len {
+            return None;
+        }
+        Some(self.buf.split_to(len).freeze())
+    }
+}
+
+/// Transport which writes every frame going through it to `W`.
+///
+/// A failure of writing a record is logged once, and the recording stops. It never
+/// breaks the connection.
+pub struct Recorder<T, W: Write = BufWriter<File>> {
+    io: T,
+    writer: Option<W>,
+    inbound: Splitter,
+    outbound: Splitter,
+}
+
+impl<T> Recorder<T, BufWriter<File>> {
+    /// Record to a file at `path`. The file is truncated if it exists.
+    pub fn create<P: AsRef<Path>>(io: T, path: P) -> io::Result<Recorder<T, BufWriter<File>>> {
+        let file = File::create(path)?;
+        Ok(Recorder::new(io, BufWriter::new(file)))
+    }
+}
+
+impl<T, W: Write> Recorder<T, W> {
+    pub fn new(io: T, writer: W) -> Recorder<T, W> {
+        let mut recorder = Recorder {
+            io,
+            writer: Some(writer),
+            inbound: Splitter::new(),
+            outbound: Splitter::new(),
+        };
+        recorder.write_line(FILE_HEADER);
+        recorder
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let splitter = match direction {
+            Direction::Outbound => &mut self.outbound,
+            Direction::Inbound => &mut self.inbound,
+        };
+        splitter.push(bytes);
+        let mut lines = Vec::new();
+        while let Some(bytes) = splitter.next() {
+            let bytes = match direction {
+                Direction::Outbound => redact(bytes),
+                Direction::Inbound => bytes,
+            };
+            let record = Record {
+                timestamp: now(),
+                direction,
+                bytes,
+            };
+            lines.push(record.to_string());
+        }
+        for line in lines {
+            self.write_line(&line);
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let result = match self.writer {
+            Some(ref mut writer) => writeln!(writer, "{}", line).and_then(|()| writer.flush()),
+            None => return,
+        };
+        if let Err(e) = result {
+            warn!("Stop recording because of failure to write a record : {}", e);
+            self.writer = None;
+        }
+    }
+}
+
+impl<T: Read, W: Write> Read for Recorder<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        self.record(Direction::Inbound, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write, W: Write> Write for Recorder<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.record(Direction::Outbound, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead, W: Write> AsyncRead for Recorder<T, W> {}
+
+impl<T: AsyncWrite, W: Write> AsyncWrite for Recorder<T, W> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// Transport which replays inbound frames of a recording.
+///
+/// Inbound frames are read in the recorded order, but a frame recorded after an outbound
+/// frame is not read until client writes a frame. Timestamps are ignored. Heartbeat frames
+/// client writes are ignored, because they depend on timing.
+///
+/// Each frame client writes is compared with the outbound frame in the recording by frame
+/// type and channel, plus class and method id for a method frame. Whole bytes are not
+/// compared because field tables such as client properties have no fixed order.
+/// A mismatch is logged as a warning, or is an `InvalidData` error in strict mode.
+/// Reading returns EOF after the last record.
+pub struct Replay {
+    records: VecDeque<Record>,
+    /// Offset in the first record which is partially read.
+    offset: usize,
+    outbound: Splitter,
+    is_strict: bool,
+    task: Option<Task>,
+}
+
+impl Replay {
+    /// Read a recording from a file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        Ok(Replay::new(Record::read_all(File::open(path)?)?))
+    }
+
+    pub fn new(records: Vec<Record>) -> Replay {
+        Replay {
+            records: records
+                .into_iter()
+                .filter(|r| !(r.direction == Direction::Outbound && r.is_heartbeat()))
+                .collect(),
+            offset: 0,
+            outbound: Splitter::new(),
+            is_strict: false,
+            task: None,
+        }
+    }
+
+    /// Fail on the first frame which does not match the recording.
+    pub fn strict(mut self) -> Replay {
+        self.is_strict = true;
+        self
+    }
+
+    /// Returns true if every record is replayed.
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    fn check_outbound(&mut self, sent: Record) -> io::Result<()> {
+        if sent.is_heartbeat() {
+            return Ok(());
+        }
+        let idx = self.records
+            .iter()
+            .position(|r| r.direction == Direction::Outbound);
+        let result = match idx.and_then(|idx| self.records.remove(idx)) {
+            Some(ref recorded) if summary(recorded) == summary(&sent) => Ok(()),
+            Some(recorded) => Err(format!(
+                "Client sent {} but {} is recorded",
+                summary(&sent),
+                summary(&recorded)
+            )),
+            None => Err(format!("Client sent {} after the recording", summary(&sent))),
+        };
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+        match result {
+            Err(ref msg) if self.is_strict => Err(invalid_data(msg.clone())),
+            Err(msg) => {
+                warn!("{}", msg);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+/// Frame type, channel, and class and method id of a method frame.
+fn summary(record: &Record) -> String {
+    if record.is_protocol_header() {
+        return "protocol-header".into();
+    }
+    let bytes = &record.bytes;
+    match record.frame_type() {
+        1 if bytes.len() >= 11 => format!(
+            "method {}.{} on channel {}",
+            (u16::from(bytes[7]) << 8) | u16::from(bytes[8]),
+            (u16::from(bytes[9]) << 8) | u16::from(bytes[10]),
+            record.channel()
+        ),
+        typ => format!("frame type {} on channel {}", typ, record.channel()),
+    }
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.records.front() {
+            Some(record) if record.direction == Direction::Inbound => {
+                let rest = &record.bytes[self.offset..];
+                let n = ::std::cmp::min(rest.len(), buf.len());
+                buf[..n].copy_from_slice(&rest[..n]);
+                n
+            }
+            Some(_) => {
+                self.task = Some(task::current());
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            None => return Ok(0),
+        };
+        self.offset += n;
+        if self.offset == self.records[0].bytes.len() {
+            self.records.pop_front();
+            self.offset = 0;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outbound.push(buf);
+        while let Some(bytes) = self.outbound.next() {
+            let sent = Record {
+                timestamp: now(),
+                direction: Direction::Outbound,
+                bytes,
+            };
+            self.check_outbound(sent)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Replay {}
+
+impl AsyncWrite for Replay {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
@@ -0,0 +1,331 @@
+use amqpr_codec::AmqpString;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::task::{self, Task};
+use futures::unsync::oneshot;
+
+use tokio_core::reactor::{Handle, Timeout};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use basic::consume::StartConsumeOption;
+use basic::deliver::DeliveredItem;
+use basic::publish::PublishItem;
+use connection::{Channel, Consumer, Reply, Written};
+use queue::declare::{DeclareQueueOption, DeclareResult};
+use errors::*;
+
+pub use direct_reply_to::DIRECT_REPLY_TO;
+
+/// Where replies are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyQueue {
+    /// Declare a server-named exclusive queue for this client.
+    Exclusive,
+    /// Use RabbitMQ's direct reply-to. No queue is declared.
+    DirectReplyTo,
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcClientOption {
+    pub reply_queue: ReplyQueue,
+    /// Timeout of each request which is sent by `RpcClient::call`.
+    pub timeout: Duration,
+}
+
+impl Default for RpcClientOption {
+    fn default() -> RpcClientOption {
+        RpcClientOption {
+            reply_queue: ReplyQueue::Exclusive,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A client of request/reply pattern on a channel.
+///
+/// Each request is published with `reply_to` and a generated `correlation_id`, and the
+/// future returned by `call` resolves to the reply having the same `correlation_id`.
+/// Replies are consumed by a background task spawned on the `Handle`.
+///
+/// You can clone it to send requests from several places. When the last clone is dropped,
+/// the background task finishes and requests waiting for replies fail with
+/// `RpcClientClosed` error. The consumer of replies stays on the channel until the channel
+/// is closed.
+#[derive(Clone)]
+pub struct RpcClient {
+    channel: Channel,
+    reply_to: AmqpString,
+    timeout: Duration,
+    handle: Handle,
+    pending: Rc<RefCell<Pending>>,
+    _alive: Rc<Alive>,
+}
+
+struct Pending {
+    next_id: u64,
+    waiters: HashMap<String, oneshot::Sender<Result<DeliveredItem, Error>>>,
+    /// Why replies are not received any more.
+    closed: Option<String>,
+    router: Option<Task>,
+}
+
+/// Shared by every clone of `RpcClient`. Stops `ReplyRouter` when it is dropped.
+struct Alive(Rc<RefCell<Pending>>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        let mut pending = self.0.borrow_mut();
+        pending.close("RpcClient is dropped".into());
+        if let Some(task) = pending.router.take() {
+            task.notify();
+        }
+    }
+}
+
+impl Pending {
+    fn close(&mut self, reason: String) {
+        for (_, waiter) in self.waiters.drain() {
+            let _ = waiter.send(Err(ErrorKind::RpcClientClosed(reason.clone()).into()));
+        }
+        self.closed = Some(reason);
+    }
+}
+
+impl RpcClient {
+    /// Prepare a reply queue on given channel, and start consuming it.
+    pub fn start(channel: Channel, option: RpcClientOption, handle: &Handle) -> RpcClientStarted {
+        let declaring = match option.reply_queue {
+            ReplyQueue::Exclusive => {
+                let declare = DeclareQueueOption {
+                    name: "".into(),
+                    is_passive: false,
+                    is_durable: false,
+                    is_exclusive: true,
+                    is_auto_delete: true,
+                };
+                Some(channel.declare_queue(declare))
+            }
+            ReplyQueue::DirectReplyTo => None,
+        };
+
+        RpcClientStarted {
+            channel: Some(channel),
+            declaring,
+            timeout: option.timeout,
+            handle: handle.clone(),
+        }
+    }
+
+    /// Name of the queue replies are sent to.
+    pub fn reply_to(&self) -> &str {
+        &self.reply_to
+    }
+
+    /// Publish a request, and wait for the reply until the timeout in `RpcClientOption`.
+    /// `reply_to` and `correlation_id` properties of `item` are overwritten.
+    pub fn call(&self, item: PublishItem) -> RpcResponse {
+        self.call_with_timeout(item, self.timeout)
+    }
+
+    /// Same as `call` but with given timeout.
+    pub fn call_with_timeout(&self, mut item: PublishItem, timeout: Duration) -> RpcResponse {
+        let (tx, rx) = oneshot::channel();
+        let correlation_id = {
+            let mut pending = self.pending.borrow_mut();
+            pending.next_id += 1;
+            let correlation_id = format!("amqpr-rpc.{}", pending.next_id);
+            match pending.closed {
+                Some(ref reason) => {
+                    let _ = tx.send(Err(ErrorKind::RpcClientClosed(reason.clone()).into()));
+                }
+                None => {
+                    pending.waiters.insert(correlation_id.clone(), tx);
+                }
+            }
+            correlation_id
+        };
+
+        item.header.reply_to = Some(self.reply_to.clone());
+        item.header.correlation_id = Some(AmqpString::from(correlation_id.clone()));
+        let written = self.channel.publish(item);
+        let (timeout, error) = match Timeout::new(timeout, &self.handle) {
+            Ok(timeout) => (Some(timeout), None),
+            Err(e) => (None, Some(Error::from(e))),
+        };
+
+        RpcResponse {
+            correlation_id,
+            rx,
+            written: Some(written),
+            timeout,
+            error,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+
+
+/// A future which will be completed when `RpcClient` is ready to send requests.
+pub struct RpcClientStarted {
+    channel: Option<Channel>,
+    declaring: Option<Reply<DeclareResult>>,
+    timeout: Duration,
+    handle: Handle,
+}
+
+impl Future for RpcClientStarted {
+    type Item = RpcClient;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<RpcClient, Error> {
+        let (queue, reply_to) = match self.declaring {
+            Some(ref mut declaring) => {
+                let declared = try_ready!(declaring.poll());
+                (declared.queue.clone(), declared.queue)
+            }
+            None => (AmqpString::from(DIRECT_REPLY_TO), AmqpString::from(DIRECT_REPLY_TO)),
+        };
+        let channel = self.channel
+            .take()
+            .expect("You never poll RpcClientStarted after it is completed");
+
+        let consume = StartConsumeOption {
+            queue,
+            consumer_tag: "".into(),
+            is_no_local: false,
+            is_no_ack: true,
+            is_exclusive: self.declaring.is_some(),
+        };
+        let pending = Rc::new(RefCell::new(Pending {
+            next_id: 0,
+            waiters: HashMap::new(),
+            closed: None,
+            router: None,
+        }));
+        let router = ReplyRouter {
+            consumer: channel.consume(consume),
+            pending: pending.clone(),
+        };
+        self.handle.spawn(router);
+
+        Ok(Async::Ready(RpcClient {
+            channel,
+            reply_to,
+            timeout: self.timeout,
+            handle: self.handle.clone(),
+            pending: pending.clone(),
+            _alive: Rc::new(Alive(pending)),
+        }))
+    }
+}
+
+/// A future which will be completed when the reply to a request arrives.
+/// It fails with `RpcTimeout` error if the reply does not arrive in time.
+pub struct RpcResponse {
+    correlation_id: String,
+    rx: oneshot::Receiver<Result<DeliveredItem, Error>>,
+    written: Option<Written>,
+    timeout: Option<Timeout>,
+    /// Error which occurred before the request is sent.
+    error: Option<Error>,
+    pending: Rc<RefCell<Pending>>,
+}
+
+impl RpcResponse {
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+}
+
+impl Future for RpcResponse {
+    type Item = DeliveredItem;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<DeliveredItem, Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        let is_written = match self.written {
+            Some(ref mut written) => written.poll()?.is_ready(),
+            None => false,
+        };
+        if is_written {
+            debug!("Request {} is sent", self.correlation_id);
+            self.written = None;
+        }
+
+        match self.rx.poll() {
+            Ok(Async::Ready(result)) => return result.map(Async::Ready),
+            Ok(Async::NotReady) => {}
+            Err(_canceled) => {
+                return Err(ErrorKind::RpcClientClosed("reply consumer is dropped".into()).into())
+            }
+        }
+
+        match self.timeout {
+            Some(ref mut timeout) => try_ready!(timeout.poll()),
+            None => return Ok(Async::NotReady),
+        }
+        Err(ErrorKind::RpcTimeout(self.correlation_id.clone()).into())
+    }
+}
+
+impl Drop for RpcResponse {
+    fn drop(&mut self) {
+        self.pending.borrow_mut().waiters.remove(&self.correlation_id);
+    }
+}
+
+/// Background task which routes each reply to the waiter having its `correlation_id`.
+/// It finishes when the consumer finishes or every `RpcClient` is dropped.
+struct ReplyRouter {
+    consumer: Consumer,
+    pending: Rc<RefCell<Pending>>,
+}
+
+impl Future for ReplyRouter {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            {
+                let mut pending = self.pending.borrow_mut();
+                if pending.closed.is_some() {
+                    debug!("Stop routing replies : {:?}", pending.closed);
+                    return Ok(Async::Ready(()));
+                }
+                pending.router = Some(task::current());
+            }
+
+            let item = match self.consumer.poll() {
+                Ok(Async::Ready(Some(item))) => item,
+                Ok(Async::Ready(None)) => {
+                    self.pending.borrow_mut().close("reply consumer is finished".into());
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    warn!("Fail to receive reply : {}", e);
+                    self.pending.borrow_mut().close(e.to_string());
+                    return Ok(Async::Ready(()));
+                }
+            };
+
+            let waiter = item.correlation_id()
+                .and_then(|id| self.pending.borrow_mut().waiters.remove(id));
+            match waiter {
+                Some(waiter) => {
+                    let _ = waiter.send(Ok(item));
+                }
+                None => debug!("Skip reply which nobody waits for : {:?}", item.correlation_id()),
+            }
+        }
+    }
+}
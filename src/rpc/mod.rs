@@ -0,0 +1,13 @@
+//! Request/reply pattern built on `Connection` and `Channel`.
+//!
+//! It is not built on `publish` and `subscribe_stream`, because they take a socket by value.
+//! A client would need to split one socket between the reply stream and every caller,
+//! and a server could not ack a request while the consumer owns the socket. `Channel`
+//! already shares one connection among them and routes deliveries by consumer tag.
+
+pub mod client;
+pub mod server;
+
+pub use self::client::{ReplyQueue, RpcClient, RpcClientOption, RpcClientStarted, RpcResponse,
+                       DIRECT_REPLY_TO};
+pub use self::server::{RpcServer, RpcServerOption};
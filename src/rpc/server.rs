@@ -0,0 +1,179 @@
+use amqpr_codec::AmqpString;
+
+use bytes::Bytes;
+
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use futures::stream::FuturesUnordered;
+
+use basic::consume::StartConsumeOption;
+use basic::deliver::DeliveredItem;
+use basic::qos::QosOption;
+use connection::{Channel, Consumer, Reply};
+use errors::*;
+
+#[derive(Debug, Clone)]
+pub struct RpcServerOption {
+    /// Queue which requests arrive at. It must be declared beforehand.
+    pub queue: AmqpString,
+    /// If it is empty, a unique tag is generated.
+    pub consumer_tag: AmqpString,
+    /// Max number of requests being handled at the same time on the channel.
+    /// It is also sent as `prefetch_count`. 0 means no limit.
+    pub concurrency: u16,
+}
+
+type Handling = Box<dyn Future<Item = (), Error = Error>>;
+
+/// A server of request/reply pattern on a channel.
+///
+/// Each request is passed to the handler, and the body it resolves to is published to
+/// `reply_to` of the request with the same `correlation_id`. The request is acknowledged
+/// after the reply is written. If the handler fails or the reply can not be written, the
+/// request is rejected without requeue. A request without `reply_to` is acknowledged
+/// without reply. A request which can not be acknowledged nor rejected is logged, and
+/// the server keeps serving other requests.
+///
+/// It is a future which runs until the consumer finishes, so you should spawn it or
+/// run it. It fails only when the consumer fails.
+pub struct RpcServer<F> {
+    channel: Channel,
+    handler: F,
+    concurrency: usize,
+    qos: Option<Reply<()>>,
+    consumer: Option<Consumer>,
+    handlings: FuturesUnordered<Handling>,
+}
+
+impl<F, R> RpcServer<F>
+where
+    F: FnMut(DeliveredItem) -> R,
+    R: IntoFuture<Item = Bytes, Error = Error>,
+    R::Future: 'static,
+{
+    /// Set `prefetch_count` and start consuming the queue with manual acknowledgement.
+    pub fn new(channel: Channel, option: RpcServerOption, handler: F) -> RpcServer<F> {
+        let qos = channel.qos(QosOption {
+            prefetch_size: 0,
+            prefetch_count: option.concurrency,
+            is_global: false,
+        });
+        let consumer = channel.consume(StartConsumeOption {
+            queue: option.queue,
+            consumer_tag: option.consumer_tag,
+            is_no_local: false,
+            is_no_ack: false,
+            is_exclusive: false,
+        });
+
+        RpcServer {
+            channel,
+            handler,
+            concurrency: option.concurrency as usize,
+            qos: Some(qos),
+            consumer: Some(consumer),
+            handlings: FuturesUnordered::new(),
+        }
+    }
+
+    pub fn consumer_tag(&self) -> Option<&str> {
+        self.consumer.as_ref().map(Consumer::consumer_tag)
+    }
+
+    /// Number of requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.handlings.len()
+    }
+
+    fn has_room(&self) -> bool {
+        self.concurrency == 0 || self.handlings.len() < self.concurrency
+    }
+
+    fn handle(&mut self, request: DeliveredItem) -> Handling {
+        let delivery_tag = request.meta.delivery_tag;
+        let response = request.make_response(Bytes::new());
+        let channel = self.channel.clone();
+
+        let handling = (self.handler)(request).into_future().then(move |result| -> Handling {
+            match (result, response) {
+                (Ok(body), Ok(mut response)) => {
+                    response.body = body;
+                    let written = channel.publish(response);
+                    Box::new(written.then(move |written| -> Handling {
+                        match written {
+                            Ok(()) => Box::new(channel.ack(delivery_tag, false)),
+                            Err(e) => {
+                                warn!("Fail to reply to request {} : {}", delivery_tag, e);
+                                Box::new(channel.reject(delivery_tag, false))
+                            }
+                        }
+                    }))
+                }
+                (Ok(_), Err(e)) => {
+                    warn!("Can not reply to request {} : {}", delivery_tag, e);
+                    Box::new(channel.ack(delivery_tag, false))
+                }
+                (Err(e), _) => {
+                    warn!("Fail to handle request {} : {}", delivery_tag, e);
+                    Box::new(channel.reject(delivery_tag, false))
+                }
+            }
+        });
+        // A request which can not be settled does not stop other requests.
+        let handling = handling.or_else(move |e| {
+            error!("Fail to ack or reject request {} : {}", delivery_tag, e);
+            Ok(())
+        });
+        Box::new(handling)
+    }
+}
+
+impl<F, R> Future for RpcServer<F>
+where
+    F: FnMut(DeliveredItem) -> R,
+    R: IntoFuture<Item = Bytes, Error = Error>,
+    R::Future: 'static,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        if let Some(ref mut qos) = self.qos {
+            try_ready!(qos.poll());
+        }
+        self.qos = None;
+
+        loop {
+            let mut is_progressed = false;
+
+            while self.has_room() {
+                let polled = match self.consumer {
+                    Some(ref mut consumer) => consumer.poll()?,
+                    None => break,
+                };
+                match polled {
+                    Async::Ready(Some(request)) => {
+                        let handling = self.handle(request);
+                        self.handlings.push(handling);
+                        is_progressed = true;
+                    }
+                    Async::Ready(None) => {
+                        debug!("Consumer of RpcServer is finished");
+                        self.consumer = None;
+                    }
+                    Async::NotReady => break,
+                }
+            }
+
+            while let Async::Ready(Some(())) = self.handlings.poll()? {
+                is_progressed = true;
+            }
+
+            if self.consumer.is_none() && self.handlings.is_empty() {
+                return Ok(Async::Ready(()));
+            }
+            if !is_progressed {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
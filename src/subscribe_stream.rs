@@ -1,11 +1,18 @@
 //! Convenient module to subscribe item.
 
-use futures::{Future, Stream, Sink, Poll, Async};
+use futures::{Future, Stream, Sink, Poll, Async, AsyncSink, StartSend};
+use futures::task::{self, Task};
 
 use amqpr_codec::Frame;
 
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use basic::ack::ack_frame;
 use basic::consume::{start_consume, ConsumeStarted};
 use basic::deliver::{get_delivered, Delivered, DeliveredItem};
+use basic::recover::recover_frame;
 use errors::Error;
 
 pub use basic::consume::StartConsumeOption;
@@ -36,10 +43,21 @@ where
     S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
     E: From<Error>,
 {
+    let shared = Rc::new(RefCell::new(Shared {
+        outgoing: VecDeque::new(),
+        task: None,
+    }));
+    let socket = AckSocket {
+        socket,
+        shared: shared.clone(),
+    };
     let consume_started = start_consume(ch_id, socket, option);
-    let sub_stream = SubscribeStream::SendingConsumeMethod(consume_started);
 
-    sub_stream
+    SubscribeStream {
+        ch_id,
+        shared,
+        state: SubscribeState::SendingConsumeMethod(consume_started),
+    }
 }
 
 
@@ -49,14 +67,39 @@ where
 /// Stream of subscribed item from AMQP server.
 /// This stream is based on `no_ack` consume because of performance.
 /// But that may cause decreasing of reliability.
-/// If you want reliability rather than performance, you should use `subscribe_stream_ack`
-/// function (after we implement that...).
-pub enum SubscribeStream<S, E>
+/// If you want reliability rather than performance, you should consume with `is_no_ack: false`
+/// and acknowledge each item through `AckHandle` returned by `ack_handle` method.
+pub struct SubscribeStream<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+{
+    ch_id: u16,
+    shared: Rc<RefCell<Shared>>,
+    state: SubscribeState<S, E>,
+}
+
+
+enum SubscribeState<S, E>
 where
     S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
 {
-    SendingConsumeMethod(ConsumeStarted<S>),
-    ReceivingDeliverd(Delivered<S>),
+    SendingConsumeMethod(ConsumeStarted<AckSocket<S>>),
+    ReceivingDeliverd(Delivered<AckSocket<S>>),
+}
+
+
+impl<S, E> SubscribeStream<S, E>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+{
+    /// Returns `AckHandle` which sends `Ack` or `Recover` message through the socket this
+    /// stream holds. Queued messages are sent when this stream is polled next time.
+    pub fn ack_handle(&self) -> AckHandle {
+        AckHandle {
+            ch_id: self.ch_id,
+            shared: self.shared.clone(),
+        }
+    }
 }
 
 
@@ -70,14 +113,14 @@ where
     type Error = E;
 
     fn poll(&mut self) -> Poll<Option<DeliveredItem>, Self::Error> {
-        use self::SubscribeStream::*;
+        use self::SubscribeState::*;
 
-        let (item, socket) = match self {
-            &mut SendingConsumeMethod(ref mut fut) => {
+        let (item, socket) = match self.state {
+            SendingConsumeMethod(ref mut fut) => {
                 let socket = try_ready!(fut.poll());
                 (None, socket)
             }
-            &mut ReceivingDeliverd(ref mut del) => {
+            ReceivingDeliverd(ref mut del) => {
                 let (item, socket) = try_ready!(del.poll());
                 (Some(item), socket)
             }
@@ -85,7 +128,7 @@ where
 
         // Start to receive new delivered item.
         let delivered = get_delivered(socket);
-        *self = ReceivingDeliverd(delivered);
+        self.state = ReceivingDeliverd(delivered);
 
         match item {
             Some(bytes) => Ok(Async::Ready(Some(bytes))),
@@ -93,3 +136,118 @@ where
         }
     }
 }
+
+
+
+/// A handle to acknowledge items yielded by `SubscribeStream`.
+/// You can clone it and move it into a closure such as `for_each`.
+#[derive(Clone)]
+pub struct AckHandle {
+    ch_id: u16,
+    shared: Rc<RefCell<Shared>>,
+}
+
+struct Shared {
+    outgoing: VecDeque<Frame>,
+    task: Option<Task>,
+}
+
+impl AckHandle {
+    /// Acknowledge an item having given `delivery_tag`.
+    /// If `is_multiple` is true, all items up to and including it are acknowledged.
+    pub fn ack(&self, delivery_tag: u64, is_multiple: bool) {
+        self.push(ack_frame(self.ch_id, delivery_tag, is_multiple));
+    }
+
+    /// Ask AMQP server to redeliver all unacknowledged items on this channel.
+    /// This is useful to reset unacked window after local processing failure without
+    /// closing the channel. `Recover-Ok` method is skipped by `SubscribeStream`.
+    pub fn recover(&self, requeue: bool) {
+        self.push(recover_frame(self.ch_id, requeue));
+    }
+
+    fn push(&self, frame: Frame) {
+        self.push_frames(vec![frame]);
+    }
+
+    /// Queue frames which are sent after `Consume` method.
+    pub(crate) fn push_frames(&self, frames: Vec<Frame>) {
+        let mut shared = self.shared.borrow_mut();
+        shared.outgoing.extend(frames);
+        if let Some(task) = shared.task.take() {
+            task.notify();
+        }
+    }
+}
+
+
+
+/// Socket wrapper which sends frames queued by `AckHandle` and skips `Recover-Ok` method.
+struct AckSocket<S> {
+    socket: S,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl<S, E> AckSocket<S>
+where
+    S: Sink<SinkItem = Frame, SinkError = E>,
+{
+    fn flush_outgoing(&mut self) -> Result<(), E> {
+        let mut shared = self.shared.borrow_mut();
+        shared.task = Some(task::current());
+        while let Some(frame) = shared.outgoing.pop_front() {
+            if let AsyncSink::NotReady(frame) = self.socket.start_send(frame)? {
+                shared.outgoing.push_front(frame);
+                break;
+            }
+        }
+        self.socket.poll_complete()?;
+        Ok(())
+    }
+}
+
+impl<S, E> Stream for AckSocket<S>
+where
+    S: Stream<Item = Frame, Error = E> + Sink<SinkItem = Frame, SinkError = E>,
+{
+    type Item = Frame;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, E> {
+        self.flush_outgoing()?;
+        loop {
+            let frame = try_ready!(self.socket.poll());
+            let is_recover_ok = frame
+                .as_ref()
+                .and_then(|f| f.method())
+                .and_then(|m| m.basic())
+                .and_then(|c| c.recover_ok())
+                .is_some();
+            if is_recover_ok {
+                debug!("Receive recover-ok response");
+                continue;
+            }
+            return Ok(Async::Ready(frame));
+        }
+    }
+}
+
+impl<S, E> Sink for AckSocket<S>
+where
+    S: Sink<SinkItem = Frame, SinkError = E>,
+{
+    type SinkItem = Frame;
+    type SinkError = E;
+
+    fn start_send(&mut self, item: Frame) -> StartSend<Frame, E> {
+        self.socket.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), E> {
+        self.socket.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), E> {
+        self.socket.close()
+    }
+}
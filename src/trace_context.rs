@@ -0,0 +1,292 @@
+//! W3C Trace Context propagation through message headers.
+//!
+//! `TraceContext` is written into `traceparent` and `tracestate` entries of the `headers`
+//! property, as W3C "Trace Context: AMQP protocol" describes. Use `inject_sink` on top of
+//! `publish_sink` to inject the current context into every item, and `extract_stream` on
+//! top of `subscribe_stream` to get a child context of the publisher's one for each
+//! delivered item. With `publish`, call `TraceContext::inject` on the item beforehand.
+//!
+//! This module does not depend on any tracing library. Give a closure returning the
+//! context of your current span to `inject_sink`, and start your consumer span with the
+//! context `extract_stream` yields.
+//!
+//! Ids made by `TraceContext::new_root` and `TraceContext::child` do not come from a
+//! cryptographic random source, because this crate does not depend on `rand`. Each id is
+//! a 64 bit hash, so ids collide as often as random 64 bit values, and a 16 byte
+//! `trace-id` is not stronger than that. It is weaker than the 128 bit randomness W3C
+//! recommends for `trace-id`. Make the root context by your tracing library if it matters.
+
+use futures::{Async, Poll, Sink, StartSend, Stream};
+
+use amqpr_codec::{AmqpString, FieldArgument};
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use basic::deliver::DeliveredItem;
+use basic::publish::PublishItem;
+
+/// Header key of trace parent.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Header key of vendor specific trace state.
+pub const TRACESTATE: &str = "tracestate";
+
+const SAMPLED: u8 = 0x01;
+
+/// Position in a distributed trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// Id of the span which publishes or consumes a message. `parent-id` in `traceparent`.
+    pub span_id: [u8; 8],
+    /// `trace-flags`. `0x01` is sampled flag.
+    pub flags: u8,
+    /// `tracestate` header which is passed through untouched.
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Start a new trace. See the module document about uniqueness of ids.
+    pub fn new_root(is_sampled: bool) -> TraceContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_id());
+        trace_id[8..].copy_from_slice(&random_id());
+        TraceContext {
+            trace_id,
+            span_id: random_id(),
+            flags: if is_sampled { SAMPLED } else { 0 },
+            tracestate: None,
+        }
+    }
+
+    /// Context of a new span in the same trace, whose parent is this span.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            span_id: random_id(),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    /// Parse `traceparent` and `tracestate` header values.
+    /// Returns `None` if `traceparent` is invalid.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<TraceContext> {
+        let fields: Vec<&str> = traceparent.trim().split('-').collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        let version = parse_hex::<[u8; 1]>(fields[0])?[0];
+        // Version 00 has exactly 4 fields. Later versions may append more.
+        if version == 0xff || (version == 0 && fields.len() != 4) {
+            return None;
+        }
+        let trace_id = parse_hex::<[u8; 16]>(fields[1])?;
+        let span_id = parse_hex::<[u8; 8]>(fields[2])?;
+        let flags = parse_hex::<[u8; 1]>(fields[3])?[0];
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            flags,
+            tracestate: tracestate
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+        })
+    }
+
+    /// `traceparent` header value such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            Hex(&self.trace_id),
+            Hex(&self.span_id),
+            self.flags
+        )
+    }
+
+    /// Write this context into `headers` of `item`. Existing trace headers are replaced.
+    pub fn inject(&self, mut item: PublishItem) -> PublishItem {
+        let headers = item.header.headers.get_or_insert_with(HashMap::new);
+        headers.insert(
+            AmqpString::from(TRACEPARENT),
+            FieldArgument::LongString(AmqpString::from(self.traceparent())),
+        );
+        match self.tracestate {
+            Some(ref state) => {
+                headers.insert(
+                    AmqpString::from(TRACESTATE),
+                    FieldArgument::LongString(AmqpString::from(state.clone())),
+                );
+            }
+            None => {
+                headers.remove(&AmqpString::from(TRACESTATE));
+            }
+        }
+        item
+    }
+
+    /// Read the context of the publisher from `headers` of `item`.
+    pub fn extract(item: &DeliveredItem) -> Option<TraceContext> {
+        let traceparent = item.header_value(TRACEPARENT).and_then(as_str)?;
+        let tracestate = item.header_value(TRACESTATE).and_then(as_str);
+        TraceContext::parse(traceparent, tracestate)
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.traceparent())
+    }
+}
+
+fn as_str(arg: &FieldArgument) -> Option<&str> {
+    match *arg {
+        FieldArgument::LongString(ref s) | FieldArgument::ShortString(ref s) => Some(s),
+        _ => None,
+    }
+}
+
+/// Fixed size byte array parsed from lowercase hex.
+fn parse_hex<A: Default + AsMut<[u8]>>(hex: &str) -> Option<A> {
+    let mut bytes = A::default();
+    {
+        let out = bytes.as_mut();
+        if hex.len() != out.len() * 2 || hex.bytes().any(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+    }
+    Some(bytes)
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Non zero id hashing a process-wide counter and the current time with the random keys
+/// of `RandomState`. Not cryptographically secure.
+fn random_id() -> [u8; 8] {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        if let Ok(elapsed) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            hasher.write_u32(elapsed.subsec_nanos());
+        }
+        let id = hasher.finish();
+        if id != 0 {
+            return id.to_be_bytes();
+        }
+    }
+}
+
+/// Returns `InjectSink` which injects the context `current` returns into every item before
+/// passing it to `sink`. An item is passed untouched when `current` returns `None`.
+pub fn inject_sink<S, F>(sink: S, current: F) -> InjectSink<S, F>
+where
+    S: Sink<SinkItem = PublishItem>,
+    F: FnMut() -> Option<TraceContext>,
+{
+    InjectSink { sink, current }
+}
+
+pub struct InjectSink<S, F> {
+    sink: S,
+    current: F,
+}
+
+impl<S, F> InjectSink<S, F> {
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S, F> Sink for InjectSink<S, F>
+where
+    S: Sink<SinkItem = PublishItem>,
+    F: FnMut() -> Option<TraceContext>,
+{
+    type SinkItem = PublishItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: PublishItem) -> StartSend<PublishItem, S::SinkError> {
+        let item = match (self.current)() {
+            Some(context) => context.inject(item),
+            None => item,
+        };
+        self.sink.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.sink.close()
+    }
+}
+
+/// Returns `ExtractStream` which yields each item from `stream` with a context of the
+/// consumer span. It is a child of the context in the item's headers, or `None` if the item
+/// has no valid context.
+pub fn extract_stream<St>(stream: St) -> ExtractStream<St>
+where
+    St: Stream<Item = DeliveredItem>,
+{
+    ExtractStream { stream }
+}
+
+pub struct ExtractStream<St> {
+    stream: St,
+}
+
+impl<St> ExtractStream<St> {
+    pub fn get_ref(&self) -> &St {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> St {
+        self.stream
+    }
+}
+
+impl<St> Stream for ExtractStream<St>
+where
+    St: Stream<Item = DeliveredItem>,
+{
+    type Item = (DeliveredItem, Option<TraceContext>);
+    type Error = St::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, St::Error> {
+        let item = match try_ready!(self.stream.poll()) {
+            Some(item) => item,
+            None => return Ok(Async::Ready(None)),
+        };
+        let context = TraceContext::extract(&item).map(|parent| parent.child());
+        Ok(Async::Ready(Some((item, context))))
+    }
+}
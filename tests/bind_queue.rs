@@ -1,4 +1,5 @@
 extern crate amqpr_api;
+extern crate amqpr_codec;
 extern crate bytes;
 extern crate futures;
 extern crate log4rs;
@@ -6,15 +7,18 @@ extern crate log;
 extern crate tokio_core;
 
 use tokio_core::reactor::Core;
-use tokio_core::net::TcpStream;
-use futures::Future;
+use futures::{Future, Stream};
 
-use amqpr_api::{bind_queue, declare_exchange, declare_queue, open_channel, start_handshake};
+use bytes::Bytes;
+
+use amqpr_codec::content_header::Properties;
+
+use amqpr_api::{bind_queue, declare_exchange, declare_queue, open_channel};
 use amqpr_api::queue::declare::DeclareQueueOption;
 use amqpr_api::queue::bind::BindQueueOption;
 use amqpr_api::exchange::declare::{DeclareExchangeOption, ExchangeType};
 use amqpr_api::handshake::SimpleHandshaker;
-use amqpr_api::errors::*;
+use amqpr_api::mock::MockBroker;
 
 const LOCAL_CHANNEL_ID: u16 = 42;
 
@@ -24,16 +28,12 @@ fn main() {
 
     let mut core = Core::new().unwrap();
 
-    let handshaker = SimpleHandshaker {
-        user: "guest".into(),
-        pass: "guest".into(),
-        virtual_host: "/".into(),
-    };
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
 
-    let future = TcpStream::connect(&"127.0.0.1:5672".parse().unwrap(), &core.handle())
-        .map_err(|e| Error::from(e))
-        .and_then(|socket| start_handshake(handshaker, socket))
-        .and_then(|socket| open_channel(LOCAL_CHANNEL_ID, socket))
+    let future = open_channel(LOCAL_CHANNEL_ID, socket)
         .and_then(|socket| {
             let option = DeclareExchangeOption {
                 name: "bind_queue_test".into(),
@@ -45,7 +45,9 @@ fn main() {
             };
             declare_exchange(LOCAL_CHANNEL_ID, socket, option)
         })
-        .and_then(|socket| {
+        // `declare_exchange` does not wait for Declare-Ok method.
+        .and_then(|socket| socket.into_future().map_err(|(e, _socket)| e))
+        .and_then(|(_declare_ok, socket)| {
             let option = DeclareQueueOption {
                 name: "bind_queue_test".into(),
                 is_passive: false,
@@ -65,6 +67,9 @@ fn main() {
         });
 
     core.run(future).unwrap();
+
+    broker.publish("bind_queue_test", "", Properties::new(), Bytes::from_static(b"bind test"));
+    assert_eq!(broker.messages("bind_queue_test").len(), 1);
 }
 
 fn logger() {
@@ -0,0 +1,42 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate futures;
+
+use futures::{stream, Future, Stream};
+
+use amqpr_codec::Frame;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::{BlockedMethod, ConnectionClass};
+use amqpr_api::watch_blocked;
+use amqpr_api::blocked::BlockedEvent;
+use amqpr_api::errors::*;
+
+#[test]
+fn main() {
+    let blocked = BlockedMethod {
+        reason: "low on memory".into(),
+    };
+    let frames = vec![
+        Frame::new_method(0, MethodPayload::Connection(ConnectionClass::Blocked(blocked))),
+        Frame::new_heartbeat(0),
+        Frame::new_method(0, MethodPayload::Connection(ConnectionClass::Unblocked)),
+    ];
+
+    let socket = stream::iter_ok::<_, Error>(frames);
+    let (watched, events) = watch_blocked(socket);
+
+    let passed = watched.collect().wait().unwrap();
+    assert_eq!(passed, vec![Frame::new_heartbeat(0)]);
+    assert!(!events.is_blocked());
+
+    let events = events.collect().wait().unwrap();
+    assert_eq!(
+        events,
+        vec![
+            BlockedEvent::Blocked {
+                reason: "low on memory".into(),
+            },
+            BlockedEvent::Unblocked,
+        ]
+    );
+}
@@ -0,0 +1,50 @@
+extern crate amqpr_api;
+
+use amqpr_api::channel::ChannelIdAllocator;
+use amqpr_api::errors::*;
+
+#[test]
+fn allocate_up_to_channel_max() {
+    let mut allocator = ChannelIdAllocator::new(3);
+    assert_eq!(allocator.allocate().unwrap(), 1);
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert_eq!(allocator.allocate().unwrap(), 3);
+    match allocator.allocate() {
+        Err(Error(ErrorKind::ChannelIdExhausted(3), _)) => {}
+        other => panic!("Unexpected result : {:?}", other.map_err(|e| e.to_string())),
+    }
+
+    allocator.release(2);
+    assert!(!allocator.is_allocated(2));
+    assert_eq!(allocator.allocate().unwrap(), 2);
+}
+
+#[test]
+fn reuse_smallest_released_id() {
+    let mut allocator = ChannelIdAllocator::new(0);
+    assert_eq!(allocator.channel_max(), u16::MAX);
+    for _ in 0..5 {
+        allocator.allocate().unwrap();
+    }
+    allocator.release(4);
+    allocator.release(2);
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert_eq!(allocator.allocate().unwrap(), 4);
+    assert_eq!(allocator.allocate().unwrap(), 6);
+}
+
+#[test]
+fn reserve_explicit_id() {
+    let mut allocator = ChannelIdAllocator::new(10);
+    allocator.reserve(3).unwrap();
+    assert!(allocator.is_allocated(3));
+    assert!(!allocator.is_allocated(1));
+
+    assert!(allocator.reserve(3).is_err());
+    assert!(allocator.reserve(0).is_err());
+    assert!(allocator.reserve(11).is_err());
+
+    assert_eq!(allocator.allocate().unwrap(), 1);
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert_eq!(allocator.allocate().unwrap(), 4);
+}
@@ -0,0 +1,76 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+
+use std::collections::HashMap;
+
+use amqpr_codec::{AmqpString, FieldArgument};
+use amqpr_codec::method::connection::StartMethod;
+use amqpr_api::handshake::{ClientProperties, Handshaker, SimpleHandshaker};
+
+fn start_method() -> StartMethod {
+    StartMethod {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: "PLAIN".into(),
+        locales: "en_US".into(),
+    }
+}
+
+fn long_string(s: &str) -> FieldArgument {
+    FieldArgument::LongString(AmqpString::from(s.to_string()))
+}
+
+#[test]
+fn send_connection_name_and_crate_version() {
+    let mut handshaker =
+        SimpleHandshaker::new("guest", "guest", "/").connection_name("order-service");
+    let start_ok = handshaker.reply_to_start(&start_method()).unwrap();
+    let props = start_ok.client_properties;
+
+    assert_eq!(
+        props.get(&AmqpString::from("connection_name")),
+        Some(&long_string("order-service"))
+    );
+    assert_eq!(
+        props.get(&AmqpString::from("version")),
+        Some(&long_string(env!("CARGO_PKG_VERSION")))
+    );
+    assert!(props.contains_key(&AmqpString::from("capabilities")));
+}
+
+#[test]
+fn advertise_only_handled_capabilities() {
+    let mut handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let props = handshaker.reply_to_start(&start_method()).unwrap().client_properties;
+    let capabilities = match props.get(&AmqpString::from("capabilities")) {
+        Some(&FieldArgument::NestedTable(ref capabilities)) => capabilities.clone(),
+        other => panic!("Unexpected capabilities {:?}", other),
+    };
+
+    assert_eq!(
+        capabilities.get(&AmqpString::from("connection.blocked")),
+        Some(&FieldArgument::Boolean(true))
+    );
+    assert!(!capabilities.contains_key(&AmqpString::from("consumer_cancel_notify")));
+    assert!(!capabilities.contains_key(&AmqpString::from("basic.nack")));
+}
+
+#[test]
+fn custom_properties() {
+    let properties = ClientProperties::new()
+        .product("order-service")
+        .property("region", long_string("ap-northeast-1"));
+    let mut handshaker =
+        SimpleHandshaker::new("guest", "guest", "/").client_properties(properties);
+    let props = handshaker.reply_to_start(&start_method()).unwrap().client_properties;
+
+    assert_eq!(
+        props.get(&AmqpString::from("product")),
+        Some(&long_string("order-service"))
+    );
+    assert_eq!(
+        props.get(&AmqpString::from("region")),
+        Some(&long_string("ap-northeast-1"))
+    );
+}
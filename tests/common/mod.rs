@@ -0,0 +1,252 @@
+//! Frames and a `Connection` on `Script` used by tests of `Connection`.
+#![allow(dead_code)]
+
+use tokio_core::reactor::{Core, Handle};
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{AckMethod, BasicClass, ConsumeMethod, DeliverMethod, GetOkMethod,
+                                 PublishMethod, QosMethod, RejectMethod};
+use amqpr_codec::method::channel::{ChannelClass, CloseMethod, OpenMethod, OpenOkMethod};
+use amqpr_codec::method::connection::{self, ConnectionClass};
+use amqpr_codec::method::queue::{DeclareMethod, DeclareOkMethod, QueueClass};
+use amqpr_api::Connection;
+use amqpr_api::handshake::ConnectionInfo;
+use amqpr_api::mock::Script;
+use amqpr_api::queue::declare::DeclareQueueOption;
+
+pub fn info(channel_max: u16, heartbeat: u16) -> ConnectionInfo {
+    ConnectionInfo {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: vec!["PLAIN".into()],
+        locales: vec!["en_US".into()],
+        channel_max,
+        frame_max: 0,
+        heartbeat,
+    }
+}
+
+pub fn connect(script: &Script, handle: &Handle) -> Connection {
+    Connection::from_socket(script.socket(), info(0, 0), handle)
+}
+
+/// Appends steps of `Channel.Open` method.
+pub fn open_channel(script: Script, channel_id: u16) -> Script {
+    let open = OpenMethod {
+        reserved1: "".into(),
+    };
+    let open_ok = OpenOkMethod {
+        reserved1: "".into(),
+    };
+    script
+        .expect(channel_frame(channel_id, ChannelClass::Open(open)))
+        .reply(channel_frame(channel_id, ChannelClass::OpenOk(open_ok)))
+}
+
+/// Appends steps of `Connection.Close` method which is sent when every handle is dropped.
+pub fn close_connection(script: Script) -> Script {
+    script
+        .expect(connection_close(200, "Normal shutdown"))
+        .reply(Frame::new_method(0, MethodPayload::Connection(ConnectionClass::CloseOk)))
+}
+
+/// Turn the core until every step of `script` is done.
+pub fn finish(core: &mut Core, script: &Script) {
+    for _ in 0..200 {
+        if script.is_done() {
+            break;
+        }
+        core.turn(Some(Duration::from_millis(10)));
+    }
+    script.assert_done();
+}
+
+pub fn connection_close(reply_code: u16, reply_text: &'static str) -> Frame {
+    let close = connection::CloseMethod {
+        reply_code,
+        reply_text: reply_text.into(),
+        class_id: 0,
+        method_id: 0,
+    };
+    Frame::new_method(0, MethodPayload::Connection(ConnectionClass::Close(close)))
+}
+
+pub fn channel_frame(channel_id: u16, class: ChannelClass) -> Frame {
+    Frame::new_method(channel_id, MethodPayload::Channel(class))
+}
+
+pub fn channel_close(channel_id: u16, reply_code: u16, reply_text: &'static str) -> Frame {
+    let close = CloseMethod {
+        reply_code,
+        reply_text: reply_text.into(),
+        class_id: 0,
+        method_id: 0,
+    };
+    channel_frame(channel_id, ChannelClass::Close(close))
+}
+
+pub fn basic_frame(channel_id: u16, class: BasicClass) -> Frame {
+    Frame::new_method(channel_id, MethodPayload::Basic(class))
+}
+
+pub fn queue_option(name: &'static str) -> DeclareQueueOption {
+    DeclareQueueOption {
+        name: name.into(),
+        is_passive: false,
+        is_durable: false,
+        is_exclusive: true,
+        is_auto_delete: true,
+    }
+}
+
+/// `Queue.Declare` method of `queue_option(name)`.
+pub fn declare_queue(channel_id: u16, name: &'static str) -> Frame {
+    let declare = DeclareMethod {
+        reserved1: 0,
+        queue: name.into(),
+        passive: false,
+        durable: false,
+        exclusive: true,
+        auto_delete: true,
+        no_wait: false,
+        arguments: HashMap::new(),
+    };
+    Frame::new_method(channel_id, MethodPayload::Queue(QueueClass::Declare(declare)))
+}
+
+pub fn declare_ok(channel_id: u16, queue: &'static str) -> Frame {
+    let declare_ok = DeclareOkMethod {
+        queue: queue.into(),
+        message_count: 0,
+        consumer_count: 0,
+    };
+    Frame::new_method(channel_id, MethodPayload::Queue(QueueClass::DeclareOk(declare_ok)))
+}
+
+pub fn consume(channel_id: u16, queue: &'static str, consumer_tag: &'static str, no_ack: bool, exclusive: bool) -> Frame {
+    let consume = ConsumeMethod {
+        reserved1: 0,
+        queue: queue.into(),
+        consumer_tag: consumer_tag.into(),
+        no_local: false,
+        no_ack,
+        exclusive,
+        no_wait: true,
+        arguments: HashMap::new(),
+    };
+    basic_frame(channel_id, BasicClass::Consume(consume))
+}
+
+pub fn qos(channel_id: u16, prefetch_count: u16) -> Frame {
+    let qos = QosMethod {
+        prefetch_size: 0,
+        prefetch_count,
+        global: false,
+    };
+    basic_frame(channel_id, BasicClass::Qos(qos))
+}
+
+pub fn ack(channel_id: u16, delivery_tag: u64) -> Frame {
+    let ack = AckMethod {
+        delivery_tag,
+        multiple: false,
+    };
+    basic_frame(channel_id, BasicClass::Ack(ack))
+}
+
+pub fn reject(channel_id: u16, delivery_tag: u64) -> Frame {
+    let reject = RejectMethod {
+        delivery_tag,
+        requeue: false,
+    };
+    basic_frame(channel_id, BasicClass::Reject(reject))
+}
+
+/// Frames of a message published to the default exchange.
+pub fn publish(channel_id: u16, routing_key: &'static str, properties: Properties, body: &'static [u8]) -> Vec<Frame> {
+    let publish = PublishMethod {
+        reserved1: 0,
+        exchange: "".into(),
+        routing_key: routing_key.into(),
+        mandatory: false,
+        immediate: false,
+    };
+    let mut frames = vec![basic_frame(channel_id, BasicClass::Publish(publish))];
+    frames.extend(content(channel_id, properties, &[body]));
+    frames
+}
+
+/// Frames of a message delivered to `consumer_tag`. The body is split into `bodies`.
+pub fn deliver(
+    channel_id: u16,
+    consumer_tag: &'static str,
+    delivery_tag: u64,
+    properties: Properties,
+    bodies: &[&'static [u8]],
+) -> Vec<Frame> {
+    let deliver = DeliverMethod {
+        consumer_tag: consumer_tag.into(),
+        delivery_tag,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "queue".into(),
+    };
+    let mut frames = vec![basic_frame(channel_id, BasicClass::Deliver(deliver))];
+    frames.extend(content(channel_id, properties, bodies));
+    frames
+}
+
+/// Frames of `basic.get-ok` and its content.
+pub fn get_ok(channel_id: u16, delivery_tag: u64, body: &'static [u8]) -> Vec<Frame> {
+    let get_ok = GetOkMethod {
+        delivery_tag,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "queue".into(),
+        message_count: 0,
+    };
+    let mut frames = vec![basic_frame(channel_id, BasicClass::GetOk(get_ok))];
+    frames.extend(content(channel_id, Properties::new(), &[body]));
+    frames
+}
+
+fn content(channel_id: u16, properties: Properties, bodies: &[&'static [u8]]) -> Vec<Frame> {
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: bodies.iter().map(|b| b.len() as u64).sum(),
+        properties,
+    };
+    let mut frames = vec![Frame::new(channel_id, FramePayload::ContentHeader(header))];
+    for body in bodies {
+        let body = ContentBodyPayload {
+            bytes: Bytes::from_static(body),
+        };
+        frames.push(Frame::new(channel_id, FramePayload::ContentBody(body)));
+    }
+    frames
+}
+
+/// Appends `expect` steps of each frame.
+pub fn expect_all(mut script: Script, frames: Vec<Frame>) -> Script {
+    for frame in frames {
+        script = script.expect(frame);
+    }
+    script
+}
+
+/// Appends `reply` steps of each frame.
+pub fn reply_all(mut script: Script, frames: Vec<Frame>) -> Script {
+    for frame in frames {
+        script = script.reply(frame);
+    }
+    script
+}
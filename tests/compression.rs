@@ -0,0 +1,272 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+use futures::{stream, Future, Sink, Stream};
+use futures::unsync::mpsc;
+
+use bytes::Bytes;
+
+use amqpr_codec::Frame;
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, DeliverMethod};
+use amqpr_api::{get_delivered, publish_sink_without_flow};
+use amqpr_api::basic::deliver::DeliveredItem;
+use amqpr_api::basic::publish::{PublishItem, PublishOption};
+use amqpr_api::compression::*;
+use amqpr_api::errors::*;
+
+/// Toy algorithm which reverses body.
+struct Reverse;
+
+impl Compression for Reverse {
+    fn encoding(&self) -> &str {
+        "x-reverse"
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        Ok(bytes.iter().rev().cloned().collect::<Vec<u8>>().into())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        self.compress(bytes)
+    }
+}
+
+fn item<B: Into<Bytes>>(body: B) -> PublishItem {
+    PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "queue".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: Properties::new(),
+        body: body.into(),
+    }
+}
+
+fn delivered(item: PublishItem) -> DeliveredItem {
+    DeliveredItem {
+        meta: DeliverMethod {
+            consumer_tag: "ctag".into(),
+            delivery_tag: 1,
+            redeliverd: false,
+            exchange: item.meta.exchange,
+            routing_key: item.meta.routing_key,
+        },
+        header: ContentHeaderPayload {
+            class_id: 60,
+            body_size: item.body.len() as u64,
+            properties: item.header,
+        },
+        body: ContentBodyPayload { bytes: item.body },
+    }
+}
+
+/// Compress items through `CompressSink`, and decompress them through `DecompressStream`.
+fn round_trip(compressions: Compressions, encoding: &str, items: Vec<PublishItem>) -> Vec<(PublishItem, DeliveredItem)> {
+    let (tx, rx) = mpsc::unbounded();
+    let sink = compress_sink(
+        tx.sink_map_err(|_| Error::from("receiver is dropped")),
+        compressions.clone(),
+        encoding,
+    );
+    drop(sink.send_all(stream::iter_ok::<_, Error>(items)).wait().unwrap());
+
+    let compressed: Vec<PublishItem> = rx.collect().wait().unwrap();
+    let delivered = stream::iter_ok::<_, Error>(compressed.clone().into_iter().map(delivered));
+    let decompressed = decompress_stream(delivered, compressions).collect().wait().unwrap();
+    compressed.into_iter().zip(decompressed).collect()
+}
+
+#[test]
+fn registered_encoding_only() {
+    let compressions = Compressions::new().register(Reverse);
+
+    let results = round_trip(compressions.clone(), "x-reverse", vec![item(&b"hello"[..])]);
+    let (compressed, decompressed) = &results[0];
+    assert_eq!(compressed.header.content_encoding, Some("x-reverse".into()));
+    assert_eq!(compressed.body, Bytes::from_static(b"olleh"));
+    assert_eq!(decompressed.content_encoding(), None);
+    assert_eq!(decompressed.body.bytes, Bytes::from_static(b"hello"));
+
+    // Unknown encoding is passed through untouched.
+    let mut unknown = item(&b"raw"[..]);
+    unknown.header.content_encoding = Some("br".into());
+    let passed = compressions.decompress(delivered(unknown)).unwrap();
+    assert_eq!(passed.content_encoding(), Some("br"));
+    assert_eq!(passed.body.bytes, Bytes::from_static(b"raw"));
+
+    match compressions.compress("br", item(&b"raw"[..])) {
+        Err(Error(ErrorKind::UnknownContentEncoding(ref encoding), _)) => assert_eq!(encoding, "br"),
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Unknown encoding should be refused"),
+    }
+}
+
+/// Frames of `Deliver` message having the content of published `frames`.
+fn deliver_frames(mut frames: Vec<Frame>) -> Vec<Frame> {
+    let deliver = DeliverMethod {
+        consumer_tag: "ctag".into(),
+        delivery_tag: 1,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "queue".into(),
+    };
+    frames[0] = Frame::new_method(1, MethodPayload::Basic(BasicClass::Deliver(deliver)));
+    frames
+}
+
+#[test]
+fn publish_and_get_delivered() {
+    let compressions = Compressions::new().register(Reverse);
+    let (tx, rx) = mpsc::unbounded();
+    let published = publish_compressed(1, tx, item(&b"hello"[..]), &compressions, "x-reverse").unwrap();
+    drop(published.wait().unwrap());
+    let frames: Vec<Frame> = rx.collect().wait().unwrap();
+    assert_eq!(
+        frames[1].content_header().unwrap().properties.content_encoding,
+        Some("x-reverse".into())
+    );
+    assert_eq!(frames[2].content_body().unwrap().bytes, Bytes::from_static(b"olleh"));
+
+    let frames = stream::iter_ok::<_, Error>(deliver_frames(frames));
+    let delivered = decompress_delivered(get_delivered(frames), compressions.clone());
+    let (decompressed, _) = delivered.wait().unwrap();
+    assert_eq!(decompressed.content_encoding(), None);
+    assert_eq!(decompressed.body.bytes, Bytes::from_static(b"hello"));
+
+    assert!(publish_compressed(1, mpsc::unbounded().0, item(&b"raw"[..]), &compressions, "br").is_err());
+}
+
+#[test]
+fn compress_on_broadcast_sink() {
+    let (tx, rx) = mpsc::unbounded();
+    let sink = publish_sink_without_flow(1, tx.sink_map_err(|_| Error::from("receiver is dropped")))
+        .compress(Compressions::new().register(Reverse), "x-reverse");
+    drop(sink.send(item(&b"hello"[..])).wait().unwrap());
+
+    let frames: Vec<Frame> = rx.collect().wait().unwrap();
+    assert_eq!(
+        frames[1].content_header().unwrap().properties.content_encoding,
+        Some("x-reverse".into())
+    );
+    assert_eq!(frames[2].content_body().unwrap().bytes, Bytes::from_static(b"olleh"));
+}
+
+#[test]
+fn refuse_too_large_decompressed_body() {
+    let compressions = Compressions::new().register(Reverse).max_decompressed_size(4);
+    let compressed = compressions.compress("x-reverse", item(&b"hello"[..])).unwrap();
+    match compressions.decompress(delivered(compressed.clone())) {
+        Err(Error(ErrorKind::DecompressedTooLarge(ref encoding, 4), _)) => assert_eq!(encoding, "x-reverse"),
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Too large body should be refused"),
+    }
+
+    // It is passed through untouched.
+    let items = stream::iter_ok::<_, Error>(vec![delivered(compressed)]);
+    let passed = decompress_stream(items, compressions).collect().wait().unwrap();
+    assert_eq!(passed[0].content_encoding(), Some("x-reverse"));
+    assert_eq!(passed[0].body.bytes, Bytes::from_static(b"olleh"));
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn compress_and_decompress_on_connection() {
+    use tokio_core::reactor::Core;
+    use amqpr_api::basic::StartConsumeOption;
+    use amqpr_api::handshake::SimpleHandshaker;
+    use amqpr_api::mock::MockBroker;
+    use amqpr_api::queue::DeclareQueueOption;
+
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let connection = broker.connection(handshaker, &core.handle()).unwrap();
+    connection.set_compressions(Compressions::new().register(Reverse));
+    let channel = core.run(connection.create_channel()).unwrap();
+    let declare = DeclareQueueOption {
+        name: "queue".into(),
+        is_passive: false,
+        is_durable: false,
+        is_exclusive: false,
+        is_auto_delete: false,
+    };
+    core.run(channel.declare_queue(declare)).unwrap();
+
+    core.run(channel.publish_compressed(item(&b"hello"[..]), "x-reverse")).unwrap();
+    let messages = broker.messages("queue");
+    assert_eq!(messages[0].properties.content_encoding, Some("x-reverse".into()));
+    assert_eq!(messages[0].body, Bytes::from_static(b"olleh"));
+    assert!(core.run(channel.publish_compressed(item(&b"raw"[..]), "br")).is_err());
+
+    let option = StartConsumeOption {
+        queue: "queue".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: true,
+        is_exclusive: false,
+    };
+    let (delivered, _) = core.run(channel.consume(option).into_future()).map_err(|(e, _)| e).unwrap();
+    let delivered = delivered.unwrap();
+    assert_eq!(delivered.content_encoding(), None);
+    assert_eq!(delivered.body.bytes, Bytes::from_static(b"hello"));
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+#[test]
+fn stop_decompressing_at_max_size() {
+    let body = Bytes::from(vec![0u8; 1024 * 1024]);
+    for encoding in &["gzip", "zstd", "lz4"] {
+        if Compressions::default().get(encoding).is_none() {
+            continue;
+        }
+        let compressions = Compressions::default().max_decompressed_size(1024);
+        let compressed = compressions.compress(encoding, item(body.clone())).unwrap();
+        match compressions.decompress(delivered(compressed)) {
+            Err(Error(ErrorKind::DecompressedTooLarge(_, 1024), _)) => {}
+            Err(e) => panic!("Unexpected error by {} : {}", encoding, e),
+            Ok(_) => panic!("Too large body by {} should be refused", encoding),
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+#[test]
+fn compress_large_body_by_every_enabled_algorithm() {
+    let body = Bytes::from(
+        (0..1000)
+            .map(|i| format!("{{\"id\":{},\"name\":\"item\"}}", i))
+            .collect::<String>(),
+    );
+    for encoding in &["gzip", "zstd", "lz4"] {
+        if Compressions::default().get(encoding).is_none() {
+            continue;
+        }
+        let results = round_trip(Compressions::default(), encoding, vec![item(body.clone())]);
+        let (compressed, decompressed) = &results[0];
+        assert!(compressed.body.len() * 2 < body.len(), "{} does not compress", encoding);
+        assert_eq!(decompressed.body.bytes, body);
+        assert_eq!(decompressed.header.body_size, body.len() as u64);
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn broken_body_is_passed_through() {
+    let mut broken = item(&b"not gzip"[..]);
+    broken.header.content_encoding = Some("gzip".into());
+    let compressions = Compressions::default();
+    assert!(compressions.decompress(delivered(broken.clone())).is_err());
+
+    let items = stream::iter_ok::<_, Error>(vec![delivered(broken)]);
+    let passed = decompress_stream(items, compressions).collect().wait().unwrap();
+    assert_eq!(passed[0].content_encoding(), Some("gzip"));
+    assert_eq!(passed[0].body.bytes, Bytes::from_static(b"not gzip"));
+}
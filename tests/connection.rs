@@ -0,0 +1,446 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+mod common;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::{Core, Timeout};
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use amqpr_codec::Frame;
+use amqpr_codec::content_header::Properties;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{AckMethod, BasicClass, CancelMethod, CancelOkMethod, GetEmptyMethod,
+                                 GetMethod, NackMethod, RecoverMethod};
+use amqpr_codec::method::channel::ChannelClass;
+use amqpr_codec::method::connection::ConnectionClass;
+use amqpr_codec::method::exchange::{self, ExchangeClass};
+use amqpr_codec::method::queue::{self, PurgeMethod, PurgeOkMethod, QueueClass, UnbindMethod};
+use amqpr_api::Connection;
+use amqpr_api::basic::GetOption;
+use amqpr_api::basic::consume::StartConsumeOption;
+use amqpr_api::exchange::DeleteExchangeOption;
+use amqpr_api::queue::{DeleteQueueOption, UnbindQueueOption};
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::mock::{MockBroker, Script};
+use amqpr_api::errors::*;
+
+use common::*;
+
+#[test]
+fn allocate_channel_ids() {
+    let mut core = Core::new().unwrap();
+    let script = close_connection(open_channel(open_channel(Script::new(), 1), 2));
+    let connection = connect(&script, &core.handle());
+
+    let ch1 = core.run(connection.create_channel()).unwrap();
+    let ch2 = core.run(connection.create_channel()).unwrap();
+    assert_eq!((ch1.id(), ch2.id()), (1, 2));
+
+    drop((connection, ch1, ch2));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn replies_are_routed_in_order() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(open_channel(Script::new(), 1), 2)
+        .expect(declare_queue(1, "first"))
+        .expect(declare_queue(1, ""))
+        .expect(declare_queue(2, "other"))
+        .reply(declare_ok(2, "other"))
+        .reply(declare_ok(1, "first"))
+        .reply(declare_ok(1, "amq.gen-1"));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let ch1 = core.run(connection.create_channel()).unwrap();
+    let ch2 = core.run(connection.create_channel()).unwrap();
+
+    let first = ch1.declare_queue(queue_option("first"));
+    let second = ch1.declare_queue(queue_option(""));
+    let other = ch2.declare_queue(queue_option("other"));
+    let (first, second, other) = core.run(first.join3(second, other)).unwrap();
+    assert_eq!(&*first.queue, "first");
+    assert_eq!(&*second.queue, "amq.gen-1");
+    assert_eq!(&*other.queue, "other");
+
+    drop((connection, ch1, ch2));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn consume_and_channel_closed_by_server() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1).expect(consume(1, "queue", "amqpr.ctag-1.1", false, false));
+    let script = reply_all(script, deliver(1, "amqpr.ctag-1.1", 1, Properties::new(), &[b"hello", b" world"]))
+        .reply(channel_close(1, 404, "NOT_FOUND"))
+        .expect(channel_frame(1, ChannelClass::CloseOk));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let option = StartConsumeOption {
+        queue: "queue".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    };
+    let consumer = channel.consume(option);
+    assert_eq!(consumer.consumer_tag(), "amqpr.ctag-1.1");
+
+    let (item, consumer) = core.run(consumer.into_future()).map_err(|(e, _)| e).unwrap();
+    let item = item.unwrap();
+    assert_eq!(item.meta.delivery_tag, 1);
+    assert_eq!(item.body.bytes, Bytes::from_static(b"hello world"));
+
+    match core.run(consumer.into_future()) {
+        Err((Error(ErrorKind::ChannelClosedByServer(1, 404, _), _), _)) => {}
+        Err((e, _)) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Consumer should fail"),
+    }
+    match core.run(channel.declare_queue(queue_option("closed"))) {
+        Err(Error(ErrorKind::ChannelClosed(1), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Channel should be closed"),
+    }
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn consumer_cancelled_by_server() {
+    let mut core = Core::new().unwrap();
+    let cancel = CancelMethod {
+        consumer_tag: "amqpr.ctag-1.1".into(),
+        no_wait: false,
+    };
+    let cancel_ok = CancelOkMethod {
+        consumer_tag: "amqpr.ctag-1.1".into(),
+    };
+    let script = open_channel(Script::new(), 1)
+        .expect(consume(1, "queue", "amqpr.ctag-1.1", false, false))
+        .reply(basic_frame(1, BasicClass::Cancel(cancel)))
+        .expect(basic_frame(1, BasicClass::CancelOk(cancel_ok)));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let consumer = channel.consume(StartConsumeOption {
+        queue: "queue".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    });
+    assert!(core.run(consumer.collect()).unwrap().is_empty());
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn cancel_consumer() {
+    let mut core = Core::new().unwrap();
+    let cancel = CancelMethod {
+        consumer_tag: "amqpr.ctag-1.1".into(),
+        no_wait: false,
+    };
+    let cancel_ok = CancelOkMethod {
+        consumer_tag: "amqpr.ctag-1.1".into(),
+    };
+    let script = open_channel(Script::new(), 1)
+        .expect(consume(1, "queue", "amqpr.ctag-1.1", false, false))
+        .expect(basic_frame(1, BasicClass::Cancel(cancel)));
+    let script = reply_all(script, deliver(1, "amqpr.ctag-1.1", 1, Properties::new(), &[b"hello"]))
+        .reply(basic_frame(1, BasicClass::CancelOk(cancel_ok)));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let consumer = channel.consume(StartConsumeOption {
+        queue: "queue".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    });
+    core.run(channel.cancel(String::from(consumer.consumer_tag()))).unwrap();
+    let items = core.run(consumer.collect()).unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].body.bytes, Bytes::from_static(b"hello"));
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn get_item_or_empty() {
+    let mut core = Core::new().unwrap();
+    let get = GetMethod {
+        reserved1: 0,
+        queue: "queue".into(),
+        no_ack: true,
+    };
+    let get_empty = GetEmptyMethod {
+        reserved1: "".into(),
+    };
+    let script = open_channel(Script::new(), 1).expect(basic_frame(1, BasicClass::Get(get.clone())));
+    let script = reply_all(script, get_ok(1, 1, b"hello"))
+        .expect(basic_frame(1, BasicClass::Get(get)))
+        .reply(basic_frame(1, BasicClass::GetEmpty(get_empty)));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let option = GetOption {
+        queue: "queue".into(),
+        is_no_ack: true,
+    };
+    let item = core.run(channel.get(option.clone())).unwrap().unwrap();
+    assert_eq!(item.meta.delivery_tag, 1);
+    assert_eq!(&*item.meta.consumer_tag, "");
+    assert_eq!(item.body.bytes, Bytes::from_static(b"hello"));
+    assert!(core.run(channel.get(option)).unwrap().is_none());
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn delete_and_recover() {
+    let mut core = Core::new().unwrap();
+    let unbind = UnbindMethod {
+        reserved1: 0,
+        queue: "queue".into(),
+        exchange: "exchange".into(),
+        routing_key: "key".into(),
+        arguments: HashMap::new(),
+    };
+    let purge = PurgeMethod {
+        reserved1: 0,
+        queue: "queue".into(),
+        no_wait: false,
+    };
+    let delete_queue = queue::DeleteMethod {
+        reserved1: 0,
+        queue: "queue".into(),
+        if_unused: true,
+        if_empty: false,
+        no_wait: false,
+    };
+    let delete_exchange = exchange::DeleteMethod {
+        reserved1: 0,
+        exchange: "exchange".into(),
+        if_unused: false,
+        no_wait: false,
+    };
+    let nack = NackMethod {
+        delivery_tag: 3,
+        multiple: true,
+    };
+    let script = open_channel(Script::new(), 1)
+        .expect(queue_frame(1, QueueClass::Unbind(unbind)))
+        .reply(queue_frame(1, QueueClass::UnbindOk))
+        .expect(queue_frame(1, QueueClass::Purge(purge)))
+        .reply(queue_frame(1, QueueClass::PurgeOk(PurgeOkMethod { message_count: 2 })))
+        .expect(queue_frame(1, QueueClass::Delete(delete_queue)))
+        .reply(queue_frame(1, QueueClass::DeleteOk(queue::DeleteOkMethod { message_count: 1 })))
+        .expect(exchange_frame(1, ExchangeClass::Delete(delete_exchange)))
+        .reply(exchange_frame(1, ExchangeClass::DeleteOk))
+        .expect(basic_frame(1, BasicClass::Nack(nack)))
+        .expect(basic_frame(1, BasicClass::Recover(RecoverMethod { requeue: true })))
+        .reply(basic_frame(1, BasicClass::RecoverOk));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let unbind = UnbindQueueOption {
+        queue: "queue".into(),
+        exchange: "exchange".into(),
+        routing_key: "key".into(),
+    };
+    core.run(channel.unbind_queue(unbind)).unwrap();
+    assert_eq!(core.run(channel.purge_queue("queue")).unwrap(), 2);
+    let delete_queue = DeleteQueueOption {
+        name: "queue".into(),
+        is_if_unused: true,
+        is_if_empty: false,
+    };
+    assert_eq!(core.run(channel.delete_queue(delete_queue)).unwrap(), 1);
+    let delete_exchange = DeleteExchangeOption {
+        name: "exchange".into(),
+        is_if_unused: false,
+    };
+    core.run(channel.delete_exchange(delete_exchange)).unwrap();
+    core.run(channel.nack(3, true)).unwrap();
+    core.run(channel.recover(true)).unwrap();
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+fn queue_frame(channel_id: u16, class: QueueClass) -> Frame {
+    Frame::new_method(channel_id, MethodPayload::Queue(class))
+}
+
+fn exchange_frame(channel_id: u16, class: ExchangeClass) -> Frame {
+    Frame::new_method(channel_id, MethodPayload::Exchange(class))
+}
+
+#[test]
+fn skip_methods_which_are_not_replies() {
+    let mut core = Core::new().unwrap();
+    let ack = AckMethod {
+        delivery_tag: 1,
+        multiple: false,
+    };
+    let script = open_channel(Script::new(), 1)
+        .expect(declare_queue(1, "queue"))
+        .reply(basic_frame(1, BasicClass::Ack(ack)))
+        .reply(declare_ok(1, "queue"));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let declared = core.run(channel.declare_queue(queue_option("queue"))).unwrap();
+    assert_eq!(&*declared.queue, "queue");
+
+    drop((connection, channel));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn connection_closed_by_server() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1)
+        .expect(declare_queue(1, "queue"))
+        .reply(connection_close(320, "CONNECTION_FORCED"))
+        .expect(Frame::new_method(0, MethodPayload::Connection(ConnectionClass::CloseOk)));
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    match core.run(channel.declare_queue(queue_option("queue"))) {
+        Err(Error(ErrorKind::ConnectionClosedByServer(320, _), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Declare should fail"),
+    }
+    finish(&mut core, &script);
+    assert!(connection.is_closed());
+    assert!(core.run(connection.create_channel()).is_err());
+}
+
+#[test]
+fn recycle_channel_ids_up_to_channel_max() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(open_channel(Script::new(), 1), 2)
+        .expect(channel_close(1, 200, "Normal shutdown"))
+        .reply(channel_frame(1, ChannelClass::CloseOk))
+        // A stale frame for the closed channel is rejected. The round trip on ch2 makes
+        // sure that it is processed before the id is reused.
+        .reply(declare_ok(1, "stale"))
+        .expect(declare_queue(2, "ch2"))
+        .reply(declare_ok(2, "ch2"));
+    let script = open_channel(script, 1)
+        .expect(declare_queue(1, "fresh"))
+        .reply(declare_ok(1, "fresh"));
+    let script = close_connection(script);
+    let connection = Connection::from_socket(script.socket(), info(2, 0), &core.handle());
+    let ch1 = core.run(connection.create_channel()).unwrap();
+    let ch2 = core.run(connection.create_channel()).unwrap();
+    assert_eq!((ch1.id(), ch2.id()), (1, 2));
+
+    match core.run(connection.create_channel()) {
+        Err(Error(ErrorKind::ChannelIdExhausted(2), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Channel id should be exhausted"),
+    }
+    assert!(core.run(connection.create_channel_with_id(3)).is_err());
+
+    let closed = ch1.close();
+    // Channel is refused while waiting for close-ok.
+    assert!(core.run(ch1.declare_queue(queue_option("closing"))).is_err());
+    core.run(closed).unwrap();
+
+    assert_eq!(&*core.run(ch2.declare_queue(queue_option("ch2"))).unwrap().queue, "ch2");
+
+    let ch3 = core.run(connection.create_channel()).unwrap();
+    assert_eq!(ch3.id(), 1);
+    let declared = ch3.declare_queue(queue_option("fresh"));
+    assert_eq!(&*core.run(declared).unwrap().queue, "fresh");
+
+    drop((connection, ch1, ch2, ch3));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn close_when_every_handle_is_dropped() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let connection = broker.connection(handshaker, &core.handle()).unwrap();
+    let channel = core.run(connection.create_channel()).unwrap();
+    core.run(channel.declare_queue(queue_option("exclusive"))).unwrap();
+    assert!(broker.has_queue("exclusive"));
+
+    // A clone keeps the connection open.
+    let cloned = channel.clone();
+    drop((connection, channel));
+    core.turn(Some(Duration::from_millis(10)));
+    assert!(broker.has_queue("exclusive"));
+
+    drop(cloned);
+    for _ in 0..100 {
+        if !broker.has_queue("exclusive") {
+            break;
+        }
+        core.turn(Some(Duration::from_millis(10)));
+    }
+    // The exclusive queue is deleted when `MockSocket` is dropped, that is, the driver
+    // is finished.
+    assert!(!broker.has_queue("exclusive"));
+    let close = broker.received_frames().pop().unwrap();
+    assert_eq!(close, connection_close(200, "Normal shutdown"));
+}
+
+#[test]
+fn fail_after_missed_heartbeats() {
+    let mut core = Core::new().unwrap();
+    // `MockBroker` never sends heartbeat.
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let (mut info, socket) = broker.handshake(handshaker).unwrap();
+    info.heartbeat = 1;
+    let connection = Connection::from_socket(socket, info, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+    core.run(channel.declare_queue(queue_option("queue"))).unwrap();
+
+    let started = Instant::now();
+    let option = StartConsumeOption {
+        queue: "queue".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    };
+    let consumed = channel.consume(option).into_future().map(|_| ()).map_err(|(e, _)| e);
+    let timeout = Timeout::new(Duration::from_secs(5), &core.handle())
+        .unwrap()
+        .map_err(Error::from);
+    match core.run(consumed.select(timeout)) {
+        Err((Error(ErrorKind::MissedHeartbeats(2), _), _)) => {}
+        Err((e, _)) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Connection should fail"),
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(1500), "{:?}", elapsed);
+    assert!(connection.is_closed());
+}
@@ -7,19 +7,18 @@ extern crate log;
 extern crate tokio_core;
 
 use tokio_core::reactor::Core;
-use tokio_core::net::TcpStream;
-use futures::Future;
+use futures::{Future, Stream};
 
 use bytes::Bytes;
 
-use amqpr_codec::args::Properties;
+use amqpr_codec::content_header::Properties;
 use amqpr_api::{bind_queue, declare_exchange, declare_queue, get_delivered, open_channel, publish,
-                start_consume, start_handshake};
+                start_consume};
 use amqpr_api::exchange::declare::{DeclareExchangeOption, ExchangeType};
 use amqpr_api::queue::{BindQueueOption, DeclareQueueOption};
 use amqpr_api::basic::{PublishItem, PublishOption, StartConsumeOption};
 use amqpr_api::handshake::SimpleHandshaker;
-use amqpr_api::errors::*;
+use amqpr_api::mock::MockBroker;
 
 const LOCAL_CHANNEL_ID: u16 = 42;
 
@@ -29,16 +28,12 @@ fn main() {
 
     let mut core = Core::new().unwrap();
 
-    let handshaker = SimpleHandshaker {
-        user: "guest".into(),
-        pass: "guest".into(),
-        virtual_host: "/".into(),
-    };
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
 
-    let future = TcpStream::connect(&"127.0.0.1:5672".parse().unwrap(), &core.handle())
-        .map_err(|e| Error::from(e))
-        .and_then(|socket| start_handshake(handshaker, socket))
-        .and_then(|socket| open_channel(LOCAL_CHANNEL_ID, socket))
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
+
+    let future = open_channel(LOCAL_CHANNEL_ID, socket)
         .and_then(|socket| {
             let option = DeclareExchangeOption {
                 name: "consume_test".into(),
@@ -50,7 +45,9 @@ fn main() {
             };
             declare_exchange(LOCAL_CHANNEL_ID, socket, option)
         })
-        .and_then(|socket| {
+        // `declare_exchange` does not wait for Declare-Ok method.
+        .and_then(|socket| socket.into_future().map_err(|(e, _socket)| e))
+        .and_then(|(_declare_ok, socket)| {
             let option = DeclareQueueOption {
                 name: "consume_test".into(),
                 is_passive: false,
@@ -95,7 +92,10 @@ fn main() {
         })
         .and_then(|socket| get_delivered(socket));
 
-    core.run(future).unwrap();
+    let (item, _socket) = core.run(future).unwrap();
+    assert_eq!(item.body.bytes, Bytes::from_static(b"pubish test"));
+    assert_eq!(&*item.meta.exchange, "consume_test");
+    assert!(broker.messages("consume_test").is_empty());
 }
 
 fn logger() {
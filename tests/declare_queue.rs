@@ -6,31 +6,27 @@ extern crate log;
 extern crate tokio_core;
 
 use tokio_core::reactor::Core;
-use tokio_core::net::TcpStream;
 use futures::Future;
 
-use amqpr_api::{declare_queue, open_channel, start_handshake};
+use amqpr_api::{declare_queue, open_channel};
 use amqpr_api::queue::declare::DeclareQueueOption;
 use amqpr_api::handshake::SimpleHandshaker;
-use amqpr_api::errors::*;
+use amqpr_api::mock::MockBroker;
 
 const LOCAL_CHANNEL_ID: u16 = 42;
 
+#[test]
 fn main() {
     logger();
 
     let mut core = Core::new().unwrap();
 
-    let handshaker = SimpleHandshaker {
-        user: "guest".into(),
-        pass: "guest".into(),
-        virtual_host: "/".into(),
-    };
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
 
-    let future = TcpStream::connect(&"127.0.0.1:5672".parse().unwrap(), &core.handle())
-        .map_err(|e| Error::from(e))
-        .and_then(|socket| start_handshake(handshaker, socket))
-        .and_then(|socket| open_channel(LOCAL_CHANNEL_ID, socket))
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
+
+    let future = open_channel(LOCAL_CHANNEL_ID, socket)
         .and_then(|socket| {
             let option = DeclareQueueOption {
                 name: "declare_queue_test".into(),
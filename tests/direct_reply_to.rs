@@ -0,0 +1,88 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+mod common;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use amqpr_codec::content_header::Properties;
+use amqpr_api::basic::consume::StartConsumeOption;
+use amqpr_api::basic::publish::{PublishItem, PublishOption};
+use amqpr_api::direct_reply_to::{direct_reply_to, DIRECT_REPLY_TO};
+use amqpr_api::mock::Script;
+use amqpr_api::errors::*;
+
+use common::*;
+
+fn request(reply_to: Option<&'static str>) -> PublishItem {
+    PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "rpc_queue".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: properties(reply_to),
+        body: Bytes::from_static(b"request"),
+    }
+}
+
+fn properties(reply_to: Option<&'static str>) -> Properties {
+    let mut properties = Properties::new();
+    properties.reply_to = reply_to.map(Into::into);
+    properties
+}
+
+#[test]
+fn consume_pseudo_queue_before_publishing() {
+    let mut core = Core::new().unwrap();
+    let script = Script::new().expect(consume(1, DIRECT_REPLY_TO, "", true, false));
+    let script = expect_all(script, publish(1, "rpc_queue", properties(Some(DIRECT_REPLY_TO)), b"request"));
+    let script = reply_all(script, deliver(1, "amq.ctag-1", 1, Properties::new(), &[b"reply"]));
+
+    let replies = direct_reply_to(1, script.socket());
+    // Published before the stream is polled, but sent after `Consume` method.
+    replies.request_handle().publish(request(None));
+    let (reply, _) = core.run(replies.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(reply.unwrap().body.bytes, Bytes::from_static(b"reply"));
+    script.assert_done();
+}
+
+#[test]
+fn channel_refuses_direct_reply_to_before_consuming() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1);
+    let script = expect_all(script, publish(1, "rpc_queue", properties(Some("queue")), b"request"))
+        .expect(consume(1, DIRECT_REPLY_TO, "amqpr.ctag-1.1", true, false));
+    let script = expect_all(script, publish(1, "rpc_queue", properties(Some(DIRECT_REPLY_TO)), b"request"));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    match core.run(channel.publish(request(Some(DIRECT_REPLY_TO)))) {
+        Err(Error(ErrorKind::DirectReplyToNotConsumed(1), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Publish should be refused"),
+    }
+    // Other reply_to is not affected.
+    core.run(channel.publish(request(Some("queue")))).unwrap();
+
+    let option = StartConsumeOption {
+        queue: DIRECT_REPLY_TO.into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: true,
+        is_exclusive: false,
+    };
+    let consumer = channel.consume(option);
+    core.run(channel.publish(request(Some(DIRECT_REPLY_TO)))).unwrap();
+
+    drop((connection, channel, consumer));
+    finish(&mut core, &script);
+}
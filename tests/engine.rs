@@ -0,0 +1,403 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, DeliverMethod, GetOkMethod};
+use amqpr_codec::method::channel::{self, ChannelClass};
+use amqpr_codec::method::connection::{self, ConnectionClass, StartMethod, TuneMethod};
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use amqpr_api::engine::{Engine, Event};
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::errors::*;
+
+fn connection_frame(class: ConnectionClass) -> Frame {
+    Frame::new_method(0, MethodPayload::Connection(class))
+}
+
+fn channel_frame(channel_id: u16, class: ChannelClass) -> Frame {
+    Frame::new_method(channel_id, MethodPayload::Channel(class))
+}
+
+fn method_name(frame: Option<Frame>) -> String {
+    match frame.map(|f| f.payload) {
+        Some(FramePayload::Method(method)) => format!("{:?}", method),
+        other => panic!("Expected a method but found {:?}", other),
+    }
+}
+
+/// Engine after handshake with `heartbeat` negotiated at `now`.
+fn connected(heartbeat: u16, now: Instant) -> Engine<SimpleHandshaker> {
+    let mut engine = Engine::new(SimpleHandshaker::new("guest", "guest", "/").heartbeat(heartbeat));
+    assert_eq!(engine.poll_transmit(), Some(Bytes::from_static(b"AMQP\x00\x00\x09\x01")));
+    assert_eq!(engine.open_channel().unwrap_err().to_string(), "Handshake is not completed yet");
+
+    let start = StartMethod {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: "PLAIN".into(),
+        locales: "en_US".into(),
+    };
+    engine.handle_frame(connection_frame(ConnectionClass::Start(start)), now).unwrap();
+    assert!(method_name(engine.poll_frame()).starts_with("Connection(StartOk("));
+    assert!(engine.poll_frame().is_none());
+
+    let tune = TuneMethod {
+        channel_max: 2,
+        frame_max: 131_072,
+        heartbeat,
+    };
+    engine.handle_frame(connection_frame(ConnectionClass::Tune(tune)), now).unwrap();
+    assert!(method_name(engine.poll_frame()).starts_with("Connection(TuneOk("));
+    assert!(method_name(engine.poll_frame()).starts_with("Connection(Open("));
+    assert!(engine.poll_event().is_none());
+
+    let open_ok = connection::OpenOkMethod {
+        reserved1: "".into(),
+    };
+    engine.handle_frame(connection_frame(ConnectionClass::OpenOk(open_ok)), now).unwrap();
+    match engine.poll_event() {
+        Some(Event::Connected(info)) => {
+            assert_eq!(info.channel_max, 2);
+            assert_eq!(info.heartbeat, heartbeat);
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+    assert!(engine.is_connected());
+    engine
+}
+
+#[test]
+fn channel_and_delivery() {
+    let now = Instant::now();
+    let mut engine = connected(0, now);
+    assert_eq!(engine.poll_timeout(), None);
+
+    let channel_id = engine.open_channel().unwrap();
+    assert!(method_name(engine.poll_frame()).starts_with("Channel(Open("));
+    let open_ok = channel::OpenOkMethod {
+        reserved1: "".into(),
+    };
+    engine.handle_frame(channel_frame(channel_id, ChannelClass::OpenOk(open_ok)), now).unwrap();
+    match engine.poll_event() {
+        Some(Event::ChannelOpened(id)) => assert_eq!(id, channel_id),
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    // Body is split into two frames.
+    let deliver = DeliverMethod {
+        consumer_tag: "ctag".into(),
+        delivery_tag: 1,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "queue".into(),
+    };
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: 6,
+        properties: Properties::new(),
+    };
+    let frames = vec![
+        Frame::new_method(channel_id, MethodPayload::Basic(BasicClass::Deliver(deliver))),
+        Frame::new_content_header(channel_id, header),
+        Frame::new_content_body(channel_id, ContentBodyPayload { bytes: "hel".into() }),
+        Frame::new_content_body(channel_id, ContentBodyPayload { bytes: "lo!".into() }),
+    ];
+    for frame in frames {
+        engine.handle_frame(frame, now).unwrap();
+    }
+    match engine.poll_event() {
+        Some(Event::Delivered(id, item)) => {
+            assert_eq!(id, channel_id);
+            assert_eq!(item.meta.delivery_tag, 1);
+            assert_eq!(item.body.bytes, Bytes::from_static(b"hello!"));
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    // Reply to `basic.get` has content too.
+    let get_ok = GetOkMethod {
+        delivery_tag: 2,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "queue".into(),
+        message_count: 0,
+    };
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: 3,
+        properties: Properties::new(),
+    };
+    let frames = vec![
+        Frame::new_method(channel_id, MethodPayload::Basic(BasicClass::GetOk(get_ok))),
+        Frame::new_content_header(channel_id, header),
+        Frame::new_content_body(channel_id, ContentBodyPayload { bytes: "bye".into() }),
+    ];
+    for frame in frames {
+        engine.handle_frame(frame, now).unwrap();
+    }
+    match engine.poll_event() {
+        Some(Event::Got(id, item)) => {
+            assert_eq!(id, channel_id);
+            assert_eq!(item.meta.delivery_tag, 2);
+            assert_eq!(item.body.bytes, Bytes::from_static(b"bye"));
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    // Server closes the channel.
+    let close = channel::CloseMethod {
+        reply_code: 404,
+        reply_text: "NOT_FOUND".into(),
+        class_id: 50,
+        method_id: 10,
+    };
+    engine.handle_frame(channel_frame(channel_id, ChannelClass::Close(close)), now).unwrap();
+    assert_eq!(method_name(engine.poll_frame()), "Channel(CloseOk)");
+    match engine.poll_event() {
+        Some(Event::ChannelClosed(id, Some((404, ref text)))) if text == "NOT_FOUND" => {
+            assert_eq!(id, channel_id)
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+    let flow = MethodPayload::Channel(ChannelClass::Flow(channel::FlowMethod { active: false }));
+    match engine.send_method(channel_id, flow) {
+        Err(Error(ErrorKind::ChannelClosed(id), _)) => assert_eq!(id, channel_id),
+        result => panic!("Unexpected result {:?}", result),
+    }
+    // The id is reused.
+    assert_eq!(engine.open_channel().unwrap(), channel_id);
+    assert!(method_name(engine.poll_frame()).starts_with("Channel(Open("));
+
+    // Client closes the connection.
+    engine.close().unwrap();
+    assert!(method_name(engine.poll_frame()).starts_with("Connection(Close("));
+    engine.handle_frame(connection_frame(ConnectionClass::CloseOk), now).unwrap();
+    match engine.poll_event() {
+        Some(Event::Closed(None)) => {}
+        event => panic!("Unexpected event {:?}", event),
+    }
+    assert!(engine.is_closed());
+    assert!(engine.handle_eof().is_ok());
+}
+
+#[test]
+fn heartbeat_over_bytes() {
+    let now = Instant::now();
+    let mut engine = connected(10, now);
+    assert_eq!(engine.poll_timeout(), Some(now + Duration::from_secs(5)));
+    let due = engine.poll_timeout().unwrap();
+
+    // Nothing is sent before the time.
+    engine.handle_timeout(due - Duration::from_secs(1)).unwrap();
+    assert!(engine.poll_transmit().is_none());
+
+    engine.handle_timeout(due).unwrap();
+    assert_eq!(
+        engine.poll_transmit(),
+        Some(Bytes::from_static(&[8, 0, 0, 0, 0, 0, 0, 0xCE]))
+    );
+    assert_eq!(engine.poll_timeout(), Some(due + Duration::from_secs(5)));
+
+    // A heartbeat from server split at odd position is just consumed.
+    engine.handle_bytes(&[8, 0, 0], due).unwrap();
+    engine.handle_bytes(&[0, 0, 0, 0, 0xCE], due).unwrap();
+    assert!(engine.poll_event().is_none());
+    assert!(engine.poll_frame().is_none());
+
+    match engine.handle_eof() {
+        Err(Error(ErrorKind::UnexpectedConnectionClose, _)) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    match engine.poll_event() {
+        Some(Event::Closed(None)) => {}
+        event => panic!("Unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn fail_after_missed_heartbeats() {
+    let mut engine = connected(1, Instant::now());
+    // Heartbeat is sent every half of the interval.
+    let due = engine.poll_timeout().unwrap();
+    engine.handle_timeout(due).unwrap();
+    assert_eq!(engine.poll_frame(), Some(Frame::new_heartbeat(0)));
+    assert!(engine.poll_event().is_none());
+
+    engine.handle_timeout(due + Duration::from_millis(500)).unwrap();
+    assert_eq!(engine.poll_frame(), Some(Frame::new_heartbeat(0)));
+    match engine.poll_event() {
+        Some(Event::HeartbeatMissed(1)) => {}
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    match engine.handle_timeout(due + Duration::from_millis(1500)) {
+        Err(Error(ErrorKind::MissedHeartbeats(2), _)) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    match (engine.poll_event(), engine.poll_event()) {
+        (Some(Event::HeartbeatMissed(2)), Some(Event::Closed(None))) => {}
+        events => panic!("Unexpected events {:?}", events),
+    }
+    assert!(engine.is_closed());
+    assert!(engine.poll_frame().is_none());
+    assert_eq!(engine.poll_timeout(), None);
+}
+
+#[test]
+fn reset_missed_heartbeats_on_frame() {
+    let mut engine = connected(1, Instant::now());
+    let due = engine.poll_timeout().unwrap();
+    engine.handle_timeout(due + Duration::from_millis(500)).unwrap();
+    match engine.poll_event() {
+        Some(Event::HeartbeatMissed(1)) => {}
+        event => panic!("Unexpected event {:?}", event),
+    }
+
+    let received = due + Duration::from_millis(600);
+    engine.handle_frame(Frame::new_heartbeat(0), received).unwrap();
+    // Server is silent for less than 2 intervals since the heartbeat.
+    engine.handle_timeout(received + Duration::from_millis(1900)).unwrap();
+    match engine.poll_event() {
+        Some(Event::HeartbeatMissed(1)) => {}
+        event => panic!("Unexpected event {:?}", event),
+    }
+    assert!(engine.is_connected());
+}
+
+#[test]
+fn wrong_protocol_header() {
+    let now = Instant::now();
+    let mut engine = Engine::new(SimpleHandshaker::new("guest", "guest", "/"));
+    engine.handle_bytes(b"AMQP", now).unwrap();
+    match engine.handle_bytes(b"\x00\x01\x00\x00", now) {
+        Err(Error(ErrorKind::WrongProtocolHeader(received), _)) => {
+            assert_eq!(received, b"AMQP\x00\x01\x00\x00".to_vec())
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+    assert!(engine.is_closed());
+}
+
+#[test]
+fn authentication_failure_on_eof() {
+    let now = Instant::now();
+    let mut engine = Engine::new(SimpleHandshaker::new("guest", "wrong", "/"));
+    let start = StartMethod {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: "PLAIN".into(),
+        locales: "en_US".into(),
+    };
+    engine.handle_frame(connection_frame(ConnectionClass::Start(start)), now).unwrap();
+    match engine.handle_eof() {
+        Err(Error(ErrorKind::AuthenticationFailure(_), _)) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    // No `Closed` event because it was never connected.
+    assert!(engine.poll_event().is_none());
+}
+
+/// Exchange frames between `engine` and `socket` until `socket` has nothing to yield,
+/// and returns events.
+#[cfg(feature = "test-util")]
+fn pump(engine: &mut Engine<SimpleHandshaker>, socket: &mut amqpr_api::mock::MockSocket) -> Vec<Event> {
+    use futures::{Async, Sink, Stream};
+
+    loop {
+        while let Some(frame) = engine.poll_frame() {
+            socket.start_send(frame).unwrap();
+        }
+        match socket.poll().unwrap() {
+            Async::Ready(Some(frame)) => engine.handle_frame(frame, Instant::now()).unwrap(),
+            _ => break,
+        }
+    }
+    let mut events = Vec::new();
+    while let Some(event) = engine.poll_event() {
+        events.push(event);
+    }
+    events
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn against_mock_broker() {
+    use amqpr_codec::method::queue::{DeclareMethod, QueueClass};
+    use amqpr_codec::method::basic::ConsumeMethod;
+    use amqpr_api::basic::{PublishItem, PublishOption};
+    use amqpr_api::mock::MockBroker;
+    use futures::{future, Future};
+
+    let broker = MockBroker::new();
+    let (_, mut socket) = broker.handshake(SimpleHandshaker::new("guest", "guest", "/")).unwrap();
+    let mut engine = connected(0, Instant::now());
+
+    future::lazy(move || {
+        let channel_id = engine.open_channel().unwrap();
+        let declare = DeclareMethod {
+            reserved1: 0,
+            queue: "engine".into(),
+            passive: false,
+            durable: false,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        let item = PublishItem {
+            meta: PublishOption {
+                exchange: "".into(),
+                routing_key: "engine".into(),
+                is_mandatory: false,
+                is_immediate: false,
+            },
+            header: Properties::new(),
+            body: Bytes::from_static(b"body"),
+        };
+        let consume = ConsumeMethod {
+            reserved1: 0,
+            queue: "engine".into(),
+            consumer_tag: "ctag".into(),
+            no_local: false,
+            no_ack: true,
+            exclusive: false,
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        let events = pump(&mut engine, &mut socket);
+        match events[..] {
+            [Event::ChannelOpened(id)] => assert_eq!(id, channel_id),
+            _ => panic!("Unexpected events {:?}", events),
+        }
+
+        engine.send_method(channel_id, MethodPayload::Queue(QueueClass::Declare(declare))).unwrap();
+        engine.publish(channel_id, item).unwrap();
+        engine.send_method(channel_id, MethodPayload::Basic(BasicClass::Consume(consume))).unwrap();
+        let events = pump(&mut engine, &mut socket);
+        match events[..] {
+            [
+                Event::Method(_, MethodPayload::Queue(QueueClass::DeclareOk(_))),
+                Event::Method(_, MethodPayload::Basic(BasicClass::ConsumeOk(_))),
+                Event::Delivered(id, ref item),
+            ] => {
+                assert_eq!(id, channel_id);
+                assert_eq!(item.body.bytes, Bytes::from_static(b"body"));
+            }
+            _ => panic!("Unexpected events {:?}", events),
+        }
+        Ok::<_, ()>(())
+    }).wait()
+        .unwrap();
+}
@@ -1,35 +1,83 @@
+//! `MockBroker::handshake` runs `FrameHandshaking` future, which `Handshaking` runs after the
+//! protocol header. Frames do not go through bytes, because amqpr-codec 0.3.2 fails to
+//! decode methods from bytes.
+
 extern crate amqpr_api;
+extern crate amqpr_codec;
 extern crate bytes;
 extern crate futures;
 extern crate log4rs;
 extern crate log;
 extern crate tokio_core;
 
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::ConnectionClass;
 use tokio_core::reactor::Core;
-use tokio_core::net::TcpStream;
-use futures::Future;
 
-use amqpr_api::start_handshake;
-use amqpr_api::handshake::SimpleHandshaker;
-use amqpr_api::errors::*;
+use amqpr_api::handshake::{start_frame_handshake, SimpleHandshaker};
+use amqpr_api::mock::MockBroker;
+use amqpr_api::errors::ErrorKind;
 
 #[test]
 fn main() {
     logger();
 
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+
+    let (info, _socket) = broker.handshake(handshaker).unwrap();
+    assert_eq!(info.product(), Some("amqpr-mock"));
+    assert!(info.has_capability("basic.nack"));
+    assert!(info.frame_max > 0);
+}
+
+#[test]
+fn frame_handshaking_on_reactor() {
     let mut core = Core::new().unwrap();
+    let broker = MockBroker::new().channel_max(16);
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+
+    let (info, _socket) = core.run(start_frame_handshake(handshaker, broker.socket())).unwrap();
+    assert_eq!(info.channel_max, 16);
 
-    let handshaker = SimpleHandshaker {
-        user: "guest".into(),
-        pass: "guest".into(),
-        virtual_host: "/".into(),
-    };
+    let methods: Vec<_> = broker
+        .received_frames()
+        .into_iter()
+        .map(|frame| match frame.method() {
+            Some(&MethodPayload::Connection(ref class)) => class.clone(),
+            method => panic!("Unexpected method {:?}", method),
+        })
+        .collect();
+    match methods.as_slice() {
+        [ConnectionClass::StartOk(start_ok), ConnectionClass::TuneOk(tune_ok), ConnectionClass::Open(open)] => {
+            assert_eq!(&*start_ok.mechanism, "PLAIN");
+            assert_eq!(tune_ok.channel_max, 16);
+            assert_eq!(&*open.virtual_host, "/");
+        }
+        methods => panic!("Unexpected methods {:?}", methods),
+    }
+}
+
+#[test]
+fn wrong_password() {
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "wrong", "/");
 
-    let future = TcpStream::connect(&"127.0.0.1:5672".parse().unwrap(), &core.handle())
-        .map_err(|e| Error::from(e))
-        .and_then(|socket| start_handshake(handshaker, socket));
+    match broker.handshake(handshaker).map(|_| ()).unwrap_err().kind() {
+        &ErrorKind::AuthenticationFailure(_) => {}
+        kind => panic!("Unexpected error {:?}", kind),
+    }
+}
+
+#[test]
+fn wrong_virtual_host() {
+    let broker = MockBroker::new().virtual_host("/test");
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
 
-    core.run(future).unwrap();
+    match broker.handshake(handshaker).map(|_| ()).unwrap_err().kind() {
+        &ErrorKind::VirtualHostAccessRefused(ref vhost, _) => assert_eq!(vhost, "/"),
+        kind => panic!("Unexpected error {:?}", kind),
+    }
 }
 
 fn logger() {
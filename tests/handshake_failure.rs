@@ -0,0 +1,156 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+use tokio_core::reactor::Core;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_io::io::{read_exact, write_all};
+use futures::{Future, Stream};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use amqpr_codec::Frame;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::{CloseMethod, ConnectionClass, StartMethod, TuneMethod};
+use amqpr_api::engine::HandshakeEngine;
+use amqpr_api::handshake::{start_handshake, start_handshake_with_timeout, SimpleHandshaker};
+use amqpr_api::errors::*;
+
+#[test]
+fn wrong_protocol_header() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // AMQP 1.0 broker replies its own protocol header and closes connection.
+    let server = listener
+        .incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(|(conn, _)| read_exact(conn.unwrap().0, [0; 8]))
+        .and_then(|(conn, _)| write_all(conn, *b"AMQP\x00\x01\x00\x00"));
+    handle.spawn(server.map(|_| ()).map_err(|_| ()));
+
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let future = TcpStream::connect(&addr, &handle)
+        .map_err(Error::from)
+        .and_then(|socket| start_handshake(handshaker, socket));
+
+    match core.run(future) {
+        Err(Error(ErrorKind::WrongProtocolHeader(received), _)) => {
+            assert_eq!(received, b"AMQP\x00\x01\x00\x00".to_vec())
+        }
+        _ => panic!("Handshake must fail with WrongProtocolHeader"),
+    }
+}
+
+#[test]
+fn timeout() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Server accepts connection but never responds.
+    let server = listener.incoming().for_each(|conn| {
+        ::std::mem::forget(conn);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|_| ()));
+
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let timeout = Duration::from_millis(100);
+    let future = TcpStream::connect(&addr, &handle)
+        .map_err(Error::from)
+        .and_then(move |socket| {
+            start_handshake_with_timeout(handshaker, socket, timeout, &handle)
+        });
+
+    match core.run(future) {
+        Err(Error(ErrorKind::HandshakeTimeout(_), _)) => {}
+        _ => panic!("Handshake must fail with HandshakeTimeout"),
+    }
+}
+
+// amqpr-codec 0.3.2 fails to decode methods from bytes, so a fake server on a socket can
+// not go further than the protocol header. Following tests feed frames into
+// `HandshakeEngine`, which `FrameHandshaking` drives after the protocol header.
+
+fn connection_frame(class: ConnectionClass) -> Frame {
+    Frame::new_method(0, MethodPayload::Connection(class))
+}
+
+fn start_frame() -> Frame {
+    connection_frame(ConnectionClass::Start(StartMethod {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: "PLAIN".into(),
+        locales: "en_US".into(),
+    }))
+}
+
+fn close_frame(reply_code: u16, reply_text: &'static str, method_id: u16) -> Frame {
+    connection_frame(ConnectionClass::Close(CloseMethod {
+        reply_code,
+        reply_text: reply_text.into(),
+        class_id: 10,
+        method_id,
+    }))
+}
+
+/// Returns name of methods the engine sends.
+fn sent_methods(engine: &mut HandshakeEngine<SimpleHandshaker>) -> Vec<String> {
+    let mut methods = Vec::new();
+    while let Some(frame) = engine.poll_frame() {
+        let method = frame.method().and_then(|m| m.connection()).map(|c| format!("{:?}", c));
+        let name = method.expect("Only connection methods are sent");
+        methods.push(name.split('(').next().unwrap().to_string());
+    }
+    methods
+}
+
+#[test]
+fn authentication_failure() {
+    // RabbitMQ closes connection with 403 after start-ok when client has
+    // "authentication_failure_close" capability.
+    let mut engine = HandshakeEngine::new(SimpleHandshaker::new("guest", "wrong", "/"));
+    engine.handle_frame(&start_frame()).unwrap();
+    assert_eq!(sent_methods(&mut engine), vec!["StartOk"]);
+
+    let close = close_frame(403, "ACCESS_REFUSED - Login was refused", 11);
+    match engine.handle_frame(&close) {
+        Err(Error(ErrorKind::AuthenticationFailure(reason), _)) => {
+            assert_eq!(reason, "ACCESS_REFUSED - Login was refused")
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn virtual_host_access_refused() {
+    let mut engine = HandshakeEngine::new(SimpleHandshaker::new("guest", "guest", "/missing"));
+    engine.handle_frame(&start_frame()).unwrap();
+    let tune = TuneMethod {
+        channel_max: 0,
+        frame_max: 131_072,
+        heartbeat: 0,
+    };
+    engine.handle_frame(&connection_frame(ConnectionClass::Tune(tune))).unwrap();
+    assert_eq!(sent_methods(&mut engine), vec!["StartOk", "TuneOk", "Open"]);
+
+    let close = close_frame(530, "NOT_ALLOWED - vhost /missing not found", 40);
+    match engine.handle_frame(&close) {
+        Err(Error(ErrorKind::VirtualHostAccessRefused(virtual_host, reason), _)) => {
+            assert_eq!(virtual_host, "/missing");
+            assert_eq!(reason, "NOT_ALLOWED - vhost /missing not found");
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
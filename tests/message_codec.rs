@@ -0,0 +1,121 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate serde;
+
+use futures::{stream, Future, Sink, Stream};
+use futures::unsync::mpsc;
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::basic::DeliverMethod;
+use amqpr_api::basic::deliver::DeliveredItem;
+use amqpr_api::basic::publish::{PublishItem, PublishOption};
+use amqpr_api::message_codec::*;
+use amqpr_api::errors::*;
+
+fn option() -> PublishOption {
+    PublishOption {
+        exchange: "".into(),
+        routing_key: "queue".into(),
+        is_mandatory: false,
+        is_immediate: false,
+    }
+}
+
+fn delivered(item: PublishItem) -> DeliveredItem {
+    DeliveredItem {
+        meta: DeliverMethod {
+            consumer_tag: "ctag".into(),
+            delivery_tag: 1,
+            redeliverd: false,
+            exchange: item.meta.exchange,
+            routing_key: item.meta.routing_key,
+        },
+        header: ContentHeaderPayload {
+            class_id: 60,
+            body_size: item.body.len() as u64,
+            properties: item.header,
+        },
+        body: ContentBodyPayload { bytes: item.body },
+    }
+}
+
+/// Publish `values` through `TypedSink`, and decode them through `TypedStream`.
+fn round_trip<C, T>(codec: C, values: Vec<T>) -> Vec<Decoded<T>>
+where
+    C: MessageCodec + Clone,
+    T: ::serde::Serialize + ::serde::de::DeserializeOwned,
+{
+    let (tx, rx) = mpsc::unbounded();
+    let sink = typed_sink(
+        tx.sink_map_err(|_| Error::from("receiver is dropped")),
+        codec.clone(),
+        option(),
+    );
+    let sink = sink.send_all(stream::iter_ok::<_, Error>(values)).wait().unwrap();
+    drop(sink);
+
+    let items: Vec<PublishItem> = rx.collect().wait().unwrap();
+    for item in &items {
+        assert_eq!(item.header.content_type, Some(codec.content_type().to_string().into()));
+    }
+    let delivered = stream::iter_ok::<_, Error>(items.into_iter().map(delivered));
+    typed_stream(delivered, codec).collect().wait().unwrap()
+}
+
+
+#[cfg(feature = "json")]
+#[test]
+fn json_round_trip_and_decode_error() {
+    let mut value = HashMap::new();
+    value.insert("id".to_string(), 1);
+    let decoded = round_trip(Json, vec![value.clone()]);
+    assert_eq!(decoded[0].value.as_ref().unwrap(), &value);
+
+    // Broken or mistyped messages are reported one by one without finishing the stream.
+    let mut wrong_type = Properties::new();
+    wrong_type.content_type = Some("text/plain".into());
+    let items = vec![
+        PublishItem { meta: option(), header: Properties::new(), body: Bytes::from_static(b"{") },
+        PublishItem { meta: option(), header: wrong_type, body: Bytes::from_static(b"{}") },
+        PublishItem { meta: option(), header: Properties::new(), body: Bytes::from_static(b"{\"id\":2}") },
+    ];
+    let delivered = stream::iter_ok::<_, Error>(items.into_iter().map(delivered));
+    let decoded: Vec<Decoded<HashMap<String, i32>>> = typed_stream(delivered, Json).collect().wait().unwrap();
+    assert_eq!(decoded.len(), 3);
+    match decoded[0].value {
+        Err(Error(ErrorKind::DecodeMessage(_), _)) => {}
+        ref other => panic!("Unexpected result : {:?}", other),
+    }
+    match decoded[1].value {
+        Err(Error(ErrorKind::UnexpectedContentType(ref expected, ref found), _)) => {
+            assert_eq!((expected.as_str(), found.as_str()), ("application/json", "text/plain"))
+        }
+        ref other => panic!("Unexpected result : {:?}", other),
+    }
+    assert_eq!(decoded[2].value.as_ref().unwrap()["id"], 2);
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_round_trip() {
+    let values = vec![(1u32, "first".to_string()), (2, "second".to_string())];
+    let decoded = round_trip(MessagePack, values.clone());
+    let decoded: Vec<_> = decoded.into_iter().map(|d| d.value.unwrap()).collect();
+    assert_eq!(decoded, values);
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn bincode_round_trip() {
+    let values = vec![vec![1u64, 2, 3], vec![]];
+    let decoded = round_trip(Bincode, values.clone());
+    let decoded: Vec<_> = decoded.into_iter().map(|d| d.value.unwrap()).collect();
+    assert_eq!(decoded, values);
+}
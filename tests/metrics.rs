@@ -0,0 +1,132 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use amqpr_codec::content_header::Properties;
+use amqpr_api::basic::{PublishItem, PublishOption, StartConsumeOption};
+use amqpr_api::queue::DeclareQueueOption;
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::metrics::Observer;
+use amqpr_api::mock::MockBroker;
+
+#[derive(Default)]
+struct Events(Mutex<Vec<String>>);
+
+impl Events {
+    fn push(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn count(&self, event: &str) -> usize {
+        self.0.lock().unwrap().iter().filter(|e| *e == event).count()
+    }
+}
+
+impl Observer for Events {
+    fn frame_sent(&self, channel_id: u16, kind: &'static str) {
+        self.push(format!("> {} {}", channel_id, kind));
+    }
+
+    fn frame_received(&self, channel_id: u16, kind: &'static str) {
+        self.push(format!("< {} {}", channel_id, kind));
+    }
+
+    fn published(&self, channel_id: u16, _latency: Duration) {
+        self.push(format!("published {}", channel_id));
+    }
+
+    fn delivered(&self, channel_id: u16) {
+        self.push(format!("delivered {}", channel_id));
+    }
+
+    fn acked(&self, channel_id: u16) {
+        self.push(format!("acked {}", channel_id));
+    }
+}
+
+#[test]
+fn connection_reports_publish_delivery_and_ack() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let connection = broker.connection(handshaker, &core.handle()).unwrap();
+    let events = Arc::new(Events::default());
+    connection.set_observer(events.clone());
+
+    let channel = core.run(connection.create_channel()).unwrap();
+    let id = channel.id();
+    let option = DeclareQueueOption {
+        name: "metrics".into(),
+        is_passive: false,
+        is_durable: false,
+        is_exclusive: false,
+        is_auto_delete: false,
+    };
+    core.run(channel.declare_queue(option)).unwrap();
+
+    let item = PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "metrics".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: Properties::new(),
+        body: Bytes::from_static(b"body"),
+    };
+    core.run(channel.publish(item)).unwrap();
+
+    let consumer = channel.consume(StartConsumeOption {
+        queue: "metrics".into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    });
+    let (item, _consumer) = core.run(consumer.into_future().map_err(|(e, _)| e)).unwrap();
+    let tag = item.unwrap().meta.delivery_tag;
+    core.run(channel.ack(tag, false)).unwrap();
+
+    assert_eq!(events.count(&format!("> {} channel.open", id)), 1);
+    assert_eq!(events.count(&format!("< {} channel.open-ok", id)), 1);
+    assert_eq!(events.count(&format!("> {} basic.publish", id)), 1);
+    assert_eq!(events.count(&format!("> {} content-body", id)), 1);
+    assert_eq!(events.count(&format!("published {}", id)), 1);
+    assert_eq!(events.count(&format!("< {} basic.deliver", id)), 1);
+    assert_eq!(events.count(&format!("delivered {}", id)), 1);
+    assert_eq!(events.count(&format!("acked {}", id)), 1);
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_text_format() {
+    use amqpr_api::metrics::PrometheusObserver;
+
+    let observer = PrometheusObserver::new();
+    observer.frame_sent(1, "basic.publish");
+    observer.frame_sent(1, "basic.publish");
+    observer.frame_received(1, "basic.deliver");
+    observer.bytes_sent(100);
+    observer.published(1, Duration::from_millis(2));
+    observer.reconnected();
+
+    let text = observer.render();
+    assert!(text.contains("# TYPE amqpr_frames_sent_total counter\n"));
+    assert!(text.contains("amqpr_frames_sent_total{kind=\"basic.publish\"} 2\n"));
+    assert!(text.contains("amqpr_frames_received_total{kind=\"basic.deliver\"} 1\n"));
+    assert!(text.contains("amqpr_bytes_sent_total 100\n"));
+    assert!(text.contains("amqpr_reconnects_total 1\n"));
+    assert!(text.contains("amqpr_publish_latency_seconds_bucket{le=\"0.001\"} 0\n"));
+    assert!(text.contains("amqpr_publish_latency_seconds_bucket{le=\"0.005\"} 1\n"));
+    assert!(text.contains("amqpr_publish_latency_seconds_count 1\n"));
+}
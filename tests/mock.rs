@@ -0,0 +1,176 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use amqpr_codec::AmqpString;
+use amqpr_codec::content_header::Properties;
+use amqpr_api::{Channel, Connection};
+use amqpr_api::basic::{PublishItem, PublishOption, QosOption, StartConsumeOption};
+use amqpr_api::exchange::declare::{DeclareExchangeOption, ExchangeType};
+use amqpr_api::queue::{BindQueueOption, DeclareQueueOption};
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::mock::MockBroker;
+use amqpr_api::errors::*;
+
+fn connect(core: &mut Core, broker: &MockBroker) -> (Connection, Channel) {
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let connection = broker.connection(handshaker, &core.handle()).unwrap();
+    let channel = core.run(connection.create_channel()).unwrap();
+    (connection, channel)
+}
+
+fn queue_option(name: &'static str) -> DeclareQueueOption {
+    DeclareQueueOption {
+        name: name.into(),
+        is_passive: false,
+        is_durable: false,
+        is_exclusive: false,
+        is_auto_delete: false,
+    }
+}
+
+fn consume_option(queue: &'static str) -> StartConsumeOption {
+    StartConsumeOption {
+        queue: queue.into(),
+        consumer_tag: "".into(),
+        is_no_local: false,
+        is_no_ack: false,
+        is_exclusive: false,
+    }
+}
+
+#[test]
+fn passive_declare_of_missing_queue_closes_channel() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let (_connection, channel) = connect(&mut core, &broker);
+
+    let mut option = queue_option("missing");
+    option.is_passive = true;
+    let err = core.run(channel.declare_queue(option)).unwrap_err();
+    match *err.kind() {
+        ErrorKind::ChannelClosedByServer(_, 404, _) => {}
+        ref kind => panic!("Unexpected error {:?}", kind),
+    }
+    assert!(!broker.has_queue("missing"));
+}
+
+#[test]
+fn server_named_queue() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let (_connection, channel) = connect(&mut core, &broker);
+
+    let declared = core.run(channel.declare_queue(queue_option(""))).unwrap();
+    assert!(declared.queue.starts_with("amq.gen-"));
+    assert!(broker.has_queue(&declared.queue));
+}
+
+#[test]
+fn topic_exchange_routes_by_pattern() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let (_connection, channel) = connect(&mut core, &broker);
+
+    let option = DeclareExchangeOption {
+        name: "logs".into(),
+        typ: ExchangeType::Topic,
+        is_passive: false,
+        is_durable: false,
+        is_auto_delete: false,
+        is_internal: false,
+    };
+    core.run(channel.declare_exchange(option)).unwrap();
+    core.run(channel.declare_queue(queue_option("errors"))).unwrap();
+    for key in &["*.error", "kernel.#"] {
+        let option = BindQueueOption {
+            queue: "errors".into(),
+            exchange: "logs".into(),
+            routing_key: AmqpString::from(*key),
+        };
+        core.run(channel.bind_queue(option)).unwrap();
+    }
+
+    for key in &["app.error", "app.info", "kernel", "kernel.disk.info"] {
+        let item = PublishItem {
+            meta: PublishOption {
+                exchange: "logs".into(),
+                routing_key: AmqpString::from(*key),
+                is_mandatory: false,
+                is_immediate: false,
+            },
+            header: Properties::new(),
+            body: Bytes::from(key.as_bytes()),
+        };
+        core.run(channel.publish(item)).unwrap();
+    }
+
+    let bodies: Vec<Bytes> = broker.messages("errors").into_iter().map(|m| m.body).collect();
+    assert_eq!(bodies, vec![
+        Bytes::from_static(b"app.error"),
+        Bytes::from_static(b"kernel"),
+        Bytes::from_static(b"kernel.disk.info"),
+    ]);
+}
+
+#[test]
+fn prefetch_holds_deliveries_until_ack() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let (_connection, channel) = connect(&mut core, &broker);
+
+    core.run(channel.declare_queue(queue_option("tasks"))).unwrap();
+    let qos = QosOption {
+        prefetch_size: 0,
+        prefetch_count: 1,
+        is_global: false,
+    };
+    core.run(channel.qos(qos)).unwrap();
+    for body in &[&b"first"[..], &b"second"[..]] {
+        assert!(broker.publish("", "tasks", Properties::new(), Bytes::from(*body)));
+    }
+
+    let consumer = channel.consume(consume_option("tasks"));
+    let (first, consumer) = core.run(consumer.into_future()).map_err(|(e, _)| e).unwrap();
+    let first = first.unwrap();
+    assert_eq!(first.body.bytes, Bytes::from_static(b"first"));
+    assert_eq!(broker.unacked_count(), 1);
+    assert_eq!(broker.messages("tasks").len(), 1);
+
+    core.run(channel.ack(first.meta.delivery_tag, false)).unwrap();
+    let (second, _consumer) = core.run(consumer.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(second.unwrap().body.bytes, Bytes::from_static(b"second"));
+    assert!(broker.messages("tasks").is_empty());
+}
+
+#[test]
+fn dropped_connection_requeues_unacked_messages() {
+    let mut core = Core::new().unwrap();
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
+
+    let future = amqpr_api::open_channel(1, socket).and_then(|socket| {
+        amqpr_api::declare_queue(1, socket, queue_option("jobs")).map(|(_result, socket)| socket)
+    });
+    let socket = core.run(future).unwrap();
+    broker.publish("", "jobs", Properties::new(), Bytes::from_static(b"job"));
+
+    let future = amqpr_api::start_consume(1, socket, consume_option("jobs"))
+        .and_then(amqpr_api::get_delivered);
+    let (item, socket) = core.run(future).unwrap();
+    assert!(!item.meta.redeliverd);
+    assert_eq!(broker.unacked_count(), 1);
+
+    drop(socket);
+    let messages = broker.messages("jobs");
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].redelivered);
+}
@@ -0,0 +1,123 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use amqpr_codec::FieldArgument;
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::basic::DeliverMethod;
+use amqpr_api::basic::deliver::DeliveredItem;
+use amqpr_api::basic::properties::{DeliveryMode, PropertiesBuilder};
+use amqpr_api::errors::*;
+
+fn delivered(properties: Properties) -> DeliveredItem {
+    DeliveredItem {
+        meta: DeliverMethod {
+            consumer_tag: "ctag".into(),
+            delivery_tag: 1,
+            redeliverd: false,
+            exchange: "".into(),
+            routing_key: "queue".into(),
+        },
+        header: ContentHeaderPayload {
+            class_id: 60,
+            body_size: 0,
+            properties,
+        },
+        body: ContentBodyPayload {
+            bytes: Bytes::new(),
+        },
+    }
+}
+
+#[test]
+fn build_and_read_properties() {
+    let timestamp = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let properties = PropertiesBuilder::new()
+        .content_type("application/json")
+        .content_encoding("gzip")
+        .persistent()
+        .priority(5)
+        .correlation_id("corr-1")
+        .reply_to("replies")
+        .expiration(Duration::from_millis(1500))
+        .message_id("msg-1")
+        .timestamp(timestamp)
+        .message_type("order.created")
+        .user_id("guest")
+        .app_id("order-service")
+        .header("retry", FieldArgument::SignedLong(3))
+        .build();
+
+    assert_eq!(properties.delivery_mode, Some(2));
+    assert_eq!(properties.expiration, Some("1500".into()));
+    assert_eq!(properties.timestamp, Some(1_500_000_000));
+
+    let item = delivered(properties);
+    assert_eq!(item.content_type(), Some("application/json"));
+    assert_eq!(item.content_encoding(), Some("gzip"));
+    assert_eq!(item.delivery_mode(), Some(DeliveryMode::Persistent));
+    assert!(item.is_persistent());
+    assert_eq!(item.priority(), Some(5));
+    assert_eq!(item.correlation_id(), Some("corr-1"));
+    assert_eq!(item.reply_to(), Some("replies"));
+    assert_eq!(item.expiration(), Some(Duration::from_millis(1500)));
+    assert_eq!(item.message_id(), Some("msg-1"));
+    assert_eq!(item.timestamp(), Some(timestamp));
+    assert_eq!(item.message_type(), Some("order.created"));
+    assert_eq!(item.user_id(), Some("guest"));
+    assert_eq!(item.app_id(), Some("order-service"));
+    assert_eq!(item.header_value("retry"), Some(&FieldArgument::SignedLong(3)));
+}
+
+#[test]
+fn missing_or_invalid_properties() {
+    let mut properties = Properties::new();
+    properties.delivery_mode = Some(7);
+    properties.expiration = Some("soon".into());
+
+    let item = delivered(properties);
+    assert_eq!(item.delivery_mode(), None);
+    assert!(!item.is_persistent());
+    assert_eq!(item.expiration(), None);
+    assert_eq!(item.reply_to(), None);
+    assert_eq!(item.timestamp(), None);
+    assert_eq!(item.header_value("retry"), None);
+}
+
+#[test]
+fn clamp_priority_to_9() {
+    assert_eq!(PropertiesBuilder::new().priority(10).build().priority, Some(9));
+    assert_eq!(PropertiesBuilder::new().priority(9).build().priority, Some(9));
+}
+
+#[test]
+fn make_response_to_reply_to() {
+    let properties = PropertiesBuilder::new()
+        .reply_to("amq.gen-reply")
+        .correlation_id("corr-1")
+        .content_type("text/plain")
+        .build();
+    let response = delivered(properties)
+        .make_response(Bytes::from_static(b"pong"))
+        .unwrap();
+
+    assert_eq!(&*response.meta.exchange, "");
+    assert_eq!(&*response.meta.routing_key, "amq.gen-reply");
+    assert_eq!(response.header.correlation_id, Some("corr-1".into()));
+    assert_eq!(response.header.content_type, None);
+    assert_eq!(response.body, Bytes::from_static(b"pong"));
+}
+
+#[test]
+fn make_response_without_reply_to() {
+    match delivered(Properties::new()).make_response("pong") {
+        Err(Error(ErrorKind::NoReplyTo(1), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("make_response should fail"),
+    }
+}
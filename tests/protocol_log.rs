@@ -0,0 +1,85 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+
+use futures::{Future, Stream};
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, DeliverMethod};
+use amqpr_codec::method::connection::{ConnectionClass, StartOkMethod};
+use amqpr_api::protocol_log::{display, LogConfig};
+use amqpr_api::trace_frames;
+
+fn deliver_frame() -> Frame {
+    let deliver = DeliverMethod {
+        consumer_tag: "tag".into(),
+        delivery_tag: 17,
+        redeliverd: false,
+        exchange: "x".into(),
+        routing_key: "y".into(),
+    };
+    Frame::new_method(42, MethodPayload::Basic(BasicClass::Deliver(deliver)))
+}
+
+fn body_frame(body: &'static [u8]) -> Frame {
+    let body = ContentBodyPayload {
+        bytes: Bytes::from_static(body),
+    };
+    Frame::new(42, FramePayload::ContentBody(body))
+}
+
+#[test]
+fn deliver_in_one_line() {
+    assert_eq!(
+        display(&deliver_frame()).to_string(),
+        "channel=42 basic.deliver tag=17 exchange=x rk=y"
+    );
+}
+
+#[test]
+fn start_ok_response_is_redacted() {
+    let start_ok = StartOkMethod {
+        client_properties: HashMap::new(),
+        mechanism: "PLAIN".into(),
+        response: "\u{0}guest\u{0}secret".into(),
+        locale: "en_US".into(),
+    };
+    let frame = Frame::new_method(0, MethodPayload::Connection(ConnectionClass::StartOk(start_ok)));
+    let line = display(&frame).to_string();
+
+    assert_eq!(
+        line,
+        "channel=0 connection.start-ok mechanism=PLAIN response=<redacted> locale=en_US"
+    );
+}
+
+#[test]
+fn body_is_hidden_by_default_and_truncated() {
+    let frame = body_frame(b"hello world");
+    assert_eq!(display(&frame).to_string(), "channel=42 content-body size=11");
+
+    let config = LogConfig {
+        log_body: true,
+        max_body_len: 5,
+    };
+    assert_eq!(
+        config.display(&frame).to_string(),
+        "channel=42 content-body size=11 body=\"hello\"..."
+    );
+}
+
+#[test]
+fn traced_socket_passes_frames_through() {
+    let frames = vec![deliver_frame(), body_frame(b"body")];
+    let stream = futures::stream::iter_ok::<_, ()>(frames.clone());
+
+    let received = trace_frames(stream, LogConfig::default()).collect().wait().unwrap();
+    assert_eq!(received, frames);
+}
@@ -7,16 +7,16 @@ extern crate log;
 extern crate tokio_core;
 
 use tokio_core::reactor::Core;
-use tokio_core::net::TcpStream;
 use futures::Future;
 
 use bytes::Bytes;
 
-use amqpr_api::{declare_exchange, open_channel, publish, start_handshake};
+use amqpr_api::{declare_exchange, open_channel, publish};
 use amqpr_api::exchange::declare::{DeclareExchangeOption, ExchangeType};
-use amqpr_api::basic::publish::PublishOption;
+use amqpr_codec::content_header::Properties;
+use amqpr_api::basic::{PublishItem, PublishOption};
 use amqpr_api::handshake::SimpleHandshaker;
-use amqpr_api::errors::*;
+use amqpr_api::mock::MockBroker;
 
 const LOCAL_CHANNEL_ID: u16 = 42;
 
@@ -26,16 +26,12 @@ fn main() {
 
     let mut core = Core::new().unwrap();
 
-    let handshaker = SimpleHandshaker {
-        user: "guest".into(),
-        pass: "guest".into(),
-        virtual_host: "/".into(),
-    };
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
 
-    let future = TcpStream::connect(&"127.0.0.1:5672".parse().unwrap(), &core.handle())
-        .map_err(|e| Error::from(e))
-        .and_then(|socket| start_handshake(handshaker, socket))
-        .and_then(|socket| open_channel(LOCAL_CHANNEL_ID, socket))
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
+
+    let future = open_channel(LOCAL_CHANNEL_ID, socket)
         .and_then(|socket| {
             let option = DeclareExchangeOption {
                 name: "publish_test".into(),
@@ -54,11 +50,23 @@ fn main() {
                 is_mandatory: false,
                 is_immediate: false,
             };
-            let bytes = Bytes::from_static(b"pubish test");
-            publish(LOCAL_CHANNEL_ID, socket, bytes, option)
+            let item = PublishItem {
+                meta: option,
+                header: Properties::new(),
+                body: Bytes::from_static(b"pubish test"),
+            };
+            publish(LOCAL_CHANNEL_ID, socket, item)
         });
 
     core.run(future).unwrap();
+
+    // Publish method, content header and content body.
+    let frames = broker.received_frames();
+    let publish = &frames[frames.len() - 3];
+    let publish = publish.method().and_then(|m| m.basic()).and_then(|c| c.publish());
+    assert_eq!(&*publish.unwrap().exchange, "publish_test");
+    let body = frames.last().unwrap().content_body().unwrap();
+    assert_eq!(body.bytes, Bytes::from_static(b"pubish test"));
 }
 
 fn logger() {
@@ -0,0 +1,141 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+
+use futures::{future, Async, AsyncSink, Future, Sink};
+
+use bytes::Bytes;
+
+use amqpr_codec::Frame;
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, PublishMethod};
+use amqpr_codec::method::channel::{ChannelClass, CloseMethod, FlowMethod, FlowOkMethod};
+use amqpr_api::{publish_sink, publish_sink_without_flow};
+use amqpr_api::basic::{PublishItem, PublishOption};
+use amqpr_api::mock::Script;
+use amqpr_api::errors::*;
+
+const CHANNEL_ID: u16 = 3;
+
+fn item() -> PublishItem {
+    PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "flow".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: Properties::new(),
+        body: Bytes::from_static(b"body"),
+    }
+}
+
+fn item_frames() -> Vec<Frame> {
+    let publish = PublishMethod {
+        reserved1: 0,
+        exchange: "".into(),
+        routing_key: "flow".into(),
+        mandatory: false,
+        immediate: false,
+    };
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: 4,
+        properties: Properties::new(),
+    };
+    vec![
+        Frame::new_method(CHANNEL_ID, MethodPayload::Basic(BasicClass::Publish(publish))),
+        Frame::new_content_header(CHANNEL_ID, header),
+        Frame::new_content_body(
+            CHANNEL_ID,
+            ContentBodyPayload {
+                bytes: Bytes::from_static(b"body"),
+            },
+        ),
+    ]
+}
+
+fn flow(channel_id: u16, active: bool) -> Frame {
+    let flow = FlowMethod { active };
+    Frame::new_method(channel_id, MethodPayload::Channel(ChannelClass::Flow(flow)))
+}
+
+fn flow_ok(active: bool) -> Frame {
+    let flow_ok = FlowOkMethod { active };
+    Frame::new_method(CHANNEL_ID, MethodPayload::Channel(ChannelClass::FlowOk(flow_ok)))
+}
+
+#[test]
+fn pause_and_resume_on_flow() {
+    // The heartbeat is sent by the test itself, so that the server resumes flow only after
+    // we saw the sink paused.
+    let mut script = Script::new()
+        .reply(flow(CHANNEL_ID + 1, false))
+        .reply(flow(CHANNEL_ID, false))
+        .expect(flow_ok(false))
+        .expect(Frame::new_heartbeat(0))
+        .reply(flow(CHANNEL_ID, true))
+        .expect(flow_ok(true));
+    for frame in item_frames() {
+        script = script.expect(frame);
+    }
+    // Keeps the socket open until the test ends.
+    script = script.expect(Frame::new_heartbeat(0));
+
+    let mut sink = publish_sink(CHANNEL_ID, script.socket());
+    let mut other = script.socket();
+    future::lazy(move || {
+        // Flow on another channel is skipped.
+        match sink.start_send(item()).unwrap() {
+            AsyncSink::NotReady(_) => {}
+            AsyncSink::Ready => panic!("Sink accepted an item while flow is paused"),
+        }
+        assert!(!sink.is_flow_active());
+
+        other.start_send(Frame::new_heartbeat(0)).unwrap();
+        assert!(sink.start_send(item()).unwrap().is_ready());
+        assert!(sink.is_flow_active());
+        assert_eq!(sink.poll_complete().unwrap(), Async::Ready(()));
+        other.start_send(Frame::new_heartbeat(0)).unwrap();
+        Ok::<_, Error>(())
+    }).wait()
+        .unwrap();
+
+    script.assert_done();
+}
+
+#[test]
+fn fail_on_channel_close() {
+    let close = CloseMethod {
+        reply_code: 404,
+        reply_text: "NOT_FOUND - no exchange 'missing'".into(),
+        class_id: 60,
+        method_id: 40,
+    };
+    let script = Script::new().reply(Frame::new_method(
+        CHANNEL_ID,
+        MethodPayload::Channel(ChannelClass::Close(close)),
+    ));
+
+    let mut sink = publish_sink(CHANNEL_ID, script.socket());
+    let result = future::lazy(move || sink.start_send(item())).wait();
+    match result {
+        Err(Error(ErrorKind::ChannelClosedByServer(CHANNEL_ID, 404, _), _)) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn sink_only_socket() {
+    let mut script = Script::new();
+    for frame in item_frames() {
+        script = script.expect(frame);
+    }
+
+    let sink = publish_sink_without_flow(CHANNEL_ID, script.socket());
+    sink.send(item()).wait().unwrap();
+    script.assert_done();
+}
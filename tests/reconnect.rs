@@ -0,0 +1,391 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+
+use tokio_core::reactor::Core;
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use amqpr_api::reconnect::{connect, ReconnectOption};
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::errors::*;
+
+#[test]
+fn give_up_after_max_attempts() {
+    let mut core = Core::new().unwrap();
+
+    // Take a free port and close it so that nobody listens on it.
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let option = ReconnectOption {
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(20),
+        max_attempts: Some(2),
+    };
+    let future = connect(
+        addr,
+        || SimpleHandshaker::new("guest", "guest", "/"),
+        option,
+        &core.handle(),
+    );
+
+    match core.run(future) {
+        Err(Error(ErrorKind::ReconnectFailed(attempts), _)) => assert_eq!(attempts, 2),
+        _ => panic!("Connecting must fail with ReconnectFailed"),
+    }
+}
+
+#[cfg(feature = "test-util")]
+mod scripted {
+    use super::*;
+
+    use futures::{future, Future, Sink, Stream};
+    use tokio_core::reactor::Handle;
+
+    use std::cell::Cell;
+    use std::collections::{HashMap, VecDeque};
+    use std::rc::Rc;
+
+    use amqpr_codec::Frame;
+    use amqpr_codec::method::MethodPayload;
+    use amqpr_codec::method::basic::{BasicClass, ConsumeMethod, ConsumeOkMethod};
+    use amqpr_codec::method::channel::{self, ChannelClass};
+    use amqpr_codec::method::exchange::{self, ExchangeClass};
+    use amqpr_codec::method::queue::{self, QueueClass};
+    use amqpr_api::handshake::ConnectionInfo;
+    use amqpr_api::mock::{Script, ScriptedSocket};
+    use amqpr_api::reconnect::{connect_with, Connect, Connector, ReconnectingSocket};
+
+    /// Connects to given scripts in order, and counts connections. Every script ends with
+    /// the connection lost.
+    struct Scripts(VecDeque<ScriptedSocket>, Rc<Cell<u32>>);
+
+    impl Connector for Scripts {
+        type Socket = ScriptedSocket;
+
+        fn connect(&mut self, _handle: &Handle) -> Connect<ScriptedSocket> {
+            self.1.set(self.1.get() + 1);
+            let info = ConnectionInfo {
+                version_major: 0,
+                version_minor: 9,
+                server_properties: HashMap::new(),
+                mechanisms: vec!["PLAIN".into()],
+                locales: vec!["en_US".into()],
+                channel_max: 0,
+                frame_max: 0,
+                heartbeat: 0,
+            };
+            match self.0.pop_front() {
+                Some(socket) => Box::new(future::ok((info, socket))),
+                None => Box::new(future::err("No more script".into())),
+            }
+        }
+    }
+
+    fn start(core: &mut Core, scripts: &[&Script]) -> ReconnectingSocket<Scripts> {
+        start_with(core, scripts, Some(3), Rc::new(Cell::new(0)))
+    }
+
+    fn start_with(
+        core: &mut Core,
+        scripts: &[&Script],
+        max_attempts: Option<u32>,
+        connects: Rc<Cell<u32>>,
+    ) -> ReconnectingSocket<Scripts> {
+        let option = ReconnectOption {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            max_attempts,
+        };
+        let connector = Scripts(scripts.iter().map(|s| s.socket()).collect(), connects);
+        let future = connect_with(connector, option, &core.handle());
+        core.run(future).unwrap()
+    }
+
+    /// Send `frame` and returns the next frame received.
+    fn call(
+        core: &mut Core,
+        socket: ReconnectingSocket<Scripts>,
+        frame: Frame,
+    ) -> (Frame, ReconnectingSocket<Scripts>) {
+        let future = socket
+            .send(frame)
+            .and_then(|socket| socket.into_future().map_err(|(e, _)| e));
+        match core.run(future).unwrap() {
+            (Some(reply), socket) => (reply, socket),
+            (None, _) => panic!("Socket is finished"),
+        }
+    }
+
+    /// Send a heartbeat. The first script is already finished, so this reconnects.
+    fn reconnect(
+        core: &mut Core,
+        socket: ReconnectingSocket<Scripts>,
+    ) -> ReconnectingSocket<Scripts> {
+        let socket = core.run(socket.send(Frame::new_heartbeat(0))).unwrap();
+        assert_eq!(socket.reconnects(), 1);
+        socket
+    }
+
+    fn channel_frame(channel_id: u16, class: ChannelClass) -> Frame {
+        Frame::new_method(channel_id, MethodPayload::Channel(class))
+    }
+
+    fn open(channel_id: u16) -> (Frame, Frame) {
+        let open = channel::OpenMethod {
+            reserved1: "".into(),
+        };
+        let open_ok = channel::OpenOkMethod {
+            reserved1: "".into(),
+        };
+        (
+            channel_frame(channel_id, ChannelClass::Open(open)),
+            channel_frame(channel_id, ChannelClass::OpenOk(open_ok)),
+        )
+    }
+
+    fn close(channel_id: u16) -> (Frame, Frame) {
+        let close = channel::CloseMethod {
+            reply_code: 200,
+            reply_text: "Normal shutdown".into(),
+            class_id: 0,
+            method_id: 0,
+        };
+        (
+            channel_frame(channel_id, ChannelClass::Close(close)),
+            channel_frame(channel_id, ChannelClass::CloseOk),
+        )
+    }
+
+    fn declare_exchange(name: &'static str) -> (Frame, Frame) {
+        let declare = exchange::DeclareMethod {
+            reserved1: 0,
+            exchange: name.into(),
+            typ: "direct".into(),
+            passive: false,
+            durable: false,
+            auto_delete: false,
+            internal: false,
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        (
+            Frame::new_method(1, MethodPayload::Exchange(ExchangeClass::Declare(declare))),
+            Frame::new_method(1, MethodPayload::Exchange(ExchangeClass::DeclareOk)),
+        )
+    }
+
+    fn declare_queue(name: &'static str, replied: &'static str) -> (Frame, Frame) {
+        let declare = queue::DeclareMethod {
+            reserved1: 0,
+            queue: name.into(),
+            passive: false,
+            durable: false,
+            exclusive: true,
+            auto_delete: false,
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        let declare_ok = queue::DeclareOkMethod {
+            queue: replied.into(),
+            message_count: 0,
+            consumer_count: 0,
+        };
+        (
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::Declare(declare))),
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::DeclareOk(declare_ok))),
+        )
+    }
+
+    fn bind_queue(name: &'static str) -> (Frame, Frame) {
+        let bind = queue::BindMethod {
+            reserved1: 0,
+            queue: name.into(),
+            exchange: "logs".into(),
+            routing_key: "info".into(),
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        (
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::Bind(bind))),
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::BindOk)),
+        )
+    }
+
+    fn delete_queue(name: &'static str) -> (Frame, Frame) {
+        let delete = queue::DeleteMethod {
+            reserved1: 0,
+            queue: name.into(),
+            if_unused: false,
+            if_empty: false,
+            no_wait: false,
+        };
+        let delete_ok = queue::DeleteOkMethod { message_count: 0 };
+        (
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::Delete(delete))),
+            Frame::new_method(1, MethodPayload::Queue(QueueClass::DeleteOk(delete_ok))),
+        )
+    }
+
+    fn consume(channel_id: u16, name: &'static str) -> (Frame, Frame) {
+        let consume = ConsumeMethod {
+            reserved1: 0,
+            queue: name.into(),
+            consumer_tag: "ctag".into(),
+            no_local: false,
+            no_ack: true,
+            exclusive: false,
+            no_wait: false,
+            arguments: HashMap::new(),
+        };
+        let consume_ok = ConsumeOkMethod {
+            consumer_tag: "ctag".into(),
+        };
+        (
+            Frame::new_method(channel_id, MethodPayload::Basic(BasicClass::Consume(consume))),
+            Frame::new_method(channel_id, MethodPayload::Basic(BasicClass::ConsumeOk(consume_ok))),
+        )
+    }
+
+    /// Appends `expect(request)` and `reply(reply)` steps.
+    fn step(script: Script, (request, reply): (Frame, Frame)) -> Script {
+        script.expect(request).reply(reply)
+    }
+
+    #[test]
+    fn replay_topology_after_connection_lost() {
+        let mut core = Core::new().unwrap();
+        let mut first = Script::new();
+        for pair in vec![
+            open(1),
+            declare_exchange("logs"),
+            declare_queue("logs.info", "logs.info"),
+            bind_queue("logs.info"),
+            declare_queue("tmp", "tmp"),
+            delete_queue("tmp"),
+            open(2),
+            consume(2, "logs.info"),
+            close(2),
+            open(2),
+        ] {
+            first = step(first, pair);
+        }
+        // Channel 2 is opened once, and the consumer on closed channel is not replayed.
+        let mut second = Script::new();
+        for pair in vec![
+            open(1),
+            open(2),
+            declare_exchange("logs"),
+            declare_queue("logs.info", "logs.info"),
+            bind_queue("logs.info"),
+        ] {
+            second = step(second, pair);
+        }
+        second = second.expect(Frame::new_heartbeat(0));
+
+        let mut socket = start(&mut core, &[&first, &second]);
+        for (request, reply) in vec![
+            open(1),
+            declare_exchange("logs"),
+            declare_queue("logs.info", "logs.info"),
+            bind_queue("logs.info"),
+            declare_queue("tmp", "tmp"),
+            delete_queue("tmp"),
+            open(2),
+            consume(2, "logs.info"),
+            close(2),
+            open(2),
+        ] {
+            let (received, s) = call(&mut core, socket, request);
+            assert_eq!(received, reply);
+            socket = s;
+        }
+        first.assert_done();
+
+        reconnect(&mut core, socket);
+        second.assert_done();
+    }
+
+    #[test]
+    fn rename_server_named_queue() {
+        let mut core = Core::new().unwrap();
+        let first = step(step(Script::new(), open(1)), declare_queue("", "amq.gen-1"));
+        let first = step(first, bind_queue("amq.gen-1"));
+        let second = step(step(Script::new(), open(1)), declare_queue("", "amq.gen-2"));
+        let second = step(second, bind_queue("amq.gen-2"))
+            .expect(Frame::new_heartbeat(0));
+        let second = step(second, consume(1, "amq.gen-2")).expect(Frame::new_heartbeat(0));
+
+        let mut socket = start(&mut core, &[&first, &second]);
+        for (request, _) in vec![open(1), declare_queue("", "amq.gen-1"), bind_queue("amq.gen-1")] {
+            socket = call(&mut core, socket, request).1;
+        }
+
+        // User keeps using the name server replied at first.
+        let socket = reconnect(&mut core, socket);
+        let (received, socket) = call(&mut core, socket, consume(1, "amq.gen-1").0);
+        assert_eq!(received, consume(1, "amq.gen-2").1);
+        core.run(socket.send(Frame::new_heartbeat(0))).unwrap();
+        second.assert_done();
+    }
+
+    #[test]
+    fn reconnect_once_with_max_attempts_1() {
+        let mut core = Core::new().unwrap();
+        let first = step(Script::new(), open(1));
+        let connects = Rc::new(Cell::new(0));
+
+        let socket = start_with(&mut core, &[&first], Some(1), connects.clone());
+        let socket = call(&mut core, socket, open(1).0).1;
+        first.assert_done();
+
+        // The first connection is lost, and the only reconnect attempt finds no script.
+        match core.run(socket.send(Frame::new_heartbeat(0))) {
+            Err(Error(ErrorKind::ReconnectFailed(1), _)) => {}
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(connects.get(), 2);
+    }
+
+    #[test]
+    fn fail_replay_closed_by_server() {
+        let mut core = Core::new().unwrap();
+        let first = step(step(Script::new(), open(1)), declare_queue("locked", "locked"));
+        let locked = channel::CloseMethod {
+            reply_code: 405,
+            reply_text: "RESOURCE_LOCKED - cannot obtain exclusive access to locked queue".into(),
+            class_id: 50,
+            method_id: 10,
+        };
+        let second = step(Script::new(), open(1))
+            .expect(declare_queue("locked", "locked").0)
+            .reply(channel_frame(1, ChannelClass::Close(locked)));
+        let connects = Rc::new(Cell::new(0));
+
+        let mut socket = start_with(&mut core, &[&first, &second], Some(1), connects.clone());
+        for (request, _) in vec![open(1), declare_queue("locked", "locked")] {
+            socket = call(&mut core, socket, request).1;
+        }
+
+        // Replay does not wait for declare-ok forever, and the attempt is counted.
+        let error = match core.run(socket.send(Frame::new_heartbeat(0))) {
+            Err(error) => error,
+            Ok(_) => panic!("Reconnect must fail"),
+        };
+        match *error.kind() {
+            ErrorKind::ReconnectFailed(1) => {}
+            ref kind => panic!("Unexpected error {:?}", kind),
+        }
+        let cause = error.iter().nth(1).map(|e| e.to_string());
+        assert_eq!(
+            cause,
+            Some("Channel 1 was closed by server : 405 RESOURCE_LOCKED - cannot obtain exclusive \
+                  access to locked queue"
+                .into())
+        );
+        second.assert_done();
+        assert_eq!(connects.get(), 2);
+    }
+}
@@ -0,0 +1,162 @@
+extern crate amqpr_api;
+extern crate futures;
+extern crate tokio_core;
+
+use futures::future;
+use tokio_core::reactor::Core;
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use amqpr_api::recording::{Direction, Record, Recorder, Replay};
+
+const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+
+/// Writer whose contents can be read after it is moved into `Recorder`.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn method_frame(channel: u16, class_id: u16, method_id: u16, args: &[u8]) -> Vec<u8> {
+    let size = 4 + args.len() as u32;
+    let mut frame = vec![1, (channel >> 8) as u8, channel as u8];
+    frame.extend_from_slice(&[(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8]);
+    frame.extend_from_slice(&[(class_id >> 8) as u8, class_id as u8, (method_id >> 8) as u8, method_id as u8]);
+    frame.extend_from_slice(args);
+    frame.push(0xCE);
+    frame
+}
+
+fn record(direction: Direction, bytes: &[u8]) -> Record {
+    Record {
+        timestamp: 0,
+        direction,
+        bytes: bytes.to_vec().into(),
+    }
+}
+
+/// Protocol header, Start, Start-Ok and Tune.
+fn records() -> Vec<Record> {
+    vec![
+        record(Direction::Outbound, PROTOCOL_HEADER),
+        record(Direction::Inbound, &method_frame(0, 10, 10, &[0, 9])),
+        record(Direction::Outbound, &method_frame(0, 10, 11, &[1, 2, 3])),
+        record(Direction::Inbound, &method_frame(0, 10, 30, &[0, 7, 0, 0, 16, 0, 0, 0])),
+    ]
+}
+
+/// Runs `f` inside a task, because `Replay` registers it when it can not read yet.
+fn in_task<F: FnOnce()>(f: F) {
+    Core::new()
+        .unwrap()
+        .run(future::lazy(|| {
+            f();
+            Ok::<(), ()>(())
+        }))
+        .unwrap();
+}
+
+#[test]
+fn record_replayed_conversation() {
+    in_task(|| {
+        let buf = SharedBuf::default();
+        let mut recorder = Recorder::new(Replay::new(records()), buf.clone());
+        let mut read = [0; 64];
+
+        // Start is recorded after the protocol header.
+        let err = recorder.read(&mut read).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        recorder.write_all(PROTOCOL_HEADER).unwrap();
+        let n = recorder.read(&mut read).unwrap();
+        assert_eq!(&read[..n], &method_frame(0, 10, 10, &[0, 9])[..]);
+
+        // Start-Ok in two writes.
+        let start_ok = method_frame(0, 10, 11, &[1, 2, 3]);
+        recorder.write_all(&start_ok[..5]).unwrap();
+        recorder.write_all(&start_ok[5..]).unwrap();
+        let n = recorder.read(&mut read).unwrap();
+        assert_eq!(n, 20);
+        assert_eq!(recorder.read(&mut read).unwrap(), 0);
+        assert!(recorder.get_ref().is_finished());
+
+        let text = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        assert!(text.starts_with("# amqpr-api recording 1\n"));
+        let recorded = Record::read_all(text.as_bytes()).unwrap();
+        let fields: Vec<String> = recorded
+            .iter()
+            .map(|r| r.to_string().splitn(5, ' ').skip(1).take(3).collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(fields, vec![
+            "> protocol-header -",
+            "< method 0",
+            "> method 0",
+            "< method 0",
+        ]);
+        // Arguments of Start-Ok are not recorded.
+        let mut expected = records();
+        expected[2] = record(Direction::Outbound, &method_frame(0, 10, 11, &[]));
+        for (recorded, expected) in recorded.iter().zip(expected.iter()) {
+            assert_eq!(recorded.direction, expected.direction);
+            assert_eq!(recorded.bytes, expected.bytes);
+        }
+    });
+}
+
+#[test]
+fn strict_replay_fails_on_another_method() {
+    in_task(|| {
+        let mut replay = Replay::new(records()).strict();
+        let mut read = [0; 64];
+
+        replay.write_all(PROTOCOL_HEADER).unwrap();
+        assert!(replay.read(&mut read).unwrap() > 0);
+
+        let err = replay.write_all(&method_frame(0, 10, 31, &[])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let msg = err.to_string();
+        assert!(msg.contains("Client sent method 10.31 on channel 0"), "{}", msg);
+        assert!(msg.contains("method 10.11 on channel 0 is recorded"), "{}", msg);
+    });
+}
+
+#[test]
+fn lenient_replay_ignores_mismatch_and_heartbeat() {
+    in_task(|| {
+        let mut replay = Replay::new(records());
+        let mut read = [0; 64];
+
+        replay.write_all(PROTOCOL_HEADER).unwrap();
+        assert!(replay.read(&mut read).unwrap() > 0);
+        replay.write_all(&[8, 0, 0, 0, 0, 0, 0, 0xCE]).unwrap();
+        replay.write_all(&method_frame(1, 20, 10, &[0])).unwrap();
+
+        let n = replay.read(&mut read).unwrap();
+        assert_eq!(&read[..n], &method_frame(0, 10, 30, &[0, 7, 0, 0, 16, 0, 0, 0])[..]);
+        assert!(replay.is_finished());
+    });
+}
+
+#[test]
+fn parse_record_line() {
+    let line = "1508371200012345 < method 3 0100030000000400140029ce";
+    let record = Record::parse(line).unwrap().unwrap();
+    assert_eq!(record.timestamp, 1508371200012345);
+    assert_eq!(record.direction, Direction::Inbound);
+    assert_eq!(record.bytes, method_frame(3, 20, 41, &[]));
+    assert_eq!(record.to_string(), line);
+
+    assert!(Record::parse("# comment").unwrap().is_none());
+    assert!(Record::parse("0 < method 3 0100030000000400140029").is_err());
+    assert!(Record::parse("0 ? method 3 0100030000000400140029ce").is_err());
+}
@@ -0,0 +1,101 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate log4rs;
+extern crate log;
+extern crate tokio_core;
+
+use tokio_core::reactor::Core;
+use futures::{Future, Stream};
+
+use bytes::Bytes;
+
+use amqpr_codec::content_header::Properties;
+use amqpr_api::{declare_queue, open_channel, publish, subscribe_stream};
+use amqpr_api::queue::DeclareQueueOption;
+use amqpr_api::basic::{PublishItem, PublishOption, StartConsumeOption};
+use amqpr_api::handshake::SimpleHandshaker;
+use amqpr_api::mock::MockBroker;
+
+const LOCAL_CHANNEL_ID: u16 = 42;
+
+#[test]
+fn main() {
+    logger();
+
+    let mut core = Core::new().unwrap();
+
+    let broker = MockBroker::new();
+    let handshaker = SimpleHandshaker::new("guest", "guest", "/");
+
+    let (_info, socket) = broker.handshake(handshaker).unwrap();
+
+    let future = open_channel(LOCAL_CHANNEL_ID, socket)
+        .and_then(|socket| {
+            let option = DeclareQueueOption {
+                name: "recover_test".into(),
+                is_passive: false,
+                is_durable: false,
+                is_exclusive: false,
+                is_auto_delete: true,
+            };
+            declare_queue(LOCAL_CHANNEL_ID, socket, option).map(|(_result, socket)| socket)
+        })
+        .and_then(|socket| {
+            let option = PublishOption {
+                exchange: "".into(),
+                routing_key: "recover_test".into(),
+                is_mandatory: false,
+                is_immediate: false,
+            };
+            let item = PublishItem {
+                meta: option,
+                header: Properties::new(),
+                body: Bytes::from_static(b"recover test"),
+            };
+            publish(LOCAL_CHANNEL_ID, socket, item)
+        })
+        .and_then(|socket| {
+            let option = StartConsumeOption {
+                queue: "recover_test".into(),
+                consumer_tag: "".into(),
+                is_no_local: false,
+                is_no_ack: false,
+                is_exclusive: false,
+            };
+            let stream = subscribe_stream(LOCAL_CHANNEL_ID, socket, option);
+            let handle = stream.ack_handle();
+
+            // Fail to process first delivery, then receive it again.
+            stream
+                .map(move |item| {
+                    if item.meta.redeliverd {
+                        handle.ack(item.meta.delivery_tag, false);
+                    } else {
+                        handle.recover(true);
+                    }
+                    item
+                })
+                .skip_while(|item| Ok(!item.meta.redeliverd))
+                .into_future()
+                .map_err(|(e, _stream)| e)
+        });
+
+    let (item, _stream) = core.run(future).unwrap();
+    assert!(item.unwrap().meta.redeliverd);
+}
+
+fn logger() {
+    use log::LevelFilter;
+    use log4rs::append::console::ConsoleAppender;
+    use log4rs::config::{Appender, Config, Root};
+    let stdout = ConsoleAppender::builder().build();
+
+    let config = Config::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .build(Root::builder().appender("stdout").build(LevelFilter::Info))
+        .unwrap();
+
+    log4rs::init_config(config).unwrap();
+}
@@ -0,0 +1,200 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+mod common;
+
+use futures::Future;
+use futures::unsync::oneshot;
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use amqpr_codec::content_header::Properties;
+use amqpr_codec::method::basic::BasicClass;
+use amqpr_api::basic::properties::PropertiesBuilder;
+use amqpr_api::basic::publish::{PublishItem, PublishOption};
+use amqpr_api::mock::Script;
+use amqpr_api::rpc::{ReplyQueue, RpcClient, RpcClientOption, RpcServer, RpcServerOption,
+                     DIRECT_REPLY_TO};
+use amqpr_api::errors::*;
+
+use common::*;
+
+fn request(body: &'static [u8]) -> PublishItem {
+    PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "rpc_queue".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: Properties::new(),
+        body: Bytes::from_static(body),
+    }
+}
+
+fn request_properties(reply_to: &'static str, correlation_id: &'static str) -> Properties {
+    PropertiesBuilder::new()
+        .reply_to(reply_to)
+        .correlation_id(correlation_id)
+        .build()
+}
+
+fn reply_properties(correlation_id: &'static str) -> Properties {
+    PropertiesBuilder::new().correlation_id(correlation_id).build()
+}
+
+#[test]
+fn call_with_exclusive_reply_queue() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1)
+        .expect(declare_queue(1, ""))
+        .reply(declare_ok(1, "amq.gen-reply"))
+        .expect(consume(1, "amq.gen-reply", "amqpr.ctag-1.1", true, true));
+    let script = expect_all(script, publish(1, "rpc_queue", request_properties("amq.gen-reply", "amqpr-rpc.1"), b"first"));
+    let script = expect_all(script, publish(1, "rpc_queue", request_properties("amq.gen-reply", "amqpr-rpc.2"), b"second"));
+    // Replies arrive in reverse order.
+    let tag = "amqpr.ctag-1.1";
+    let script = reply_all(script, deliver(1, tag, 1, reply_properties("amqpr-rpc.2"), &[b"second reply"]));
+    let script = reply_all(script, deliver(1, tag, 2, reply_properties("unknown"), &[b"ignored"]));
+    let script = reply_all(script, deliver(1, tag, 3, reply_properties("amqpr-rpc.1"), &[b"first reply"]));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let started = RpcClient::start(channel, RpcClientOption::default(), &core.handle());
+    let client = core.run(started).unwrap();
+    assert_eq!(client.reply_to(), "amq.gen-reply");
+
+    let first = client.call(request(b"first"));
+    let second = client.call(request(b"second"));
+    assert_eq!(first.correlation_id(), "amqpr-rpc.1");
+    let (first, second) = core.run(first.join(second)).unwrap();
+    assert_eq!(first.body.bytes, Bytes::from_static(b"first reply"));
+    assert_eq!(second.body.bytes, Bytes::from_static(b"second reply"));
+
+    drop((connection, client));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn call_with_direct_reply_to_and_timeout() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1).expect(consume(1, DIRECT_REPLY_TO, "amqpr.ctag-1.1", true, false));
+    let script = expect_all(script, publish(1, "rpc_queue", request_properties(DIRECT_REPLY_TO, "amqpr-rpc.1"), b"request"));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let option = RpcClientOption {
+        reply_queue: ReplyQueue::DirectReplyTo,
+        timeout: Duration::from_millis(50),
+    };
+    let client = core.run(RpcClient::start(channel, option, &core.handle())).unwrap();
+    assert_eq!(client.reply_to(), DIRECT_REPLY_TO);
+
+    match core.run(client.call(request(b"request"))) {
+        Err(Error(ErrorKind::RpcTimeout(id), _)) => assert_eq!(id, "amqpr-rpc.1"),
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Request should be timed out"),
+    }
+
+    drop((connection, client));
+    finish(&mut core, &script);
+}
+
+#[test]
+fn stop_routing_replies_when_client_is_dropped() {
+    let mut core = Core::new().unwrap();
+    let script = open_channel(Script::new(), 1).expect(consume(1, DIRECT_REPLY_TO, "amqpr.ctag-1.1", true, false));
+    let script = expect_all(script, publish(1, "rpc_queue", request_properties(DIRECT_REPLY_TO, "amqpr-rpc.1"), b"request"));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let option = RpcClientOption {
+        reply_queue: ReplyQueue::DirectReplyTo,
+        timeout: Duration::from_secs(5),
+    };
+    let client = core.run(RpcClient::start(channel, option, &core.handle())).unwrap();
+    let cloned = client.clone();
+    let response = client.call(request(b"request"));
+    drop(client);
+    core.turn(Some(Duration::from_millis(10)));
+
+    drop(cloned);
+    match core.run(response) {
+        Err(Error(ErrorKind::RpcClientClosed(_), _)) => {}
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Request should fail"),
+    }
+
+    drop(connection);
+    finish(&mut core, &script);
+}
+
+#[test]
+fn serve_requests_one_by_one() {
+    let mut core = Core::new().unwrap();
+    let tag = "amqpr.ctag-1.1";
+    let script = open_channel(Script::new(), 1)
+        .expect(qos(1, 1))
+        .reply(basic_frame(1, BasicClass::QosOk))
+        .expect(consume(1, "rpc_queue", tag, false, false));
+    let script = reply_all(script, deliver(1, tag, 1, request_properties("reply", "c1"), &[b"first"]));
+    let script = reply_all(script, deliver(1, tag, 2, request_properties("reply", "c2"), &[b"second"]));
+    let script = expect_all(script, publish(1, "reply", reply_properties("c1"), b"first reply"))
+        .expect(ack(1, 1))
+        // Failed request is rejected without reply.
+        .expect(reject(1, 2));
+    let script = close_connection(script);
+    let connection = connect(&script, &core.handle());
+    let channel = core.run(connection.create_channel()).unwrap();
+
+    let handlings = Rc::new(RefCell::new(Vec::new()));
+    let handlings2 = handlings.clone();
+    let option = RpcServerOption {
+        queue: "rpc_queue".into(),
+        consumer_tag: "".into(),
+        concurrency: 1,
+    };
+    let rpc_server = RpcServer::new(channel, option, move |request| {
+        let (tx, rx) = oneshot::channel::<Bytes>();
+        handlings2.borrow_mut().push((request.body.bytes.clone(), tx));
+        rx.map_err(|_| Error::from("handler is canceled"))
+    });
+    core.handle().spawn(rpc_server.map_err(|e| panic!("RpcServer fails : {}", e)));
+
+    while handlings.borrow().is_empty() {
+        core.turn(Some(Duration::from_millis(10)));
+    }
+    for _ in 0..5 {
+        core.turn(Some(Duration::from_millis(1)));
+    }
+    // The second request waits until the first one is acknowledged.
+    assert_eq!(handlings.borrow().len(), 1);
+
+    let (body, tx) = handlings.borrow_mut().remove(0);
+    assert_eq!(body, Bytes::from_static(b"first"));
+    tx.send(Bytes::from_static(b"first reply")).unwrap();
+
+    while handlings.borrow().is_empty() {
+        core.turn(Some(Duration::from_millis(10)));
+    }
+    let (body, _) = handlings.borrow_mut().remove(0);
+    assert_eq!(body, Bytes::from_static(b"second"));
+    for _ in 0..5 {
+        core.turn(Some(Duration::from_millis(1)));
+    }
+
+    // `RpcServer` keeps the connection open until it is closed explicitly.
+    core.run(connection.close()).unwrap();
+    finish(&mut core, &script);
+}
@@ -0,0 +1,138 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use amqpr_codec::AmqpString;
+use amqpr_codec::method::connection::{SecureMethod, StartMethod};
+use amqpr_api::handshake::{Handshaker, SimpleHandshaker};
+use amqpr_api::handshake::sasl::{AmqPlain, External, Plain, SaslMechanism};
+use amqpr_api::errors::*;
+
+fn start_method(mechanisms: &'static str) -> StartMethod {
+    StartMethod {
+        version_major: 0,
+        version_minor: 9,
+        server_properties: HashMap::new(),
+        mechanisms: mechanisms.into(),
+        locales: "en_US".into(),
+    }
+}
+
+#[test]
+fn plain_response() {
+    let mut plain = Plain {
+        user: "guest".into(),
+        pass: "secret".into(),
+    };
+    assert_eq!(plain.initial_response().unwrap(), b"\0guest\0secret".to_vec());
+    assert!(plain.reply_to_challenge(b"challenge").is_err());
+}
+
+#[test]
+fn amqplain_response() {
+    let mut amqplain = AmqPlain {
+        user: "guest".into(),
+        pass: "secret".into(),
+    };
+    let expected = b"\x05LOGINS\x00\x00\x00\x05guest\x08PASSWORDS\x00\x00\x00\x06secret".to_vec();
+    assert_eq!(amqplain.initial_response().unwrap(), expected);
+}
+
+#[test]
+fn select_offered_mechanism() {
+    let mut handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    let start_ok = handshaker
+        .reply_to_start(&start_method("EXTERNAL AMQPLAIN"))
+        .unwrap();
+    assert_eq!(&*start_ok.mechanism, "AMQPLAIN");
+
+    let mechanisms: Vec<Box<dyn SaslMechanism>> = vec![Box::new(External::default())];
+    let mut handshaker = SimpleHandshaker::with_mechanisms(mechanisms, "/");
+    let start_ok = handshaker
+        .reply_to_start(&start_method("PLAIN EXTERNAL"))
+        .unwrap();
+    assert_eq!(&*start_ok.mechanism, "EXTERNAL");
+    assert_eq!(&*start_ok.response, "");
+}
+
+#[test]
+fn no_supported_mechanism() {
+    let mut handshaker = SimpleHandshaker::new("guest", "guest", "/");
+    match handshaker.reply_to_start(&start_method("EXTERNAL")) {
+        Err(Error(ErrorKind::NoSupportedSaslMechanism(offered), _)) => {
+            assert_eq!(offered, "EXTERNAL")
+        }
+        _ => panic!("Handshaker must fail when no mechanism is supported"),
+    }
+}
+
+/// Mechanism which records challenges and replies with `response`.
+struct Recording {
+    challenges: Rc<RefCell<Vec<Vec<u8>>>>,
+    response: Vec<u8>,
+}
+
+impl SaslMechanism for Recording {
+    fn name(&self) -> &str {
+        "RECORDING"
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(Vec::new())
+    }
+
+    fn reply_to_challenge(&mut self, challenge: &[u8]) -> Result<Vec<u8>, Error> {
+        self.challenges.borrow_mut().push(challenge.to_vec());
+        Ok(self.response.clone())
+    }
+}
+
+fn recording(response: &[u8]) -> (SimpleHandshaker, Rc<RefCell<Vec<Vec<u8>>>>) {
+    let challenges = Rc::new(RefCell::new(Vec::new()));
+    let mechanism = Recording {
+        challenges: challenges.clone(),
+        response: response.to_vec(),
+    };
+    let mechanisms: Vec<Box<dyn SaslMechanism>> = vec![Box::new(mechanism)];
+    let mut handshaker = SimpleHandshaker::with_mechanisms(mechanisms, "/");
+    handshaker.reply_to_start(&start_method("RECORDING")).unwrap();
+    (handshaker, challenges)
+}
+
+#[test]
+fn non_utf8_challenge() {
+    let (mut handshaker, challenges) = recording(b"response");
+    let challenge: Vec<u8> = (0..=255).collect();
+    let secure = SecureMethod {
+        challenge: AmqpString::from(challenge.clone()),
+    };
+    let secure_ok = handshaker.reply_to_secure(&secure).unwrap();
+    assert_eq!(&*secure_ok.response, "response");
+    assert_eq!(*challenges.borrow(), vec![challenge]);
+}
+
+#[test]
+fn non_utf8_response_fails() {
+    let (mut handshaker, _) = recording(b"\xff");
+    let secure = SecureMethod {
+        challenge: "challenge".into(),
+    };
+    match handshaker.reply_to_secure(&secure) {
+        Err(Error(ErrorKind::SaslFailure(_), _)) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ())),
+    }
+
+    // Length of the password is 0x80.
+    let mechanisms: Vec<Box<dyn SaslMechanism>> = vec![Box::new(AmqPlain {
+        user: "guest".into(),
+        pass: "p".repeat(128),
+    })];
+    let mut handshaker = SimpleHandshaker::with_mechanisms(mechanisms, "/");
+    match handshaker.reply_to_start(&start_method("AMQPLAIN")) {
+        Err(Error(ErrorKind::SaslFailure(_), _)) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ())),
+    }
+}
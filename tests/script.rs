@@ -0,0 +1,193 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use std::collections::HashMap;
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{BasicClass, ConsumeMethod, DeliverMethod};
+use amqpr_codec::method::channel::{ChannelClass, OpenMethod, OpenOkMethod};
+use amqpr_codec::method::queue::{DeclareMethod, DeclareOkMethod, QueueClass};
+use amqpr_api::{declare_queue, get_delivered, open_channel, subscribe_stream};
+use amqpr_api::basic::StartConsumeOption;
+use amqpr_api::queue::DeclareQueueOption;
+use amqpr_api::mock::Script;
+use amqpr_api::errors::*;
+
+const CHANNEL_ID: u16 = 42;
+
+fn open_frames() -> (Frame, Frame) {
+    let open = OpenMethod {
+        reserved1: "".into(),
+    };
+    let open_ok = OpenOkMethod {
+        reserved1: "".into(),
+    };
+    (
+        Frame::new_method(CHANNEL_ID, MethodPayload::Channel(ChannelClass::Open(open))),
+        Frame::new_method(CHANNEL_ID, MethodPayload::Channel(ChannelClass::OpenOk(open_ok))),
+    )
+}
+
+fn declare_frame(name: &'static str) -> Frame {
+    let declare = DeclareMethod {
+        reserved1: 0,
+        queue: name.into(),
+        passive: false,
+        durable: false,
+        exclusive: false,
+        auto_delete: true,
+        no_wait: false,
+        arguments: HashMap::new(),
+    };
+    Frame::new_method(CHANNEL_ID, MethodPayload::Queue(QueueClass::Declare(declare)))
+}
+
+fn declare_option(name: &'static str) -> DeclareQueueOption {
+    DeclareQueueOption {
+        name: name.into(),
+        is_passive: false,
+        is_durable: false,
+        is_exclusive: false,
+        is_auto_delete: true,
+    }
+}
+
+#[test]
+fn open_channel_and_declare_queue() {
+    let mut core = Core::new().unwrap();
+    let (open, open_ok) = open_frames();
+    let declare_ok = DeclareOkMethod {
+        queue: "script_test".into(),
+        message_count: 3,
+        consumer_count: 0,
+    };
+    let script = Script::new()
+        .expect(open)
+        .reply(open_ok)
+        .expect(declare_frame("script_test"))
+        .reply(Frame::new_method(CHANNEL_ID, MethodPayload::Queue(QueueClass::DeclareOk(declare_ok))));
+
+    let future = open_channel(CHANNEL_ID, script.socket())
+        .and_then(|socket| declare_queue(CHANNEL_ID, socket, declare_option("script_test")));
+    let (result, _socket) = core.run(future).unwrap();
+
+    assert_eq!(result.message_count, 3);
+    script.assert_done();
+}
+
+#[test]
+fn unexpected_frame_fails_with_diff() {
+    let mut core = Core::new().unwrap();
+    let (open, open_ok) = open_frames();
+    let script = Script::new()
+        .expect(open)
+        .reply(open_ok)
+        .expect(declare_frame("expected_queue"));
+
+    let future = open_channel(CHANNEL_ID, script.socket())
+        .and_then(|socket| declare_queue(CHANNEL_ID, socket, declare_option("sent_queue")));
+    let err = core.run(future).map(|_| ()).unwrap_err();
+
+    let diff = match *err.kind() {
+        ErrorKind::ScriptMismatch(ref diff) => diff.clone(),
+        ref kind => panic!("Unexpected error {:?}", kind),
+    };
+    assert!(diff.lines().any(|l| l.starts_with("- ") && l.contains("expected_queue")));
+    assert!(diff.lines().any(|l| l.starts_with("+ ") && l.contains("sent_queue")));
+    assert!(diff.lines().any(|l| l.starts_with("  ") && l.contains("channel: 42")));
+}
+
+#[test]
+fn consecutive_deliveries() {
+    let mut core = Core::new().unwrap();
+    let consume = ConsumeMethod {
+        reserved1: 0,
+        queue: "script_test".into(),
+        consumer_tag: "script_tag".into(),
+        no_local: false,
+        no_ack: true,
+        exclusive: false,
+        no_wait: true,
+        arguments: HashMap::new(),
+    };
+    let deliver = |delivery_tag| DeliverMethod {
+        consumer_tag: "script_tag".into(),
+        delivery_tag,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "script_test".into(),
+    };
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: 4,
+        properties: Properties::new(),
+    };
+    let body = ContentBodyPayload {
+        bytes: Bytes::from_static(b"body"),
+    };
+    let script = Script::new()
+        .expect(Frame::new_method(CHANNEL_ID, MethodPayload::Basic(BasicClass::Consume(consume))))
+        .reply(Frame::new_method(CHANNEL_ID, MethodPayload::Basic(BasicClass::Deliver(deliver(1)))))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentHeader(header.clone())))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentBody(body.clone())))
+        .reply(Frame::new_method(CHANNEL_ID, MethodPayload::Basic(BasicClass::Deliver(deliver(2)))))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentHeader(header)))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentBody(body)));
+
+    let option = StartConsumeOption {
+        queue: "script_test".into(),
+        consumer_tag: "script_tag".into(),
+        is_no_local: false,
+        is_no_ack: true,
+        is_exclusive: false,
+    };
+    let stream = subscribe_stream(CHANNEL_ID, script.socket(), option);
+    let items = core.run(stream.take(2).collect()).unwrap();
+
+    let tags: Vec<u64> = items.iter().map(|item| item.meta.delivery_tag).collect();
+    assert_eq!(tags, vec![1, 2]);
+    script.assert_done();
+}
+
+#[test]
+fn skip_heartbeats_inside_delivery() {
+    let mut core = Core::new().unwrap();
+    let deliver = DeliverMethod {
+        consumer_tag: "script_tag".into(),
+        delivery_tag: 1,
+        redeliverd: false,
+        exchange: "".into(),
+        routing_key: "script_test".into(),
+    };
+    let header = ContentHeaderPayload {
+        class_id: 60,
+        body_size: 4,
+        properties: Properties::new(),
+    };
+    let body = ContentBodyPayload {
+        bytes: Bytes::from_static(b"body"),
+    };
+    let script = Script::new()
+        .reply(Frame::new_heartbeat(0))
+        .reply(Frame::new_method(CHANNEL_ID, MethodPayload::Basic(BasicClass::Deliver(deliver))))
+        .reply(Frame::new_heartbeat(0))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentHeader(header)))
+        .reply(Frame::new_heartbeat(0))
+        .reply(Frame::new(CHANNEL_ID, FramePayload::ContentBody(body)));
+
+    let (item, _socket) = core.run(get_delivered(script.socket())).unwrap();
+    assert_eq!(item.meta.delivery_tag, 1);
+    assert_eq!(item.body.bytes, Bytes::from_static(b"body"));
+    script.assert_done();
+}
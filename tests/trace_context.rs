@@ -0,0 +1,126 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+
+use futures::{stream, Future, Sink, Stream};
+use futures::unsync::mpsc;
+
+use std::collections::HashMap;
+
+use amqpr_codec::{AmqpString, FieldArgument};
+use amqpr_codec::content_body::ContentBodyPayload;
+use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+use amqpr_codec::method::basic::DeliverMethod;
+use amqpr_api::basic::deliver::DeliveredItem;
+use amqpr_api::basic::publish::{PublishItem, PublishOption};
+use amqpr_api::trace_context::*;
+use amqpr_api::errors::*;
+
+const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+fn item() -> PublishItem {
+    PublishItem {
+        meta: PublishOption {
+            exchange: "".into(),
+            routing_key: "queue".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        },
+        header: Properties::new(),
+        body: "body".into(),
+    }
+}
+
+fn delivered(item: PublishItem) -> DeliveredItem {
+    DeliveredItem {
+        meta: DeliverMethod {
+            consumer_tag: "ctag".into(),
+            delivery_tag: 1,
+            redeliverd: false,
+            exchange: item.meta.exchange,
+            routing_key: item.meta.routing_key,
+        },
+        header: ContentHeaderPayload {
+            class_id: 60,
+            body_size: item.body.len() as u64,
+            properties: item.header,
+        },
+        body: ContentBodyPayload { bytes: item.body },
+    }
+}
+
+#[test]
+fn parse_and_format_traceparent() {
+    let context = TraceContext::parse(TRACEPARENT_VALUE, Some("congo=t61rcWkgMzE")).unwrap();
+    assert_eq!(context.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+    assert!(context.is_sampled());
+    assert_eq!(context.traceparent(), TRACEPARENT_VALUE);
+    assert_eq!(context.tracestate, Some("congo=t61rcWkgMzE".to_string()));
+
+    // Uppercase, all zero ids, version ff and extra fields of version 00 are invalid.
+    assert!(TraceContext::parse(&TRACEPARENT_VALUE.to_uppercase(), None).is_none());
+    assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01", None).is_none());
+    assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01", None).is_none());
+    assert!(TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", None).is_none());
+    assert!(TraceContext::parse(&format!("{}-extra", TRACEPARENT_VALUE), None).is_none());
+    // Later versions may have extra fields.
+    assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra", None).is_some());
+}
+
+#[test]
+fn child_keeps_trace_id() {
+    let root = TraceContext::new_root(true);
+    let child = root.child();
+    assert_eq!(child.trace_id, root.trace_id);
+    assert_ne!(child.span_id, root.span_id);
+    assert_eq!(TraceContext::parse(&child.traceparent(), None), Some(child));
+}
+
+#[test]
+fn inject_replaces_existing_headers() {
+    let mut item = item();
+    let mut headers = HashMap::new();
+    headers.insert(AmqpString::from("tracestate"), FieldArgument::LongString("stale".into()));
+    headers.insert(AmqpString::from("x-other"), FieldArgument::Boolean(true));
+    item.header.headers = Some(headers);
+
+    let context = TraceContext::parse(TRACEPARENT_VALUE, None).unwrap();
+    let delivered = delivered(context.inject(item));
+
+    assert_eq!(
+        delivered.header_value("traceparent"),
+        Some(&FieldArgument::LongString(TRACEPARENT_VALUE.into()))
+    );
+    assert_eq!(delivered.header_value("tracestate"), None);
+    assert_eq!(delivered.header_value("x-other"), Some(&FieldArgument::Boolean(true)));
+    assert_eq!(TraceContext::extract(&delivered), Some(context));
+}
+
+#[test]
+fn inject_sink_and_extract_stream() {
+    let root = TraceContext::new_root(true);
+    let current = root.clone();
+    let mut calls = 0;
+
+    let (tx, rx) = mpsc::unbounded();
+    let sink = inject_sink(tx.sink_map_err(|_| Error::from("receiver is dropped")), move || {
+        // Only the first item is published in a span.
+        calls += 1;
+        if calls == 1 {
+            Some(current.clone())
+        } else {
+            None
+        }
+    });
+    drop(sink.send_all(stream::iter_ok::<_, Error>(vec![item(), item()])).wait().unwrap());
+
+    let published: Vec<PublishItem> = rx.collect().wait().unwrap();
+    let delivered = stream::iter_ok::<_, Error>(published.into_iter().map(delivered));
+    let extracted = extract_stream(delivered).collect().wait().unwrap();
+
+    let consumer = extracted[0].1.clone().unwrap();
+    assert_eq!(consumer.trace_id, root.trace_id);
+    assert_ne!(consumer.span_id, root.span_id);
+    assert_eq!(extracted[1].1, None);
+}
@@ -0,0 +1,38 @@
+extern crate amqpr_api;
+extern crate amqpr_codec;
+
+use amqpr_codec::method::connection::TuneMethod;
+use amqpr_api::handshake::{Handshaker, SimpleHandshaker};
+
+#[test]
+fn negotiate_smaller_limit() {
+    let mut handshaker = SimpleHandshaker::new("guest", "guest", "/")
+        .channel_max(100)
+        .frame_max(0)
+        .heartbeat(30);
+    let tune = TuneMethod {
+        channel_max: 2047,
+        frame_max: 131072,
+        heartbeat: 0,
+    };
+
+    let tune_ok = handshaker.reply_to_tune(&tune);
+    assert_eq!(tune_ok.channel_max, 100);
+    assert_eq!(tune_ok.frame_max, 131072);
+    assert_eq!(tune_ok.heartbeat, 30);
+}
+
+#[test]
+fn negotiate_server_limit() {
+    let mut handshaker = SimpleHandshaker::new("guest", "guest", "/").channel_max(4096);
+    let tune = TuneMethod {
+        channel_max: 2047,
+        frame_max: 4096,
+        heartbeat: 10,
+    };
+
+    let tune_ok = handshaker.reply_to_tune(&tune);
+    assert_eq!(tune_ok.channel_max, 2047);
+    assert_eq!(tune_ok.frame_max, 4096);
+    assert_eq!(tune_ok.heartbeat, 10);
+}